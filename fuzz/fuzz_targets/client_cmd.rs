@@ -0,0 +1,18 @@
+#![no_main]
+
+use std::io::BufReader;
+
+use arbitrary::Unstructured;
+use libfuzzer_sys::fuzz_target;
+use richter::common::net::{generate, ClientCmd};
+
+fuzz_target!(|data: &[u8]| {
+    let mut u = Unstructured::new(data);
+    let protocol = match generate::protocol(&mut u) {
+        Ok(p) => p,
+        Err(_) => return,
+    };
+
+    let mut reader = BufReader::new(u.take_rest());
+    while ClientCmd::deserialize(&mut reader, protocol).is_ok() {}
+});
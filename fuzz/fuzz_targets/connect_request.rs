@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use richter::common::net::connect::Request;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Request::from_bytes(data);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use richter::common::net::connect::Response;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Response::from_bytes(data);
+});
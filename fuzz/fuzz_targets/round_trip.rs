@@ -0,0 +1,59 @@
+#![no_main]
+
+//! Checks that every generated message reads back the way it was written.
+
+use std::io::BufReader;
+
+use arbitrary::{Result, Unstructured};
+use libfuzzer_sys::fuzz_target;
+use richter::common::net::{
+    connect::{ConnectPacket, Request, Response},
+    generate, ClientCmd, ServerCmd, TempEntity,
+};
+
+fn check(u: &mut Unstructured) -> Result<()> {
+    let protocol = generate::protocol(u)?;
+
+    match u.int_in_range(0..=4)? {
+        0 => {
+            let src = generate::server_cmd(u, protocol)?;
+            let mut packet = Vec::new();
+            src.serialize(&mut packet, protocol).unwrap();
+            let dst = ServerCmd::deserialize(&mut BufReader::new(packet.as_slice()), protocol);
+            assert_eq!(Some(src), dst.unwrap());
+        }
+
+        1 => {
+            let src = generate::client_cmd(u, protocol)?;
+            let mut packet = Vec::new();
+            src.serialize(&mut packet, protocol).unwrap();
+            let dst = ClientCmd::deserialize(&mut BufReader::new(packet.as_slice()), protocol);
+            assert_eq!(src, dst.unwrap());
+        }
+
+        2 => {
+            let src = generate::temp_entity(u, protocol)?;
+            let mut packet = Vec::new();
+            src.write_temp_entity(&mut packet, protocol).unwrap();
+            let dst =
+                TempEntity::read_temp_entity(&mut BufReader::new(packet.as_slice()), protocol);
+            assert_eq!(src, dst.unwrap());
+        }
+
+        3 => {
+            let src = generate::request(u)?;
+            assert_eq!(src, Request::from_bytes(&src.to_bytes().unwrap()).unwrap());
+        }
+
+        _ => {
+            let src = generate::response(u)?;
+            assert_eq!(src, Response::from_bytes(&src.to_bytes().unwrap()).unwrap());
+        }
+    }
+
+    Ok(())
+}
+
+fuzz_target!(|data: &[u8]| {
+    let _ = check(&mut Unstructured::new(data));
+});
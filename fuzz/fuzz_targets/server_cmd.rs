@@ -0,0 +1,19 @@
+#![no_main]
+
+use std::io::BufReader;
+
+use arbitrary::Unstructured;
+use libfuzzer_sys::fuzz_target;
+use richter::common::net::{generate, ServerCmd};
+
+fuzz_target!(|data: &[u8]| {
+    let mut u = Unstructured::new(data);
+    let protocol = match generate::protocol(&mut u) {
+        Ok(p) => p,
+        Err(_) => return,
+    };
+
+    // every command consumes at least its code byte, so this ends with the input
+    let mut reader = BufReader::new(u.take_rest());
+    while let Ok(Some(_)) = ServerCmd::deserialize(&mut reader, protocol) {}
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use std::io::BufReader;
+
+use arbitrary::Unstructured;
+use libfuzzer_sys::fuzz_target;
+use richter::common::net::{generate, TempEntity};
+
+fuzz_target!(|data: &[u8]| {
+    let mut u = Unstructured::new(data);
+    let protocol = match generate::protocol(&mut u) {
+        Ok(p) => p,
+        Err(_) => return,
+    };
+
+    let _ = TempEntity::read_temp_entity(&mut BufReader::new(u.take_rest()), protocol);
+});
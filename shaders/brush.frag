@@ -45,19 +45,21 @@ layout(location = 1) out vec4 normal_attachment;
 layout(location = 2) out vec4 light_attachment;
 
 vec4 calc_light() {
-    vec4 light = vec4(0.0, 0.0, 0.0, 0.0);
+    vec3 light = vec3(0.0, 0.0, 0.0);
     for (int i = 0; i < 4 && f_lightmap_anim[i] != LIGHTMAP_ANIM_END; i++) {
-        float map = texture(
+        vec3 map = texture(
             sampler2D(u_lightmap_texture[i], u_lightmap_sampler),
             f_lightmap
-        ).r;
+        ).rgb;
 
         // range [0, 4]
         float style = frame_uniforms.light_anim_frames[f_lightmap_anim[i]];
-        light[i] = map * style;
+
+        // each style contributes at most 1.0
+        light += min(map * style, vec3(1.0));
     }
 
-    return light;
+    return vec4(light, 1.0);
 }
 
 void main() {
@@ -73,11 +75,9 @@ void main() {
                 f_diffuse
             ).r;
 
-            if (fullbright != 0.0) {
-                light_attachment = vec4(0.25);
-            } else {
-                light_attachment = calc_light();
-            }
+            // fullbright texels ignore the lightmaps. the mask is either 0 or 1 for indexed
+            // textures but can be anywhere in between for replacement images
+            light_attachment = mix(calc_light(), vec4(1.0), fullbright);
             break;
 
         case TEXTURE_KIND_WARP:
@@ -93,7 +93,7 @@ void main() {
                 sampler2D(u_diffuse_texture, u_diffuse_sampler),
                 warp_texcoord
             );
-            light_attachment = vec4(0.25);
+            light_attachment = vec4(1.0);
             break;
 
         case TEXTURE_KIND_SKY:
@@ -118,7 +118,7 @@ void main() {
                 cloud_factor = 1.0;
             }
             diffuse_attachment = mix(sky_color, cloud_color, cloud_factor);
-            light_attachment = vec4(0.25);
+            light_attachment = vec4(1.0);
             break;
 
         // not possible
@@ -54,7 +54,8 @@ void main() {
 
   vec4 out_color = in_color;
 
-  float light = in_light.x + in_light.y + in_light.z + in_light.w;
+  // the light level of each color channel
+  vec3 light = in_light.rgb;
   for (uint i = 0; i < u_deferred.light_count && i < MAX_LIGHTS; i++) {
     vec4 dlight = u_deferred.lights[i];
     vec3 dir = normalize(position - dlight_origin(dlight));
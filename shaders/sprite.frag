@@ -18,5 +18,5 @@ void main() {
 
   // rescale normal to [0, 1]
   normal_attachment = vec4(f_normal / 2.0 + 0.5, 1.0);
-  light_attachment = vec4(1.0, 1.0, 1.0, 1.0);
+  light_attachment = vec4(4.0, 4.0, 4.0, 1.0);
 }
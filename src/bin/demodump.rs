@@ -0,0 +1,178 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+extern crate richter;
+
+use std::{
+    fs::File,
+    io::{stdout, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use richter::{
+    client::demo::DemoServer,
+    common::{
+        engine,
+        net::ServerCmd,
+        vfs::{Vfs, VirtualFile},
+    },
+};
+
+use serde::Serialize;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// Print one JSON object per demo message.
+    #[structopt(long)]
+    json: bool,
+
+    /// The directory containing `id1/`, used if DEMO isn't a path to a file.
+    #[structopt(long, parse(from_os_str))]
+    base_dir: Option<PathBuf>,
+
+    /// A demo file, or the name of a demo in the game directory or its PAKs.
+    #[structopt(name = "DEMO")]
+    demo: String,
+}
+
+/// A decoded demo message.
+#[derive(Serialize)]
+struct DumpedMessage<'a> {
+    message: usize,
+
+    /// Playback time in seconds.
+    time: f32,
+
+    /// Pitch, yaw and roll in degrees.
+    view_angles: [f32; 3],
+
+    cmds: &'a [ServerCmd],
+}
+
+impl<'a> DumpedMessage<'a> {
+    fn write_text<W>(&self, mut writer: W) -> std::io::Result<()>
+    where
+        W: Write,
+    {
+        let [pitch, yaw, roll] = self.view_angles;
+        writeln!(
+            writer,
+            "message {} at {:.3}s, view angles ({}, {}, {})",
+            self.message, self.time, pitch, yaw, roll
+        )?;
+
+        for cmd in self.cmds.iter() {
+            writeln!(writer, "  {:?}", cmd)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let opt = Opt::from_args();
+
+    let path = Path::new(&opt.demo);
+    let vfs;
+    let mut demfile = if path.is_file() {
+        match File::open(path) {
+            Ok(f) => VirtualFile::FileBacked(BufReader::new(f)),
+            Err(e) => {
+                eprintln!("Couldn't open {}: {}", opt.demo, e);
+                exit(1);
+            }
+        }
+    } else {
+        let base_dir = opt
+            .base_dir
+            .clone()
+            .unwrap_or_else(|| std::env::current_dir().unwrap());
+        vfs = Vfs::with_base_dir(base_dir);
+        match vfs.open(&opt.demo) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("Couldn't open {}: {}", opt.demo, e);
+                exit(1);
+            }
+        }
+    };
+
+    let mut demserv = match DemoServer::new(&mut demfile) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("Couldn't load demo: {}", e);
+            exit(1);
+        }
+    };
+
+    let stdout = stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let mut message = 0;
+    let mut cmds = Vec::new();
+
+    while let Some(msg) = demserv.next() {
+        let angles = msg.view_angles();
+
+        // decode as much of the message as possible so the command that
+        // failed to parse can be found from the ones before it
+        let mut msg_cmds = demserv.cmds(message).unwrap();
+        let error = loop {
+            match msg_cmds.next() {
+                Some(Ok(cmd)) => cmds.push(cmd),
+                Some(Err(e)) => break Some((e, msg_cmds.position())),
+                None => break None,
+            }
+        };
+
+        let dumped = DumpedMessage {
+            message,
+            time: engine::duration_to_f32(demserv.time()),
+            view_angles: [angles.x.0, angles.y.0, angles.z.0],
+            cmds: &cmds,
+        };
+
+        let written = if opt.json {
+            serde_json::to_writer(&mut out, &dumped)
+                .map_err(std::io::Error::from)
+                .and_then(|_| writeln!(out))
+        } else {
+            dumped.write_text(&mut out)
+        };
+
+        if let Err(e) = written {
+            eprintln!("Couldn't write output: {}", e);
+            exit(1);
+        }
+
+        if let Some((e, position)) = error {
+            let _ = out.flush();
+            eprintln!("{} (at byte {})", e, position);
+            exit(1);
+        }
+
+        cmds.clear();
+        message += 1;
+    }
+
+    if let Err(e) = out.flush() {
+        eprintln!("Couldn't write output: {}", e);
+        exit(1);
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+extern crate richter;
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use richter::{
+    client::{
+        demo::{self, DemoServer},
+        demo_edit::{DemoEditError, DemoEditor},
+    },
+    common::{
+        net::ServerCmd,
+        vfs::{Vfs, VirtualFile},
+    },
+};
+
+use chrono::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Cuts and joins demos")]
+struct Opt {
+    /// Where to write the new demo.
+    #[structopt(short, long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// Server commands to leave out, e.g. `StuffText,CenterPrint`.
+    #[structopt(long, use_delimiter = true)]
+    drop: Vec<String>,
+
+    /// The directory containing `id1/`, used for demos which aren't paths to
+    /// files.
+    #[structopt(long, parse(from_os_str))]
+    base_dir: Option<PathBuf>,
+
+    /// Sections to join, each a demo with an optional time range given as
+    /// `DEMO@START-END`. Times are in seconds or `minutes:seconds`, and either
+    /// may be left out, as in `duel.dem@1:30-`.
+    #[structopt(name = "SECTION", required = true)]
+    sections: Vec<String>,
+}
+
+struct Section {
+    demo: String,
+    start: Option<Duration>,
+    end: Option<Duration>,
+}
+
+fn parse_section(s: &str) -> Result<Section, String> {
+    let (demo, range) = match s.rsplit_once('@') {
+        Some((demo, range)) => (demo, Some(range)),
+        None => (s, None),
+    };
+
+    let parse_bound = |t: &str| match t {
+        "" => Ok(None),
+        t => demo::parse_time(t)
+            .map(Some)
+            .ok_or_else(|| format!("Invalid time: {}", t)),
+    };
+
+    let (start, end) = match range {
+        Some(range) => match range.split_once('-') {
+            Some((start, end)) => (parse_bound(start)?, parse_bound(end)?),
+            None => return Err(format!("Invalid time range: {}", range)),
+        },
+        None => (None, None),
+    };
+
+    Ok(Section {
+        demo: demo.to_owned(),
+        start,
+        end,
+    })
+}
+
+fn load_demo(name: &str, vfs: &mut Option<Vfs>, base_dir: &Option<PathBuf>) -> DemoServer {
+    let path = Path::new(name);
+    let result = if path.is_file() {
+        File::open(path).map_err(|e| e.to_string()).and_then(|f| {
+            DemoServer::new(&mut VirtualFile::FileBacked(BufReader::new(f)))
+                .map_err(|e| e.to_string())
+        })
+    } else {
+        let vfs = vfs.get_or_insert_with(|| {
+            let base_dir = base_dir
+                .clone()
+                .unwrap_or_else(|| std::env::current_dir().unwrap());
+            Vfs::with_base_dir(base_dir)
+        });
+
+        vfs.open(name)
+            .map_err(|e| e.to_string())
+            .and_then(|mut f| DemoServer::new(&mut f).map_err(|e| e.to_string()))
+    };
+
+    match result {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("Couldn't load {}: {}", name, e);
+            exit(1);
+        }
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let opt = Opt::from_args();
+
+    let sections: Vec<Section> = match opt.sections.iter().map(|s| parse_section(s)).collect() {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+
+    let mut vfs = None;
+    let demos: Vec<DemoServer> = sections
+        .iter()
+        .map(|s| load_demo(&s.demo, &mut vfs, &opt.base_dir))
+        .collect();
+
+    let file = match File::create(&opt.output) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("Couldn't create {}: {}", opt.output.display(), e);
+            exit(1);
+        }
+    };
+
+    let mut editor = match DemoEditor::new(BufWriter::new(file), demos[0].track_override()) {
+        Ok(e) => e,
+        Err(e) => fail(&opt.output, e.into()),
+    };
+
+    if let Err(e) = editor.drop_cmds(&opt.drop) {
+        eprintln!("{}", e);
+        eprintln!("Server commands are: {}", ServerCmd::NAMES.join(", "));
+        exit(1);
+    }
+
+    for (section, demo) in sections.iter().zip(demos.iter()) {
+        if let Err(e) = editor.append(demo, section.start, section.end) {
+            eprintln!("Couldn't add {}: {}", section.demo, e);
+            exit(1);
+        }
+    }
+
+    if let Err(e) = editor.finish() {
+        fail(&opt.output, e);
+    }
+}
+
+fn fail(output: &Path, error: DemoEditError) -> ! {
+    eprintln!("Couldn't write {}: {}", output.display(), error);
+    exit(1);
+}
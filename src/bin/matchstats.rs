@@ -0,0 +1,195 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+extern crate richter;
+
+use std::{
+    fs::File,
+    io::{stdout, BufReader},
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use richter::{
+    client::{
+        demo::DemoServer,
+        match_stats::{MatchStats, PlayerStats},
+    },
+    common::vfs::{Vfs, VirtualFile},
+};
+
+use serde::Serialize;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Reports match statistics from demos")]
+struct Opt {
+    /// Print the statistics as JSON.
+    #[structopt(long)]
+    json: bool,
+
+    /// The directory containing `id1/`, used for demos which aren't paths to
+    /// files.
+    #[structopt(long, parse(from_os_str))]
+    base_dir: Option<PathBuf>,
+
+    /// Demo files, or names of demos in the game directory or its PAKs.
+    #[structopt(name = "DEMO", required = true)]
+    demos: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct DemoReport {
+    demo: String,
+    #[serde(flatten)]
+    stats: MatchStats,
+    totals: Vec<PlayerStats>,
+}
+
+fn load_demo(name: &str, vfs: &mut Option<Vfs>, base_dir: &Option<PathBuf>) -> DemoServer {
+    let path = Path::new(name);
+    let result = if path.is_file() {
+        File::open(path).map_err(|e| e.to_string()).and_then(|f| {
+            DemoServer::new(&mut VirtualFile::FileBacked(BufReader::new(f)))
+                .map_err(|e| e.to_string())
+        })
+    } else {
+        let vfs = vfs.get_or_insert_with(|| {
+            let base_dir = base_dir
+                .clone()
+                .unwrap_or_else(|| std::env::current_dir().unwrap());
+            Vfs::with_base_dir(base_dir)
+        });
+
+        vfs.open(name)
+            .map_err(|e| e.to_string())
+            .and_then(|mut f| DemoServer::new(&mut f).map_err(|e| e.to_string()))
+    };
+
+    match result {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("Couldn't load {}: {}", name, e);
+            exit(1);
+        }
+    }
+}
+
+fn format_time(seconds: f32) -> String {
+    let seconds = seconds.max(0.0) as u32;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn print_players(players: &[PlayerStats]) {
+    println!(
+        "  {:<15} {:>5} {:>6} {:>6} {:>6} {:>7}",
+        "Name", "Frags", "Deaths", "Time", "Damage", "Pickups"
+    );
+
+    let mut players: Vec<&PlayerStats> = players.iter().collect();
+    players.sort_by_key(|p| std::cmp::Reverse(p.frags));
+    for player in players {
+        if !player.recorder {
+            println!(
+                "  {:<15} {:>5} {:>6} {:>6}",
+                player.name,
+                player.frags,
+                player.deaths,
+                format_time(player.time)
+            );
+            continue;
+        }
+
+        println!(
+            "  {:<15} {:>5} {:>6} {:>6} {:>6} {:>7}",
+            player.name,
+            player.frags,
+            player.deaths,
+            format_time(player.time),
+            player.damage_taken,
+            player.pickups
+        );
+
+        if !player.items.is_empty() {
+            let items: Vec<String> = player
+                .items
+                .iter()
+                .map(|(item, count)| format!("{} x{}", item, count))
+                .collect();
+            println!("    items: {}", items.join(", "));
+        }
+    }
+}
+
+fn print_report(report: &DemoReport) {
+    println!("{}", report.demo);
+
+    for level in report.stats.levels.iter() {
+        println!(
+            "{} ({}), {}{}",
+            level.map,
+            level.title,
+            format_time(level.duration),
+            if level.finished { "" } else { ", unfinished" }
+        );
+        print_players(&level.players);
+    }
+
+    if report.stats.levels.len() > 1 {
+        println!("Total");
+        print_players(&report.totals);
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let opt = Opt::from_args();
+
+    let mut vfs = None;
+    let mut reports = Vec::new();
+    for name in opt.demos.iter() {
+        let demo = load_demo(name, &mut vfs, &opt.base_dir);
+        let stats = match MatchStats::from_demo(&demo) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("Couldn't read {}: {}", name, e);
+                exit(1);
+            }
+        };
+
+        reports.push(DemoReport {
+            demo: name.clone(),
+            totals: stats.totals(),
+            stats,
+        });
+    }
+
+    if opt.json {
+        if let Err(e) = serde_json::to_writer_pretty(stdout(), &reports) {
+            eprintln!("Couldn't write JSON: {}", e);
+            exit(1);
+        }
+        println!();
+    } else {
+        for (i, report) in reports.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            print_report(report);
+        }
+    }
+}
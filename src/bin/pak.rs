@@ -0,0 +1,205 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+extern crate richter;
+
+use std::{
+    fs::{self, File},
+    path::{Component, Path, PathBuf},
+    process::exit,
+};
+
+use richter::common::pak::{Pak, PakBuilder};
+
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Creates and edits PAK archives")]
+enum Opt {
+    /// Creates an archive from files and directories.
+    Create {
+        /// The directory archive paths are relative to.
+        #[structopt(short = "C", long, parse(from_os_str), default_value = ".")]
+        directory: PathBuf,
+
+        #[structopt(name = "PAK", parse(from_os_str))]
+        pak: PathBuf,
+
+        /// Files to add. Directories are added with everything in them.
+        #[structopt(name = "FILE", parse(from_os_str), required = true)]
+        files: Vec<PathBuf>,
+    },
+
+    /// Lists the files in an archive with their sizes.
+    List {
+        #[structopt(name = "PAK", parse(from_os_str))]
+        pak: PathBuf,
+    },
+
+    /// Adds files to an archive, replacing any already at the same paths.
+    Add {
+        /// The directory archive paths are relative to.
+        #[structopt(short = "C", long, parse(from_os_str), default_value = ".")]
+        directory: PathBuf,
+
+        #[structopt(name = "PAK", parse(from_os_str))]
+        pak: PathBuf,
+
+        /// Files to add. Directories are added with everything in them.
+        #[structopt(name = "FILE", parse(from_os_str), required = true)]
+        files: Vec<PathBuf>,
+    },
+
+    /// Removes files from an archive.
+    Delete {
+        #[structopt(name = "PAK", parse(from_os_str))]
+        pak: PathBuf,
+
+        /// Paths of the files to remove within the archive.
+        #[structopt(name = "NAME", required = true)]
+        names: Vec<String>,
+    },
+}
+
+fn main() {
+    env_logger::init();
+
+    let result = match Opt::from_args() {
+        Opt::Create {
+            directory,
+            pak,
+            files,
+        } => add_files(PakBuilder::new(), &directory, &files)
+            .and_then(|builder| write_pak(&pak, &builder)),
+
+        Opt::List { pak } => open_pak(&pak).map(|pak| {
+            for (path, data) in pak.iter() {
+                println!("{:>10}  {}", data.len(), path);
+            }
+        }),
+
+        Opt::Add {
+            directory,
+            pak,
+            files,
+        } => open_pak(&pak)
+            .and_then(|p| copy_pak(&pak, &p))
+            .and_then(|builder| add_files(builder, &directory, &files))
+            .and_then(|builder| write_pak(&pak, &builder)),
+
+        Opt::Delete { pak, names } => open_pak(&pak)
+            .and_then(|p| copy_pak(&pak, &p))
+            .and_then(|mut builder| {
+                for name in names.iter() {
+                    if !builder.remove(name) {
+                        return Err(format!("No such file in {}: {}", pak.display(), name));
+                    }
+                }
+
+                Ok(builder)
+            })
+            .and_then(|builder| write_pak(&pak, &builder)),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
+
+fn open_pak(path: &Path) -> Result<Pak, String> {
+    Pak::new(path).map_err(|e| format!("Couldn't open {}: {}", path.display(), e))
+}
+
+fn copy_pak(path: &Path, pak: &Pak) -> Result<PakBuilder, String> {
+    PakBuilder::from_pak(pak).map_err(|e| format!("Couldn't copy {}: {}", path.display(), e))
+}
+
+// adds each file, or everything in each directory, to the archive under its
+// path relative to `directory`
+fn add_files(
+    mut builder: PakBuilder,
+    directory: &Path,
+    files: &[PathBuf],
+) -> Result<PakBuilder, String> {
+    let mut paths = Vec::new();
+    for file in files {
+        collect_files(&directory.join(file), &mut paths)?;
+    }
+
+    for path in paths {
+        let relative = path
+            .strip_prefix(directory)
+            .ok()
+            .filter(|r| r.components().all(|c| matches!(c, Component::Normal(_))))
+            .ok_or_else(|| format!("{} is outside {}", path.display(), directory.display()))?;
+
+        let name = relative
+            .components()
+            .map(|c| c.as_os_str().to_str())
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| format!("Non-UTF-8 file name: {}", relative.display()))?
+            .join("/");
+
+        let data =
+            fs::read(&path).map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+        builder
+            .add(&name, data)
+            .map_err(|e| format!("Couldn't add {}: {}", path.display(), e))?;
+    }
+
+    Ok(builder)
+}
+
+fn collect_files(path: &Path, paths: &mut Vec<PathBuf>) -> Result<(), String> {
+    if !path.is_dir() {
+        paths.push(path.to_path_buf());
+        return Ok(());
+    }
+
+    let mut entries = fs::read_dir(path)
+        .and_then(|entries| {
+            entries
+                .map(|e| e.map(|e| e.path()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| format!("Couldn't read {}: {}", path.display(), e))?;
+    entries.sort();
+
+    for entry in entries {
+        collect_files(&entry, paths)?;
+    }
+
+    Ok(())
+}
+
+// writes to a temporary file first, so the archive is left alone if anything
+// goes wrong
+fn write_pak(path: &Path, builder: &PakBuilder) -> Result<(), String> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    File::create(&temp_path)
+        .map_err(|e| e.to_string())
+        .and_then(|f| builder.write(f).map_err(|e| e.to_string()))
+        .and_then(|()| fs::rename(&temp_path, path).map_err(|e| e.to_string()))
+        .map_err(|e| {
+            let _ = fs::remove_file(&temp_path);
+            format!("Couldn't write {}: {}", path.display(), e)
+        })
+}
@@ -7,7 +7,7 @@ use std::{
     rc::Rc,
 };
 
-use richter::client::render::Extent2d;
+use richter::{client::render::Extent2d, common::vfs::Vfs};
 
 use chrono::Utc;
 
@@ -16,21 +16,28 @@ const BYTES_PER_PIXEL: u32 = 4;
 /// Implements the "screenshot" command.
 ///
 /// This function returns a boxed closure which sets the `screenshot_path`
-/// argument to `Some` when called.
+/// argument to `Some` when called. Paths are relative to the game directory
+/// and may not leave it.
 pub fn cmd_screenshot(
+    vfs: Rc<Vfs>,
     screenshot_path: Rc<RefCell<Option<PathBuf>>>,
 ) -> Box<dyn Fn(&[&str]) -> String> {
     Box::new(move |args| {
-        let path = match args.len() {
+        let name = match args.len() {
             // TODO: make default path configurable
-            0 => PathBuf::from(format!("richter-{}.png", Utc::now().format("%FT%H-%M-%S"))),
-            1 => PathBuf::from(args[0]),
+            0 => format!("richter-{}.png", Utc::now().format("%FT%H-%M-%S")),
+            1 => args[0].to_owned(),
             _ => {
                 log::error!("Usage: screenshot [PATH]");
                 return "Usage: screenshot [PATH]".to_owned();
             }
         };
 
+        let path = match vfs.game_file_path(&name) {
+            Ok(p) => p,
+            Err(e) => return format!("{}", e),
+        };
+
         screenshot_path.replace(Some(path));
         String::new()
     })
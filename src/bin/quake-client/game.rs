@@ -35,7 +35,10 @@ use richter::{
         trace::TraceFrame,
         Client, ClientError,
     },
-    common::console::{CmdRegistry, Console, CvarRegistry},
+    common::{
+        console::{CmdRegistry, Console, CvarRegistry},
+        vfs::Vfs,
+    },
 };
 
 use chrono::Duration;
@@ -61,6 +64,7 @@ impl Game {
         cvars: Rc<RefCell<CvarRegistry>>,
         cmds: Rc<RefCell<CmdRegistry>>,
         input: Rc<RefCell<Input>>,
+        vfs: Rc<Vfs>,
         client: Client,
     ) -> Result<Game, Error> {
         // set up input commands
@@ -69,7 +73,7 @@ impl Game {
         // set up screenshots
         let screenshot_path = Rc::new(RefCell::new(None));
         cmds.borrow_mut()
-            .insert("screenshot", cmd_screenshot(screenshot_path.clone()))
+            .insert("screenshot", cmd_screenshot(vfs, screenshot_path.clone()))
             .unwrap();
 
         // set up frame tracing
@@ -24,9 +24,9 @@ mod menu;
 mod trace;
 
 use std::{
-    cell::{Ref, RefCell, RefMut},
+    cell::{Cell, Ref, RefCell, RefMut},
     fs::File,
-    io::{Cursor, Read, Write},
+    io::{self, BufWriter, Read, Write},
     net::SocketAddr,
     path::{Path, PathBuf},
     process::exit,
@@ -36,7 +36,6 @@ use std::{
 use game::Game;
 
 use chrono::Duration;
-use common::net::ServerCmd;
 use richter::{
     client::{
         self,
@@ -44,14 +43,16 @@ use richter::{
         input::{Input, InputFocus},
         menu::Menu,
         render::{self, Extent2d, GraphicsState, UiRenderer, DIFFUSE_ATTACHMENT_FORMAT},
+        slist::ServerList,
         Client,
     },
     common::{
         self,
         console::{CmdRegistry, Console, CvarRegistry},
         host::{Host, Program},
-        vfs::Vfs,
+        vfs::{Vfs, BASE_GAME},
     },
+    server,
 };
 use structopt::StructOpt;
 use wgpu::{CompositeAlphaMode, PresentMode};
@@ -79,23 +80,38 @@ struct ClientProgram {
 
     game: Game,
     input: Rc<RefCell<Input>>,
+
+    // set by the "game" command once the virtual filesystem has been rebuilt
+    game_changed: Rc<Cell<bool>>,
 }
 
 impl ClientProgram {
-    pub async fn new(window: Window, base_dir: Option<PathBuf>, trace: bool) -> ClientProgram {
-        let vfs = Vfs::with_base_dir(base_dir.unwrap_or(common::default_base_dir()));
+    pub async fn new(
+        window: Window,
+        base_dir: Option<PathBuf>,
+        game: Option<String>,
+        trace: bool,
+    ) -> ClientProgram {
+        let vfs = Vfs::with_game(
+            base_dir.unwrap_or(common::default_base_dir()),
+            game.as_deref(),
+        );
 
         let con_names = Rc::new(RefCell::new(Vec::new()));
 
         let cvars = Rc::new(RefCell::new(CvarRegistry::new(con_names.clone())));
         client::register_cvars(&cvars.borrow()).unwrap();
+        server::register_cvars(&cvars.borrow()).unwrap();
         render::register_cvars(&cvars.borrow());
 
         let cmds = Rc::new(RefCell::new(CmdRegistry::new(con_names)));
         // TODO: register commands as other subsystems come online
 
         let console = Rc::new(RefCell::new(Console::new(cmds.clone(), cvars.clone())));
-        let menu = Rc::new(RefCell::new(menu::build_main_menu().unwrap()));
+        let server_list = Rc::new(RefCell::new(ServerList::new()));
+        let menu = Rc::new(RefCell::new(
+            menu::build_main_menu(console.clone(), server_list.clone()).unwrap(),
+        ));
 
         let input = Rc::new(RefCell::new(Input::new(
             InputFocus::Console,
@@ -198,6 +214,65 @@ impl ClientProgram {
             )
             .unwrap();
 
+        // implements "host_writeconfig" command
+        let config_vfs = vfs.clone();
+        let config_cvars = cvars.clone();
+        let config_input = input.clone();
+        cmds.borrow_mut()
+            .insert_or_replace(
+                "host_writeconfig",
+                Box::new(move |args| match args.len() {
+                    0 => match write_config(
+                        &config_vfs,
+                        &config_cvars.borrow(),
+                        &config_input.borrow(),
+                    ) {
+                        Ok(path) => format!("Wrote {}", path.display()),
+                        Err(e) => format!("Couldn't write config: {}", e),
+                    },
+
+                    _ => "usage: host_writeconfig".to_owned(),
+                }),
+            )
+            .unwrap();
+
+        // implements "game" command
+        let game_vfs = vfs.clone();
+        let game_cvars = cvars.clone();
+        let game_input = input.clone();
+        let game_changed = Rc::new(Cell::new(false));
+        let changed = game_changed.clone();
+        cmds.borrow_mut()
+            .insert_or_replace(
+                "game",
+                Box::new(move |args| match args {
+                    [] => format!(
+                        "\"game\" is \"{}\"",
+                        game_vfs.game().as_deref().unwrap_or(BASE_GAME)
+                    ),
+
+                    [game] => {
+                        // keep the settings made under the game being left
+                        if let Err(e) =
+                            write_config(&game_vfs, &game_cvars.borrow(), &game_input.borrow())
+                        {
+                            log::warn!("Couldn't write config: {}", e);
+                        }
+
+                        match game_vfs.set_game(Some(game)) {
+                            Ok(()) => {
+                                changed.set(true);
+                                String::new()
+                            }
+                            Err(e) => format!("{}", e),
+                        }
+                    }
+
+                    _ => "usage: game [directory]".to_owned(),
+                }),
+            )
+            .unwrap();
+
         // this will also execute config.cfg and autoexec.cfg (assuming an unmodified quake.rc)
         console.borrow().stuff_text("exec quake.rc\n");
 
@@ -207,11 +282,19 @@ impl ClientProgram {
             cmds.clone(),
             console.clone(),
             input.clone(),
+            server_list,
             &gfx_state,
             &menu.borrow(),
         );
 
-        let game = Game::new(cvars.clone(), cmds.clone(), input.clone(), client).unwrap();
+        let game = Game::new(
+            cvars.clone(),
+            cmds.clone(),
+            input.clone(),
+            vfs.clone(),
+            client,
+        )
+        .unwrap();
 
         ClientProgram {
             vfs,
@@ -226,7 +309,26 @@ impl ClientProgram {
             ui_renderer,
             game,
             input,
+            game_changed,
+        }
+    }
+
+    /// Reloads everything loaded from the old game directory after the `game`
+    /// command switches to a new one.
+    fn reload_game(&mut self) {
+        self.game.client.disconnect();
+
+        let mut gfx_state = self.gfx_state.borrow_mut();
+        if let Err(e) = gfx_state.reload_game_data() {
+            log::error!("Couldn't reload graphics: {}", e);
         }
+
+        self.ui_renderer = Rc::new(UiRenderer::new(&gfx_state, &self.menu.borrow()));
+        self.game
+            .client
+            .reload_renderer(&gfx_state, &self.menu.borrow());
+
+        self.console.borrow().stuff_text("exec quake.rc\n");
     }
 
     /// Builds a new swap chain with the specified present mode and the window's current dimensions.
@@ -316,6 +418,10 @@ impl Program for ClientProgram {
             self.recreate_swap_chain(wgpu::PresentMode::Immediate);
         }
 
+        if self.game_changed.replace(false) {
+            self.reload_game();
+        }
+
         let size: Extent2d = self.window.inner_size().into();
 
         // TODO: warn user if r_msaa_samples is invalid
@@ -358,6 +464,10 @@ impl Program for ClientProgram {
     }
 
     fn shutdown(&mut self) {
+        if let Err(e) = write_config(&self.vfs, &self.cvars.borrow(), &self.input.borrow()) {
+            log::error!("Couldn't write config: {}", e);
+        }
+
         // TODO: do cleanup things here
         exit(0);
     }
@@ -369,6 +479,27 @@ impl Program for ClientProgram {
     fn cvars_mut(&self) -> RefMut<CvarRegistry> {
         self.cvars.borrow_mut()
     }
+
+    fn uncapped_frame_rate(&self) -> bool {
+        // benchmarks run as fast as frames can be rendered
+        self.game.client.timedemo_running()
+    }
+}
+
+/// Writes the key bindings and archived cvars to `config.cfg` in the game
+/// directory, returning the path written to.
+fn write_config(vfs: &Vfs, cvars: &CvarRegistry, input: &Input) -> io::Result<PathBuf> {
+    let path = match vfs.game_dir() {
+        Some(dir) => dir.join("config.cfg"),
+        None => return Err(io::Error::new(io::ErrorKind::NotFound, "no game directory")),
+    };
+
+    let mut file = BufWriter::new(File::create(&path)?);
+    input.write_bindings(&mut file)?;
+    cvars.write_archived(&mut file)?;
+    file.flush()?;
+
+    Ok(path)
 }
 
 #[derive(StructOpt, Debug)]
@@ -387,6 +518,10 @@ struct Opt {
 
     #[structopt(long)]
     base_dir: Option<PathBuf>,
+
+    /// Mod directory to load over id1.
+    #[structopt(long)]
+    game: Option<String>,
 }
 
 fn main() {
@@ -417,8 +552,12 @@ fn main() {
         }
     };
 
-    let client_program =
-        futures::executor::block_on(ClientProgram::new(window, opt.base_dir, opt.trace));
+    let client_program = futures::executor::block_on(ClientProgram::new(
+        window,
+        opt.base_dir,
+        opt.game,
+        opt.trace,
+    ));
 
     // TODO: make dump_demo part of top-level binary and allow choosing file name
     if let Some(ref demo) = opt.dump_demo {
@@ -430,7 +569,7 @@ fn main() {
             }
         };
 
-        let mut demserv = match DemoServer::new(&mut demfile) {
+        let demserv = match DemoServer::new(&mut demfile) {
             Ok(d) => d,
             Err(e) => {
                 eprintln!("error starting demo server: {}", e);
@@ -439,22 +578,15 @@ fn main() {
         };
 
         let mut outfile = File::create("demodump.txt").unwrap();
-        loop {
-            match demserv.next() {
-                Some(msg) => {
-                    let mut curs = Cursor::new(msg.message());
-                    loop {
-                        match ServerCmd::deserialize(&mut curs) {
-                            Ok(Some(cmd)) => write!(&mut outfile, "{:#?}\n", cmd).unwrap(),
-                            Ok(None) => break,
-                            Err(e) => {
-                                eprintln!("error processing demo: {}", e);
-                                std::process::exit(1);
-                            }
-                        }
+        for id in 0..demserv.message_count() {
+            for cmd in demserv.cmds(id).unwrap() {
+                match cmd {
+                    Ok(cmd) => writeln!(&mut outfile, "{:#?}", cmd).unwrap(),
+                    Err(e) => {
+                        eprintln!("error processing demo: {}", e);
+                        std::process::exit(1);
                     }
                 }
-                None => break,
             }
         }
 
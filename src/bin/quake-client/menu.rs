@@ -18,14 +18,25 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use richter::client::menu::{Menu, MenuBodyView, MenuBuilder, MenuView};
+use std::{cell::RefCell, rc::Rc};
+
+use richter::{
+    client::{
+        menu::{Menu, MenuBodyView, MenuBuilder, MenuView},
+        slist::ServerList,
+    },
+    common::console::Console,
+};
 
 use failure::Error;
 
-pub fn build_main_menu() -> Result<Menu, Error> {
+pub fn build_main_menu(
+    console: Rc<RefCell<Console>>,
+    server_list: Rc<RefCell<ServerList>>,
+) -> Result<Menu, Error> {
     Ok(MenuBuilder::new()
         .add_submenu("Single Player", build_menu_sp()?)
-        .add_submenu("Multiplayer", build_menu_mp()?)
+        .add_submenu("Multiplayer", build_menu_mp(console, server_list)?)
         .add_submenu("Options", build_menu_options()?)
         .add_action("Help/Ordering", Box::new(|| ()))
         .add_action("Quit", Box::new(|| ()))
@@ -52,9 +63,12 @@ fn build_menu_sp() -> Result<Menu, Error> {
         }))
 }
 
-fn build_menu_mp() -> Result<Menu, Error> {
+fn build_menu_mp(
+    console: Rc<RefCell<Console>>,
+    server_list: Rc<RefCell<ServerList>>,
+) -> Result<Menu, Error> {
     Ok(MenuBuilder::new()
-        .add_submenu("Join a Game", build_menu_mp_join()?)
+        .add_submenu("Join a Game", build_menu_mp_join(console, server_list)?)
         // .add_submenu("New Game", unimplemented!())
         // .add_submenu("Setup", unimplemented!())
         .build(MenuView {
@@ -66,9 +80,12 @@ fn build_menu_mp() -> Result<Menu, Error> {
         }))
 }
 
-fn build_menu_mp_join() -> Result<Menu, Error> {
+fn build_menu_mp_join(
+    console: Rc<RefCell<Console>>,
+    server_list: Rc<RefCell<ServerList>>,
+) -> Result<Menu, Error> {
     Ok(MenuBuilder::new()
-        .add_submenu("TCP", build_menu_mp_join_tcp()?)
+        .add_submenu("TCP", build_menu_mp_join_tcp(console, server_list)?)
         // .add_textbox // description
         .build(MenuView {
             draw_plaque: true,
@@ -79,20 +96,50 @@ fn build_menu_mp_join() -> Result<Menu, Error> {
         }))
 }
 
-fn build_menu_mp_join_tcp() -> Result<Menu, Error> {
+fn build_menu_mp_join_tcp(
+    console: Rc<RefCell<Console>>,
+    server_list: Rc<RefCell<ServerList>>,
+) -> Result<Menu, Error> {
     // Join Game - TCP/IP          // title
     //
-    //  Address: 127.0.0.1         // label
-    //
-    //  Port     [26000]           // text field
+    //  Search for local games...  // action
     //
-    //  Search for local games...  // menu
-    //
-    //  Join game at:              // label
-    //  [                        ] // text field
+    //  Servers                    // list
+    //  hostname        map      1/ 8
+    let search_console = console.clone();
+    let entries_list = server_list.clone();
+
     Ok(MenuBuilder::new()
-        // .add
-        .add_toggle("placeholder", false, Box::new(|_| ()))
+        .add_action(
+            "Search for local games...",
+            Box::new(move || search_console.borrow().stuff_text("slist")),
+        )
+        .add_list(
+            "Servers",
+            Box::new(move || {
+                entries_list
+                    .borrow()
+                    .entries()
+                    .iter()
+                    .map(|e| {
+                        format!(
+                            "{:<15.15} {:<8.8} {:>2}/{:>2}",
+                            e.info.hostname,
+                            e.info.levelname,
+                            e.info.client_count,
+                            e.info.client_max
+                        )
+                    })
+                    .collect()
+            }),
+            Box::new(move |i| {
+                if let Some(entry) = server_list.borrow().entries().get(i) {
+                    let console = console.borrow();
+                    console.stuff_text(format!("connect {}", entry.address));
+                    console.stuff_text("togglemenu");
+                }
+            }),
+        )
         .build(MenuView {
             draw_plaque: true,
             title_path: "gfx/p_multi.lmp".to_string(),
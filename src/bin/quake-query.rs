@@ -0,0 +1,139 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+extern crate richter;
+
+use std::{io::stdout, process::exit};
+
+use richter::{
+    client::slist::resolve_host,
+    common::net::query::{query_server, ServerStatus},
+};
+
+use chrono::Duration;
+use serde::Serialize;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// Print the results as JSON.
+    #[structopt(long)]
+    json: bool,
+
+    /// How long to wait for each reply, in milliseconds.
+    #[structopt(long, default_value = "1000")]
+    timeout: i64,
+
+    /// Server addresses, with an optional port (default 26000).
+    #[structopt(name = "SERVER", required = true)]
+    servers: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum QueryResult {
+    Status(ServerStatus),
+    Error { address: String, error: String },
+}
+
+fn query(host: &str, timeout: Duration) -> QueryResult {
+    let remote = match resolve_host(host) {
+        Some(r) => r,
+        None => {
+            return QueryResult::Error {
+                address: host.to_owned(),
+                error: String::from("couldn't resolve address"),
+            }
+        }
+    };
+
+    match query_server(remote, timeout) {
+        Ok(status) => QueryResult::Status(status),
+        Err(e) => QueryResult::Error {
+            address: host.to_owned(),
+            error: e.to_string(),
+        },
+    }
+}
+
+fn print_status(status: &ServerStatus) {
+    let info = &status.info;
+    println!(
+        "{} {} {} {}/{} (protocol {})",
+        status.address,
+        info.hostname,
+        info.levelname,
+        info.client_count,
+        info.client_max,
+        info.protocol_version
+    );
+
+    if !status.players.is_empty() {
+        println!(
+            "  {:>2} {:<15} {:>5} {:>5} {:>8} Address",
+            "#", "Name", "Frags", "Color", "Time"
+        );
+        for player in status.players.iter() {
+            println!(
+                "  {:>2} {:<15} {:>5} {:>2}/{:<2} {:>5}:{:02} {}",
+                player.player_id,
+                player.player_name,
+                player.frags,
+                (player.colors >> 4) & 0xF,
+                player.colors & 0xF,
+                player.connect_duration / 60,
+                player.connect_duration % 60,
+                player.address,
+            );
+        }
+    }
+
+    for rule in status.rules.iter() {
+        println!("  {} = {}", rule.cvar_name, rule.cvar_val);
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let opt = Opt::from_args();
+
+    let timeout = Duration::milliseconds(opt.timeout);
+    let results: Vec<QueryResult> = opt.servers.iter().map(|s| query(s, timeout)).collect();
+
+    if opt.json {
+        if let Err(e) = serde_json::to_writer_pretty(stdout(), &results) {
+            eprintln!("Couldn't write JSON: {}", e);
+            exit(1);
+        }
+        println!();
+    } else {
+        for result in results.iter() {
+            match result {
+                QueryResult::Status(status) => print_status(status),
+                QueryResult::Error { address, error } => println!("{} error: {}", address, error),
+            }
+        }
+    }
+
+    // like qstat, report failure if any server didn't answer
+    if results
+        .iter()
+        .any(|r| matches!(r, QueryResult::Error { .. }))
+    {
+        exit(1);
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+extern crate richter;
+
+use std::{cell::RefCell, path::PathBuf, process::exit, rc::Rc, thread, time::Instant};
+
+use richter::{
+    common::{
+        self,
+        console::CvarRegistry,
+        net::{connect::DEFAULT_PORT, MAX_CLIENTS},
+        vfs::Vfs,
+    },
+    server::{self, net::NetServer, Session},
+};
+
+use chrono::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(long)]
+    base_dir: Option<PathBuf>,
+
+    /// Mod directory to load over id1.
+    #[structopt(long)]
+    game: Option<String>,
+
+    #[structopt(long)]
+    port: Option<u16>,
+
+    #[structopt(long, default_value = "8")]
+    max_clients: usize,
+
+    #[structopt(long, default_value = "start")]
+    map: String,
+
+    #[structopt(long)]
+    hostname: Option<String>,
+
+    /// Password for remote console commands. rcon is disabled without one.
+    #[structopt(long)]
+    rcon_password: Option<String>,
+
+    /// Run a cooperative game instead of deathmatch.
+    #[structopt(long)]
+    coop: bool,
+}
+
+fn main() {
+    env_logger::init();
+    let opt = Opt::from_args();
+
+    let vfs = Rc::new(Vfs::with_game(
+        opt.base_dir.unwrap_or(common::default_base_dir()),
+        opt.game.as_deref(),
+    ));
+
+    let cvars = Rc::new(RefCell::new(CvarRegistry::new(Rc::new(RefCell::new(
+        Vec::new(),
+    )))));
+    server::register_cvars(&cvars.borrow()).unwrap();
+
+    let max_clients = opt.max_clients.clamp(1, MAX_CLIENTS);
+    {
+        let cvars = cvars.borrow();
+        cvars.set("maxplayers", &max_clients.to_string()).unwrap();
+        if let Some(ref hostname) = opt.hostname {
+            cvars.set("hostname", hostname).unwrap();
+        }
+        if let Some(ref password) = opt.rcon_password {
+            cvars.set("rcon_password", password).unwrap();
+        }
+
+        // like the original engine, multiplayer dedicated servers default to deathmatch
+        let deathmatch = !opt.coop && max_clients > 1;
+        cvars.set("coop", if opt.coop { "1" } else { "0" }).unwrap();
+        cvars
+            .set("deathmatch", if deathmatch { "1" } else { "0" })
+            .unwrap();
+    }
+
+    let session = match Session::load(vfs, cvars.clone(), max_clients, &opt.map) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Couldn't load {}: {}", opt.map, e);
+            exit(1);
+        }
+    };
+
+    let port = opt.port.unwrap_or(DEFAULT_PORT);
+    let mut server = match NetServer::bind(("0.0.0.0", port), session, cvars.clone()) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Couldn't listen on port {}: {}", port, e);
+            exit(1);
+        }
+    };
+
+    println!(
+        "Running {} on port {} with {} client slots",
+        opt.map, port, max_clients
+    );
+
+    loop {
+        let frame_start = Instant::now();
+
+        // run frames at a fixed rate
+        let tick = cvars.borrow().get_value("sys_ticrate").unwrap_or(0.05);
+        let frame_time = Duration::microseconds((tick as f64 * 1_000_000.0) as i64);
+
+        if let Err(e) = server.frame(frame_time) {
+            eprintln!("Server error: {}", e);
+            server.shutdown();
+            exit(1);
+        }
+
+        if let Some(rest) = frame_time
+            .to_std()
+            .ok()
+            .and_then(|t| t.checked_sub(frame_start.elapsed()))
+        {
+            thread::sleep(rest);
+        }
+    }
+}
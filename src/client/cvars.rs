@@ -18,7 +18,7 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use crate::common::console::{CvarRegistry, ConsoleError};
+use crate::common::console::{ConsoleError, CvarRegistry};
 
 pub fn register_cvars(cvars: &CvarRegistry) -> Result<(), ConsoleError> {
     cvars.register("cl_anglespeedkey", "1.5")?;
@@ -34,16 +34,25 @@ pub fn register_cvars(cvars: &CvarRegistry) -> Result<(), ConsoleError> {
     cvars.register_archive("_cl_name", "player")?;
     cvars.register("cl_nolerp", "0")?;
     cvars.register("cl_pitchspeed", "150")?;
+    cvars.register("cl_predict", "0")?;
     cvars.register("cl_rollangle", "2.0")?;
     cvars.register("cl_rollspeed", "200")?;
     cvars.register("cl_shownet", "0")?;
     cvars.register("cl_sidespeed", "350")?;
     cvars.register("cl_upspeed", "200")?;
     cvars.register("cl_yawspeed", "140")?;
+    cvars.register("demo_speed", "1")?;
     cvars.register("fov", "90")?;
     cvars.register_archive("m_pitch", "0.022")?;
     cvars.register_archive("m_yaw", "0.022")?;
+    cvars.register("net_fakedup", "0")?;
+    cvars.register("net_fakejitter", "0")?;
+    cvars.register("net_fakelag", "0")?;
+    cvars.register("net_fakeloss", "0")?;
+    cvars.register("net_fakereorder", "0")?;
+    cvars.register("rcon_server", "")?;
     cvars.register_archive("sensitivity", "3")?;
+    cvars.register_archive("slist_hosts", "")?;
     cvars.register("v_idlescale", "0")?;
     cvars.register("v_ipitch_cycle", "1")?;
     cvars.register("v_ipitch_level", "0.3")?;
@@ -1,17 +1,33 @@
-use std::{io, ops::Range};
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    ops::Range,
+};
 
-use crate::common::{
-    net::{self, NetError},
-    util::read_f32_3,
-    vfs::VirtualFile,
+use crate::{
+    client::{state::StateSnapshot, timedemo::TimeDemo},
+    common::{
+        engine,
+        net::{self, NetError, Protocol, ProtocolVersion, ServerCmd},
+        util::read_f32_3,
+        vfs::VirtualFile,
+    },
 };
 
 use arrayvec::ArrayVec;
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use cgmath::{Deg, Vector3};
+use chrono::Duration;
 use io::BufReader;
+use num::FromPrimitive as _;
 use thiserror::Error;
 
+/// How much playback time passes between client state snapshots.
+const SNAPSHOT_INTERVAL_SECS: i64 = 10;
+
+/// How long the playback controls stay on screen after they're used.
+const CONTROLS_DISPLAY_SECS: i64 = 3;
+
 /// An error returned by a demo server.
 #[derive(Error, Debug)]
 pub enum DemoServerError {
@@ -27,6 +43,15 @@ pub enum DemoServerError {
     Net(#[from] NetError),
 }
 
+/// An error returned while decoding the server commands in a demo message.
+#[derive(Error, Debug)]
+pub enum DemoCmdError {
+    #[error("Couldn't decode message {message}: {error}")]
+    BadMessage { message: usize, error: NetError },
+    #[error("Invalid protocol version in message {0}")]
+    InvalidProtocol(usize),
+}
+
 struct DemoMessage {
     view_angles: Vector3<Deg<f32>>,
     msg_range: Range<usize>,
@@ -50,7 +75,99 @@ impl<'a> DemoMessageView<'a> {
     }
 }
 
+/// An iterator over the server commands in a demo message.
+///
+/// Commands after a `ServerInfo` are decoded with the protocol it announces.
+/// Iteration ends after the first error.
+pub struct DemoCmds<'a> {
+    message_id: usize,
+    reader: io::Cursor<&'a [u8]>,
+    protocol: Protocol,
+
+    // protocol announced by the last command, if it was a ServerInfo
+    next_protocol: Option<Protocol>,
+
+    done: bool,
+}
+
+impl<'a> DemoCmds<'a> {
+    fn new(message_id: usize, message: &'a [u8], protocol: Protocol) -> DemoCmds<'a> {
+        DemoCmds {
+            message_id,
+            reader: io::Cursor::new(message),
+            protocol,
+            next_protocol: None,
+            done: false,
+        }
+    }
+
+    /// Returns the protocol the last command was decoded with.
+    ///
+    /// Before the first command this is the protocol in effect at the start of
+    /// the message, and once the message is exhausted it's the protocol for
+    /// the next one.
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Returns the offset in bytes of the next command in the message.
+    pub fn position(&self) -> u64 {
+        self.reader.position()
+    }
+}
+
+impl<'a> Iterator for DemoCmds<'a> {
+    type Item = Result<ServerCmd, DemoCmdError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        if let Some(protocol) = self.next_protocol.take() {
+            self.protocol = protocol;
+        }
+
+        let cmd = match ServerCmd::deserialize(&mut self.reader, self.protocol) {
+            Ok(Some(cmd)) => cmd,
+            Ok(None) => {
+                self.done = true;
+                return None;
+            }
+            Err(error) => {
+                self.done = true;
+                return Some(Err(DemoCmdError::BadMessage {
+                    message: self.message_id,
+                    error,
+                }));
+            }
+        };
+
+        if let ServerCmd::ServerInfo {
+            protocol_version,
+            protocol_flags,
+            ..
+        } = cmd
+        {
+            match ProtocolVersion::from_i32(protocol_version) {
+                Some(version) => self.next_protocol = Some(Protocol::new(version, protocol_flags)),
+                None => {
+                    self.done = true;
+                    return Some(Err(DemoCmdError::InvalidProtocol(self.message_id)));
+                }
+            }
+        }
+
+        Some(Ok(cmd))
+    }
+}
+
 /// A server that yields commands from a demo file.
+///
+/// Playback can be paused, sped up or slowed down, and moved to any point in
+/// the demo. Each message is assigned a playback time when the demo is loaded;
+/// this is the server time relative to the start of the demo, with the levels
+/// of a multi-level demo played back to back.
 pub struct DemoServer {
     track_override: Option<u32>,
 
@@ -61,6 +178,29 @@ pub struct DemoServer {
 
     // all message data
     message_data: Vec<u8>,
+
+    // playback time of each message
+    message_times: Vec<Duration>,
+
+    // ids of the messages which begin a new level
+    level_starts: Vec<usize>,
+
+    // protocol in effect at the start of each message
+    protocols: Vec<Protocol>,
+
+    paused: bool,
+    speed: f32,
+    seek_target: Option<Duration>,
+
+    // time left to show the playback controls
+    controls_time: Duration,
+
+    // client state snapshots keyed by snapshot interval, along with the id of
+    // the next message to send once restored
+    snapshots: BTreeMap<i64, (usize, StateSnapshot)>,
+
+    // if Some, the demo is being benchmarked
+    timedemo: Option<TimeDemo>,
 }
 
 impl DemoServer {
@@ -130,11 +270,22 @@ impl DemoServer {
             });
         }
 
+        let (message_times, level_starts, protocols) = index_messages(&messages, &message_data);
+
         Ok(DemoServer {
             track_override,
             message_id: 0,
             messages,
             message_data,
+            message_times,
+            level_starts,
+            protocols,
+            paused: false,
+            speed: 1.0,
+            seek_target: None,
+            controls_time: Duration::zero(),
+            snapshots: BTreeMap::new(),
+            timedemo: None,
         })
     }
 
@@ -146,15 +297,47 @@ impl DemoServer {
             return None;
         }
 
-        let msg = &self.messages[self.message_id];
         self.message_id += 1;
+        self.message(self.message_id - 1)
+    }
 
-        Some(DemoMessageView {
+    /// Returns the number of messages in the demo.
+    pub fn message_count(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Returns the message with the given id without affecting playback.
+    pub fn message(&self, message_id: usize) -> Option<DemoMessageView<'_>> {
+        self.messages.get(message_id).map(|msg| DemoMessageView {
             view_angles: msg.view_angles,
             message: &self.message_data[msg.msg_range.clone()],
         })
     }
 
+    /// Returns an iterator over the server commands in the message with the
+    /// given id.
+    pub fn cmds(&self, message_id: usize) -> Option<DemoCmds<'_>> {
+        self.messages.get(message_id).map(|msg| {
+            DemoCmds::new(
+                message_id,
+                &self.message_data[msg.msg_range.clone()],
+                self.protocols[message_id],
+            )
+        })
+    }
+
+    /// Decodes all the server commands in the message with the given id.
+    ///
+    /// Returns an empty list if there is no such message.
+    pub fn message_cmds(&self, message_id: usize) -> Result<Vec<ServerCmd>, DemoCmdError> {
+        self.cmds(message_id).into_iter().flatten().collect()
+    }
+
+    /// Returns the playback time of the message with the given id.
+    pub fn message_time(&self, message_id: usize) -> Option<Duration> {
+        self.message_times.get(message_id).copied()
+    }
+
     /// Returns the currently playing demo's music track override, if any.
     ///
     /// If this is `Some`, any `CdTrack` commands from the demo server should
@@ -163,4 +346,510 @@ impl DemoServer {
     pub fn track_override(&self) -> Option<u32> {
         self.track_override
     }
+
+    /// Returns the id of the next message to be sent.
+    pub fn message_id(&self) -> usize {
+        self.message_id
+    }
+
+    /// Moves playback to the message with the given id.
+    ///
+    /// The client state must be brought up to date with the messages before
+    /// it, either by restoring a snapshot or by replaying them.
+    pub fn set_message_id(&mut self, message_id: usize) {
+        self.message_id = message_id.min(self.messages.len());
+    }
+
+    /// Returns the total playback time of the demo.
+    pub fn duration(&self) -> Duration {
+        self.message_times
+            .last()
+            .copied()
+            .unwrap_or_else(Duration::zero)
+    }
+
+    /// Returns the playback time of the last message sent.
+    pub fn time(&self) -> Duration {
+        match self.message_id {
+            0 => Duration::zero(),
+            id => self.message_times[id - 1],
+        }
+    }
+
+    /// Returns the number of messages which must be sent to reach the given
+    /// playback time.
+    pub fn messages_until(&self, time: Duration) -> usize {
+        self.message_times.partition_point(|t| *t <= time)
+    }
+
+    /// Returns the id of the message which began the level that is loaded
+    /// after sending `message_count` messages.
+    pub fn level_start(&self, message_count: usize) -> usize {
+        match self.level_starts.partition_point(|s| *s < message_count) {
+            0 => 0,
+            i => self.level_starts[i - 1],
+        }
+    }
+
+    /// Returns whether playback is paused.
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Pauses or resumes playback.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.show_controls();
+    }
+
+    /// Returns the playback speed.
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Sets the playback speed, where 1 is the speed at which the demo was
+    /// recorded.
+    pub fn set_speed(&mut self, speed: f32) {
+        let speed = speed.max(0.0);
+        if speed != self.speed {
+            self.speed = speed;
+            self.show_controls();
+        }
+    }
+
+    /// Requests that playback move to the given time.
+    ///
+    /// The seek is carried out by the client on its next frame.
+    pub fn seek(&mut self, time: Duration) {
+        self.seek_target = Some(time.max(Duration::zero()).min(self.duration()));
+        self.show_controls();
+    }
+
+    /// Returns the pending seek target, if any, and clears it.
+    pub fn take_seek(&mut self) -> Option<Duration> {
+        self.seek_target.take()
+    }
+
+    fn show_controls(&mut self) {
+        self.controls_time = Duration::seconds(CONTROLS_DISPLAY_SECS);
+    }
+
+    /// Counts down how long the playback controls are shown.
+    pub fn update_controls(&mut self, frame_time: Duration) {
+        self.controls_time = (self.controls_time - frame_time).max(Duration::zero());
+    }
+
+    /// Returns whether the playback controls should be shown.
+    pub fn controls_visible(&self) -> bool {
+        self.paused || self.speed != 1.0 || self.controls_time > Duration::zero()
+    }
+
+    /// Benchmarks playback of this demo.
+    ///
+    /// Messages are sent one per frame regardless of their timestamps.
+    pub fn start_timedemo(&mut self, timedemo: TimeDemo) {
+        self.timedemo = Some(timedemo);
+    }
+
+    /// Returns the benchmark in progress, if any.
+    pub fn timedemo_mut(&mut self) -> Option<&mut TimeDemo> {
+        self.timedemo.as_mut()
+    }
+
+    /// Returns whether this demo is being benchmarked.
+    pub fn is_timedemo(&self) -> bool {
+        self.timedemo.is_some()
+    }
+
+    /// Ends the benchmark in progress, if any, and returns its results.
+    pub fn take_timedemo(&mut self) -> Option<TimeDemo> {
+        self.timedemo.take()
+    }
+
+    fn snapshot_slot(&self) -> i64 {
+        self.time().num_seconds() / SNAPSHOT_INTERVAL_SECS
+    }
+
+    /// Returns whether a snapshot should be taken at the current position.
+    pub fn wants_snapshot(&self) -> bool {
+        !self.snapshots.contains_key(&self.snapshot_slot())
+    }
+
+    /// Stores a snapshot of the client state at the current position.
+    pub fn save_snapshot(&mut self, snapshot: StateSnapshot) {
+        let slot = self.snapshot_slot();
+        self.snapshots.insert(slot, (self.message_id, snapshot));
+    }
+
+    /// Returns the latest snapshot from which the first `message_count`
+    /// messages can be reached, along with the id of the next message to send
+    /// once it's restored.
+    pub fn snapshot_before(&self, message_count: usize) -> Option<(usize, &StateSnapshot)> {
+        let level_start = self.level_start(message_count);
+
+        self.snapshots
+            .values()
+            .rev()
+            .filter(|(id, _)| *id <= message_count && self.level_start(*id) == level_start)
+            .map(|(id, snapshot)| (*id, snapshot))
+            .next()
+    }
+}
+
+/// Parses a demo time given either in seconds or as `minutes:seconds`.
+pub fn parse_time(s: &str) -> Option<Duration> {
+    let (minutes, seconds) = match s.split_once(':') {
+        Some((m, s)) => (m.parse::<u32>().ok()?, s),
+        None => (0, s),
+    };
+
+    let seconds = seconds.parse::<f32>().ok().filter(|s| s.is_finite())?;
+    Some(Duration::minutes(minutes as i64) + engine::duration_from_f32(seconds))
+}
+
+/// Assigns a playback time to each message, finds the messages which begin a
+/// new level and records the protocol in effect at the start of each message.
+///
+/// If a message can't be parsed, it and all following messages are assigned
+/// the last known time and protocol.
+fn index_messages(
+    messages: &[DemoMessage],
+    message_data: &[u8],
+) -> (Vec<Duration>, Vec<usize>, Vec<Protocol>) {
+    let mut times = Vec::with_capacity(messages.len());
+    let mut level_starts = Vec::new();
+    let mut protocols = Vec::with_capacity(messages.len());
+
+    let mut protocol = Protocol::default();
+    let mut time = Duration::zero();
+
+    // playback time at which the current level began
+    let mut level_offset = Duration::zero();
+
+    // server time of the first update in the current level
+    let mut level_base = None;
+
+    'messages: for (id, msg) in messages.iter().enumerate() {
+        protocols.push(protocol);
+
+        let mut cmds = DemoCmds::new(id, &message_data[msg.msg_range.clone()], protocol);
+        for cmd in cmds.by_ref() {
+            match cmd {
+                Ok(ServerCmd::ServerInfo { .. }) => {
+                    level_starts.push(id);
+                    level_offset = time;
+                    level_base = None;
+                }
+
+                Ok(ServerCmd::Time { time: t }) => {
+                    let t = engine::duration_from_f32(t);
+                    let base = *level_base.get_or_insert(t);
+
+                    // keep playback time monotonic even if the server's isn't
+                    time = time.max(level_offset + (t - base));
+                }
+
+                Ok(_) => (),
+
+                Err(e) => {
+                    warn!("Demo can only be seeked up to message {}: {}", id, e);
+                    break 'messages;
+                }
+            }
+        }
+
+        protocol = cmds.protocol();
+        times.push(time);
+    }
+
+    times.resize(messages.len(), time);
+    protocols.resize(messages.len(), protocol);
+
+    (times, level_starts, protocols)
+}
+
+/// Writes server messages to a demo file.
+///
+/// A demo file begins with the music track to play (or `-1` to follow the
+/// `CdTrack` commands in the demo) on a line of its own. Each message follows
+/// as its length, the client's view angles and the message data.
+pub struct DemoRecorder<W>
+where
+    W: Write,
+{
+    writer: W,
+}
+
+impl<W> DemoRecorder<W>
+where
+    W: Write,
+{
+    /// Starts a demo on the given writer.
+    ///
+    /// If `track_override` is `Some`, that music track is played for the whole
+    /// demo.
+    pub fn new(mut writer: W, track_override: Option<u32>) -> io::Result<DemoRecorder<W>> {
+        match track_override {
+            Some(t) => writeln!(writer, "{}", t)?,
+            None => writeln!(writer, "-1")?,
+        }
+
+        Ok(DemoRecorder { writer })
+    }
+
+    /// Writes a server message as received along with the view angles at that
+    /// time.
+    pub fn write_message(
+        &mut self,
+        view_angles: Vector3<Deg<f32>>,
+        message: &[u8],
+    ) -> io::Result<()> {
+        self.writer
+            .write_u32::<LittleEndian>(message.len() as u32)?;
+        for angle in [view_angles.x, view_angles.y, view_angles.z] {
+            self.writer.write_f32::<LittleEndian>(angle.0)?;
+        }
+        self.writer.write_all(message)
+    }
+
+    /// Serializes server commands into as few messages as possible and writes
+    /// them.
+    ///
+    /// Messages are split between commands so that none exceeds
+    /// `net::MAX_MESSAGE` bytes.
+    pub fn write_cmds(
+        &mut self,
+        view_angles: Vector3<Deg<f32>>,
+        cmds: &[ServerCmd],
+        protocol: Protocol,
+    ) -> Result<(), NetError> {
+        let mut message = Vec::new();
+        let mut cmd_data = Vec::new();
+
+        for cmd in cmds {
+            cmd_data.clear();
+            cmd.serialize(&mut cmd_data, protocol)?;
+
+            if !message.is_empty() && message.len() + cmd_data.len() > net::MAX_MESSAGE {
+                self.write_message(view_angles, &message)?;
+                message.clear();
+            }
+
+            message.extend_from_slice(&cmd_data);
+        }
+
+        if !message.is_empty() {
+            self.write_message(view_angles, &message)?;
+        }
+
+        Ok(())
+    }
+
+    /// Ends the demo, returning the underlying writer.
+    ///
+    /// This writes a final `Disconnect` so that playback ends here.
+    pub fn finish(mut self, view_angles: Vector3<Deg<f32>>) -> Result<W, NetError> {
+        self.write_cmds(view_angles, &[ServerCmd::Disconnect], Protocol::default())?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::net::SignOnStage;
+    use std::io::Cursor;
+
+    fn angles() -> Vector3<Deg<f32>> {
+        Vector3::new(Deg(10.0), Deg(90.0), Deg(0.0))
+    }
+
+    fn play(data: &[u8]) -> DemoServer {
+        DemoServer::new(&mut VirtualFile::MemoryBacked(Cursor::new(data.into()))).unwrap()
+    }
+
+    #[test]
+    fn test_recorded_demo_plays_back() {
+        let mut recorder = DemoRecorder::new(Vec::new(), None).unwrap();
+        recorder.write_message(angles(), b"first").unwrap();
+        recorder.write_message(angles(), b"second").unwrap();
+        let data = recorder.finish(angles()).unwrap();
+
+        let mut server = play(&data);
+        assert_eq!(server.track_override(), None);
+
+        let msg = server.next().unwrap();
+        assert_eq!(msg.message(), b"first");
+        assert_eq!(msg.view_angles(), angles());
+        assert_eq!(server.next().unwrap().message(), b"second");
+
+        let last = server.next().unwrap();
+        let mut reader = io::BufReader::new(last.message());
+        assert_eq!(
+            ServerCmd::deserialize(&mut reader, Protocol::default()).unwrap(),
+            Some(ServerCmd::Disconnect)
+        );
+        assert!(server.next().is_none());
+    }
+
+    #[test]
+    fn test_track_override() {
+        let recorder = DemoRecorder::new(Vec::new(), Some(4)).unwrap();
+        let data = recorder.finish(angles()).unwrap();
+        assert_eq!(play(&data).track_override(), Some(4));
+    }
+
+    #[test]
+    fn test_write_cmds_splits_long_messages() {
+        let cmds: Vec<ServerCmd> = (0..100)
+            .map(|i| ServerCmd::LightStyle {
+                id: i as u8,
+                value: "a".repeat(200),
+            })
+            .chain(std::iter::once(ServerCmd::SignOnStage {
+                stage: SignOnStage::Prespawn,
+            }))
+            .collect();
+
+        let mut recorder = DemoRecorder::new(Vec::new(), None).unwrap();
+        recorder
+            .write_cmds(angles(), &cmds, Protocol::default())
+            .unwrap();
+        let data = recorder.finish(angles()).unwrap();
+
+        let mut server = play(&data);
+        let mut read = Vec::new();
+        let mut message_count = 0;
+        while let Some(msg) = server.next() {
+            assert!(msg.message().len() <= net::MAX_MESSAGE);
+            message_count += 1;
+
+            let mut reader = io::BufReader::new(msg.message());
+            while let Some(cmd) = ServerCmd::deserialize(&mut reader, Protocol::default()).unwrap()
+            {
+                read.push(cmd);
+            }
+        }
+
+        // three messages of commands plus the disconnect
+        assert_eq!(message_count, 4);
+        assert_eq!(read.pop(), Some(ServerCmd::Disconnect));
+        assert_eq!(read, cmds);
+    }
+
+    #[test]
+    fn test_message_times_span_levels() {
+        let server_info = || ServerCmd::ServerInfo {
+            protocol_version: net::ProtocolVersion::NetQuake as i32,
+            protocol_flags: net::ProtocolFlags::empty(),
+            max_clients: 1,
+            game_type: net::GameType::CoOp,
+            message: String::from("test"),
+            model_precache: Vec::new(),
+            sound_precache: Vec::new(),
+        };
+        let time = |time| ServerCmd::Time { time };
+
+        let mut recorder = DemoRecorder::new(Vec::new(), None).unwrap();
+        for cmd in [
+            server_info(),
+            time(1.0),
+            time(1.5),
+            server_info(),
+            time(1.0),
+            time(3.0),
+        ] {
+            recorder
+                .write_cmds(angles(), &[cmd], Protocol::default())
+                .unwrap();
+        }
+        let data = recorder.finish(angles()).unwrap();
+
+        // the second level picks up where the first left off
+        let mut server = play(&data);
+        let secs = |s| engine::duration_from_f32(s);
+        assert_eq!(server.duration(), secs(2.5));
+        assert_eq!(server.messages_until(secs(0.0)), 2);
+        assert_eq!(server.messages_until(secs(0.5)), 5);
+        assert_eq!(server.messages_until(secs(2.5)), 7);
+        assert_eq!(server.level_start(3), 0);
+        assert_eq!(server.level_start(5), 3);
+
+        server.set_message_id(3);
+        assert_eq!(server.time(), secs(0.5));
+        server.seek(secs(10.0));
+        assert_eq!(server.take_seek(), Some(secs(2.5)));
+        assert_eq!(server.take_seek(), None);
+    }
+
+    #[test]
+    fn test_cmds_follow_protocol() {
+        let server_info = |protocol_version| ServerCmd::ServerInfo {
+            protocol_version,
+            protocol_flags: net::ProtocolFlags::empty(),
+            max_clients: 1,
+            game_type: net::GameType::CoOp,
+            message: String::from("test"),
+            model_precache: Vec::new(),
+            sound_precache: Vec::new(),
+        };
+        let fitz = Protocol::from(ProtocolVersion::FitzQuake);
+        let stat = ServerCmd::UpdateStat {
+            stat: net::ClientStat::Health,
+            value: 100,
+        };
+
+        let mut recorder = DemoRecorder::new(Vec::new(), None).unwrap();
+        recorder
+            .write_cmds(
+                angles(),
+                &[server_info(ProtocolVersion::FitzQuake as i32)],
+                Protocol::default(),
+            )
+            .unwrap();
+        recorder.write_cmds(angles(), &[stat], fitz).unwrap();
+        recorder
+            .write_cmds(angles(), &[server_info(9999)], fitz)
+            .unwrap();
+        let data = recorder.finish(angles()).unwrap();
+
+        let server = play(&data);
+        assert_eq!(server.cmds(0).unwrap().protocol(), Protocol::default());
+        assert_eq!(server.cmds(1).unwrap().protocol(), fitz);
+        assert_eq!(
+            server.message_cmds(1).unwrap(),
+            vec![ServerCmd::UpdateStat {
+                stat: net::ClientStat::Health,
+                value: 100,
+            }]
+        );
+        assert!(matches!(
+            server.message_cmds(2),
+            Err(DemoCmdError::InvalidProtocol(2))
+        ));
+        assert!(server.message_cmds(4).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_controls_shown_after_use() {
+        let data = DemoRecorder::new(Vec::new(), None)
+            .unwrap()
+            .finish(angles())
+            .unwrap();
+        let mut server = play(&data);
+        assert!(!server.controls_visible());
+
+        server.set_speed(1.0);
+        assert!(!server.controls_visible());
+
+        server.seek(Duration::zero());
+        assert!(server.controls_visible());
+        server.update_controls(Duration::seconds(CONTROLS_DISPLAY_SECS));
+        assert!(!server.controls_visible());
+
+        server.set_paused(true);
+        server.update_controls(Duration::seconds(CONTROLS_DISPLAY_SECS));
+        assert!(server.controls_visible());
+    }
 }
@@ -0,0 +1,498 @@
+//! Demo editing.
+//!
+//! A `DemoEditor` writes a new demo made of sections of existing ones. Each
+//! section begins with the sign-on data for its level, so it can be played
+//! back on its own, and any sections after it follow as if the server had
+//! changed levels.
+//!
+//! A section which starts partway through a level keeps the level's sign-on
+//! messages as recorded. The state which changed between the end of the
+//! sign-on and the start of the section (light styles, scores, stats and the
+//! like) is summarized in a single message after it; entity state catches up
+//! by itself, since the server sends every visible entity in each update.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    ops::Range,
+};
+
+use crate::{
+    client::demo::{DemoCmdError, DemoRecorder, DemoServer},
+    common::{
+        engine,
+        net::{ClientStat, NetError, ServerCmd, SignOnStage},
+    },
+};
+
+use cgmath::{Deg, Vector3};
+use chrono::Duration;
+use num::FromPrimitive as _;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DemoEditError {
+    #[error("No such server command: {0}")]
+    NoSuchCmd(String),
+    #[error("No demo messages between {start} and {end}")]
+    EmptySection { start: String, end: String },
+    #[error("Message {0} is partway through a level with no sign-on data")]
+    NoSignOn(usize),
+    #[error("{0}")]
+    Demo(#[from] DemoCmdError),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Network error: {0}")]
+    Net(#[from] NetError),
+}
+
+/// Writes a demo made of sections of other demos.
+pub struct DemoEditor<W>
+where
+    W: Write,
+{
+    recorder: DemoRecorder<W>,
+
+    // names of the commands left out of the new demo
+    dropped: Vec<&'static str>,
+
+    // view angles of the last message written
+    view_angles: Vector3<Deg<f32>>,
+}
+
+impl<W> DemoEditor<W>
+where
+    W: Write,
+{
+    /// Starts a new demo on the given writer.
+    ///
+    /// If `track_override` is `Some`, that music track is played for the whole
+    /// demo.
+    pub fn new(writer: W, track_override: Option<u32>) -> io::Result<DemoEditor<W>> {
+        Ok(DemoEditor {
+            recorder: DemoRecorder::new(writer, track_override)?,
+            dropped: Vec::new(),
+            view_angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+        })
+    }
+
+    /// Leaves out the server commands with the given names (as returned by
+    /// `ServerCmd::name`) from everything appended after this.
+    ///
+    /// Dropping commands needed to set up a level, such as `ServerInfo` or
+    /// `SignOnStage`, will make the demo unplayable.
+    pub fn drop_cmds<S>(&mut self, names: &[S]) -> Result<(), DemoEditError>
+    where
+        S: AsRef<str>,
+    {
+        for name in names {
+            let name = name.as_ref();
+            match ServerCmd::NAMES
+                .iter()
+                .find(|n| n.eq_ignore_ascii_case(name))
+            {
+                Some(n) => self.dropped.push(n),
+                None => return Err(DemoEditError::NoSuchCmd(name.to_owned())),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Appends the messages of `demo` with playback times between `start` and
+    /// `end`, inclusive.
+    ///
+    /// A bound of `None` extends the section to that end of the demo. The
+    /// section is preceded by the sign-on data for its level if it doesn't
+    /// begin with it.
+    pub fn append(
+        &mut self,
+        demo: &DemoServer,
+        start: Option<Duration>,
+        end: Option<Duration>,
+    ) -> Result<(), DemoEditError> {
+        let count = demo.message_count();
+        let first = match start {
+            Some(t) => (0..count)
+                .find(|id| demo.message_time(*id).unwrap() >= t)
+                .unwrap_or(count),
+            None => 0,
+        };
+        let last = match end {
+            Some(t) => demo.messages_until(t),
+            None => count,
+        };
+
+        if first >= last {
+            let fmt = |t: Option<Duration>| match t {
+                Some(t) => format!("{:.3}s", engine::duration_to_f32(t)),
+                None => String::from("the end"),
+            };
+
+            return Err(DemoEditError::EmptySection {
+                start: fmt(start),
+                end: fmt(end),
+            });
+        }
+
+        let level_start = demo.level_start(first + 1);
+
+        // the section begins at the start of the demo or of a level
+        if level_start == first {
+            return self.copy(demo, first..last);
+        }
+
+        let has_sign_on = demo
+            .message_cmds(level_start)?
+            .iter()
+            .any(|cmd| matches!(cmd, ServerCmd::ServerInfo { .. }));
+        if !has_sign_on {
+            return Err(DemoEditError::NoSignOn(first));
+        }
+
+        // the sign-on ends with the message that tells the client to begin
+        let mut sign_on_end = level_start;
+        while sign_on_end < first {
+            let cmds = demo.message_cmds(sign_on_end)?;
+            sign_on_end += 1;
+
+            let begin = cmds.iter().any(|cmd| {
+                matches!(
+                    cmd,
+                    ServerCmd::SignOnStage {
+                        stage: SignOnStage::Begin
+                    }
+                )
+            });
+            if begin {
+                break;
+            }
+        }
+
+        // the section starts during the sign-on, so there's nothing to skip
+        if sign_on_end >= first {
+            return self.copy(demo, level_start..last);
+        }
+
+        self.copy(demo, level_start..sign_on_end)?;
+
+        let mut state = LevelState::default();
+        for id in sign_on_end..first {
+            for cmd in demo.message_cmds(id)? {
+                state.update(cmd);
+            }
+        }
+
+        let cmds: Vec<ServerCmd> = state
+            .into_cmds()
+            .into_iter()
+            .filter(|cmd| self.keep(cmd))
+            .collect();
+        let view_angles = demo.message(first).unwrap().view_angles();
+        let protocol = demo.cmds(first).unwrap().protocol();
+        self.recorder.write_cmds(view_angles, &cmds, protocol)?;
+
+        self.copy(demo, first..last)
+    }
+
+    /// Ends the demo, returning the underlying writer.
+    pub fn finish(self) -> Result<W, DemoEditError> {
+        Ok(self.recorder.finish(self.view_angles)?)
+    }
+
+    fn keep(&self, cmd: &ServerCmd) -> bool {
+        // the demo only ends when the editor is finished
+        !matches!(cmd, ServerCmd::Disconnect) && !self.dropped.contains(&cmd.name())
+    }
+
+    // copies the messages with the given ids, leaving out dropped commands
+    fn copy(&mut self, demo: &DemoServer, ids: Range<usize>) -> Result<(), DemoEditError> {
+        for id in ids {
+            let msg = demo.message(id).unwrap();
+            let cmds = demo.message_cmds(id)?;
+            self.view_angles = msg.view_angles();
+
+            if cmds.iter().all(|cmd| self.keep(cmd)) {
+                // nothing to leave out, so the message is copied as recorded
+                self.recorder
+                    .write_message(msg.view_angles(), msg.message())?;
+                continue;
+            }
+
+            // each command is written with the protocol it was recorded in
+            let mut message = Vec::new();
+            let mut cmds = demo.cmds(id).unwrap();
+            while let Some(cmd) = cmds.next() {
+                let cmd = cmd?;
+                if self.keep(&cmd) {
+                    cmd.serialize(&mut message, cmds.protocol())?;
+                }
+            }
+
+            if !message.is_empty() {
+                self.recorder.write_message(msg.view_angles(), &message)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The persistent state set by server commands after the sign-on.
+#[derive(Default)]
+struct LevelState {
+    stats: BTreeMap<i32, i32>,
+    light_styles: BTreeMap<u8, ServerCmd>,
+    names: BTreeMap<u8, ServerCmd>,
+    frags: BTreeMap<u8, ServerCmd>,
+    colors: BTreeMap<u8, ServerCmd>,
+    view: Option<ServerCmd>,
+    cd_track: Option<ServerCmd>,
+    pause: Option<ServerCmd>,
+    skybox: Option<ServerCmd>,
+    fog: Option<ServerCmd>,
+
+    // intermission, finale or cutscene
+    intermission: Option<ServerCmd>,
+
+    // baselines, static entities and static sounds
+    spawned: Vec<ServerCmd>,
+}
+
+impl LevelState {
+    fn update(&mut self, cmd: ServerCmd) {
+        match cmd {
+            ServerCmd::UpdateStat { stat, value } => {
+                self.stats.insert(stat as i32, value);
+            }
+            ServerCmd::KilledMonster => {
+                *self
+                    .stats
+                    .entry(ClientStat::KilledMonsters as i32)
+                    .or_insert(0) += 1;
+            }
+            ServerCmd::FoundSecret => {
+                *self
+                    .stats
+                    .entry(ClientStat::FoundSecrets as i32)
+                    .or_insert(0) += 1;
+            }
+            ServerCmd::LightStyle { id, .. } => {
+                self.light_styles.insert(id, cmd);
+            }
+            ServerCmd::UpdateName { player_id, .. } => {
+                self.names.insert(player_id, cmd);
+            }
+            ServerCmd::UpdateFrags { player_id, .. } => {
+                self.frags.insert(player_id, cmd);
+            }
+            ServerCmd::UpdateColors { player_id, .. } => {
+                self.colors.insert(player_id, cmd);
+            }
+            ServerCmd::SetView { .. } => self.view = Some(cmd),
+            ServerCmd::CdTrack { .. } => self.cd_track = Some(cmd),
+            ServerCmd::SetPause { .. } => self.pause = Some(cmd),
+            ServerCmd::Skybox { .. } => self.skybox = Some(cmd),
+            ServerCmd::Fog { .. } => self.fog = Some(cmd),
+            ServerCmd::Intermission | ServerCmd::Finale { .. } | ServerCmd::Cutscene { .. } => {
+                self.intermission = Some(cmd)
+            }
+            ServerCmd::SpawnBaseline { .. }
+            | ServerCmd::SpawnStatic { .. }
+            | ServerCmd::SpawnStaticSound { .. } => self.spawned.push(cmd),
+            _ => (),
+        }
+    }
+
+    fn into_cmds(self) -> Vec<ServerCmd> {
+        let mut cmds = self.spawned;
+        cmds.extend(self.light_styles.into_values());
+        cmds.extend(self.names.into_values());
+        cmds.extend(self.frags.into_values());
+        cmds.extend(self.colors.into_values());
+        cmds.extend(
+            self.stats
+                .into_iter()
+                .map(|(stat, value)| ServerCmd::UpdateStat {
+                    stat: ClientStat::from_i32(stat).unwrap(),
+                    value,
+                }),
+        );
+        cmds.extend(
+            [
+                self.view,
+                self.cd_track,
+                self.pause,
+                self.skybox,
+                self.fog,
+                self.intermission,
+            ]
+            .into_iter()
+            .flatten(),
+        );
+
+        cmds
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{
+        net::{self, Protocol, ProtocolVersion},
+        vfs::VirtualFile,
+    };
+    use std::io::Cursor;
+
+    fn angles() -> Vector3<Deg<f32>> {
+        Vector3::new(Deg(0.0), Deg(45.0), Deg(0.0))
+    }
+
+    fn server_info(message: &str) -> ServerCmd {
+        ServerCmd::ServerInfo {
+            protocol_version: ProtocolVersion::NetQuake as i32,
+            protocol_flags: net::ProtocolFlags::empty(),
+            max_clients: 1,
+            game_type: net::GameType::CoOp,
+            message: message.to_owned(),
+            model_precache: Vec::new(),
+            sound_precache: Vec::new(),
+        }
+    }
+
+    fn sign_on(stage: SignOnStage) -> ServerCmd {
+        ServerCmd::SignOnStage { stage }
+    }
+
+    fn time(time: f32) -> ServerCmd {
+        ServerCmd::Time { time }
+    }
+
+    fn light_style(id: u8, value: &str) -> ServerCmd {
+        ServerCmd::LightStyle {
+            id,
+            value: value.to_owned(),
+        }
+    }
+
+    // records a level with the given gameplay messages after its sign-on
+    fn record(level: &str, messages: &[Vec<ServerCmd>]) -> DemoServer {
+        let mut recorder = DemoRecorder::new(Vec::new(), None).unwrap();
+        let sign_on_msgs = [
+            vec![server_info(level), sign_on(SignOnStage::Prespawn)],
+            vec![light_style(0, "m"), sign_on(SignOnStage::ClientInfo)],
+            vec![sign_on(SignOnStage::Begin)],
+        ];
+
+        for cmds in sign_on_msgs.iter().chain(messages.iter()) {
+            recorder
+                .write_cmds(angles(), cmds, Protocol::default())
+                .unwrap();
+        }
+
+        load(&recorder.finish(angles()).unwrap())
+    }
+
+    fn load(data: &[u8]) -> DemoServer {
+        DemoServer::new(&mut VirtualFile::MemoryBacked(Cursor::new(data.into()))).unwrap()
+    }
+
+    // returns the commands in each message
+    fn read_all(demo: &DemoServer) -> Vec<Vec<ServerCmd>> {
+        (0..demo.message_count())
+            .map(|id| demo.message_cmds(id).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_trim_regenerates_state() {
+        let demo = record(
+            "level",
+            &[
+                vec![time(1.0), light_style(0, "a")],
+                vec![
+                    time(2.0),
+                    ServerCmd::KilledMonster,
+                    ServerCmd::UpdateFrags {
+                        player_id: 0,
+                        new_frags: 3,
+                    },
+                ],
+                vec![time(3.0), light_style(0, "z")],
+                vec![time(4.0)],
+            ],
+        );
+
+        let mut editor = DemoEditor::new(Vec::new(), None).unwrap();
+        editor
+            .append(
+                &demo,
+                Some(Duration::seconds(2)),
+                Some(Duration::seconds(2)),
+            )
+            .unwrap();
+        let edited = load(&editor.finish().unwrap());
+        let messages = read_all(&edited);
+
+        // sign-on, state, the section and the disconnect
+        assert_eq!(messages.len(), 6);
+        assert_eq!(messages[0][0], server_info("level"));
+        assert_eq!(messages[2], vec![sign_on(SignOnStage::Begin)]);
+        assert_eq!(
+            messages[3],
+            vec![
+                light_style(0, "a"),
+                ServerCmd::UpdateFrags {
+                    player_id: 0,
+                    new_frags: 3,
+                },
+                ServerCmd::UpdateStat {
+                    stat: ClientStat::KilledMonsters,
+                    value: 1,
+                },
+            ]
+        );
+        assert_eq!(messages[4][0], time(3.0));
+        assert_eq!(messages[5], vec![ServerCmd::Disconnect]);
+    }
+
+    #[test]
+    fn test_join_drops_cmds() {
+        let center_print = || ServerCmd::CenterPrint {
+            text: String::from("hello"),
+        };
+        let first = record("first", &[vec![time(1.0), center_print()]]);
+        let second = record("second", &[vec![center_print()], vec![time(1.0)]]);
+
+        let mut editor = DemoEditor::new(Vec::new(), None).unwrap();
+        editor.drop_cmds(&["centerprint"]).unwrap();
+        editor.append(&first, None, None).unwrap();
+        editor.append(&second, None, None).unwrap();
+        let edited = load(&editor.finish().unwrap());
+        let messages = read_all(&edited);
+
+        // the message holding only a CenterPrint is left out, as is the
+        // disconnect at the end of the first demo
+        assert_eq!(messages.len(), 9);
+        assert_eq!(messages[3], vec![time(1.0)]);
+        assert_eq!(messages[4][0], server_info("second"));
+        assert_eq!(messages[7], vec![time(1.0)]);
+        assert_eq!(messages[8], vec![ServerCmd::Disconnect]);
+        assert_eq!(edited.level_start(5), 4);
+    }
+
+    #[test]
+    fn test_bad_edits() {
+        let demo = record("level", &[vec![time(1.0)]]);
+        let mut editor = DemoEditor::new(Vec::new(), None).unwrap();
+
+        assert!(matches!(
+            editor.drop_cmds(&["NotACommand"]),
+            Err(DemoEditError::NoSuchCmd(_))
+        ));
+        assert!(matches!(
+            editor.append(&demo, Some(Duration::seconds(5)), None),
+            Err(DemoEditError::EmptySection { .. })
+        ));
+    }
+}
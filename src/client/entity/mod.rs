@@ -23,7 +23,7 @@ pub mod particle;
 use crate::common::{
     alloc::LinkedSlab,
     engine,
-    net::{EntityEffects, EntityState, EntityUpdate},
+    net::{self, EntityEffects, EntityState, EntityUpdate},
 };
 
 use cgmath::{Deg, Vector3};
@@ -35,7 +35,7 @@ pub const MAX_BEAMS: usize = 24;
 pub const MAX_TEMP_ENTITIES: usize = 64;
 pub const MAX_STATIC_ENTITIES: usize = 128;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ClientEntity {
     pub force_link: bool,
     pub baseline: EntityState,
@@ -51,6 +51,7 @@ pub struct ClientEntity {
     colormap: Option<u8>,
     pub sync_base: Duration,
     pub effects: EntityEffects,
+    pub alpha: u8,
     pub light_id: Option<usize>,
     // vis_frame: usize,
 }
@@ -75,6 +76,7 @@ impl ClientEntity {
             colormap: None,
             sync_base: Duration::zero(),
             effects: baseline.effects,
+            alpha: baseline.alpha,
             light_id: None,
         }
     }
@@ -98,6 +100,7 @@ impl ClientEntity {
             colormap: None,
             sync_base: Duration::zero(),
             effects: EntityEffects::empty(),
+            alpha: net::ENTITY_ALPHA_DEFAULT,
             light_id: None,
         }
     }
@@ -133,6 +136,7 @@ impl ClientEntity {
         self.frame_id = new_state.frame_id;
         self.skin_id = new_state.skin_id;
         self.effects = new_state.effects;
+        self.alpha = new_state.alpha;
         self.colormap = update.colormap;
 
         if self.force_link {
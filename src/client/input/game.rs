@@ -18,6 +18,7 @@
 use std::{
     cell::{Cell, RefCell},
     collections::HashMap,
+    io::{self, Write},
     rc::Rc,
     str::FromStr,
     string::ToString,
@@ -566,6 +567,34 @@ impl GameInput {
         self.bindings.borrow().get(&input.into()).map(|t| t.clone())
     }
 
+    /// Writes the current bindings as console commands which restore them.
+    pub fn write_bindings<W>(&self, mut writer: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        let mut binds: Vec<String> = self
+            .bindings
+            .borrow()
+            .iter()
+            .map(|(input, target)| {
+                let target = match target {
+                    BindTarget::ConsoleInput { text } => text.clone(),
+                    t => t.to_string(),
+                };
+
+                format!("bind \"{}\" \"{}\"", input.to_string(), target)
+            })
+            .collect();
+        binds.sort();
+
+        writeln!(writer, "unbindall")?;
+        for bind in binds {
+            writeln!(writer, "{}", bind)?;
+        }
+
+        Ok(())
+    }
+
     pub fn handle_event<T>(&mut self, outer_event: Event<T>) {
         let (input, state): (BindInput, _) = match outer_event {
             Event::WindowEvent { event, .. } => match event {
@@ -744,6 +773,7 @@ impl GameInput {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::common::console::CvarRegistry;
 
     #[test]
     fn test_action_to_string() {
@@ -760,4 +790,32 @@ mod test {
 
         assert_eq!(target.to_string(), "+forward");
     }
+
+    #[test]
+    fn test_write_bindings() {
+        let cmds = Rc::new(RefCell::new(CmdRegistry::new(Rc::new(RefCell::new(
+            Vec::new(),
+        )))));
+        let cvars = Rc::new(RefCell::new(CvarRegistry::new(Rc::new(RefCell::new(
+            Vec::new(),
+        )))));
+        let console = Rc::new(RefCell::new(Console::new(cmds, cvars)));
+
+        let mut game_input = GameInput::new(console);
+        game_input.bind(
+            BindInput::from_str("w").unwrap(),
+            BindTarget::from_str("+forward").unwrap(),
+        );
+        game_input.bind(
+            BindInput::from_str("f1").unwrap(),
+            BindTarget::from_str("map e1m1").unwrap(),
+        );
+
+        let mut config = Vec::new();
+        game_input.write_bindings(&mut config).unwrap();
+        assert_eq!(
+            String::from_utf8(config).unwrap(),
+            "unbindall\nbind \"F1\" \"map e1m1\"\nbind \"W\" \"+forward\"\n"
+        );
+    }
 }
@@ -19,7 +19,7 @@ pub mod console;
 pub mod game;
 pub mod menu;
 
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, io, rc::Rc};
 
 use crate::{
     client::menu::Menu,
@@ -126,6 +126,14 @@ impl Input {
         }
     }
 
+    /// Writes the current key bindings as console commands which restore them.
+    pub fn write_bindings<W>(&self, writer: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        self.game_input.write_bindings(writer)
+    }
+
     pub fn register_cmds(&self, cmds: &mut CmdRegistry) {
         self.game_input.register_cmds(cmds);
     }
@@ -0,0 +1,627 @@
+//! Match statistics from demos.
+//!
+//! The server commands in a demo are enough to follow the scores, names and
+//! deaths of every player, and the items and damage of the player the demo
+//! was recorded by. Other players' deaths are counted from obituaries, which
+//! the server prints as a line starting with the name of the player who died.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    client::demo::{DemoCmdError, DemoServer},
+    common::net::{ItemFlags, ServerCmd},
+};
+
+use serde::Serialize;
+use thiserror::Error;
+
+/// Server messages starting with a player's name which aren't obituaries.
+const NON_OBITUARIES: &[&str] = &["entered the game", "left the game", "changed name to"];
+
+#[derive(Error, Debug)]
+pub enum MatchStatsError {
+    #[error("{0}")]
+    Demo(#[from] DemoCmdError),
+}
+
+/// Statistics for each level of a demo.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct MatchStats {
+    pub levels: Vec<LevelStats>,
+}
+
+impl MatchStats {
+    /// Collects statistics from every message of a demo.
+    pub fn from_demo(demo: &DemoServer) -> Result<MatchStats, MatchStatsError> {
+        let mut analyzer = MatchAnalyzer::new();
+
+        for id in 0..demo.message_count() {
+            for cmd in demo.message_cmds(id)? {
+                analyzer.update(&cmd);
+            }
+        }
+
+        Ok(analyzer.finish())
+    }
+
+    /// Sums each player's statistics over all levels, matching players by
+    /// name.
+    pub fn totals(&self) -> Vec<PlayerStats> {
+        let mut totals: Vec<PlayerStats> = Vec::new();
+
+        for player in self.levels.iter().flat_map(|l| l.players.iter()) {
+            let total = match totals.iter_mut().find(|t| t.name == player.name) {
+                Some(t) => t,
+                None => {
+                    totals.push(PlayerStats::new(player.name.clone()));
+                    totals.last_mut().unwrap()
+                }
+            };
+
+            total.frags += player.frags;
+            total.deaths += player.deaths;
+            total.time += player.time;
+            total.recorder |= player.recorder;
+            total.damage_taken += player.damage_taken;
+            total.pickups += player.pickups;
+            for (item, count) in player.items.iter() {
+                *total.items.entry(item.clone()).or_insert(0) += count;
+            }
+        }
+
+        totals
+    }
+}
+
+/// Statistics for one level.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct LevelStats {
+    /// The path of the level's map, e.g. `maps/dm4.bsp`.
+    pub map: String,
+
+    /// The level's title.
+    pub title: String,
+
+    /// Server time spent in the level before the intermission, in seconds.
+    pub duration: f32,
+
+    /// Whether the level ended with an intermission.
+    pub finished: bool,
+
+    /// Everyone who played in the level. Players who left come first, in the
+    /// order they left, followed by the rest in order of player slot.
+    pub players: Vec<PlayerStats>,
+}
+
+/// Statistics for one player in one level.
+///
+/// Items, pickups and damage are only known for the player who recorded the
+/// demo.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PlayerStats {
+    pub name: String,
+    pub frags: i32,
+    pub deaths: u32,
+
+    /// Time spent in the level, in seconds.
+    pub time: f32,
+
+    /// Whether this is the player who recorded the demo.
+    pub recorder: bool,
+
+    /// Armor and health lost.
+    pub damage_taken: u32,
+
+    /// How many times each weapon, armor, key and powerup was picked up.
+    pub items: BTreeMap<String, u32>,
+
+    /// Everything picked up, including health and ammo.
+    pub pickups: u32,
+}
+
+impl PlayerStats {
+    fn new(name: String) -> PlayerStats {
+        PlayerStats {
+            name,
+            frags: 0,
+            deaths: 0,
+            time: 0.0,
+            recorder: false,
+            damage_taken: 0,
+            items: BTreeMap::new(),
+            pickups: 0,
+        }
+    }
+}
+
+// a player slot in the current level
+struct Slot {
+    stats: PlayerStats,
+
+    // server time the player joined, or None if they haven't yet
+    joined: Option<f32>,
+}
+
+// the level being followed
+struct Level {
+    stats: LevelStats,
+    slots: Vec<Option<Slot>>,
+
+    // server time of the first and last updates
+    start: Option<f32>,
+    time: f32,
+
+    // slot of the player who recorded the demo
+    recorder: Option<usize>,
+    items: ItemFlags,
+    health: i16,
+
+    // printed text not yet ended by a newline. QuakeC prints obituaries a
+    // piece at a time, so lines are only complete once the newline arrives
+    print_line: String,
+}
+
+impl Level {
+    fn new(map: String, title: String, max_clients: u8) -> Level {
+        Level {
+            stats: LevelStats {
+                map,
+                title,
+                duration: 0.0,
+                finished: false,
+                players: Vec::new(),
+            },
+            slots: (0..max_clients).map(|_| None).collect(),
+            start: None,
+            time: 0.0,
+            recorder: None,
+            items: ItemFlags::empty(),
+            health: 0,
+            print_line: String::new(),
+        }
+    }
+
+    fn slot_mut(&mut self, player_id: usize) -> Option<&mut Slot> {
+        self.slots.get_mut(player_id).and_then(|s| s.as_mut())
+    }
+
+    // stops the clock for a player, e.g. when they leave
+    fn leave(&mut self, player_id: usize) {
+        let time = self.time;
+        if let Some(slot) = self.slot_mut(player_id) {
+            if let Some(joined) = slot.joined.take() {
+                slot.stats.time += time - joined;
+            }
+        }
+    }
+
+    // stops the clock for everyone
+    fn end(&mut self) {
+        if self.stats.finished {
+            return;
+        }
+
+        for id in 0..self.slots.len() {
+            self.leave(id);
+        }
+
+        self.stats.duration = self.time - self.start.unwrap_or(self.time);
+    }
+
+    fn finish(mut self) -> LevelStats {
+        self.end();
+
+        // players who left are kept; their slot may have been reused
+        self.stats
+            .players
+            .extend(self.slots.into_iter().flatten().map(|s| s.stats));
+        self.stats
+    }
+
+    // counts a pickup by the recorder
+    fn pickup(&mut self) {
+        if let Some(slot) = self.recorder.and_then(|id| self.slot_mut(id)) {
+            slot.stats.pickups += 1;
+        }
+    }
+
+    // counts a death if the line is an obituary
+    fn obituary(&mut self, line: &str) {
+        let victim = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(id, s)| s.as_ref().map(|s| (id, s.stats.name.as_str())))
+            .filter(|(_, name)| !name.is_empty())
+            .filter_map(|(id, name)| Some((id, name.len(), line.strip_prefix(name)?)))
+            .filter(|(_, _, rest)| rest.starts_with(' '))
+            .filter(|(_, _, rest)| !NON_OBITUARIES.iter().any(|n| rest.contains(n)))
+            .max_by_key(|(_, name_len, _)| *name_len)
+            .map(|(id, _, _)| id);
+
+        // the recorder's deaths are counted from their health instead
+        if let Some(id) = victim.filter(|id| Some(*id) != self.recorder) {
+            self.slot_mut(id).unwrap().stats.deaths += 1;
+        }
+    }
+}
+
+/// Follows a stream of server commands and collects match statistics.
+pub struct MatchAnalyzer {
+    levels: Vec<LevelStats>,
+    level: Option<Level>,
+}
+
+impl MatchAnalyzer {
+    pub fn new() -> MatchAnalyzer {
+        MatchAnalyzer {
+            levels: Vec::new(),
+            level: None,
+        }
+    }
+
+    /// Updates the statistics with the next server command.
+    pub fn update(&mut self, cmd: &ServerCmd) {
+        if let ServerCmd::ServerInfo {
+            max_clients,
+            ref message,
+            ref model_precache,
+            ..
+        } = *cmd
+        {
+            if let Some(level) = self.level.take() {
+                self.levels.push(level.finish());
+            }
+
+            let map = model_precache.first().cloned().unwrap_or_default();
+            self.level = Some(Level::new(map, message.clone(), max_clients));
+            return;
+        }
+
+        // commands before the first level have nothing to apply to
+        let level = match self.level.as_mut() {
+            Some(l) => l,
+            None => return,
+        };
+
+        match *cmd {
+            ServerCmd::Time { time } => {
+                if level.start.is_none() {
+                    // everyone named during the sign-on joins now
+                    level.start = Some(time);
+                    for slot in level.slots.iter_mut().flatten() {
+                        slot.joined = Some(time);
+                    }
+                }
+
+                level.time = time;
+            }
+
+            ServerCmd::UpdateName {
+                player_id,
+                ref new_name,
+            } => {
+                let id = player_id as usize;
+                if id >= level.slots.len() {
+                    return;
+                }
+
+                if new_name.is_empty() {
+                    // the player left, so their slot is freed
+                    level.leave(id);
+                    if let Some(slot) = level.slots[id].take() {
+                        level.stats.players.push(slot.stats);
+                    }
+                    return;
+                }
+
+                let joined = level.start.map(|_| level.time);
+                let recorder = level.recorder == Some(id);
+                let slot = level.slots[id].get_or_insert_with(|| Slot {
+                    stats: PlayerStats::new(String::new()),
+                    joined: None,
+                });
+                slot.stats.name = new_name.clone();
+                slot.stats.recorder = recorder;
+                slot.joined = slot.joined.or(joined);
+            }
+
+            ServerCmd::UpdateFrags {
+                player_id,
+                new_frags,
+            } => {
+                if let Some(slot) = level.slot_mut(player_id as usize) {
+                    slot.stats.frags = new_frags as i32;
+                }
+            }
+
+            ServerCmd::SetView { ent_id } => {
+                // player entities follow the world entity
+                let id = (ent_id as usize).wrapping_sub(1);
+                level.recorder = (id < level.slots.len()).then_some(id);
+                for (i, slot) in level.slots.iter_mut().enumerate() {
+                    if let Some(slot) = slot {
+                        slot.stats.recorder = Some(i) == level.recorder;
+                    }
+                }
+            }
+
+            ServerCmd::PlayerData(ref data) => {
+                let died = level.health > 0 && data.health <= 0;
+
+                // players spawn with some items, which don't count as pickups
+                let spawned = level.health <= 0 && data.health > 0;
+                let picked_up = match spawned {
+                    true => ItemFlags::empty(),
+                    false => pickup_items(data.items) - pickup_items(level.items),
+                };
+                level.health = data.health;
+                level.items = data.items;
+
+                if let Some(slot) = level.recorder.and_then(|id| level.slot_mut(id)) {
+                    if died {
+                        slot.stats.deaths += 1;
+                    }
+
+                    for (name, _) in picked_up.iter_names() {
+                        let name = name.to_lowercase().replace('_', " ");
+                        *slot.stats.items.entry(name).or_insert(0) += 1;
+                    }
+                }
+            }
+
+            ServerCmd::Damage { armor, blood, .. } => {
+                if let Some(slot) = level.recorder.and_then(|id| level.slot_mut(id)) {
+                    slot.stats.damage_taken += armor as u32 + blood as u32;
+                }
+            }
+
+            ServerCmd::BonusFlash => level.pickup(),
+
+            // protocol 15 servers flash the screen with the `bf` command
+            ServerCmd::StuffText { ref text } => {
+                for _ in text.lines().filter(|l| l.trim() == "bf") {
+                    level.pickup();
+                }
+            }
+
+            ServerCmd::Print { ref text } => {
+                level.print_line.push_str(text);
+                while let Some(end) = level.print_line.find('\n') {
+                    let line: String = level.print_line.drain(..=end).collect();
+                    level.obituary(line.trim_end_matches('\n'));
+                }
+            }
+
+            ServerCmd::Intermission | ServerCmd::Finale { .. } | ServerCmd::Cutscene { .. } => {
+                level.end();
+                level.stats.finished = true;
+            }
+
+            _ => (),
+        }
+    }
+
+    /// Returns the statistics for every level seen.
+    pub fn finish(mut self) -> MatchStats {
+        if let Some(level) = self.level.take() {
+            self.levels.push(level.finish());
+        }
+
+        MatchStats {
+            levels: self.levels,
+        }
+    }
+}
+
+impl Default for MatchAnalyzer {
+    fn default() -> Self {
+        MatchAnalyzer::new()
+    }
+}
+
+// the items which are only held after being picked up. the ammo flags show
+// which kind of ammo the current weapon uses
+fn pickup_items(items: ItemFlags) -> ItemFlags {
+    items
+        - (ItemFlags::SHELLS
+            | ItemFlags::NAILS
+            | ItemFlags::ROCKETS
+            | ItemFlags::CELLS
+            | ItemFlags::AXE)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::net::{GameType, PlayerData, ProtocolFlags, ProtocolVersion};
+
+    fn server_info(map: &str) -> ServerCmd {
+        ServerCmd::ServerInfo {
+            protocol_version: ProtocolVersion::NetQuake as i32,
+            protocol_flags: ProtocolFlags::empty(),
+            max_clients: 4,
+            game_type: GameType::Deathmatch,
+            message: String::from("Test Level"),
+            model_precache: vec![map.to_owned()],
+            sound_precache: Vec::new(),
+        }
+    }
+
+    fn name(player_id: u8, name: &str) -> ServerCmd {
+        ServerCmd::UpdateName {
+            player_id,
+            new_name: name.to_owned(),
+        }
+    }
+
+    fn frags(player_id: u8, new_frags: i16) -> ServerCmd {
+        ServerCmd::UpdateFrags {
+            player_id,
+            new_frags,
+        }
+    }
+
+    fn print(text: &str) -> ServerCmd {
+        ServerCmd::Print {
+            text: text.to_owned(),
+        }
+    }
+
+    fn player_data(health: i16, items: ItemFlags) -> ServerCmd {
+        ServerCmd::PlayerData(PlayerData {
+            view_height: None,
+            ideal_pitch: None,
+            punch_pitch: None,
+            velocity_x: None,
+            punch_yaw: None,
+            velocity_y: None,
+            punch_roll: None,
+            velocity_z: None,
+            items,
+            on_ground: true,
+            in_water: false,
+            weapon_frame: None,
+            armor: None,
+            weapon: None,
+            health,
+            ammo: 0,
+            ammo_shells: 0,
+            ammo_nails: 0,
+            ammo_rockets: 0,
+            ammo_cells: 0,
+            active_weapon: 0,
+            weapon_alpha: None,
+        })
+    }
+
+    fn analyze(cmds: &[ServerCmd]) -> MatchStats {
+        let mut analyzer = MatchAnalyzer::new();
+        for cmd in cmds {
+            analyzer.update(cmd);
+        }
+        analyzer.finish()
+    }
+
+    #[test]
+    fn test_deathmatch() {
+        let time = |time| ServerCmd::Time { time };
+        let base = ItemFlags::SHOTGUN | ItemFlags::AXE | ItemFlags::SHELLS;
+
+        let stats = analyze(&[
+            server_info("maps/dm4.bsp"),
+            name(0, "alice"),
+            name(1, "bob"),
+            name(2, "bobby"),
+            ServerCmd::SetView { ent_id: 1 },
+            time(10.0),
+            player_data(100, base),
+            ServerCmd::BonusFlash,
+            player_data(100, base | ItemFlags::ROCKET_LAUNCHER | ItemFlags::ROCKETS),
+            ServerCmd::Damage {
+                armor: 10,
+                blood: 30,
+                source: cgmath::Vector3::new(0.0, 0.0, 0.0),
+            },
+            print("bobby ate alice's rocket\n"),
+            frags(0, 1),
+            print("\u{1}bob: nice shot\n"),
+            print("alice was gibbed by bobby's rocket\n"),
+            player_data(-20, ItemFlags::empty()),
+            frags(2, 1),
+            time(20.0),
+            name(1, ""),
+            print("bob left the game with 0 frags\n"),
+            time(70.0),
+            ServerCmd::Intermission,
+            time(80.0),
+        ]);
+
+        assert_eq!(stats.levels.len(), 1);
+        let level = &stats.levels[0];
+        assert_eq!(level.map, "maps/dm4.bsp");
+        assert_eq!(level.duration, 60.0);
+        assert!(level.finished);
+
+        // bob left, so he comes first
+        let names: Vec<&str> = level.players.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["bob", "alice", "bobby"]);
+        assert_eq!(level.players[0].time, 10.0);
+
+        let alice = &level.players[1];
+        assert!(alice.recorder);
+        assert_eq!(alice.frags, 1);
+        assert_eq!(alice.deaths, 1);
+        assert_eq!(alice.time, 60.0);
+        assert_eq!(alice.damage_taken, 40);
+        assert_eq!(alice.pickups, 1);
+        assert_eq!(alice.items.get("rocket launcher"), Some(&1));
+        assert_eq!(alice.items.len(), 1);
+
+        let bobby = &level.players[2];
+        assert!(!bobby.recorder);
+        assert_eq!(bobby.frags, 1);
+        assert_eq!(bobby.deaths, 1);
+        assert_eq!(level.players[0].deaths, 0);
+    }
+
+    #[test]
+    fn test_totals() {
+        let stats = analyze(&[
+            server_info("maps/dm1.bsp"),
+            name(0, "alice"),
+            frags(0, 3),
+            server_info("maps/dm2.bsp"),
+            name(1, "alice"),
+            name(0, "bob"),
+            frags(1, 4),
+        ]);
+
+        assert_eq!(stats.levels.len(), 2);
+        let totals = stats.totals();
+        assert_eq!(totals.len(), 2);
+        assert_eq!(totals[0].name, "alice");
+        assert_eq!(totals[0].frags, 7);
+        assert_eq!(totals[1].name, "bob");
+    }
+
+    #[test]
+    fn test_split_obituaries() {
+        let stats = analyze(&[
+            server_info("maps/dm6.bsp"),
+            name(0, "alice"),
+            name(1, "bob"),
+            ServerCmd::SetView { ent_id: 1 },
+            ServerCmd::Time { time: 1.0 },
+            player_data(100, ItemFlags::empty()),
+            // the standard QuakeC prints each part of an obituary separately
+            print("bob"),
+            print(" was ax-murdered by "),
+            print("alice"),
+            print("\n"),
+        ]);
+
+        let level = &stats.levels[0];
+        assert_eq!(level.players[1].deaths, 1);
+        assert_eq!(level.players[0].deaths, 0);
+    }
+
+    #[test]
+    fn test_stuffed_bonus_flashes() {
+        let stuff = |text: &str| ServerCmd::StuffText {
+            text: text.to_owned(),
+        };
+
+        let stats = analyze(&[
+            server_info("maps/e1m1.bsp"),
+            name(0, "alice"),
+            ServerCmd::SetView { ent_id: 1 },
+            stuff("bf\n"),
+            stuff("bf\nbf\n"),
+            stuff("bfx\n"),
+            ServerCmd::BonusFlash,
+        ]);
+
+        assert_eq!(stats.levels[0].players[0].pickups, 4);
+    }
+}
@@ -31,6 +31,7 @@ pub enum Item {
     Enum(Enum),
     Slider(Slider),
     TextField(TextField),
+    List(List),
 }
 
 pub struct Toggle {
@@ -295,6 +296,75 @@ impl TextField {
     }
 }
 
+/// A selectable list whose entries can change while the menu is open.
+pub struct List {
+    entries: Box<dyn Fn() -> Vec<String>>,
+    selected: Cell<usize>,
+    on_activate: Box<dyn Fn(usize)>,
+}
+
+impl List {
+    pub fn new(entries: Box<dyn Fn() -> Vec<String>>, on_activate: Box<dyn Fn(usize)>) -> List {
+        List {
+            entries,
+            selected: Cell::new(0),
+            on_activate,
+        }
+    }
+
+    /// Returns the current entries of the list.
+    pub fn entries(&self) -> Vec<String> {
+        (self.entries)()
+    }
+
+    /// Returns the index of the selected entry.
+    ///
+    /// This is always less than the number of entries unless the list is empty.
+    pub fn selected(&self) -> usize {
+        let len = self.entries().len();
+        self.selected.get().min(len.saturating_sub(1))
+    }
+
+    pub fn select_first(&self) {
+        self.selected.set(0);
+    }
+
+    pub fn select_last(&self) {
+        self.selected.set(self.entries().len().saturating_sub(1));
+    }
+
+    /// Selects the next entry, returning `false` if the last entry was
+    /// already selected.
+    pub fn select_next(&self) -> bool {
+        let next = self.selected() + 1;
+        if next < self.entries().len() {
+            self.selected.set(next);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Selects the previous entry, returning `false` if the first entry was
+    /// already selected.
+    pub fn select_prev(&self) -> bool {
+        match self.selected() {
+            0 => false,
+            s => {
+                self.selected.set(s - 1);
+                true
+            }
+        }
+    }
+
+    /// Runs the list's action on the selected entry, if there is one.
+    pub fn activate(&self) {
+        if !self.entries().is_empty() {
+            (self.on_activate)(self.selected());
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -381,6 +451,35 @@ mod test {
         assert_eq!(f.get(), 10.0);
     }
 
+    #[test]
+    fn test_list() {
+        let entries = Rc::new(RefCell::new(vec!["a".to_string(), "b".to_string()]));
+        let activated = Rc::new(Cell::new(None));
+
+        let entries_handle = entries.clone();
+        let activated_handle = activated.clone();
+        let list = List::new(
+            Box::new(move || entries_handle.borrow().clone()),
+            Box::new(move |i| activated_handle.set(Some(i))),
+        );
+
+        assert!(!list.select_prev());
+        assert!(list.select_next());
+        assert!(!list.select_next());
+        list.activate();
+        assert_eq!(activated.get(), Some(1));
+
+        // selection follows the list when it shrinks
+        entries.borrow_mut().pop();
+        assert_eq!(list.selected(), 0);
+
+        // empty lists have nothing to activate
+        entries.borrow_mut().clear();
+        activated.set(None);
+        list.activate();
+        assert_eq!(activated.get(), None);
+    }
+
     #[test]
     fn test_textfield() {
         let max_len = 10;
@@ -24,7 +24,7 @@ use std::cell::Cell;
 
 use failure::Error;
 
-pub use self::item::{Enum, EnumItem, Item, Slider, TextField, Toggle};
+pub use self::item::{Enum, EnumItem, Item, List, Slider, TextField, Toggle};
 
 #[derive(Clone, Copy, Debug)]
 pub enum MenuState {
@@ -113,14 +113,25 @@ impl Menu {
     }
 
     /// Select the next element of this Menu.
+    ///
+    /// Lists are stepped through one entry at a time.
     pub fn next(&self) -> Result<(), Error> {
         let m = self.active_submenu()?;
 
         let s = m.state.get().clone();
         if let MenuState::Active { index } = s {
-            m.state.replace(MenuState::Active {
-                index: (index + 1) % m.items.len(),
-            });
+            if let Item::List(ref list) = m.items[index].item {
+                if list.select_next() {
+                    return Ok(());
+                }
+            }
+
+            let index = (index + 1) % m.items.len();
+            if let Item::List(ref list) = m.items[index].item {
+                list.select_first();
+            }
+
+            m.state.replace(MenuState::Active { index });
         } else {
             bail!("Selected menu is inactive (invariant violation)");
         }
@@ -129,14 +140,25 @@ impl Menu {
     }
 
     /// Select the previous element of this Menu.
+    ///
+    /// Lists are stepped through one entry at a time.
     pub fn prev(&self) -> Result<(), Error> {
         let m = self.active_submenu()?;
 
         let s = m.state.get().clone();
         if let MenuState::Active { index } = s {
-            m.state.replace(MenuState::Active {
-                index: (index - 1) % m.items.len(),
-            });
+            if let Item::List(ref list) = m.items[index].item {
+                if list.select_prev() {
+                    return Ok(());
+                }
+            }
+
+            let index = (index + m.items.len() - 1) % m.items.len();
+            if let Item::List(ref list) = m.items[index].item {
+                list.select_last();
+            }
+
+            m.state.replace(MenuState::Active { index });
         } else {
             bail!("Selected menu is inactive (invariant violation)");
         }
@@ -177,6 +199,8 @@ impl Menu {
 
                 Item::Action(ref action) => (action)(),
 
+                Item::List(ref list) => list.activate(),
+
                 _ => (),
             }
         }
@@ -350,6 +374,22 @@ impl MenuBuilder {
         Ok(self)
     }
 
+    pub fn add_list<S>(
+        mut self,
+        name: S,
+        entries: Box<dyn Fn() -> Vec<String>>,
+        on_activate: Box<dyn Fn(usize)>,
+    ) -> MenuBuilder
+    where
+        S: AsRef<str>,
+    {
+        self.items.push(NamedMenuItem::new(
+            name,
+            Item::List(List::new(entries, on_activate)),
+        ));
+        self
+    }
+
     pub fn add_text_field<S>(
         mut self,
         name: S,
@@ -489,4 +529,33 @@ mod test {
         assert!(is_inactive(&m1.state.get()));
         assert!(is_active(&m2.state.get()));
     }
+
+    #[test]
+    fn test_menu_list_navigation() {
+        let activated = Rc::new(Cell::new(None));
+        let activated_handle = activated.clone();
+
+        let menu = MenuBuilder::new()
+            .add_action("action", Box::new(|| ()))
+            .add_list(
+                "list",
+                Box::new(|| vec!["a".to_string(), "b".to_string()]),
+                Box::new(move |i| activated_handle.set(Some(i))),
+            )
+            .build(view());
+
+        // step into the list, through its entries and back out
+        menu.next().unwrap();
+        menu.next().unwrap();
+        menu.activate().unwrap();
+        assert_eq!(activated.get(), Some(1));
+        menu.next().unwrap();
+        assert!(matches!(menu.state.get(), MenuState::Active { index: 0 }));
+
+        // wrap backwards into the last entry of the list
+        menu.prev().unwrap();
+        assert!(matches!(menu.state.get(), MenuState::Active { index: 1 }));
+        menu.activate().unwrap();
+        assert_eq!(activated.get(), Some(1));
+    }
 }
@@ -20,12 +20,17 @@
 
 mod cvars;
 pub mod demo;
+pub mod demo_edit;
 pub mod entity;
 pub mod input;
+pub mod match_stats;
 pub mod menu;
+pub mod predict;
 pub mod render;
+pub mod slist;
 pub mod sound;
 pub mod state;
+pub mod timedemo;
 pub mod trace;
 pub mod view;
 
@@ -34,39 +39,47 @@ pub use self::cvars::register_cvars;
 use std::{
     cell::RefCell,
     collections::{HashMap, VecDeque},
-    io::BufReader,
-    net::ToSocketAddrs,
+    fs::File,
+    io::{BufReader, BufWriter},
+    net::{SocketAddr, ToSocketAddrs},
     rc::Rc,
 };
 
 use crate::{
     client::{
-        demo::{DemoServer, DemoServerError},
+        demo::{DemoMessageView, DemoRecorder, DemoServer, DemoServerError},
         entity::{ClientEntity, MAX_STATIC_ENTITIES},
         input::{game::GameInput, Input},
+        slist::ServerList,
         sound::{MusicPlayer, StaticSound},
-        state::{ClientState, PlayerInfo},
+        state::{ClientState, PlayerInfo, StaticSoundInfo},
+        timedemo::TimeDemo,
         trace::{TraceEntity, TraceFrame},
         view::{IdleVars, KickVars, MouseVars, RollVars},
     },
     common::{
+        bsp::BspError,
         console::{CmdRegistry, Console, ConsoleError, CvarRegistry},
         engine,
         model::ModelError,
         net::{
             self,
-            connect::{ConnectSocket, Request, Response, CONNECT_PROTOCOL_VERSION},
+            connect::{ConnectSocket, Request, Response, CONNECT_PROTOCOL_VERSION, DEFAULT_PORT},
             BlockingMode, ClientCmd, ClientStat, ColorShift, EntityEffects, EntityState, GameType,
-            NetError, PlayerColor, QSocket, ServerCmd, SignOnStage,
+            Impairment, NetError, NetSocket, PlayerColor, Protocol, ProtocolVersion, ServerCmd,
+            SignOnStage, MAX_CLIENTS,
         },
+        pmove::PhysicsVars,
         vfs::{Vfs, VfsError},
     },
+    server::{net::NetServer, Session},
 };
 
-use cgmath::Deg;
+use cgmath::{Deg, Vector3};
 use chrono::Duration;
 use input::InputFocus;
 use menu::Menu;
+use num::FromPrimitive as _;
 use render::{ClientRenderer, GraphicsState, WorldRenderer};
 use rodio::{OutputStream, OutputStreamHandle};
 use sound::SoundError;
@@ -76,11 +89,20 @@ use view::BobVars;
 // connections are tried 3 times, see
 // https://github.com/id-Software/Quake/blob/master/WinQuake/net_dgrm.c#L1248
 const MAX_CONNECT_ATTEMPTS: usize = 3;
+
+// how long to wait for the output of an rcon command
+const RCON_TIMEOUT_MS: i64 = 2500;
 const MAX_STATS: usize = 32;
 
+// most demo messages to read in a single frame
+const MAX_DEMO_MESSAGES_PER_FRAME: usize = 64;
+
 const DEFAULT_SOUND_PACKET_VOLUME: u8 = 255;
 const DEFAULT_SOUND_PACKET_ATTENUATION: f32 = 1.0;
 
+const BONUS_FLASH_COLOR: [u8; 3] = [215, 186, 69];
+const BONUS_FLASH_PERCENT: i32 = 50;
+
 const CONSOLE_DIVIDER: &str = "\
 \n\n\
 \x1D\x1E\x1E\x1E\x1E\x1E\x1E\x1E\
@@ -89,6 +111,9 @@ const CONSOLE_DIVIDER: &str = "\
 \x1E\x1E\x1E\x1E\x1E\x1E\x1E\x1F\
 \n\n";
 
+/// A demo being recorded to disk.
+type DemoWriter = DemoRecorder<BufWriter<File>>;
+
 #[derive(Error, Debug)]
 pub enum ClientError {
     #[error("Connection rejected: {0}")]
@@ -128,6 +153,8 @@ pub enum ClientError {
     // TODO: wrap PlayError
     #[error("Failed to open audio output stream")]
     OutputStream,
+    #[error("Collision error: {0}")]
+    Bsp(#[from] BspError),
     #[error("Demo server error: {0}")]
     DemoServer(#[from] DemoServerError),
     #[error("Model error: {0}")]
@@ -195,10 +222,14 @@ enum ConnectionState {
 
 /// Possible targets that a client can be connected to.
 enum ConnectionKind {
-    /// A regular Quake server.
+    /// A regular Quake server, either remote or running in this process.
     Server {
-        /// The [`QSocket`](crate::net::QSocket) used to communicate with the server.
-        qsock: QSocket,
+        /// The [`NetSocket`](crate::common::net::NetSocket) used to communicate with the server.
+        sock: NetSocket,
+
+        /// The address the connection was requested from, or `None` for a
+        /// server in this process.
+        address: Option<SocketAddr>,
 
         /// The client's packet composition buffer.
         compose: Vec<u8>,
@@ -238,29 +269,29 @@ impl Connection {
                             ClientCmd::StringCmd {
                                 cmd: String::from("prespawn"),
                             }
-                            .serialize(compose)?;
+                            .serialize(compose, self.state.protocol)?;
                         }
                         ClientInfo => {
                             // TODO: fill in client info here
                             ClientCmd::StringCmd {
                                 cmd: format!("name \"{}\"\n", "UNNAMED"),
                             }
-                            .serialize(compose)?;
+                            .serialize(compose, self.state.protocol)?;
                             ClientCmd::StringCmd {
                                 cmd: format!("color {} {}", 0, 0),
                             }
-                            .serialize(compose)?;
+                            .serialize(compose, self.state.protocol)?;
                             // TODO: need default spawn parameters?
                             ClientCmd::StringCmd {
                                 cmd: format!("spawn {}", ""),
                             }
-                            .serialize(compose)?;
+                            .serialize(compose, self.state.protocol)?;
                         }
                         SignOnStage::Begin => {
                             ClientCmd::StringCmd {
                                 cmd: String::from("begin"),
                             }
-                            .serialize(compose)?;
+                            .serialize(compose, self.state.protocol)?;
                         }
                         SignOnStage::Done => {
                             debug!("SignOn complete");
@@ -301,13 +332,14 @@ impl Connection {
         cmds: &mut CmdRegistry,
         console: &mut Console,
         music_player: &mut MusicPlayer,
+        demo_recorder: &mut Option<DemoWriter>,
         kick_vars: KickVars,
     ) -> Result<ConnectionStatus, ClientError> {
         use ConnectionStatus::*;
 
-        let (msg, demo_view_angles, track_override) = match self.kind {
-            ConnectionKind::Server { ref mut qsock, .. } => {
-                let msg = qsock.recv_msg(match self.conn_state {
+        let (msg, demo_view_angles) = match self.kind {
+            ConnectionKind::Server { ref mut sock, .. } => {
+                let msg = sock.recv_msg(match self.conn_state {
                     // if we're in the game, don't block waiting for messages
                     ConnectionState::Connected(_) => BlockingMode::NonBlocking,
 
@@ -316,13 +348,16 @@ impl Connection {
                     ConnectionState::SignOn(_) => BlockingMode::Timeout(Duration::seconds(5)),
                 })?;
 
-                (msg, None, None)
+                (msg, None)
             }
 
             ConnectionKind::Demo(ref mut demo_srv) => {
                 // only get the next update once we've made it all the way to
                 // the previous one
-                if self.state.time >= self.state.msg_times[0] {
+                // timedemos take one message per frame regardless
+                if !demo_srv.paused()
+                    && (demo_srv.is_timedemo() || self.state.time >= self.state.msg_times[0])
+                {
                     let msg_view = match demo_srv.next() {
                         Some(v) => v,
                         None => {
@@ -332,19 +367,13 @@ impl Connection {
                         }
                     };
 
-                    let mut view_angles = msg_view.view_angles();
-                    // invert entity angles to get the camera direction right.
-                    // yaw is already inverted.
-                    view_angles.z = -view_angles.z;
-
                     // TODO: we shouldn't have to copy the message here
                     (
                         msg_view.message().to_owned(),
-                        Some(view_angles),
-                        demo_srv.track_override(),
+                        Some(demo_camera_angles(&msg_view)),
                     )
                 } else {
-                    (Vec::new(), None, demo_srv.track_override())
+                    (Vec::new(), None)
                 }
             }
         };
@@ -354,9 +383,69 @@ impl Connection {
             return Ok(Maintain);
         }
 
-        let mut reader = BufReader::new(msg.as_slice());
+        if let (ConnectionKind::Server { .. }, Some(recorder)) =
+            (&self.kind, demo_recorder.as_mut())
+        {
+            if let Err(e) = recorder.write_message(self.state.demo_view_angles(), &msg) {
+                console.println(format!("Demo recording failed: {}", e));
+                *demo_recorder = None;
+            }
+        }
+
+        let status = self.handle_server_msg(
+            &msg,
+            demo_view_angles,
+            false,
+            vfs,
+            gfx_state,
+            cmds,
+            console,
+            music_player,
+            kick_vars,
+        )?;
+
+        self.save_demo_snapshot();
+
+        Ok(status)
+    }
+
+    /// Applies the commands in a server message.
+    ///
+    /// If `replaying` is true, the message is being replayed to seek through a
+    /// demo and commands which don't affect the client state past the current
+    /// frame (sounds, effects and console text) are skipped.
+    fn handle_server_msg(
+        &mut self,
+        msg: &[u8],
+        demo_view_angles: Option<Vector3<Deg<f32>>>,
+        replaying: bool,
+        vfs: &Vfs,
+        gfx_state: &GraphicsState,
+        cmds: &mut CmdRegistry,
+        console: &mut Console,
+        music_player: &mut MusicPlayer,
+        kick_vars: KickVars,
+    ) -> Result<ConnectionStatus, ClientError> {
+        use ConnectionStatus::*;
+
+        let mut reader = BufReader::new(msg);
+
+        while let Some(cmd) = ServerCmd::deserialize(&mut reader, self.state.protocol)? {
+            if replaying
+                && matches!(
+                    cmd,
+                    ServerCmd::BonusFlash
+                        | ServerCmd::CenterPrint { .. }
+                        | ServerCmd::Particle { .. }
+                        | ServerCmd::Print { .. }
+                        | ServerCmd::Sound { .. }
+                        | ServerCmd::StuffText { .. }
+                        | ServerCmd::TempEntity { .. }
+                )
+            {
+                continue;
+            }
 
-        while let Some(cmd) = ServerCmd::deserialize(&mut reader)? {
             match cmd {
                 // TODO: have an error for this instead of panicking
                 // once all other commands have placeholder handlers, just error
@@ -365,11 +454,11 @@ impl Connection {
 
                 ServerCmd::NoOp => (),
 
-                ServerCmd::CdTrack { track, .. } => {
-                    music_player.play_track(match track_override {
-                        Some(t) => t as usize,
-                        None => track as usize,
-                    })?;
+                ServerCmd::CdTrack { track, loop_ } => {
+                    self.state.cd_track = Some((track, loop_));
+                    if !replaying {
+                        self.play_cd_track(music_player)?;
+                    }
                 }
 
                 ServerCmd::CenterPrint { text } => {
@@ -460,6 +549,7 @@ impl Connection {
 
                 ServerCmd::ServerInfo {
                     protocol_version,
+                    protocol_flags,
                     max_clients,
                     game_type,
                     message,
@@ -467,12 +557,13 @@ impl Connection {
                     sound_precache,
                 } => {
                     // check protocol version
-                    if protocol_version != net::PROTOCOL_VERSION as i32 {
-                        Err(ClientError::UnrecognizedProtocol(protocol_version))?;
-                    }
+                    let protocol = match ProtocolVersion::from_i32(protocol_version) {
+                        Some(p) => Protocol::new(p, protocol_flags),
+                        None => Err(ClientError::UnrecognizedProtocol(protocol_version))?,
+                    };
 
                     console.println(CONSOLE_DIVIDER);
-                    console.println(message);
+                    console.println(&message);
                     console.println(CONSOLE_DIVIDER);
 
                     let _server_info = ServerInfo {
@@ -483,7 +574,10 @@ impl Connection {
                     self.state = ClientState::from_server_info(
                         vfs,
                         self.state.mixer.stream(),
+                        protocol,
                         max_clients,
+                        game_type,
+                        message,
                         model_precache,
                         sound_precache,
                     )?;
@@ -494,8 +588,8 @@ impl Connection {
                         "bf",
                         Box::new(move |_| {
                             bonus_cshift.replace(ColorShift {
-                                dest_color: [215, 186, 69],
-                                percent: 50,
+                                dest_color: BONUS_FLASH_COLOR,
+                                percent: BONUS_FLASH_PERCENT,
                             });
                             String::new()
                         }),
@@ -503,6 +597,32 @@ impl Connection {
                     .unwrap();
                 }
 
+                ServerCmd::BonusFlash => {
+                    self.state.color_shifts[ColorShiftCode::Bonus as usize].replace(ColorShift {
+                        dest_color: BONUS_FLASH_COLOR,
+                        percent: BONUS_FLASH_PERCENT,
+                    });
+                }
+
+                ServerCmd::Fog {
+                    density,
+                    color,
+                    time,
+                } => {
+                    // TODO: render fog
+                    debug!(
+                        "fog: density {} color {:?} over {}s",
+                        density,
+                        color,
+                        time as f32 / 100.0
+                    );
+                }
+
+                ServerCmd::Skybox { name } => {
+                    // TODO: load skybox textures
+                    debug!("skybox: {}", name);
+                }
+
                 ServerCmd::SetAngle { angles } => self.state.set_view_angles(angles),
 
                 ServerCmd::SetView { ent_id } => {
@@ -561,6 +681,7 @@ impl Connection {
                     skin_id,
                     origin,
                     angles,
+                    alpha,
                 } => {
                     self.state.spawn_entities(
                         ent_id as usize,
@@ -572,6 +693,7 @@ impl Connection {
                             origin,
                             angles,
                             effects: EntityEffects::empty(),
+                            alpha: alpha.unwrap_or(net::ENTITY_ALPHA_DEFAULT),
                         },
                     )?;
                 }
@@ -583,6 +705,7 @@ impl Connection {
                     skin_id,
                     origin,
                     angles,
+                    alpha,
                 } => {
                     if self.state.static_entities.len() >= MAX_STATIC_ENTITIES {
                         Err(ClientError::TooManyStaticEntities)?;
@@ -597,6 +720,7 @@ impl Connection {
                             colormap,
                             skin_id: skin_id as usize,
                             effects: EntityEffects::empty(),
+                            alpha: alpha.unwrap_or(net::ENTITY_ALPHA_DEFAULT),
                         }));
                 }
 
@@ -614,6 +738,12 @@ impl Connection {
                         attenuation as f32 / 64.0,
                         &self.state.listener,
                     ));
+                    self.state.static_sound_info.push(StaticSoundInfo {
+                        origin,
+                        sound_id,
+                        volume,
+                        attenuation,
+                    });
                 }
 
                 ServerCmd::TempEntity { temp_entity } => self.state.spawn_temp_entity(&temp_entity),
@@ -711,14 +841,8 @@ impl Connection {
                 }
 
                 ServerCmd::Version { version } => {
-                    if version != net::PROTOCOL_VERSION as i32 {
-                        // TODO: handle with an error
-                        error!(
-                            "Incompatible server version: server's is {}, client's is {}",
-                            version,
-                            net::PROTOCOL_VERSION,
-                        );
-                        panic!("bad version number");
+                    if ProtocolVersion::from_i32(version).is_none() {
+                        Err(ClientError::UnrecognizedProtocol(version))?;
                     }
                 }
 
@@ -732,6 +856,124 @@ impl Connection {
         Ok(Maintain)
     }
 
+    /// Takes a snapshot of the client state if demo playback has reached the
+    /// next snapshot interval.
+    fn save_demo_snapshot(&mut self) {
+        if let (ConnectionKind::Demo(ref mut demo_srv), ConnectionState::Connected(_)) =
+            (&mut self.kind, &self.conn_state)
+        {
+            if demo_srv.wants_snapshot() {
+                demo_srv.save_snapshot(self.state.snapshot());
+            }
+        }
+    }
+
+    /// Moves demo playback to the given time.
+    ///
+    /// Playback continues from the latest point before `target` that can be
+    /// reached without replaying the demo: the current position, a snapshot or
+    /// the start of the level. The messages between there and `target` are then
+    /// replayed.
+    fn seek_demo(
+        &mut self,
+        target: Duration,
+        vfs: &Vfs,
+        gfx_state: &GraphicsState,
+        cmds: &mut CmdRegistry,
+        console: &mut Console,
+        music_player: &mut MusicPlayer,
+        kick_vars: KickVars,
+    ) -> Result<ConnectionStatus, ClientError> {
+        use ConnectionStatus::*;
+
+        let demo_srv = match self.kind {
+            ConnectionKind::Demo(ref mut d) => d,
+            _ => return Ok(Maintain),
+        };
+
+        let end = demo_srv.messages_until(target);
+        let current = demo_srv.message_id();
+        let level_start = demo_srv.level_start(end);
+        let same_level = demo_srv.level_start(current) == level_start
+            && matches!(self.conn_state, ConnectionState::Connected(_));
+
+        let from_current = same_level && current <= end;
+        match demo_srv.snapshot_before(end) {
+            Some((id, snapshot)) if same_level && (!from_current || id > current) => {
+                self.state.restore(snapshot);
+                demo_srv.set_message_id(id);
+            }
+
+            _ if from_current => (),
+
+            _ => {
+                // start the level over
+                self.state = ClientState::new(self.state.mixer.stream());
+                self.conn_state = ConnectionState::SignOn(SignOnStage::Prespawn);
+                demo_srv.set_message_id(level_start);
+            }
+        }
+
+        let cd_track = self.state.cd_track;
+
+        loop {
+            let (msg, view_angles) = match self.kind {
+                ConnectionKind::Demo(ref mut demo_srv) if demo_srv.message_id() < end => {
+                    let msg_view = demo_srv.next().unwrap();
+                    (msg_view.message().to_owned(), demo_camera_angles(&msg_view))
+                }
+
+                _ => break,
+            };
+
+            self.state.time = self.state.msg_times[0];
+            match self.handle_server_msg(
+                &msg,
+                Some(view_angles),
+                true,
+                vfs,
+                gfx_state,
+                cmds,
+                console,
+                music_player,
+                kick_vars,
+            )? {
+                Maintain => (),
+                s => return Ok(s),
+            }
+
+            self.save_demo_snapshot();
+        }
+
+        self.state.time = self.state.msg_times[0];
+        self.state.clear_effects();
+
+        // music changes were skipped while replaying
+        if self.state.cd_track != cd_track {
+            self.play_cd_track(music_player)?;
+        }
+
+        Ok(Maintain)
+    }
+
+    /// Plays the music track requested by the server, unless a demo overrides
+    /// it.
+    fn play_cd_track(&self, music_player: &mut MusicPlayer) -> Result<(), ClientError> {
+        let track_override = match self.kind {
+            ConnectionKind::Demo(ref demo_srv) => demo_srv.track_override(),
+            ConnectionKind::Server { .. } => None,
+        };
+
+        if let Some((track, _)) = self.state.cd_track {
+            music_player.play_track(match track_override {
+                Some(t) => t as usize,
+                None => track as usize,
+            })?;
+        }
+
+        Ok(())
+    }
+
     fn frame(
         &mut self,
         frame_time: Duration,
@@ -746,23 +988,116 @@ impl Connection {
         bob_vars: BobVars,
         cl_nolerp: f32,
         sv_gravity: f32,
+        cl_predict: f32,
+        physics_vars: PhysicsVars,
+        demo_recorder: &mut Option<DemoWriter>,
+        demo_speed: f32,
     ) -> Result<ConnectionStatus, ClientError> {
         debug!("frame time: {}ms", frame_time.num_milliseconds());
 
+        // demo playback runs on its own clock
+        let frame_time = match self.kind {
+            ConnectionKind::Demo(ref mut demo_srv) => {
+                demo_srv.set_speed(demo_speed);
+                demo_srv.update_controls(frame_time);
+
+                // benchmarks are timed once the level has loaded
+                if let (Some(timedemo), ConnectionState::Connected(_)) =
+                    (demo_srv.timedemo_mut(), &self.conn_state)
+                {
+                    timedemo.record_frame(frame_time);
+                }
+
+                if demo_srv.paused() {
+                    Duration::zero()
+                } else {
+                    engine::duration_from_f32(
+                        engine::duration_to_f32(frame_time) * demo_srv.speed(),
+                    )
+                }
+            }
+
+            ConnectionKind::Server { .. } => frame_time,
+        };
+
+        let seek_target = match self.kind {
+            ConnectionKind::Demo(ref mut demo_srv) => demo_srv.take_seek(),
+            ConnectionKind::Server { .. } => None,
+        };
+
+        if let Some(target) = seek_target {
+            match self.seek_demo(
+                target,
+                vfs,
+                gfx_state,
+                cmds,
+                console,
+                music_player,
+                kick_vars,
+            )? {
+                ConnectionStatus::Maintain => (),
+                s => return Ok(s),
+            }
+        }
+
         // do this _before_ parsing server messages so that we know when to
         // request the next message from the demo server.
         self.state.advance_time(frame_time);
-        match self.parse_server_msg(vfs, gfx_state, cmds, console, music_player, kick_vars)? {
-            ConnectionStatus::Maintain => (),
-            // if Disconnect or NextDemo, delegate up the chain
-            s => return Ok(s),
-        };
+
+        // demos played back quickly can need several messages per frame
+        for _ in 0..MAX_DEMO_MESSAGES_PER_FRAME {
+            match self.parse_server_msg(
+                vfs,
+                gfx_state,
+                cmds,
+                console,
+                music_player,
+                demo_recorder,
+                kick_vars,
+            )? {
+                ConnectionStatus::Maintain => (),
+                // if Disconnect or NextDemo, delegate up the chain
+                s => return Ok(s),
+            };
+
+            let behind = match self.kind {
+                ConnectionKind::Demo(ref demo_srv) => {
+                    !demo_srv.paused()
+                        && !demo_srv.is_timedemo()
+                        && self.state.time >= self.state.msg_times[0]
+                }
+                ConnectionKind::Server { .. } => false,
+            };
+
+            if !behind {
+                break;
+            }
+        }
+
+        // timedemos show each update as soon as it's read
+        if let ConnectionKind::Demo(ref demo_srv) = self.kind {
+            if demo_srv.is_timedemo() {
+                self.state.time = self.state.msg_times[0];
+            }
+        }
 
         self.state.update_interp_ratio(cl_nolerp);
 
         // interpolate entity data and spawn particle effects, lights
         self.state.update_entities()?;
 
+        // move the local player ahead of the last server update
+        match (&self.kind, &self.conn_state) {
+            (ConnectionKind::Server { sock, .. }, ConnectionState::Connected(_))
+                if cl_predict != 0.0 =>
+            {
+                let rtt = sock.rtt().unwrap_or_else(Duration::zero);
+                self.state.predict_player(&physics_vars, rtt, frame_time)?;
+            }
+
+            _ => self.state.clear_prediction(),
+        }
+
         // update temp entities (lightning, etc.)
         self.state.update_temp_entities()?;
 
@@ -775,13 +1110,14 @@ impl Connection {
             .update(self.state.time, frame_time, sv_gravity);
 
         if let ConnectionKind::Server {
-            ref mut qsock,
+            ref mut sock,
             ref mut compose,
+            ..
         } = self.kind
         {
             // respond to the server
-            if qsock.can_send() && !compose.is_empty() {
-                qsock.begin_send_msg(&compose)?;
+            if sock.can_send() && !compose.is_empty() {
+                sock.begin_send_msg(compose)?;
                 compose.clear();
             }
         }
@@ -816,8 +1152,11 @@ pub struct Client {
     output_stream_handle: OutputStreamHandle,
     music_player: Rc<RefCell<MusicPlayer>>,
     conn: Rc<RefCell<Option<Connection>>>,
+    server: Rc<RefCell<Option<NetServer>>>,
+    server_list: Rc<RefCell<ServerList>>,
     renderer: ClientRenderer,
     demo_queue: Rc<RefCell<VecDeque<String>>>,
+    demo_recorder: Rc<RefCell<Option<DemoWriter>>>,
 }
 
 impl Client {
@@ -827,10 +1166,13 @@ impl Client {
         cmds: Rc<RefCell<CmdRegistry>>,
         console: Rc<RefCell<Console>>,
         input: Rc<RefCell<Input>>,
+        server_list: Rc<RefCell<ServerList>>,
         gfx_state: &GraphicsState,
         menu: &Menu,
     ) -> Client {
         let conn = Rc::new(RefCell::new(None));
+        let server = Rc::new(RefCell::new(None));
+        let demo_recorder = Rc::new(RefCell::new(None));
 
         let (stream, handle) = match OutputStream::try_default() {
             Ok(o) => o,
@@ -860,7 +1202,40 @@ impl Client {
             .insert_or_replace("reconnect", cmd_reconnect(conn.clone(), input.clone()))
             .unwrap();
         cmds.borrow_mut()
-            .insert_or_replace("disconnect", cmd_disconnect(conn.clone(), input.clone()))
+            .insert_or_replace(
+                "disconnect",
+                cmd_disconnect(
+                    conn.clone(),
+                    server.clone(),
+                    demo_recorder.clone(),
+                    input.clone(),
+                ),
+            )
+            .unwrap();
+
+        cmds.borrow_mut()
+            .insert_or_replace("slist", cmd_slist(server_list.clone(), cvars.clone()))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace(
+                "rcon",
+                cmd_rcon(conn.clone(), server.clone(), cvars.clone()),
+            )
+            .unwrap();
+
+        // set up local server
+        cmds.borrow_mut()
+            .insert_or_replace(
+                "map",
+                cmd_map(
+                    conn.clone(),
+                    server.clone(),
+                    vfs.clone(),
+                    cvars.clone(),
+                    input.clone(),
+                    handle.clone(),
+                ),
+            )
             .unwrap();
 
         // set up demo playback
@@ -885,6 +1260,45 @@ impl Client {
             )
             .unwrap();
 
+        cmds.borrow_mut()
+            .insert_or_replace(
+                "timedemo",
+                cmd_timedemo(
+                    conn.clone(),
+                    vfs.clone(),
+                    input.clone(),
+                    handle.clone(),
+                    demo_queue.clone(),
+                ),
+            )
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("demo_pause", cmd_demo_pause(conn.clone()))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("demo_seek", cmd_demo_seek(conn.clone()))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("demo_skip", cmd_demo_skip(conn.clone()))
+            .unwrap();
+
+        // set up demo recording
+        cmds.borrow_mut()
+            .insert_or_replace(
+                "record",
+                cmd_record(
+                    conn.clone(),
+                    server.clone(),
+                    demo_recorder.clone(),
+                    vfs.clone(),
+                    console.clone(),
+                ),
+            )
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("stop", cmd_stop(conn.clone(), demo_recorder.clone()))
+            .unwrap();
+
         let music_player = Rc::new(RefCell::new(MusicPlayer::new(vfs.clone(), handle.clone())));
         cmds.borrow_mut()
             .insert_or_replace("music", cmd_music(music_player.clone()))
@@ -899,6 +1313,17 @@ impl Client {
             .insert_or_replace("music_resume", cmd_music_resume(music_player.clone()))
             .unwrap();
 
+        // set up filesystem queries
+        cmds.borrow_mut()
+            .insert_or_replace("path", cmd_path(vfs.clone()))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("dir", cmd_dir(vfs.clone()))
+            .unwrap();
+        cmds.borrow_mut()
+            .insert_or_replace("maps", cmd_maps(vfs.clone()))
+            .unwrap();
+
         Client {
             vfs,
             cvars,
@@ -909,12 +1334,23 @@ impl Client {
             output_stream_handle: handle,
             music_player,
             conn,
+            server,
+            server_list,
             renderer: ClientRenderer::new(gfx_state, menu),
             demo_queue,
+            demo_recorder,
         }
     }
 
+    /// Recreates the renderer after the graphics state has reloaded its game
+    /// data.
+    pub fn reload_renderer(&mut self, gfx_state: &GraphicsState, menu: &Menu) {
+        self.renderer = ClientRenderer::new(gfx_state, menu);
+    }
+
     pub fn disconnect(&mut self) {
+        self.stop_recording();
+        shutdown_server(&self.server);
         self.conn.replace(None);
         self.input.borrow_mut().set_focus(InputFocus::Console);
     }
@@ -926,11 +1362,51 @@ impl Client {
     ) -> Result<(), ClientError> {
         let cl_nolerp = self.cvar_value("cl_nolerp")?;
         let sv_gravity = self.cvar_value("sv_gravity")?;
+        let cl_predict = self.cvar_value("cl_predict")?;
+        let demo_speed = self.cvar_value("demo_speed")?;
+        let physics_vars = self.physics_vars()?;
         let idle_vars = self.idle_vars()?;
         let kick_vars = self.kick_vars()?;
         let roll_vars = self.roll_vars()?;
         let bob_vars = self.bob_vars()?;
 
+        // report the results of a server search once it's done
+        let slist_result = self.server_list.borrow_mut().poll();
+        match slist_result {
+            Ok(true) => {
+                let report = self.server_list.borrow().report();
+                self.console.borrow().println(report);
+            }
+            Ok(false) => (),
+            Err(e) => self
+                .console
+                .borrow()
+                .println(format!("Server search failed: {}", e)),
+        }
+
+        // run the local server, if there is one, before reading its messages
+        let server_result = match *self.server.borrow_mut() {
+            Some(ref mut server) => server.frame(frame_time),
+            None => Ok(()),
+        };
+
+        if let Err(e) = server_result {
+            self.console
+                .borrow_mut()
+                .println(format!("Server error: {}", e));
+            self.disconnect();
+            return Ok(());
+        }
+
+        // simulate network conditions on remote connections
+        if let Some(Connection {
+            kind: ConnectionKind::Server { ref mut sock, .. },
+            ..
+        }) = *self.conn.borrow_mut()
+        {
+            sock.set_impairment(Impairment::from_cvars(&self.cvars.borrow()));
+        }
+
         let status = match *self.conn.borrow_mut() {
             Some(ref mut conn) => conn.frame(
                 frame_time,
@@ -945,6 +1421,10 @@ impl Client {
                 bob_vars,
                 cl_nolerp,
                 sv_gravity,
+                cl_predict,
+                physics_vars,
+                &mut self.demo_recorder.borrow_mut(),
+                demo_speed,
             )?,
             None => Disconnect,
         };
@@ -955,7 +1435,13 @@ impl Client {
             _ => {
                 let conn = match status {
                     // if client is already disconnected, this is a no-op
-                    Disconnect => None,
+                    Disconnect => {
+                        self.stop_recording();
+                        None
+                    }
+
+                    // stop after a benchmark
+                    NextDemo if self.finish_timedemo() => None,
 
                     // get the next demo from the queue
                     NextDemo => match self.demo_queue.borrow_mut().pop_front() {
@@ -1006,6 +1492,59 @@ impl Client {
         Ok(())
     }
 
+    /// Returns whether a demo is being benchmarked.
+    pub fn timedemo_running(&self) -> bool {
+        matches!(
+            *self.conn.borrow(),
+            Some(Connection {
+                kind: ConnectionKind::Demo(ref demo_srv),
+                ..
+            }) if demo_srv.is_timedemo()
+        )
+    }
+
+    /// Reports the results of the current benchmark, if there is one.
+    ///
+    /// Returns whether there was a benchmark.
+    fn finish_timedemo(&self) -> bool {
+        let timedemo = match *self.conn.borrow_mut() {
+            Some(Connection {
+                kind: ConnectionKind::Demo(ref mut demo_srv),
+                ..
+            }) => demo_srv.take_timedemo(),
+            _ => None,
+        };
+
+        let timedemo = match timedemo {
+            Some(t) => t,
+            None => return false,
+        };
+
+        let console = self.console.borrow();
+        match timedemo.stats() {
+            Some(stats) => console.println(format!("{}", stats)),
+            None => console.println("No frames were rendered"),
+        }
+
+        if let Some(path) = timedemo.csv_path() {
+            match File::create(path).and_then(|f| timedemo.write_csv(BufWriter::new(f))) {
+                Ok(()) => console.println(format!("Wrote frame times to {}", path.display())),
+                Err(e) => console.println(format!("Couldn't write {}: {}", path.display(), e)),
+            }
+        }
+
+        true
+    }
+
+    /// Finishes the current demo recording, if there is one.
+    fn stop_recording(&self) {
+        if let Some(Err(e)) = stop_recording(&self.conn, &self.demo_recorder) {
+            self.console
+                .borrow()
+                .println(format!("Couldn't finish demo: {}", e));
+        }
+    }
+
     pub fn render(
         &mut self,
         gfx_state: &GraphicsState,
@@ -1052,18 +1591,24 @@ impl Client {
     ) -> Result<(), ClientError> {
         let move_vars = self.move_vars()?;
         let mouse_vars = self.mouse_vars()?;
+        let cl_predict = self.cvar_value("cl_predict")?;
+        let physics_vars = self.physics_vars()?;
 
         match *self.conn.borrow_mut() {
             Some(Connection {
                 ref mut state,
-                kind: ConnectionKind::Server { ref mut qsock, .. },
+                kind: ConnectionKind::Server { ref mut sock, .. },
                 ..
             }) => {
                 let move_cmd = state.handle_input(game_input, frame_time, move_vars, mouse_vars);
                 // TODO: arrayvec here
                 let mut msg = Vec::new();
-                move_cmd.serialize(&mut msg)?;
-                qsock.send_msg_unreliable(&msg)?;
+                move_cmd.serialize(&mut msg, state.protocol)?;
+                sock.send_msg_unreliable(&msg)?;
+
+                if cl_predict != 0.0 {
+                    state.predict_move(&move_cmd, frame_time, &physics_vars)?;
+                }
 
                 // clear mouse and impulse
                 game_input.refresh();
@@ -1088,6 +1633,19 @@ impl Client {
         })
     }
 
+    fn physics_vars(&self) -> Result<PhysicsVars, ClientError> {
+        Ok(PhysicsVars {
+            sv_gravity: self.cvar_value("sv_gravity")?,
+            sv_friction: self.cvar_value("sv_friction")?,
+            edgefriction: self.cvar_value("edgefriction")?,
+            sv_stopspeed: self.cvar_value("sv_stopspeed")?,
+            sv_maxspeed: self.cvar_value("sv_maxspeed")?,
+            sv_accelerate: self.cvar_value("sv_accelerate")?,
+            sv_maxvelocity: self.cvar_value("sv_maxvelocity")?,
+            sv_nostep: self.cvar_value("sv_nostep")? != 0.0,
+        })
+    }
+
     fn idle_vars(&self) -> Result<IdleVars, ClientError> {
         Ok(IdleVars {
             v_idlescale: self.cvar_value("v_idlescale")?,
@@ -1318,7 +1876,8 @@ where
     Ok(Connection {
         state: ClientState::new(stream),
         kind: ConnectionKind::Server {
-            qsock,
+            sock: qsock.into(),
+            address: Some(server_addr),
             compose: Vec::new(),
         },
         conn_state: ConnectionState::SignOn(SignOnStage::Prespawn),
@@ -1373,9 +1932,17 @@ fn cmd_reconnect(
 
 fn cmd_disconnect(
     conn: Rc<RefCell<Option<Connection>>>,
+    server: Rc<RefCell<Option<NetServer>>>,
+    demo_recorder: Rc<RefCell<Option<DemoWriter>>>,
     input: Rc<RefCell<Input>>,
 ) -> Box<dyn Fn(&[&str]) -> String> {
     Box::new(move |_| {
+        if let Some(Err(e)) = stop_recording(&conn, &demo_recorder) {
+            log::error!("Couldn't finish demo: {}", e);
+        }
+
+        shutdown_server(&server);
+
         let connected = conn.borrow().is_some();
         if connected {
             conn.replace(None);
@@ -1387,6 +1954,177 @@ fn cmd_disconnect(
     })
 }
 
+// implements the "rcon" command
+fn cmd_rcon(
+    conn: Rc<RefCell<Option<Connection>>>,
+    server: Rc<RefCell<Option<NetServer>>>,
+    cvars: Rc<RefCell<CvarRegistry>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        if args.is_empty() {
+            return "usage: rcon <command>".to_owned();
+        }
+
+        // the console parser strips quotes, so put them back where needed
+        let command = args
+            .iter()
+            .map(|a| {
+                if a.contains(char::is_whitespace) {
+                    format!("\"{}\"", a)
+                } else {
+                    a.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let rcon_server = cvars.borrow().get("rcon_server").unwrap_or_default();
+        let remote = if !rcon_server.is_empty() {
+            match slist::resolve_host(&rcon_server) {
+                Some(r) => r,
+                None => return format!("Couldn't resolve {}", rcon_server),
+            }
+        } else {
+            match *conn.borrow() {
+                Some(Connection {
+                    kind: ConnectionKind::Server { address, .. },
+                    ..
+                }) => match address {
+                    Some(a) => a,
+
+                    // no need for a password on our own server
+                    None => {
+                        return match *server.borrow_mut() {
+                            Some(ref mut s) => s.exec(&command).unwrap_or_else(|e| e.to_string()),
+                            None => "not connected".to_owned(),
+                        }
+                    }
+                },
+
+                _ => {
+                    return "You must be connected or set rcon_server to use rcon".to_owned();
+                }
+            }
+        };
+
+        let password = cvars.borrow().get("rcon_password").unwrap_or_default();
+        match rcon(remote, &password, &command) {
+            Ok(message) => message,
+            Err(e) => format!("rcon failed: {}", e),
+        }
+    })
+}
+
+/// Sends a console command to the server at `remote` and returns its output.
+fn rcon(remote: SocketAddr, password: &str, command: &str) -> Result<String, ClientError> {
+    let mut con_sock = ConnectSocket::bind("0.0.0.0:0")?;
+    con_sock.send_request(Request::rcon(password, command), remote)?;
+
+    let deadline = Duration::milliseconds(RCON_TIMEOUT_MS);
+    match con_sock.recv_response(Some(deadline))? {
+        Some((Response::Rcon(rcon), _)) => Ok(rcon.message),
+        Some(_) => Err(ClientError::InvalidConnectResponse),
+        None => Err(ClientError::NoResponse),
+    }
+}
+
+fn cmd_slist(
+    server_list: Rc<RefCell<ServerList>>,
+    cvars: Rc<RefCell<CvarRegistry>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |_| {
+        if server_list.borrow().searching() {
+            return "Already searching for servers".to_owned();
+        }
+
+        let mut output = String::new();
+        let mut hosts = Vec::new();
+        for host in cvars
+            .borrow()
+            .get("slist_hosts")
+            .unwrap_or_default()
+            .split_whitespace()
+        {
+            match slist::resolve_host(host) {
+                Some(addr) => hosts.push(addr),
+                None => output.push_str(&format!("Couldn't resolve {}\n", host)),
+            }
+        }
+
+        match server_list.borrow_mut().search(&hosts, true) {
+            Ok(()) => output.push_str("Looking for Quake servers..."),
+            Err(e) => output.push_str(&format!("{}", e)),
+        }
+
+        output
+    })
+}
+
+/// Disconnects all clients from the local server and stops it.
+fn shutdown_server(server: &RefCell<Option<NetServer>>) {
+    if let Some(mut s) = server.replace(None) {
+        s.shutdown();
+    }
+}
+
+fn cmd_map(
+    conn: Rc<RefCell<Option<Connection>>>,
+    server: Rc<RefCell<Option<NetServer>>>,
+    vfs: Rc<Vfs>,
+    cvars: Rc<RefCell<CvarRegistry>>,
+    input: Rc<RefCell<Input>>,
+    stream: OutputStreamHandle,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        if args.len() != 1 {
+            return "usage: map [MAPNAME]".to_owned();
+        }
+
+        // leave the current game before starting a new one
+        shutdown_server(&server);
+        conn.replace(None);
+
+        let max_clients = cvars
+            .borrow()
+            .get_value("maxplayers")
+            .map_or(1, |m| (m as usize).clamp(1, MAX_CLIENTS));
+
+        let session = match Session::load(vfs.clone(), cvars.clone(), max_clients, args[0]) {
+            Ok(s) => s,
+            Err(e) => return format!("Couldn't load {}: {}", args[0], e),
+        };
+
+        let mut new_server = NetServer::new(session, cvars.clone());
+
+        // only multiplayer games are visible on the network
+        if max_clients > 1 {
+            if let Err(e) = new_server.listen(("0.0.0.0", DEFAULT_PORT)) {
+                return format!("Couldn't listen on port {}: {}", DEFAULT_PORT, e);
+            }
+        }
+
+        let sock = match new_server.connect_loopback() {
+            Ok(Some(s)) => s,
+            Ok(None) => return "Server is full".to_owned(),
+            Err(e) => return format!("{}", e),
+        };
+
+        server.replace(Some(new_server));
+        conn.replace(Some(Connection {
+            state: ClientState::new(stream.clone()),
+            kind: ConnectionKind::Server {
+                sock: sock.into(),
+                address: None,
+                compose: Vec::new(),
+            },
+            conn_state: ConnectionState::SignOn(SignOnStage::Prespawn),
+        }));
+
+        input.borrow_mut().set_focus(InputFocus::Game);
+        String::new()
+    })
+}
+
 fn cmd_playdemo(
     conn: Rc<RefCell<Option<Connection>>>,
     vfs: Rc<Vfs>,
@@ -1460,6 +2198,249 @@ fn cmd_startdemos(
     })
 }
 
+// implements the "timedemo" command
+fn cmd_timedemo(
+    conn: Rc<RefCell<Option<Connection>>>,
+    vfs: Rc<Vfs>,
+    input: Rc<RefCell<Input>>,
+    stream: OutputStreamHandle,
+    demo_queue: Rc<RefCell<VecDeque<String>>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        let (demo, csv_name) = match args {
+            [demo] => (demo, None),
+            [demo, csv_name] => (demo, Some(csv_name)),
+            _ => return "usage: timedemo <demoname> [csv file]".to_owned(),
+        };
+
+        let csv_path = match csv_name {
+            Some(name) => match vfs.game_file_path(name) {
+                Ok(mut path) => {
+                    if path.extension().is_none() {
+                        path.set_extension("csv");
+                    }
+                    Some(path)
+                }
+                Err(e) => return format!("{}", e),
+            },
+
+            None => None,
+        };
+
+        let mut demo_file = match vfs.open(format!("{}.dem", demo)) {
+            Ok(f) => f,
+            Err(e) => return format!("{}", e),
+        };
+
+        let mut demo_server = match DemoServer::new(&mut demo_file) {
+            Ok(d) => d,
+            Err(e) => return format!("{}", e),
+        };
+        demo_server.start_timedemo(TimeDemo::new(csv_path));
+
+        // don't move on to other demos afterward
+        demo_queue.borrow_mut().clear();
+
+        conn.replace(Some(Connection {
+            state: ClientState::new(stream.clone()),
+            kind: ConnectionKind::Demo(demo_server),
+            conn_state: ConnectionState::SignOn(SignOnStage::Prespawn),
+        }));
+
+        input.borrow_mut().set_focus(InputFocus::Game);
+        String::new()
+    })
+}
+
+/// Runs `f` on the demo server if a demo is playing.
+fn with_demo_server<F>(conn: &RefCell<Option<Connection>>, f: F) -> String
+where
+    F: FnOnce(&mut DemoServer) -> String,
+{
+    match *conn.borrow_mut() {
+        Some(Connection {
+            kind: ConnectionKind::Demo(ref mut demo_srv),
+            ..
+        }) => f(demo_srv),
+        _ => "No demo is playing".to_owned(),
+    }
+}
+
+// implements the "demo_pause" command
+fn cmd_demo_pause(conn: Rc<RefCell<Option<Connection>>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |_| {
+        with_demo_server(&conn, |demo_srv| {
+            demo_srv.set_paused(!demo_srv.paused());
+            String::new()
+        })
+    })
+}
+
+// implements the "demo_seek" command
+fn cmd_demo_seek(conn: Rc<RefCell<Option<Connection>>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        let time = match args {
+            [t] => match demo::parse_time(t) {
+                Some(t) => t,
+                None => return format!("Invalid time: {}", t),
+            },
+            _ => return "usage: demo_seek <seconds | minutes:seconds>".to_owned(),
+        };
+
+        with_demo_server(&conn, |demo_srv| {
+            demo_srv.seek(time);
+            String::new()
+        })
+    })
+}
+
+// implements the "demo_skip" command
+fn cmd_demo_skip(conn: Rc<RefCell<Option<Connection>>>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        let offset = match args {
+            [t] => match t.strip_prefix('-') {
+                Some(t) => demo::parse_time(t).map(|t| -t),
+                None => demo::parse_time(t.strip_prefix('+').unwrap_or(t)),
+            },
+            _ => return "usage: demo_skip <seconds>".to_owned(),
+        };
+
+        let offset = match offset {
+            Some(o) => o,
+            None => return format!("Invalid time: {}", args[0]),
+        };
+
+        with_demo_server(&conn, |demo_srv| {
+            demo_srv.seek(demo_srv.time() + offset);
+            String::new()
+        })
+    })
+}
+
+/// Returns the camera angles for a demo message.
+fn demo_camera_angles(msg_view: &DemoMessageView) -> Vector3<Deg<f32>> {
+    let mut view_angles = msg_view.view_angles();
+    // invert entity angles to get the camera direction right.
+    // yaw is already inverted.
+    view_angles.z = -view_angles.z;
+    view_angles
+}
+
+/// Finishes the current demo recording, if there is one.
+///
+/// Returns `None` if no demo was being recorded.
+fn stop_recording(
+    conn: &RefCell<Option<Connection>>,
+    demo_recorder: &RefCell<Option<DemoWriter>>,
+) -> Option<Result<(), NetError>> {
+    let recorder = demo_recorder.replace(None)?;
+    let view_angles = match *conn.borrow() {
+        Some(ref c) => c.state.demo_view_angles(),
+        None => Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+    };
+
+    Some(recorder.finish(view_angles).map(|_| ()))
+}
+
+// implements the "record" command
+fn cmd_record(
+    conn: Rc<RefCell<Option<Connection>>>,
+    server: Rc<RefCell<Option<NetServer>>>,
+    demo_recorder: Rc<RefCell<Option<DemoWriter>>>,
+    vfs: Rc<Vfs>,
+    console: Rc<RefCell<Console>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        if args.is_empty() || args.len() > 3 {
+            return "usage: record <demoname> [<map> [cd track]]".to_owned();
+        }
+
+        let track_override = match args.get(2) {
+            Some(t) => match t.parse::<u32>() {
+                Ok(t) => Some(t),
+                Err(_) => return format!("Invalid cd track: {}", t),
+            },
+            None => None,
+        };
+
+        if args.len() == 1 {
+            // the sign-on can only be reconstructed from a complete client state
+            match *conn.borrow() {
+                Some(Connection {
+                    kind: ConnectionKind::Demo(_),
+                    ..
+                }) => return "Can't record during demo playback".to_owned(),
+                Some(Connection {
+                    conn_state: ConnectionState::SignOn(_),
+                    ..
+                }) => return "Can't record during sign-on".to_owned(),
+                _ => (),
+            }
+        }
+
+        let mut path = match vfs.game_file_path(args[0]) {
+            Ok(p) => p,
+            Err(e) => return format!("{}", e),
+        };
+        if path.extension().is_none() {
+            path.set_extension("dem");
+        }
+
+        if let Some(Err(e)) = stop_recording(&conn, &demo_recorder) {
+            console
+                .borrow()
+                .println(format!("Couldn't finish demo: {}", e));
+        }
+
+        if let Some(map) = args.get(1) {
+            // record from the start of the new level
+            shutdown_server(&server);
+            conn.replace(None);
+            console.borrow().stuff_text(format!("map {}", map));
+        }
+
+        let file = match File::create(&path) {
+            Ok(f) => f,
+            Err(e) => return format!("Couldn't create {}: {}", path.display(), e),
+        };
+
+        let mut recorder = match DemoRecorder::new(BufWriter::new(file), track_override) {
+            Ok(r) => r,
+            Err(e) => return format!("Couldn't write {}: {}", path.display(), e),
+        };
+
+        // if we're already in the game, write the sign-on we missed
+        if let Some(Connection {
+            ref state,
+            kind: ConnectionKind::Server { .. },
+            conn_state: ConnectionState::Connected(_),
+        }) = *conn.borrow()
+        {
+            let view_angles = state.demo_view_angles();
+            for cmds in state.signon_cmds().iter() {
+                if let Err(e) = recorder.write_cmds(view_angles, cmds, state.protocol) {
+                    return format!("Couldn't write {}: {}", path.display(), e);
+                }
+            }
+        }
+
+        demo_recorder.replace(Some(recorder));
+        format!("recording to {}", path.display())
+    })
+}
+
+// implements the "stop" command
+fn cmd_stop(
+    conn: Rc<RefCell<Option<Connection>>>,
+    demo_recorder: Rc<RefCell<Option<DemoWriter>>>,
+) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |_| match stop_recording(&conn, &demo_recorder) {
+        Some(Ok(())) => "Completed demo".to_owned(),
+        Some(Err(e)) => format!("Couldn't finish demo: {}", e),
+        None => "Not recording a demo".to_owned(),
+    })
+}
+
 fn cmd_music(music_player: Rc<RefCell<MusicPlayer>>) -> Box<dyn Fn(&[&str]) -> String> {
     Box::new(move |args| {
         if args.len() != 1 {
@@ -1497,3 +2478,53 @@ fn cmd_music_resume(music_player: Rc<RefCell<MusicPlayer>>) -> Box<dyn Fn(&[&str
         String::new()
     })
 }
+
+fn cmd_path(vfs: Rc<Vfs>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |_| {
+        let mut output = "Current search path:".to_owned();
+        for source in vfs.search_path() {
+            match source.file_count {
+                Some(count) => {
+                    output.push_str(&format!("\n{} ({} files)", source.path.display(), count))
+                }
+                None => output.push_str(&format!("\n{}", source.path.display())),
+            }
+        }
+
+        output
+    })
+}
+
+fn cmd_dir(vfs: Rc<Vfs>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |args| {
+        let pattern = match args {
+            [] => "*",
+            [pattern] => pattern,
+            _ => return "usage: dir [pattern]".to_owned(),
+        };
+
+        let files = match vfs.list(pattern) {
+            Ok(f) => f,
+            Err(e) => return format!("{}", e),
+        };
+
+        let mut output = String::new();
+        for file in files.iter() {
+            output.push_str(&format!("{} ({})\n", file.path, file.source.display()));
+        }
+        output.push_str(&format!("{} files", files.len()));
+
+        output
+    })
+}
+
+fn cmd_maps(vfs: Rc<Vfs>) -> Box<dyn Fn(&[&str]) -> String> {
+    Box::new(move |_| match vfs.list("maps/*.bsp") {
+        Ok(maps) => maps
+            .iter()
+            .map(|m| m.path.trim_start_matches("maps/").trim_end_matches(".bsp"))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Err(e) => format!("{}", e),
+    })
+}
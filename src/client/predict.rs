@@ -0,0 +1,300 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Client-side movement prediction.
+//!
+//! The NetQuake protocol doesn't tell the client which of its move commands
+//! the server has processed. A command is assumed to have been processed once
+//! the server's clock has passed the time the command was sent (as reported in
+//! the command itself) by at least the round-trip time of the connection.
+//! Commands which haven't been processed yet are replayed on top of each
+//! server update.
+
+use std::collections::VecDeque;
+
+use crate::common::{
+    bsp::BspError,
+    engine,
+    pmove::{self, MoveInput, PhysicsVars, PlayerHulls, PlayerState},
+};
+
+use cgmath::{InnerSpace as _, Vector3, Zero as _};
+use chrono::Duration;
+
+/// The most moves kept for replay. Older moves are assumed to have been
+/// processed.
+const MAX_PENDING_MOVES: usize = 128;
+
+/// How quickly prediction errors are smoothed out, per second.
+const ERROR_DECAY_RATE: f32 = 10.0;
+
+/// Prediction errors larger than this are treated as teleports and corrected
+/// immediately.
+const MAX_ERROR: f32 = 64.0;
+
+/// A move command which has been sent to the server.
+#[derive(Clone, Debug)]
+struct PendingMove {
+    send_time: Duration,
+    frame_time: Duration,
+    input: MoveInput,
+}
+
+#[derive(Debug)]
+pub struct Prediction {
+    hulls: Option<PlayerHulls>,
+    pending: VecDeque<PendingMove>,
+
+    // message time of the server update the prediction is based on
+    base_time: Option<Duration>,
+
+    // the player state after all pending moves
+    predicted: Option<PlayerState>,
+
+    // offset between the predicted and displayed origin
+    error: Vector3<f32>,
+}
+
+impl Prediction {
+    /// Creates a predictor which moves the player through the given hulls.
+    ///
+    /// If `hulls` is `None`, no prediction is done.
+    pub fn new(hulls: Option<PlayerHulls>) -> Prediction {
+        Prediction {
+            hulls,
+            pending: VecDeque::new(),
+            base_time: None,
+            predicted: None,
+            error: Vector3::zero(),
+        }
+    }
+
+    /// Discards all pending moves and the predicted state.
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.base_time = None;
+        self.predicted = None;
+        self.error = Vector3::zero();
+    }
+
+    /// Records a move sent to the server and applies it to the predicted state.
+    ///
+    /// Moves are only recorded once a server update has been received.
+    pub fn push_move(
+        &mut self,
+        vars: &PhysicsVars,
+        send_time: Duration,
+        frame_time: Duration,
+        input: MoveInput,
+    ) -> Result<(), BspError> {
+        let (hulls, predicted) = match (self.hulls.as_ref(), self.predicted.as_mut()) {
+            (Some(h), Some(p)) => (h, p),
+            _ => return Ok(()),
+        };
+
+        pmove::player_move(hulls, vars, predicted, &input, frame_time)?;
+
+        if self.pending.len() == MAX_PENDING_MOVES {
+            self.pending.pop_front();
+        }
+
+        self.pending.push_back(PendingMove {
+            send_time,
+            frame_time,
+            input,
+        });
+
+        Ok(())
+    }
+
+    /// Predicts the player state from a new server update.
+    ///
+    /// `server` is the player state reported in the update with time
+    /// `msg_time`, and `rtt` is the round-trip time to the server. If the
+    /// update has already been seen, this does nothing.
+    pub fn update(
+        &mut self,
+        vars: &PhysicsVars,
+        server: PlayerState,
+        msg_time: Duration,
+        rtt: Duration,
+    ) -> Result<(), BspError> {
+        let hulls = match self.hulls.as_ref() {
+            Some(h) => h,
+            None => return Ok(()),
+        };
+
+        if self.base_time == Some(msg_time) {
+            return Ok(());
+        }
+        self.base_time = Some(msg_time);
+
+        // drop the moves the server has processed
+        while let Some(m) = self.pending.front() {
+            if m.send_time + rtt > msg_time {
+                break;
+            }
+
+            self.pending.pop_front();
+        }
+
+        let mut state = server;
+        if let Some(ref predicted) = self.predicted {
+            // jumping is edge-triggered, so carry over the button state
+            state.jump_released = predicted.jump_released;
+        }
+
+        for m in self.pending.iter() {
+            pmove::player_move(hulls, vars, &mut state, &m.input, m.frame_time)?;
+        }
+
+        // whatever the old prediction got wrong is smoothed out over the next
+        // few frames instead of snapping the view
+        if let Some(ref old) = self.predicted {
+            self.error += old.origin - state.origin;
+            if self.error.magnitude2() > MAX_ERROR * MAX_ERROR {
+                self.error = Vector3::zero();
+            }
+        }
+
+        self.predicted = Some(state);
+
+        Ok(())
+    }
+
+    /// Decays the prediction error by one frame.
+    pub fn decay_error(&mut self, frame_time: Duration) {
+        let decay = (1.0 - ERROR_DECAY_RATE * engine::duration_to_f32(frame_time)).max(0.0);
+        self.error *= decay;
+    }
+
+    /// Returns the predicted player state, if there is one.
+    pub fn predicted(&self) -> Option<&PlayerState> {
+        self.predicted.as_ref()
+    }
+
+    /// Returns the origin at which the player should be displayed.
+    pub fn origin(&self) -> Option<Vector3<f32>> {
+        self.predicted.as_ref().map(|p| p.origin + self.error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::bsp::BspCollisionHull;
+    use cgmath::Deg;
+
+    fn prediction() -> Prediction {
+        let floor = || {
+            BspCollisionHull::for_bounds(
+                Vector3::new(-4096.0, -4096.0, -4096.0),
+                Vector3::new(4096.0, 4096.0, 0.0),
+            )
+            .unwrap()
+        };
+
+        Prediction::new(Some(PlayerHulls::new(floor(), floor())))
+    }
+
+    fn walk() -> MoveInput {
+        MoveInput {
+            angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+            forward: 400.0,
+            side: 0.0,
+            up: 0.0,
+            jump: false,
+        }
+    }
+
+    fn standing() -> PlayerState {
+        PlayerState::new(Vector3::new(0.0, 0.0, 0.5), Vector3::zero(), true)
+    }
+
+    #[test]
+    fn test_moves_ignored_before_first_update() {
+        let vars = PhysicsVars::default();
+        let mut pred = prediction();
+        let frame = Duration::milliseconds(10);
+
+        pred.push_move(&vars, Duration::zero(), frame, walk())
+            .unwrap();
+        assert!(pred.origin().is_none());
+        assert!(pred.pending.is_empty());
+    }
+
+    #[test]
+    fn test_unacknowledged_moves_replayed() {
+        let vars = PhysicsVars::default();
+        let mut pred = prediction();
+        let frame = Duration::milliseconds(10);
+        let rtt = Duration::milliseconds(200);
+
+        pred.update(&vars, standing(), Duration::seconds(1), rtt)
+            .unwrap();
+        for _ in 0..10 {
+            pred.push_move(&vars, Duration::seconds(1), frame, walk())
+                .unwrap();
+        }
+        let moved = pred.origin().unwrap();
+        assert!(moved.x > 0.0);
+
+        // the server hasn't seen the moves yet, so they're replayed on top of
+        // its update and the prediction doesn't change
+        pred.update(&vars, standing(), Duration::milliseconds(1100), rtt)
+            .unwrap();
+        assert_eq!(pred.pending.len(), 10);
+        assert!((pred.origin().unwrap() - moved).magnitude() < 0.001);
+
+        // once the moves are acknowledged, the server's state is used as is
+        let server = PlayerState::new(Vector3::new(20.0, 0.0, 0.5), Vector3::zero(), true);
+        pred.update(&vars, server, Duration::milliseconds(1200), rtt)
+            .unwrap();
+        assert!(pred.pending.is_empty());
+        assert_eq!(pred.predicted().unwrap().origin.x, 20.0);
+    }
+
+    #[test]
+    fn test_error_smoothed() {
+        let vars = PhysicsVars::default();
+        let mut pred = prediction();
+        let frame = Duration::milliseconds(10);
+
+        pred.update(&vars, standing(), Duration::seconds(1), Duration::zero())
+            .unwrap();
+
+        // the server disagrees with the prediction by 8 units
+        let server = PlayerState::new(Vector3::new(8.0, 0.0, 0.5), Vector3::zero(), true);
+        pred.update(&vars, server, Duration::seconds(2), Duration::zero())
+            .unwrap();
+        assert_eq!(pred.origin().unwrap().x, 0.0);
+
+        for _ in 0..100 {
+            pred.decay_error(frame);
+        }
+        assert!((pred.origin().unwrap().x - 8.0).abs() < 0.001);
+
+        // large errors are corrected immediately
+        let server = PlayerState::new(Vector3::new(512.0, 0.0, 0.5), Vector3::zero(), true);
+        pred.update(&vars, server, Duration::seconds(3), Duration::zero())
+            .unwrap();
+        assert_eq!(pred.origin().unwrap().x, 512.0);
+    }
+}
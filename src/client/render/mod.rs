@@ -49,6 +49,7 @@ mod cvars;
 mod error;
 mod palette;
 mod pipeline;
+mod replacement;
 mod target;
 mod ui;
 mod uniform;
@@ -61,7 +62,7 @@ pub use palette::Palette;
 pub use pipeline::Pipeline;
 pub use postprocess::PostProcessRenderer;
 pub use target::{RenderTarget, RenderTargetResolve, SwapChainTarget};
-pub use ui::{hud::HudState, UiOverlay, UiRenderer, UiState};
+pub use ui::{demo::DemoBarState, hud::HudState, UiOverlay, UiRenderer, UiState};
 pub use world::{
     deferred::{DeferredRenderer, DeferredUniforms, PointLight},
     Camera, WorldRenderer,
@@ -113,11 +114,12 @@ use failure::Error;
 const DEPTH_ATTACHMENT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 pub const DIFFUSE_ATTACHMENT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Bgra8Unorm;
 const NORMAL_ATTACHMENT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
-const LIGHT_ATTACHMENT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+// light levels can exceed 1 and differ per color channel
+const LIGHT_ATTACHMENT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
 
 const DIFFUSE_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
 const FULLBRIGHT_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
-const LIGHTMAP_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+const LIGHTMAP_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
 
 /// Create a `wgpu::TextureDescriptor` appropriate for the provided texture data.
 pub fn texture_descriptor(
@@ -219,7 +221,7 @@ impl<'a> TextureData<'a> {
         (match self {
             TextureData::Diffuse(_) => size_of::<[u8; 4]>(),
             TextureData::Fullbright(_) => size_of::<u8>(),
-            TextureData::Lightmap(_) => size_of::<u8>(),
+            TextureData::Lightmap(_) => size_of::<[u8; 4]>(),
         }) as u32
     }
 
@@ -424,7 +426,7 @@ impl GraphicsState {
             1,
             1,
             &TextureData::Lightmap(LightmapData {
-                lightmap: (&[0xFF][..]).into(),
+                lightmap: (&[0xFF; 4][..]).into(),
             }),
         );
         let default_lightmap_view = default_lightmap.create_view(&Default::default());
@@ -510,6 +512,25 @@ impl GraphicsState {
         }
     }
 
+    /// Reloads the palette and `gfx.wad` from the virtual filesystem.
+    ///
+    /// This must be called when the game directory changes, since a mod may
+    /// replace either of them. Renderers which use their contents must be
+    /// recreated afterward.
+    pub fn reload_game_data(&mut self) -> Result<(), Error> {
+        self.gfx_wad = Wad::load(self.vfs.open("gfx.wad")?)?;
+        self.palette = Palette::load(&self.vfs, "gfx/palette.lmp");
+        self.particle_pipeline = ParticlePipeline::new(
+            &self.device,
+            &self.queue,
+            &mut self.compiler.borrow_mut(),
+            self.sample_count.get(),
+            &self.palette,
+        );
+
+        Ok(())
+    }
+
     /// Rebuild all render pipelines using the new sample count.
     ///
     /// This must be called when the sample count of the render target(s) changes or the program
@@ -794,6 +815,7 @@ impl ClientRenderer {
         let ui_state = match conn {
             Some(Connection {
                 state: ref cl_state,
+                ref kind,
                 ..
             }) => UiState::InGame {
                 hud: match cl_state.intermission() {
@@ -814,6 +836,18 @@ impl ClientRenderer {
                     },
                 },
 
+                demo_bar: match kind {
+                    ConnectionKind::Demo(demo_srv) if demo_srv.controls_visible() => {
+                        Some(DemoBarState {
+                            time: demo_srv.time(),
+                            duration: demo_srv.duration(),
+                            paused: demo_srv.paused(),
+                            speed: demo_srv.speed(),
+                        })
+                    }
+                    _ => None,
+                },
+
                 overlay: match focus {
                     InputFocus::Game => None,
                     InputFocus::Console => Some(UiOverlay::Console(console)),
@@ -79,4 +79,34 @@ impl Palette {
             },
         )
     }
+
+    /// Translates a set of indices using a palette which came with a Half-Life texture.
+    ///
+    /// Half-Life palettes have no fullbright colors, and index 255 is only transparent in textures
+    /// whose names begin with `{`.
+    pub fn translate_embedded(
+        &self,
+        indices: &[u8],
+        transparent: bool,
+    ) -> (DiffuseData<'static>, FullbrightData<'static>) {
+        let mut rgba = Vec::with_capacity(indices.len() * 4);
+        for index in indices {
+            match *index {
+                0xFF if transparent => rgba.extend_from_slice(&[0; 4]),
+                i => {
+                    rgba.extend_from_slice(&self.rgb[i as usize]);
+                    rgba.push(0xFF);
+                }
+            }
+        }
+
+        (
+            DiffuseData {
+                rgba: Cow::Owned(rgba),
+            },
+            FullbrightData {
+                fullbright: Cow::Owned(vec![0; indices.len()]),
+            },
+        )
+    }
 }
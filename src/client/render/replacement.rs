@@ -0,0 +1,435 @@
+// Copyright © 2020 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! High-resolution replacements for the textures stored in BSP and MDL files.
+//!
+//! A replacement is a PNG or TGA image in the virtual filesystem, e.g. `textures/wbrick1_5.png`
+//! for a brush texture or `progs/player_0.png` for the first skin of `progs/player.mdl`. An image
+//! of the same name with `_glow` or `_luma` appended marks the parts of the texture which are drawn
+//! at full brightness.
+
+use std::{
+    borrow::Cow,
+    io::{self, Read},
+};
+
+use crate::{
+    client::render::{DiffuseData, FullbrightData},
+    common::vfs::{Vfs, VfsError},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use thiserror::Error;
+
+const IMAGE_EXTENSIONS: [&str; 2] = ["png", "tga"];
+const FULLBRIGHT_SUFFIXES: [&str; 2] = ["_glow", "_luma"];
+
+// the largest texture guaranteed to be supported by wgpu
+const MAX_IMAGE_DIMENSION: u32 = 8192;
+
+const TGA_HEADER_SIZE: usize = 18;
+const TGA_TYPE_TRUECOLOR: u8 = 2;
+const TGA_TYPE_GRAYSCALE: u8 = 3;
+const TGA_TYPE_RLE_TRUECOLOR: u8 = 10;
+const TGA_TYPE_RLE_GRAYSCALE: u8 = 11;
+const TGA_DESCRIPTOR_TOP_TO_BOTTOM: u8 = 0x20;
+
+#[derive(Error, Debug)]
+pub enum ReplacementError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Couldn't open image: {0}")]
+    Vfs(#[from] VfsError),
+    #[error("Invalid PNG image: {0}")]
+    Png(#[from] png::DecodingError),
+    #[error("Unsupported TGA image: type {image_type}, {depth} bits per pixel")]
+    UnsupportedTga { image_type: u8, depth: u8 },
+    #[error("TGA image data is too long")]
+    TgaOverflow,
+    #[error("Invalid image size: {width}x{height}")]
+    BadSize { width: u32, height: u32 },
+}
+
+/// An image decoded to 8-bit RGBA.
+#[derive(Debug)]
+pub struct RgbaImage {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+impl RgbaImage {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+
+    /// Decodes a PNG image.
+    pub fn from_png<R>(reader: R) -> Result<RgbaImage, ReplacementError>
+    where
+        R: Read,
+    {
+        let mut decoder = png::Decoder::new(reader);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data)?;
+        data.truncate(info.buffer_size());
+
+        let rgba = match info.color_type {
+            png::ColorType::Rgba => data,
+            png::ColorType::Rgb => data
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 0xFF])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => data
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => data.iter().flat_map(|l| [*l, *l, *l, 0xFF]).collect(),
+            // expanded to RGB by the decoder
+            png::ColorType::Indexed => unreachable!(),
+        };
+
+        Ok(RgbaImage {
+            width: info.width,
+            height: info.height,
+            rgba,
+        })
+    }
+
+    /// Decodes a truecolor or grayscale TGA image, which may be run-length encoded.
+    pub fn from_tga<R>(mut reader: R) -> Result<RgbaImage, ReplacementError>
+    where
+        R: Read,
+    {
+        let mut header = [0; TGA_HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        let mut fields = &header[..];
+        let id_len = fields.read_u8()?;
+        let color_map_type = fields.read_u8()?;
+        let image_type = fields.read_u8()?;
+        let _color_map_start = fields.read_u16::<LittleEndian>()?;
+        let color_map_len = fields.read_u16::<LittleEndian>()?;
+        let color_map_depth = fields.read_u8()?;
+        let _origin = [
+            fields.read_u16::<LittleEndian>()?,
+            fields.read_u16::<LittleEndian>()?,
+        ];
+        let width = fields.read_u16::<LittleEndian>()? as u32;
+        let height = fields.read_u16::<LittleEndian>()? as u32;
+        let depth = fields.read_u8()?;
+        let descriptor = fields.read_u8()?;
+
+        let bytes_per_pixel = match (image_type, depth) {
+            (TGA_TYPE_TRUECOLOR | TGA_TYPE_RLE_TRUECOLOR, 24) => 3,
+            (TGA_TYPE_TRUECOLOR | TGA_TYPE_RLE_TRUECOLOR, 32) => 4,
+            (TGA_TYPE_GRAYSCALE | TGA_TYPE_RLE_GRAYSCALE, 8) => 1,
+            _ => return Err(ReplacementError::UnsupportedTga { image_type, depth }),
+        };
+
+        if width == 0 || height == 0 || width > MAX_IMAGE_DIMENSION || height > MAX_IMAGE_DIMENSION
+        {
+            return Err(ReplacementError::BadSize { width, height });
+        }
+
+        // skip the image ID and any color map, which truecolor images don't use
+        let mut skip = id_len as u64;
+        if color_map_type != 0 {
+            skip += color_map_len as u64 * (color_map_depth as u64).div_ceil(8);
+        }
+        io::copy(&mut (&mut reader).take(skip), &mut io::sink())?;
+
+        // the buffer grows as pixels are read so a truncated file can't claim a huge allocation
+        let data_len = (width * height) as usize * bytes_per_pixel;
+        let mut pixels = Vec::new();
+        match image_type {
+            TGA_TYPE_RLE_TRUECOLOR | TGA_TYPE_RLE_GRAYSCALE => {
+                let mut pixel = [0; 4];
+                while pixels.len() < data_len {
+                    let packet = reader.read_u8()?;
+                    let count = (packet & 0x7F) as usize + 1;
+                    if pixels.len() + count * bytes_per_pixel > data_len {
+                        return Err(ReplacementError::TgaOverflow);
+                    }
+
+                    if packet & 0x80 != 0 {
+                        // one pixel repeated
+                        reader.read_exact(&mut pixel[..bytes_per_pixel])?;
+                        for _ in 0..count {
+                            pixels.extend_from_slice(&pixel[..bytes_per_pixel]);
+                        }
+                    } else {
+                        // a run of raw pixels
+                        let start = pixels.len();
+                        pixels.resize(start + count * bytes_per_pixel, 0);
+                        reader.read_exact(&mut pixels[start..])?;
+                    }
+                }
+            }
+
+            _ => {
+                (&mut reader)
+                    .take(data_len as u64)
+                    .read_to_end(&mut pixels)?;
+                if pixels.len() < data_len {
+                    Err(io::Error::from(io::ErrorKind::UnexpectedEof))?;
+                }
+            }
+        }
+
+        let mut rgba: Vec<u8> = pixels
+            .chunks_exact(bytes_per_pixel)
+            .flat_map(|p| match *p {
+                [b, g, r, a] => [r, g, b, a],
+                [b, g, r] => [r, g, b, 0xFF],
+                [l] => [l, l, l, 0xFF],
+                _ => unreachable!(),
+            })
+            .collect();
+
+        // rows are stored bottom to top unless the descriptor says otherwise
+        if descriptor & TGA_DESCRIPTOR_TOP_TO_BOTTOM == 0 {
+            let row_len = width as usize * 4;
+            let rows: Vec<&[u8]> = rgba.chunks_exact(row_len).rev().collect();
+            rgba = rows.concat();
+        }
+
+        Ok(RgbaImage {
+            width,
+            height,
+            rgba,
+        })
+    }
+
+    /// Loads the first of `<path>.png` and `<path>.tga` which exists.
+    ///
+    /// Returns `Ok(None)` if neither exists.
+    pub fn load(vfs: &Vfs, path: &str) -> Result<Option<RgbaImage>, ReplacementError> {
+        for ext in IMAGE_EXTENSIONS {
+            let full_path = format!("{}.{}", path, ext);
+            let file = match vfs.open(&full_path) {
+                Ok(f) => f,
+                Err(VfsError::NoSuchFile(_)) => continue,
+                Err(e) => Err(e)?,
+            };
+
+            debug!("Loading replacement image {}", full_path);
+            let image = match ext {
+                "png" => RgbaImage::from_png(file)?,
+                _ => RgbaImage::from_tga(file)?,
+            };
+            return Ok(Some(image));
+        }
+
+        Ok(None)
+    }
+
+    /// Converts a `_glow` or `_luma` image into a fullbright mask, which is brightest where the
+    /// image is.
+    pub fn to_fullbright(&self) -> Vec<u8> {
+        self.rgba
+            .chunks_exact(4)
+            .map(|p| (p[0].max(p[1]).max(p[2]) as u16 * p[3] as u16 / 0xFF) as u8)
+            .collect()
+    }
+}
+
+/// A replacement texture, ready to upload in place of the translated indexed data.
+pub struct Replacement {
+    pub width: u32,
+    pub height: u32,
+    pub diffuse: DiffuseData<'static>,
+    pub fullbright: FullbrightData<'static>,
+}
+
+impl Replacement {
+    /// Loads the replacement image at `path` (without an extension) and its `_glow` or `_luma`
+    /// layer, if it has one.
+    ///
+    /// Returns `None` if there is no replacement. Images which can't be decoded or are empty are
+    /// skipped with a warning, as is a fullbright layer whose size differs from the texture's.
+    pub fn load(vfs: &Vfs, path: &str) -> Option<Replacement> {
+        let image = match RgbaImage::load(vfs, path) {
+            Ok(i) => i?,
+            Err(e) => {
+                warn!("Couldn't load replacement for {}: {}", path, e);
+                return None;
+            }
+        };
+        if image.width == 0 || image.height == 0 {
+            warn!("Replacement for {} is empty", path);
+            return None;
+        }
+
+        let mut fullbright = None;
+        for suffix in FULLBRIGHT_SUFFIXES {
+            match RgbaImage::load(vfs, &format!("{}{}", path, suffix)) {
+                Ok(Some(glow)) if (glow.width, glow.height) == (image.width, image.height) => {
+                    fullbright = Some(glow.to_fullbright());
+                    break;
+                }
+                Ok(Some(glow)) => warn!(
+                    "Fullbright layer of {} is {}x{}, expected {}x{}",
+                    path, glow.width, glow.height, image.width, image.height
+                ),
+                Ok(None) => (),
+                Err(e) => warn!("Couldn't load fullbright layer of {}: {}", path, e),
+            }
+        }
+        let pixel_count = (image.width * image.height) as usize;
+
+        Some(Replacement {
+            width: image.width,
+            height: image.height,
+            diffuse: DiffuseData {
+                rgba: Cow::Owned(image.rgba),
+            },
+            fullbright: FullbrightData {
+                fullbright: Cow::Owned(fullbright.unwrap_or_else(|| vec![0; pixel_count])),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // a 2x2 image with one row of red and green and one of blue and translucent white
+    const TOP_ROW: [u8; 8] = [0xFF, 0, 0, 0xFF, 0, 0xFF, 0, 0xFF];
+    const BOTTOM_ROW: [u8; 8] = [0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x80];
+
+    fn tga_header(image_type: u8, depth: u8, descriptor: u8) -> Vec<u8> {
+        let mut header = vec![0; TGA_HEADER_SIZE];
+        header[2] = image_type;
+        header[12] = 2;
+        header[14] = 2;
+        header[16] = depth;
+        header[17] = descriptor;
+        header
+    }
+
+    fn bgra(rgba: &[u8]) -> Vec<u8> {
+        rgba.chunks_exact(4)
+            .flat_map(|p| [p[2], p[1], p[0], p[3]])
+            .collect()
+    }
+
+    #[test]
+    fn test_tga() {
+        let expected = [TOP_ROW, BOTTOM_ROW].concat();
+
+        // stored bottom to top
+        let mut tga = tga_header(TGA_TYPE_TRUECOLOR, 32, 0);
+        tga.extend(bgra(&BOTTOM_ROW));
+        tga.extend(bgra(&TOP_ROW));
+        let image = RgbaImage::from_tga(&tga[..]).unwrap();
+        assert_eq!((image.width(), image.height()), (2, 2));
+        assert_eq!(image.rgba(), &expected[..]);
+
+        // stored top to bottom, with a repeated pixel and a run of raw pixels
+        let mut tga = tga_header(TGA_TYPE_RLE_TRUECOLOR, 32, TGA_DESCRIPTOR_TOP_TO_BOTTOM);
+        tga.push(0x80);
+        tga.extend(bgra(&TOP_ROW[..4]));
+        tga.push(0x02);
+        tga.extend(bgra(&[&TOP_ROW[4..], &BOTTOM_ROW[..]].concat()));
+        let image = RgbaImage::from_tga(&tga[..]).unwrap();
+        assert_eq!(image.rgba(), &expected[..]);
+
+        // a packet which runs past the end of the image
+        let mut tga = tga_header(TGA_TYPE_RLE_GRAYSCALE, 8, 0);
+        tga.extend([0x84, 0x00]);
+        assert!(matches!(
+            RgbaImage::from_tga(&tga[..]),
+            Err(ReplacementError::TgaOverflow)
+        ));
+
+        // empty and oversized images are rejected before any pixels are read
+        for (width, height) in [(0, 2), (2, 0), (0xFFFF, 0xFFFF)] {
+            let mut tga = tga_header(TGA_TYPE_TRUECOLOR, 32, 0);
+            tga[12..14].copy_from_slice(&u16::to_le_bytes(width));
+            tga[14..16].copy_from_slice(&u16::to_le_bytes(height));
+            assert!(matches!(
+                RgbaImage::from_tga(&tga[..]),
+                Err(ReplacementError::BadSize { .. })
+            ));
+        }
+
+        // truncated pixel data
+        let mut tga = tga_header(TGA_TYPE_TRUECOLOR, 32, 0);
+        tga.extend(bgra(&BOTTOM_ROW));
+        assert!(matches!(
+            RgbaImage::from_tga(&tga[..]),
+            Err(ReplacementError::Io(_))
+        ));
+
+        // color-mapped images aren't supported
+        let tga = tga_header(1, 8, 0);
+        assert!(matches!(
+            RgbaImage::from_tga(&tga[..]),
+            Err(ReplacementError::UnsupportedTga { image_type: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_png() {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, 2, 2);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&[0xFF, 0, 0, 0, 0xFF, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF])
+            .unwrap();
+
+        let image = RgbaImage::from_png(&png[..]).unwrap();
+        assert_eq!((image.width(), image.height()), (2, 2));
+        assert_eq!(
+            image.rgba(),
+            &[TOP_ROW, [0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]].concat()[..]
+        );
+    }
+
+    #[test]
+    fn test_to_fullbright() {
+        let image = RgbaImage {
+            width: 2,
+            height: 2,
+            rgba: [TOP_ROW, BOTTOM_ROW].concat(),
+        };
+        assert_eq!(image.to_fullbright(), vec![0xFF, 0xFF, 0xFF, 0x80]);
+
+        let black = RgbaImage {
+            width: 1,
+            height: 1,
+            rgba: vec![0, 0, 0, 0xFF],
+        };
+        assert_eq!(black.to_fullbright(), vec![0]);
+    }
+}
@@ -0,0 +1,80 @@
+use crate::client::render::ui::{
+    glyph::{GlyphRendererCommand, GLYPH_HEIGHT, GLYPH_WIDTH},
+    layout::{Anchor, ScreenPosition},
+};
+
+use chrono::Duration;
+
+const BAR_LEFT: u8 = 128;
+const BAR_MIDDLE: u8 = 129;
+const BAR_RIGHT: u8 = 130;
+const BAR_HANDLE: u8 = 131;
+
+// number of glyphs between the ends of the bar
+const BAR_WIDTH: i32 = 38;
+
+// distance from the top of the screen
+const BAR_Y_OFS: i32 = -2 * GLYPH_HEIGHT as i32;
+
+/// The position and state of demo playback, shown as a scrub bar.
+pub struct DemoBarState {
+    pub time: Duration,
+    pub duration: Duration,
+    pub paused: bool,
+    pub speed: f32,
+}
+
+fn format_time(time: Duration) -> String {
+    format!("{}:{:02}", time.num_minutes(), time.num_seconds() % 60)
+}
+
+pub fn generate_commands(bar: &DemoBarState, glyph_cmds: &mut Vec<GlyphRendererCommand>) {
+    // TODO: take scale as cvar
+    let scale = 2.0;
+    let glyph_w = GLYPH_WIDTH as i32;
+    let left = -(BAR_WIDTH + 2) * glyph_w / 2;
+
+    let mut draw_glyph = |glyph_id, x_ofs| {
+        glyph_cmds.push(GlyphRendererCommand::Glyph {
+            glyph_id,
+            position: ScreenPosition::Relative {
+                anchor: Anchor::TOP_CENTER,
+                x_ofs,
+                y_ofs: BAR_Y_OFS,
+            },
+            anchor: Anchor::TOP_LEFT,
+            scale,
+        });
+    };
+
+    draw_glyph(BAR_LEFT, left);
+    for i in 0..BAR_WIDTH {
+        draw_glyph(BAR_MIDDLE, left + glyph_w * (i + 1));
+    }
+    draw_glyph(BAR_RIGHT, left + glyph_w * (BAR_WIDTH + 1));
+
+    let pos = match bar.duration.num_milliseconds() {
+        0 => 0.0,
+        d => (bar.time.num_milliseconds() as f32 / d as f32).clamp(0.0, 1.0),
+    };
+    let handle_x = left + glyph_w + ((glyph_w * (BAR_WIDTH - 1)) as f32 * pos) as i32;
+    draw_glyph(BAR_HANDLE, handle_x);
+
+    let mut text = format!("{} / {}", format_time(bar.time), format_time(bar.duration));
+    if bar.paused {
+        text.push_str("  paused");
+    } else if bar.speed != 1.0 {
+        text.push_str(&format!("  x{}", bar.speed));
+    }
+
+    glyph_cmds.push(GlyphRendererCommand::Text {
+        text,
+        position: ScreenPosition::Relative {
+            anchor: Anchor::TOP_CENTER,
+            x_ofs: 0,
+            y_ofs: BAR_Y_OFS - GLYPH_HEIGHT as i32 * 3 / 2,
+        },
+        anchor: Anchor::TOP_CENTER,
+        scale,
+    });
+}
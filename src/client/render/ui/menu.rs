@@ -252,10 +252,20 @@ impl MenuRenderer {
         scale: f32,
         glyph_cmds: &mut Vec<GlyphRendererCommand>,
     ) {
+        // lists take up a row for each entry, so items don't map directly to rows
+        let mut row = 0;
+        let mut cursor_row = 0;
+        let mut cursor_x = 200;
+
         for (item_id, item) in items.iter().enumerate() {
-            let y = MENU_HEIGHT - 32 - (GLYPH_HEIGHT * item_id) as i32;
+            if item_id == cursor_pos {
+                cursor_row = row;
+            }
+
+            let y = MENU_HEIGHT - 32 - (GLYPH_HEIGHT * row) as i32;
             let x = 16 + 24 * GLYPH_WIDTH as i32;
             self.cmd_draw_item_name(x, y, item.name(), scale, glyph_cmds);
+            row += 1;
 
             match item.item() {
                 Item::Toggle(toggle) => self.cmd_draw_item_text(
@@ -272,6 +282,21 @@ impl MenuRenderer {
                     self.cmd_draw_slider(x, y, slider.position(), scale, glyph_cmds)
                 }
                 Item::TextField(_) => (),
+                Item::List(list) => {
+                    let entries = list.entries();
+
+                    // list entries are drawn from the left edge, so put the cursor there too
+                    if item_id == cursor_pos && !entries.is_empty() {
+                        cursor_row += 1 + list.selected();
+                        cursor_x = 8;
+                    }
+
+                    for entry in entries {
+                        let y = MENU_HEIGHT - 32 - (GLYPH_HEIGHT * row) as i32;
+                        self.cmd_draw_item_text(16, y, entry, scale, glyph_cmds);
+                        row += 1;
+                    }
+                }
                 _ => (),
             }
         }
@@ -279,8 +304,8 @@ impl MenuRenderer {
         if time.num_milliseconds() / 250 % 2 == 0 {
             self.cmd_draw_glyph(
                 141,
-                200,
-                MENU_HEIGHT - 32 - 8 * cursor_pos as i32,
+                cursor_x,
+                MENU_HEIGHT - 32 - 8 * cursor_row as i32,
                 scale,
                 glyph_cmds,
             );
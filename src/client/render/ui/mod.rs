@@ -1,4 +1,5 @@
 pub mod console;
+pub mod demo;
 pub mod glyph;
 pub mod hud;
 pub mod layout;
@@ -11,6 +12,7 @@ use crate::{
         render::{
             ui::{
                 console::ConsoleRenderer,
+                demo::DemoBarState,
                 glyph::{GlyphRenderer, GlyphRendererCommand},
                 hud::{HudRenderer, HudState},
                 menu::MenuRenderer,
@@ -81,6 +83,7 @@ pub enum UiState<'a> {
     },
     InGame {
         hud: HudState<'a>,
+        demo_bar: Option<DemoBarState>,
         overlay: Option<UiOverlay<'a>>,
     },
 }
@@ -114,9 +117,13 @@ impl UiRenderer {
         quad_commands: &'pass mut Vec<QuadRendererCommand<'pass>>,
         glyph_commands: &'pass mut Vec<GlyphRendererCommand>,
     ) {
-        let (hud_state, overlay) = match ui_state {
-            UiState::Title { overlay } => (None, Some(overlay)),
-            UiState::InGame { hud, overlay } => (Some(hud), overlay.as_ref()),
+        let (hud_state, demo_bar, overlay) = match ui_state {
+            UiState::Title { overlay } => (None, None, Some(overlay)),
+            UiState::InGame {
+                hud,
+                demo_bar,
+                overlay,
+            } => (Some(hud), demo_bar.as_ref(), overlay.as_ref()),
         };
 
         if let Some(hstate) = hud_state {
@@ -124,6 +131,10 @@ impl UiRenderer {
                 .generate_commands(hstate, time, quad_commands, glyph_commands);
         }
 
+        if let Some(bar) = demo_bar {
+            demo::generate_commands(bar, glyph_commands);
+        }
+
         if let Some(o) = overlay {
             match o {
                 UiOverlay::Menu(menu) => {
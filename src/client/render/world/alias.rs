@@ -1,9 +1,10 @@
-use std::{mem::size_of, ops::Range};
+use std::{borrow::Cow, mem::size_of, ops::Range};
 
 use crate::{
     client::render::{
+        replacement::RgbaImage,
         world::{BindGroupLayoutId, WorldPipelineBase},
-        GraphicsState, Pipeline, TextureData,
+        DiffuseData, GraphicsState, Pipeline, TextureData,
     },
     common::{
         mdl::{self, AliasModel},
@@ -240,7 +241,15 @@ pub struct AliasRenderer {
 }
 
 impl AliasRenderer {
-    pub fn new(state: &GraphicsState, alias_model: &AliasModel) -> Result<AliasRenderer, Error> {
+    /// Creates a renderer for the alias model loaded from the file `name`.
+    ///
+    /// The name is used to look up replacement skins, e.g. `progs/player_0.png` for the first skin
+    /// of `progs/player.mdl` or `progs/flame2_0_1.png` for the second frame of an animated skin.
+    pub fn new(
+        state: &GraphicsState,
+        name: &str,
+        alias_model: &AliasModel,
+    ) -> Result<AliasRenderer, Error> {
         let mut vertices = Vec::new();
         let mut keyframes = Vec::new();
 
@@ -340,13 +349,40 @@ impl AliasRenderer {
                 usage: wgpu::BufferUsages::VERTEX,
             });
 
+        // an image from a texture pack takes the place of the indexed skin. alias models are drawn
+        // at full brightness, so there's no need for a fullbright layer
+        let skin_stem = name.trim_end_matches(".mdl");
+        let create_skin_texture = |indices: &[u8], path: String| {
+            let image = RgbaImage::load(state.vfs(), &path).unwrap_or_else(|e| {
+                warn!("Couldn't load replacement for {}: {}", path, e);
+                None
+            });
+
+            match image {
+                Some(image) => {
+                    let diffuse_data = DiffuseData {
+                        rgba: Cow::Borrowed(image.rgba()),
+                    };
+                    state.create_texture(
+                        None,
+                        image.width(),
+                        image.height(),
+                        &TextureData::Diffuse(diffuse_data),
+                    )
+                }
+                None => {
+                    let (diffuse_data, _fullbright_data) = state.palette.translate(indices);
+                    state.create_texture(None, w, h, &TextureData::Diffuse(diffuse_data))
+                }
+            }
+        };
+
         let mut textures = Vec::new();
-        for texture in alias_model.textures() {
+        for (skin_id, texture) in alias_model.textures().iter().enumerate() {
             match *texture {
                 mdl::Texture::Static(ref tex) => {
-                    let (diffuse_data, _fullbright_data) = state.palette.translate(tex.indices());
                     let diffuse_texture =
-                        state.create_texture(None, w, h, &TextureData::Diffuse(diffuse_data));
+                        create_skin_texture(tex.indices(), format!("{}_{}", skin_stem, skin_id));
                     let diffuse_view = diffuse_texture.create_view(&Default::default());
                     let bind_group = state
                         .device()
@@ -373,14 +409,14 @@ impl AliasRenderer {
                     let mut diffuse_views = Vec::new();
                     let mut bind_groups = Vec::new();
 
-                    for frame in tex.frames() {
+                    for (frame_id, frame) in tex.frames().iter().enumerate() {
                         total_duration = total_duration + frame.duration();
                         durations.push(frame.duration());
 
-                        let (diffuse_data, _fullbright_data) =
-                            state.palette.translate(frame.indices());
-                        let diffuse_texture =
-                            state.create_texture(None, w, h, &TextureData::Diffuse(diffuse_data));
+                        let diffuse_texture = create_skin_texture(
+                            frame.indices(),
+                            format!("{}_{}_{}", skin_stem, skin_id, frame_id),
+                        );
                         let diffuse_view = diffuse_texture.create_view(&Default::default());
                         let bind_group =
                             state
@@ -31,14 +31,15 @@ use std::{
 use crate::{
     client::render::{
         pipeline::PushConstantUpdate,
+        replacement::Replacement,
         warp,
         world::{BindGroupLayoutId, WorldPipelineBase},
-        Camera, GraphicsState, LightmapData, Pipeline, TextureData,
+        Camera, GraphicsState, LightmapData, Palette, Pipeline, TextureData,
     },
     common::{
         bsp::{
-            self, BspData, BspFace, BspLeaf, BspModel, BspTexInfo, BspTexture, BspTextureKind,
-            BspTextureMipmap,
+            self, BspData, BspFace, BspLeaf, BspModel, BspTexInfo, BspTexture, BspTextureFrame,
+            BspTextureKind, BspTextureMipmap,
         },
         math,
         util::any_slice_as_bytes,
@@ -226,6 +227,12 @@ impl Pipeline for BrushPipeline {
     }
 }
 
+// the path of a texture's replacement image, without an extension. texture packs use `#` in place
+// of the `*` which begins the names of liquid textures, since it isn't allowed in Windows paths.
+fn replacement_path(texture_name: &str) -> String {
+    format!("textures/{}", texture_name.replace('*', "#"))
+}
+
 fn calculate_lightmap_texcoords(
     position: Vector3<f32>,
     face: &BspFace,
@@ -470,7 +477,7 @@ impl BrushRendererBuilder {
         let mut lightmap_ids = Vec::new();
         for lightmap in lightmaps {
             let lightmap_data = TextureData::Lightmap(LightmapData {
-                lightmap: Cow::Borrowed(lightmap.data()),
+                lightmap: Cow::Owned(lightmap.rgba()),
             });
 
             let texture =
@@ -548,7 +555,7 @@ impl BrushRendererBuilder {
     fn create_brush_texture_frame<S>(
         &self,
         state: &GraphicsState,
-        mipmap: &[u8],
+        bsp_frame: &BspTextureFrame,
         width: u32,
         height: u32,
         name: S,
@@ -558,7 +565,21 @@ impl BrushRendererBuilder {
     {
         let name = name.as_ref();
 
-        let (diffuse_data, fullbright_data) = state.palette().translate(mipmap);
+        // an image from a texture pack takes the place of the indexed data
+        let replacement = Replacement::load(state.vfs(), &replacement_path(bsp_frame.name()));
+        let (width, height, diffuse_data, fullbright_data) = match replacement {
+            Some(r) => (r.width, r.height, r.diffuse, r.fullbright),
+            None => {
+                let mipmap = bsp_frame.mipmap(BspTextureMipmap::Full);
+                let (diffuse_data, fullbright_data) = match bsp_frame.palette() {
+                    Some(palette) => {
+                        Palette::new(palette).translate_embedded(mipmap, name.starts_with('{'))
+                    }
+                    None => state.palette().translate(mipmap),
+                };
+                (width, height, diffuse_data, fullbright_data)
+            }
+        };
         let diffuse =
             state.create_texture(None, width, height, &TextureData::Diffuse(diffuse_data));
         let fullbright = state.create_texture(
@@ -608,27 +629,13 @@ impl BrushRendererBuilder {
             BspTextureKind::Animated { primary, alternate } => {
                 let primary_frames: Vec<_> = primary
                     .iter()
-                    .map(|f| {
-                        self.create_brush_texture_frame(
-                            state,
-                            f.mipmap(BspTextureMipmap::Full),
-                            width,
-                            height,
-                            tex.name(),
-                        )
-                    })
+                    .map(|f| self.create_brush_texture_frame(state, f, width, height, tex.name()))
                     .collect();
 
                 let alternate_frames: Option<Vec<_>> = alternate.as_ref().map(|a| {
                     a.iter()
                         .map(|f| {
-                            self.create_brush_texture_frame(
-                                state,
-                                f.mipmap(BspTextureMipmap::Full),
-                                width,
-                                height,
-                                tex.name(),
-                            )
+                            self.create_brush_texture_frame(state, f, width, height, tex.name())
                         })
                         .collect()
                 });
@@ -642,7 +649,7 @@ impl BrushRendererBuilder {
             BspTextureKind::Static(bsp_tex) => {
                 BrushTexture::Static(self.create_brush_texture_frame(
                     state,
-                    bsp_tex.mipmap(BspTextureMipmap::Full),
+                    bsp_tex,
                     tex.width(),
                     tex.height(),
                     tex.name(),
@@ -337,7 +337,7 @@ impl WorldRenderer {
             } else {
                 match *model.kind() {
                     ModelKind::Alias(ref amodel) => entity_renderers.push(EntityRenderer::Alias(
-                        AliasRenderer::new(state, amodel).unwrap(),
+                        AliasRenderer::new(state, model.name(), amodel).unwrap(),
                     )),
 
                     ModelKind::Brush(ref bmodel) => {
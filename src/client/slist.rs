@@ -0,0 +1,260 @@
+// Copyright © 2020 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Server discovery.
+//!
+//! A search broadcasts a server info request on the local network and sends
+//! the same request to each host in `slist_hosts`, then collects replies for
+//! a short time.
+
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    time::Instant,
+};
+
+use crate::common::net::{
+    connect::{ConnectSocket, Request, Response, ResponseServerInfo, DEFAULT_PORT},
+    NetError, GAME_NAME,
+};
+
+use chrono::Duration;
+
+/// How long a search waits for replies.
+const SEARCH_TIMEOUT_MS: i64 = 1500;
+
+/// A server which replied to a search.
+#[derive(Clone, Debug)]
+pub struct ServerListEntry {
+    /// The address the reply came from.
+    pub address: SocketAddr,
+
+    /// The server's reply.
+    pub info: ResponseServerInfo,
+
+    /// Time between sending the request and receiving the reply.
+    pub ping: Duration,
+}
+
+/// The results of the most recent server search.
+pub struct ServerList {
+    socket: Option<ConnectSocket>,
+    search_start: Option<Instant>,
+    entries: Vec<ServerListEntry>,
+}
+
+impl ServerList {
+    pub fn new() -> ServerList {
+        ServerList {
+            socket: None,
+            search_start: None,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Returns the servers found so far, in the order they replied.
+    pub fn entries(&self) -> &[ServerListEntry] {
+        &self.entries
+    }
+
+    /// Returns whether a search is in progress.
+    pub fn searching(&self) -> bool {
+        self.search_start.is_some()
+    }
+
+    /// Begins a new search, discarding previous results.
+    ///
+    /// If `broadcast` is true, the request is also broadcast on the local
+    /// network.
+    pub fn search(&mut self, hosts: &[SocketAddr], broadcast: bool) -> Result<(), NetError> {
+        let socket = ConnectSocket::bind("0.0.0.0:0")?;
+        socket.set_nonblocking(true)?;
+        socket.set_broadcast(true)?;
+        self.socket = Some(socket);
+        self.entries.clear();
+        self.search_start = Some(Instant::now());
+
+        let socket = self.socket.as_mut().unwrap();
+
+        if broadcast {
+            let broadcast_addr = SocketAddr::from(([255, 255, 255, 255], DEFAULT_PORT));
+
+            // hosts without a network interface can still search `slist_hosts`
+            if let Err(e) = socket.send_request(Request::server_info(GAME_NAME), broadcast_addr) {
+                warn!("Couldn't broadcast server info request: {}", e);
+            }
+        }
+
+        for host in hosts {
+            socket.send_request(Request::server_info(GAME_NAME), *host)?;
+        }
+
+        Ok(())
+    }
+
+    /// Collects any replies which have arrived.
+    ///
+    /// Returns `true` if the search finished during this call.
+    pub fn poll(&mut self) -> Result<bool, NetError> {
+        let start = match self.search_start {
+            Some(s) => s,
+            None => return Ok(false),
+        };
+
+        let socket = self.socket.as_mut().unwrap();
+        loop {
+            let (response, remote) = match socket.recv_response(None) {
+                Ok(Some(r)) => r,
+                Ok(None) => break,
+
+                // ignore garbage from other hosts
+                Err(NetError::InvalidData(msg)) => {
+                    debug!("Invalid server info response: {}", msg);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            if let Response::ServerInfo(info) = response {
+                if self.entries.iter().any(|e| e.address == remote) {
+                    continue;
+                }
+
+                self.entries.push(ServerListEntry {
+                    address: remote,
+                    info,
+                    ping: Duration::from_std(start.elapsed()).unwrap_or_else(|_| Duration::zero()),
+                });
+            }
+        }
+
+        let elapsed = Duration::from_std(start.elapsed()).unwrap_or_else(|_| Duration::zero());
+        if elapsed >= Duration::milliseconds(SEARCH_TIMEOUT_MS) {
+            self.search_start = None;
+            self.socket = None;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Formats the results for the console in the style of the original
+    /// engine's `slist` command.
+    pub fn report(&self) -> String {
+        if self.entries.is_empty() {
+            return String::from("No Quake servers found.");
+        }
+
+        let mut report = String::from(
+            "Server          Map             Users Ping\n\
+             --------------- --------------- ----- ----\n",
+        );
+
+        for entry in self.entries.iter() {
+            report.push_str(&format!("{}\n", entry.summary()));
+        }
+
+        report
+    }
+}
+
+impl Default for ServerList {
+    fn default() -> Self {
+        ServerList::new()
+    }
+}
+
+impl ServerListEntry {
+    /// Returns a one-line description of the server.
+    pub fn summary(&self) -> String {
+        format!(
+            "{:<15.15} {:<15.15} {:>2}/{:>2} {:>4}",
+            self.info.hostname,
+            self.info.levelname,
+            self.info.client_count,
+            self.info.client_max,
+            self.ping.num_milliseconds(),
+        )
+    }
+}
+
+/// Resolves a host name with an optional port, using the default port if
+/// none is given.
+pub fn resolve_host(host: &str) -> Option<SocketAddr> {
+    match host.to_socket_addrs() {
+        Ok(mut addrs) => addrs.next(),
+        Err(_) => (host, DEFAULT_PORT).to_socket_addrs().ok()?.next(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::net::connect::{ConnectListener, ResponseServerInfo};
+
+    #[test]
+    fn test_resolve_host() {
+        assert_eq!(
+            resolve_host("127.0.0.1"),
+            Some(SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT)))
+        );
+        assert_eq!(
+            resolve_host("127.0.0.1:27500"),
+            Some(SocketAddr::from(([127, 0, 0, 1], 27500)))
+        );
+    }
+
+    #[test]
+    fn test_server_list_search() {
+        let listener = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = listener.local_addr().unwrap();
+
+        let mut slist = ServerList::new();
+        slist.search(&[server_addr, server_addr], false).unwrap();
+        assert!(slist.searching());
+
+        let info = ResponseServerInfo {
+            address: server_addr.to_string(),
+            hostname: String::from("test server"),
+            levelname: String::from("e1m1"),
+            client_count: 1,
+            client_max: 8,
+            protocol_version: 3,
+        };
+
+        // answer both requests, duplicates should be dropped
+        for _ in 0..2 {
+            let (request, remote) = listener.recv_request().unwrap();
+            assert!(matches!(request, Request::ServerInfo(_)));
+            listener
+                .send_response(Response::ServerInfo(info.clone()), remote)
+                .unwrap();
+        }
+
+        while !slist.poll().unwrap() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        assert!(!slist.searching());
+        assert_eq!(slist.entries().len(), 1);
+        assert_eq!(slist.entries()[0].address, server_addr);
+        assert_eq!(slist.entries()[0].info, info);
+        assert!(slist.report().contains("test server"));
+    }
+}
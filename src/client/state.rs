@@ -1,4 +1,4 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, io::Read as _, rc::Rc};
 
 use super::view::BobVars;
 use crate::{
@@ -8,6 +8,7 @@ use crate::{
             Beam, ClientEntity, Light, LightDesc, Lights, MAX_BEAMS, MAX_LIGHTS, MAX_TEMP_ENTITIES,
         },
         input::game::{Action, GameInput},
+        predict::Prediction,
         render::Camera,
         sound::{AudioSource, EntityMixer, Listener, StaticSound},
         view::{IdleVars, KickVars, MouseVars, RollVars, View},
@@ -18,16 +19,19 @@ use crate::{
         math::{self, Angles},
         model::{Model, ModelFlags, ModelKind, SyncType},
         net::{
-            self, BeamEntityKind, ButtonFlags, ColorShift, EntityEffects, ItemFlags, PlayerData,
-            PointEntityKind, TempEntity,
+            self, BeamEntityKind, ButtonFlags, ColorShift, EntityEffects, GameType, ItemFlags,
+            PlayerData, PointEntityKind, ServerCmd, SignOnStage, TempEntity,
         },
+        pmove::{MoveInput, PhysicsVars, PlayerHulls, PlayerState},
         vfs::Vfs,
+        wad3::Wad3,
     },
 };
 use arrayvec::ArrayVec;
 use cgmath::{Angle as _, Deg, InnerSpace as _, Matrix4, Vector3, Zero as _};
 use chrono::Duration;
-use net::{ClientCmd, ClientStat, EntityState, EntityUpdate, PlayerColor};
+use net::{ClientCmd, ClientStat, EntityState, EntityUpdate, PlayerColor, Protocol};
+use num::FromPrimitive as _;
 use rand::{
     distributions::{Distribution as _, Uniform},
     rngs::SmallRng,
@@ -45,6 +49,7 @@ const CACHED_SOUND_NAMES: &[&str] = &[
     "wizard/hit.wav",
 ];
 
+#[derive(Clone)]
 pub struct PlayerInfo {
     pub name: String,
     pub frags: i32,
@@ -52,11 +57,58 @@ pub struct PlayerInfo {
     // translations: [u8; VID_GRADES],
 }
 
+/// A static sound as announced by the server.
+#[derive(Clone, Debug)]
+pub struct StaticSoundInfo {
+    pub origin: Vector3<f32>,
+    pub sound_id: u16,
+    pub volume: u8,
+    pub attenuation: u8,
+}
+
+/// The parts of a [`ClientState`] which change over the course of a level.
+///
+/// Restoring a snapshot returns the client to the point in the level at which
+/// it was taken, which allows demos to be rewound without reloading the level.
+#[derive(Clone)]
+pub struct StateSnapshot {
+    cd_track: Option<(u8, u8)>,
+    entities: Vec<ClientEntity>,
+    light_styles: HashMap<u8, String>,
+    stats: [i32; MAX_STATS],
+    player_info: [Option<PlayerInfo>; net::MAX_CLIENTS],
+    msg_times: [Duration; 2],
+    time: Duration,
+    lerp_factor: f32,
+    items: ItemFlags,
+    item_get_time: [Duration; net::MAX_ITEMS],
+    face_anim_time: Duration,
+    color_shifts: [ColorShift; 4],
+    view: View,
+    msg_velocity: [Vector3<f32>; 2],
+    velocity: Vector3<f32>,
+    on_ground: bool,
+    in_water: bool,
+    intermission: Option<IntermissionKind>,
+    start_time: Duration,
+    completion_time: Option<Duration>,
+}
+
 // client information regarding the current level
 pub struct ClientState {
     // local rng
     rng: SmallRng,
 
+    // network protocol announced by the server
+    pub protocol: Protocol,
+
+    // server info kept to rebuild the sign-on for demo recording
+    pub game_type: GameType,
+    pub level_name: String,
+    pub model_precache: Vec<String>,
+    pub sound_precache: Vec<String>,
+    pub cd_track: Option<(u8, u8)>,
+
     // model precache
     pub models: Vec<Model>,
     // name-to-id map
@@ -70,6 +122,7 @@ pub struct ClientState {
 
     // ambient sounds (infinite looping, static position)
     pub static_sounds: Vec<StaticSound>,
+    pub static_sound_info: Vec<StaticSoundInfo>,
 
     // entities and entity-like things
     pub entities: Vec<ClientEntity>,
@@ -110,6 +163,10 @@ pub struct ClientState {
     // paused: bool,
     pub on_ground: bool,
     pub in_water: bool,
+
+    // local player movement prediction
+    prediction: Prediction,
+
     pub intermission: Option<IntermissionKind>,
     pub start_time: Duration,
     pub completion_time: Option<Duration>,
@@ -123,11 +180,18 @@ impl ClientState {
     pub fn new(stream: OutputStreamHandle) -> ClientState {
         ClientState {
             rng: SmallRng::from_entropy(),
+            protocol: Protocol::default(),
+            game_type: GameType::CoOp,
+            level_name: String::new(),
+            model_precache: Vec::new(),
+            sound_precache: Vec::new(),
+            cd_track: None,
             models: vec![Model::none()],
             model_names: HashMap::new(),
             sounds: Vec::new(),
             cached_sounds: HashMap::new(),
             static_sounds: Vec::new(),
+            static_sound_info: Vec::new(),
             entities: Vec::new(),
             static_entities: Vec::new(),
             temp_entities: Vec::new(),
@@ -168,6 +232,7 @@ impl ClientState {
             velocity: Vector3::zero(),
             on_ground: false,
             in_water: false,
+            prediction: Prediction::new(None),
             intermission: None,
             start_time: Duration::zero(),
             completion_time: None,
@@ -179,7 +244,10 @@ impl ClientState {
     pub fn from_server_info(
         vfs: &Vfs,
         stream: OutputStreamHandle,
+        protocol: Protocol,
         max_clients: u8,
+        game_type: GameType,
+        level_name: String,
         model_precache: Vec<String>,
         sound_precache: Vec<String>,
     ) -> Result<ClientState, ClientError> {
@@ -187,11 +255,39 @@ impl ClientState {
         let mut models = Vec::with_capacity(model_precache.len());
         models.push(Model::none());
         let mut model_names = HashMap::new();
-        for mod_name in model_precache {
+        for mod_name in model_precache.iter().cloned() {
             // BSPs can have more than one model
             if mod_name.ends_with(".bsp") {
                 let bsp_data = vfs.open(&mod_name)?;
-                let (mut brush_models, _) = bsp::load(bsp_data).unwrap();
+
+                // colored lighting is kept in a .lit file next to the map
+                let lit_name = format!("{}.lit", mod_name.trim_end_matches(".bsp"));
+                let lit = vfs.open(&lit_name).ok().and_then(|mut f| {
+                    let mut data = Vec::new();
+                    match f.read_to_end(&mut data) {
+                        Ok(_) => Some(data),
+                        Err(e) => {
+                            warn!("Couldn't read {}: {}", lit_name, e);
+                            None
+                        }
+                    }
+                });
+
+                // Half-Life maps may keep their textures in WAD3 files
+                let (mut brush_models, _) = bsp::load_with(bsp_data, lit.as_deref(), |wad_name| {
+                    let wad = vfs
+                        .open(wad_name)
+                        .map_err(|e| e.to_string())
+                        .and_then(|f| Wad3::load(f).map_err(|e| e.to_string()));
+                    match wad {
+                        Ok(w) => Some(w),
+                        Err(e) => {
+                            warn!("Couldn't load {}: {}", wad_name, e);
+                            None
+                        }
+                    }
+                })
+                .unwrap();
                 for bmodel in brush_models.drain(..) {
                     let id = models.len();
                     let name = bmodel.name().to_owned();
@@ -210,7 +306,7 @@ impl ClientState {
         }
 
         let mut sounds = vec![AudioSource::load(&vfs, "misc/null.wav")?];
-        for ref snd_name in sound_precache {
+        for snd_name in sound_precache.iter() {
             debug!("Loading sound {}: {}", sounds.len(), snd_name);
             sounds.push(AudioSource::load(vfs, snd_name)?);
             // TODO: send keepalive message?
@@ -221,12 +317,24 @@ impl ClientState {
             cached_sounds.insert(name.to_string(), AudioSource::load(vfs, name)?);
         }
 
+        // the player is moved through the worldmodel's collision hulls
+        let hulls = match models.get(1).map(|m| m.kind()) {
+            Some(ModelKind::Brush(ref bmodel)) => PlayerHulls::from_world(bmodel).ok(),
+            _ => None,
+        };
+
         Ok(ClientState {
+            protocol,
+            game_type,
+            level_name,
+            model_precache,
+            sound_precache,
             models,
             model_names,
             sounds,
             cached_sounds,
             max_players: max_clients as usize,
+            prediction: Prediction::new(hulls),
             ..ClientState::new(stream)
         })
     }
@@ -680,6 +788,252 @@ impl ClientState {
         }
     }
 
+    /// Reconstructs the sign-on messages for the current level.
+    ///
+    /// This allows a demo recording to start partway through a level. Each of
+    /// the three lists of commands ends with the sign-on stage it completes.
+    pub fn signon_cmds(&self) -> [Vec<ServerCmd>; 3] {
+        let mut server_info = vec![ServerCmd::ServerInfo {
+            protocol_version: self.protocol.version.value(),
+            protocol_flags: self.protocol.flags,
+            max_clients: self.max_players as u8,
+            game_type: self.game_type,
+            message: self.level_name.clone(),
+            model_precache: self.model_precache.clone(),
+            sound_precache: self.sound_precache.clone(),
+        }];
+
+        if let Some((track, loop_)) = self.cd_track {
+            server_info.push(ServerCmd::CdTrack { track, loop_ });
+        }
+
+        server_info.push(ServerCmd::SetView {
+            ent_id: self.view.entity_id() as i16,
+        });
+        server_info.push(ServerCmd::SignOnStage {
+            stage: SignOnStage::Prespawn,
+        });
+
+        let alpha = |a: u8| (a != net::ENTITY_ALPHA_DEFAULT).then_some(a);
+
+        let mut prespawn = Vec::new();
+        for (ent_id, ent) in self.entities.iter().enumerate() {
+            let baseline = &ent.baseline;
+            prespawn.push(ServerCmd::SpawnBaseline {
+                ent_id: ent_id as u16,
+                model_id: baseline.model_id as u16,
+                frame_id: baseline.frame_id as u16,
+                colormap: baseline.colormap,
+                skin_id: baseline.skin_id as u8,
+                origin: baseline.origin,
+                angles: baseline.angles,
+                alpha: alpha(baseline.alpha),
+            });
+        }
+
+        for ent in self.static_entities.iter() {
+            let baseline = &ent.baseline;
+            prespawn.push(ServerCmd::SpawnStatic {
+                model_id: baseline.model_id as u16,
+                frame_id: baseline.frame_id as u16,
+                colormap: baseline.colormap,
+                skin_id: baseline.skin_id as u8,
+                origin: baseline.origin,
+                angles: baseline.angles,
+                alpha: alpha(baseline.alpha),
+            });
+        }
+
+        for info in self.static_sound_info.iter() {
+            prespawn.push(ServerCmd::SpawnStaticSound {
+                origin: info.origin,
+                sound_id: info.sound_id,
+                volume: info.volume,
+                attenuation: info.attenuation,
+            });
+        }
+
+        prespawn.push(ServerCmd::SignOnStage {
+            stage: SignOnStage::ClientInfo,
+        });
+
+        let mut client_info = Vec::new();
+        let mut light_styles: Vec<_> = self.light_styles.iter().collect();
+        light_styles.sort();
+        for (id, value) in light_styles {
+            client_info.push(ServerCmd::LightStyle {
+                id: *id,
+                value: value.clone(),
+            });
+        }
+
+        for (player_id, info) in self.player_info.iter().enumerate() {
+            if let Some(info) = info {
+                let player_id = player_id as u8;
+                client_info.push(ServerCmd::UpdateName {
+                    player_id,
+                    new_name: info.name.clone(),
+                });
+                client_info.push(ServerCmd::UpdateFrags {
+                    player_id,
+                    new_frags: info.frags as i16,
+                });
+                client_info.push(ServerCmd::UpdateColors {
+                    player_id,
+                    new_colors: info.colors,
+                });
+            }
+        }
+
+        for (i, value) in self.stats.iter().enumerate() {
+            if let Some(stat) = ClientStat::from_usize(i) {
+                client_info.push(ServerCmd::UpdateStat {
+                    stat,
+                    value: *value,
+                });
+            }
+        }
+
+        client_info.push(ServerCmd::SignOnStage {
+            stage: SignOnStage::Begin,
+        });
+
+        [server_info, prespawn, client_info]
+    }
+
+    /// Returns the view angles to record in a demo.
+    pub fn demo_view_angles(&self) -> Vector3<Deg<f32>> {
+        let angles = self.view.input_angles();
+        Vector3::new(angles.pitch, angles.yaw, angles.roll)
+    }
+
+    /// Captures the state of the current level.
+    pub fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            cd_track: self.cd_track,
+            entities: self.entities.clone(),
+            light_styles: self.light_styles.clone(),
+            stats: self.stats,
+            player_info: self.player_info.clone(),
+            msg_times: self.msg_times,
+            time: self.time,
+            lerp_factor: self.lerp_factor,
+            items: self.items,
+            item_get_time: self.item_get_time,
+            face_anim_time: self.face_anim_time,
+            color_shifts: [0, 1, 2, 3].map(|i| *self.color_shifts[i].borrow()),
+            view: self.view.clone(),
+            msg_velocity: self.msg_velocity,
+            velocity: self.velocity,
+            on_ground: self.on_ground,
+            in_water: self.in_water,
+            intermission: self.intermission.clone(),
+            start_time: self.start_time,
+            completion_time: self.completion_time,
+        }
+    }
+
+    /// Returns to the point in the level at which `snapshot` was taken.
+    ///
+    /// The snapshot must have been taken from this level. Short-lived effects
+    /// like particles and dynamic lights are discarded.
+    pub fn restore(&mut self, snapshot: &StateSnapshot) {
+        let snapshot = snapshot.clone();
+        self.cd_track = snapshot.cd_track;
+        self.entities = snapshot.entities;
+        self.light_styles = snapshot.light_styles;
+        self.stats = snapshot.stats;
+        self.player_info = snapshot.player_info;
+        self.msg_times = snapshot.msg_times;
+        self.time = snapshot.time;
+        self.lerp_factor = snapshot.lerp_factor;
+        self.items = snapshot.items;
+        self.item_get_time = snapshot.item_get_time;
+        self.face_anim_time = snapshot.face_anim_time;
+        for (shift, value) in self.color_shifts.iter().zip(snapshot.color_shifts) {
+            shift.replace(value);
+        }
+        self.view = snapshot.view;
+        self.msg_velocity = snapshot.msg_velocity;
+        self.velocity = snapshot.velocity;
+        self.on_ground = snapshot.on_ground;
+        self.in_water = snapshot.in_water;
+        self.intermission = snapshot.intermission;
+        self.start_time = snapshot.start_time;
+        self.completion_time = snapshot.completion_time;
+
+        self.clear_effects();
+    }
+
+    /// Discards temporary entities, beams, particles and dynamic lights.
+    pub fn clear_effects(&mut self) {
+        self.temp_entities.clear();
+        self.beams = [None; MAX_BEAMS];
+        self.particles.clear();
+        self.lights = Lights::with_capacity(MAX_LIGHTS);
+        for ent in self.entities.iter_mut() {
+            ent.light_id = None;
+        }
+
+        self.prediction.clear();
+    }
+
+    /// Applies a move command sent to the server to the predicted player state.
+    pub fn predict_move(
+        &mut self,
+        cmd: &ClientCmd,
+        frame_time: Duration,
+        vars: &PhysicsVars,
+    ) -> Result<(), ClientError> {
+        if let (ClientCmd::Move { send_time, .. }, Some(input)) = (cmd, MoveInput::from_cmd(cmd)) {
+            self.prediction
+                .push_move(vars, *send_time, frame_time, input)?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves the player entity to its predicted position.
+    ///
+    /// This replays the moves the server has not processed yet on top of the
+    /// latest server update. `rtt` is the round-trip time to the server.
+    pub fn predict_player(
+        &mut self,
+        vars: &PhysicsVars,
+        rtt: Duration,
+        frame_time: Duration,
+    ) -> Result<(), ClientError> {
+        let view_id = self.view.entity_id();
+        if self.intermission.is_some() || view_id >= self.entities.len() {
+            self.prediction.clear();
+            return Ok(());
+        }
+
+        let server = PlayerState::new(
+            self.entities[view_id].msg_origins[0],
+            self.msg_velocity[0],
+            self.on_ground,
+        );
+        self.prediction
+            .update(vars, server, self.msg_times[0], rtt)?;
+        self.prediction.decay_error(frame_time);
+
+        if let Some(origin) = self.prediction.origin() {
+            self.entities[view_id].origin = origin;
+        }
+
+        if let Some(predicted) = self.prediction.predicted() {
+            self.velocity = predicted.velocity;
+        }
+
+        Ok(())
+    }
+
+    /// Discards any predicted movement.
+    pub fn clear_prediction(&mut self) {
+        self.prediction.clear();
+    }
+
     pub fn handle_damage(
         &mut self,
         armor: u8,
@@ -788,6 +1142,7 @@ impl ClientState {
                 colormap: update.colormap.unwrap_or(0),
                 skin_id: update.skin_id.unwrap_or(0) as usize,
                 effects: EntityEffects::empty(),
+                alpha: update.alpha.unwrap_or(net::ENTITY_ALPHA_DEFAULT),
             };
 
             self.spawn_entities(id, baseline)?;
@@ -0,0 +1,220 @@
+//! Demo benchmarking.
+//!
+//! A timedemo plays a demo back one message per frame, as fast as frames can
+//! be rendered, and records how long each frame took.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Duration;
+
+/// Frame times recorded while a timedemo is running.
+#[derive(Debug)]
+pub struct TimeDemo {
+    // where to write the per-frame durations, if anywhere
+    csv_path: Option<PathBuf>,
+
+    // whether the first frame has been seen. its duration includes loading
+    // the level, so it isn't recorded
+    started: bool,
+
+    frame_times: Vec<Duration>,
+}
+
+impl TimeDemo {
+    /// Starts a timedemo.
+    ///
+    /// If `csv_path` is `Some`, the frame times are written there when the
+    /// timedemo finishes.
+    pub fn new(csv_path: Option<PathBuf>) -> TimeDemo {
+        TimeDemo {
+            csv_path,
+            started: false,
+            frame_times: Vec::new(),
+        }
+    }
+
+    /// Records the duration of the last frame.
+    pub fn record_frame(&mut self, frame_time: Duration) {
+        if self.started {
+            self.frame_times.push(frame_time);
+        } else {
+            self.started = true;
+        }
+    }
+
+    /// Returns the path the frame times should be written to.
+    pub fn csv_path(&self) -> Option<&Path> {
+        self.csv_path.as_deref()
+    }
+
+    /// Writes the duration of each frame in milliseconds as CSV.
+    pub fn write_csv<W>(&self, mut writer: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        writeln!(writer, "frame,milliseconds")?;
+        for (frame, time) in self.frame_times.iter().enumerate() {
+            writeln!(writer, "{},{:.3}", frame, millis(*time))?;
+        }
+
+        writer.flush()
+    }
+
+    /// Summarizes the recorded frame times.
+    ///
+    /// Returns `None` if no frames were recorded.
+    pub fn stats(&self) -> Option<TimeDemoStats> {
+        if self.frame_times.is_empty() {
+            return None;
+        }
+
+        // slowest first
+        let mut sorted = self.frame_times.clone();
+        sorted.sort_by(|a, b| b.cmp(a));
+
+        let total = sorted.iter().fold(Duration::zero(), |acc, t| acc + *t);
+        let frames = sorted.len();
+
+        // the mean of the slowest fraction of frames
+        let low = |fraction: f64| {
+            let count = ((frames as f64 * fraction).ceil() as usize).max(1);
+            let sum = sorted[..count]
+                .iter()
+                .fold(Duration::zero(), |acc, t| acc + *t);
+            sum / count as i32
+        };
+
+        Some(TimeDemoStats {
+            frames,
+            total,
+            slowest: sorted[0],
+            fastest: sorted[frames - 1],
+            low_1_percent: low(0.01),
+            low_0_1_percent: low(0.001),
+        })
+    }
+}
+
+/// A summary of the frame times from a timedemo.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeDemoStats {
+    pub frames: usize,
+    pub total: Duration,
+    pub slowest: Duration,
+    pub fastest: Duration,
+
+    /// The mean frame time of the slowest 1% of frames.
+    pub low_1_percent: Duration,
+
+    /// The mean frame time of the slowest 0.1% of frames.
+    pub low_0_1_percent: Duration,
+}
+
+impl TimeDemoStats {
+    pub fn average_fps(&self) -> f64 {
+        fps(self.total / self.frames as i32)
+    }
+
+    pub fn min_fps(&self) -> f64 {
+        fps(self.slowest)
+    }
+
+    pub fn max_fps(&self) -> f64 {
+        fps(self.fastest)
+    }
+}
+
+impl fmt::Display for TimeDemoStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} frames in {:.3} seconds",
+            self.frames,
+            millis(self.total) / 1000.0
+        )?;
+        writeln!(
+            f,
+            "{:.1} fps average, {:.1} min, {:.1} max",
+            self.average_fps(),
+            self.min_fps(),
+            self.max_fps()
+        )?;
+        write!(
+            f,
+            "1% low {:.2} ms, 0.1% low {:.2} ms",
+            millis(self.low_1_percent),
+            millis(self.low_0_1_percent)
+        )
+    }
+}
+
+fn millis(time: Duration) -> f64 {
+    match time.num_microseconds() {
+        Some(us) => us as f64 / 1000.0,
+        None => time.num_milliseconds() as f64,
+    }
+}
+
+fn fps(frame_time: Duration) -> f64 {
+    match millis(frame_time) {
+        ms if ms > 0.0 => 1000.0 / ms,
+        _ => f64::INFINITY,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn timedemo(frame_times_ms: &[i64]) -> TimeDemo {
+        let mut td = TimeDemo::new(None);
+
+        // the first frame is skipped
+        td.record_frame(Duration::seconds(5));
+        for ms in frame_times_ms {
+            td.record_frame(Duration::milliseconds(*ms));
+        }
+
+        td
+    }
+
+    #[test]
+    fn test_no_frames() {
+        assert!(TimeDemo::new(None).stats().is_none());
+        assert!(timedemo(&[]).stats().is_none());
+    }
+
+    #[test]
+    fn test_stats() {
+        // 998 frames at 10ms, one at 20ms and one at 50ms
+        let mut times = vec![10; 998];
+        times.push(20);
+        times.push(50);
+
+        let stats = timedemo(&times).stats().unwrap();
+        assert_eq!(stats.frames, 1000);
+        assert_eq!(stats.total, Duration::milliseconds(10050));
+        assert_eq!(stats.slowest, Duration::milliseconds(50));
+        assert_eq!(stats.fastest, Duration::milliseconds(10));
+        assert_eq!(stats.min_fps(), 20.0);
+        assert_eq!(stats.max_fps(), 100.0);
+
+        // slowest 10 frames and slowest frame
+        assert_eq!(stats.low_1_percent, Duration::milliseconds(15));
+        assert_eq!(stats.low_0_1_percent, Duration::milliseconds(50));
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let mut csv = Vec::new();
+        timedemo(&[10, 25]).write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "frame,milliseconds\n0,10.000\n1,25.000\n"
+        );
+    }
+}
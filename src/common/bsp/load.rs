@@ -31,10 +31,12 @@ use crate::common::{
     },
     math::{Axis, Hyperplane},
     model::Model,
+    parse,
     util::read_f32_3,
+    wad3::Wad3,
 };
 
-use super::{BspTextureFrame, BspTextureKind};
+use super::{BspLightmapFormat, BspTextureFrame, BspTextureKind};
 use byteorder::{LittleEndian, ReadBytesExt};
 use cgmath::{InnerSpace, Vector3};
 use failure::ResultExt as _;
@@ -42,6 +44,11 @@ use num::FromPrimitive;
 use thiserror::Error;
 
 const VERSION: i32 = 29;
+const VERSION_HALF_LIFE: i32 = 30;
+const BSP2_MAGIC: [u8; 4] = *b"BSP2";
+const BSP2_RMQ_MAGIC: [u8; 4] = *b"2PSB";
+const LIT_MAGIC: [u8; 4] = *b"QLIT";
+const LIT_VERSION: i32 = 1;
 
 pub const MAX_MODELS: usize = 256;
 const MAX_LEAVES: usize = 32767;
@@ -60,7 +67,13 @@ const MAX_TEXTURES: usize = 0x200000;
 const _MAX_LIGHTMAP: usize = 0x100000;
 const MAX_VISLIST: usize = 0x100000;
 
+// limits which are higher for Half-Life maps
+const HALF_LIFE_MAX_ENTSTRING: usize = 0x20000;
+const HALF_LIFE_MAX_PLANES: usize = 32767;
+const HALF_LIFE_MAX_VISLIST: usize = 0x200000;
+
 const TEX_NAME_MAX: usize = 16;
+const PALETTE_COLORS: usize = 256;
 
 const NUM_AMBIENTS: usize = 4;
 
@@ -92,19 +105,38 @@ const ASCII_SMALL_J: usize = 'j' as usize;
 pub enum BspFileError {
     #[error("I/O error")]
     Io(#[from] std::io::Error),
-    #[error("unsupported BSP format version (expected {}, found {0})", VERSION)]
+    #[error(
+        "unsupported BSP format version {0} (expected {}, {}, BSP2 or 2PSB)",
+        VERSION,
+        VERSION_HALF_LIFE
+    )]
     UnsupportedVersion(i32),
+    #[error(
+        "unsupported BSP format \"{0}\" (expected version {}, {}, BSP2 or 2PSB)",
+        VERSION,
+        VERSION_HALF_LIFE
+    )]
+    UnsupportedMagic(String),
     #[error("negative BSP file section offset: {0}")]
     NegativeSectionOffset(i32),
     #[error("negative BSP file section size: {0}")]
     NegativeSectionSize(i32),
     #[error(
-        "invalid BSP file section size: section {section:?} size is {size}, must be multiple of {}",
-        section.element_size(),
+        "invalid BSP file section size: section {section:?} size is {size}, must be multiple of {element_size}"
     )]
     InvalidSectionSize {
         section: BspFileSectionId,
         size: usize,
+        element_size: usize,
+    },
+    #[error(
+        "BSP file section {section:?} has {count} elements, the limit for {format:?} is {max}"
+    )]
+    LimitExceeded {
+        section: BspFileSectionId,
+        format: BspFormat,
+        count: usize,
+        max: usize,
     },
     #[error("invalid BSP texture frame specifier: {0}")]
     InvalidTextureFrameSpecifier(String),
@@ -112,6 +144,76 @@ pub enum BspFileError {
     EmptyPrimaryAnimation(String),
 }
 
+/// The variants of the BSP file format that can be loaded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BspFormat {
+    /// The original Quake format, version 29.
+    Quake,
+
+    /// The Half-Life format, version 30. It has the same layout as the Quake format, but each
+    /// texture carries its own palette (or is stored in a WAD3 archive instead), and the lightmaps
+    /// are RGB.
+    HalfLife,
+
+    /// The first extended format from the RMQ engine, identified by `2PSB`. Indices are 32 bits
+    /// wide, but node and leaf bounding boxes are still stored as 16-bit integers.
+    Bsp2Rmq,
+
+    /// The extended format identified by `BSP2`. Indices are 32 bits wide and node and leaf
+    /// bounding boxes are stored as floats.
+    Bsp2,
+}
+
+impl BspFormat {
+    // identify the format from the first 4 bytes of the file.
+    fn from_header(header: [u8; 4]) -> Result<BspFormat, BspFileError> {
+        match header {
+            BSP2_MAGIC => Ok(BspFormat::Bsp2),
+            BSP2_RMQ_MAGIC => Ok(BspFormat::Bsp2Rmq),
+            _ => match i32::from_le_bytes(header) {
+                VERSION => Ok(BspFormat::Quake),
+                VERSION_HALF_LIFE => Ok(BspFormat::HalfLife),
+                _ if header.iter().all(|b| b.is_ascii_alphanumeric()) => Err(
+                    BspFileError::UnsupportedMagic(String::from_utf8_lossy(&header).into_owned()),
+                ),
+                other => Err(BspFileError::UnsupportedVersion(other)),
+            },
+        }
+    }
+
+    // the maximum number of elements in a BSP file section, if there is one.
+    //
+    // the extended formats lift every limit except the model count, which is bounded by the
+    // network protocol.
+    fn max_count(&self, section_id: BspFileSectionId) -> Option<usize> {
+        use BspFileSectionId::*;
+        match (self, section_id) {
+            (_, Models) => Some(MAX_MODELS),
+            (BspFormat::Quake, Entities) => Some(MAX_ENTSTRING),
+            (BspFormat::Quake, Planes) => Some(MAX_PLANES),
+            (BspFormat::Quake, Vertices) => Some(MAX_VERTICES),
+            (BspFormat::Quake, Visibility) => Some(MAX_VISLIST),
+            (BspFormat::Quake, RenderNodes) => Some(MAX_RENDER_NODES),
+            (BspFormat::Quake, CollisionNodes) => Some(MAX_COLLISION_NODES),
+            (BspFormat::Quake, Leaves) => Some(MAX_LEAVES),
+            (BspFormat::Quake, Edges) => Some(MAX_EDGES),
+            (BspFormat::Quake, EdgeList) => Some(MAX_EDGELIST),
+            (BspFormat::HalfLife, Entities) => Some(HALF_LIFE_MAX_ENTSTRING),
+            (BspFormat::HalfLife, Planes) => Some(HALF_LIFE_MAX_PLANES),
+            (BspFormat::HalfLife, Visibility) => Some(HALF_LIFE_MAX_VISLIST),
+            (BspFormat::HalfLife, section_id) => BspFormat::Quake.max_count(section_id),
+            _ => None,
+        }
+    }
+
+    fn lightmap_format(&self) -> BspLightmapFormat {
+        match self {
+            BspFormat::HalfLife => BspLightmapFormat::Rgb,
+            _ => BspLightmapFormat::Mono,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 struct BspFileSection {
     offset: u64,
@@ -138,7 +240,7 @@ impl BspFileSection {
 }
 
 const SECTION_COUNT: usize = 15;
-#[derive(Debug, FromPrimitive)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive)]
 pub enum BspFileSectionId {
     Entities = 0,
     Planes = 1,
@@ -169,36 +271,55 @@ const EDGELIST_SIZE: usize = 4;
 const MODEL_SIZE: usize = 64;
 const VERTEX_SIZE: usize = 12;
 
+// sections which differ in the extended formats
+const BSP2_RMQ_RENDER_NODE_SIZE: usize = 32;
+const BSP2_RMQ_LEAF_SIZE: usize = 32;
+const BSP2_RENDER_NODE_SIZE: usize = 44;
+const BSP2_LEAF_SIZE: usize = 44;
+const BSP2_FACE_SIZE: usize = 28;
+const BSP2_COLLISION_NODE_SIZE: usize = 12;
+const BSP2_FACELIST_SIZE: usize = 4;
+const BSP2_EDGE_SIZE: usize = 8;
+
 impl BspFileSectionId {
     // the size on disk of one element of a BSP file section.
-    fn element_size(&self) -> usize {
+    fn element_size(&self, format: BspFormat) -> usize {
         use BspFileSectionId::*;
-        match self {
-            Entities => size_of::<u8>(),
-            Planes => PLANE_SIZE,
-            Textures => size_of::<u8>(),
-            Vertices => VERTEX_SIZE,
-            Visibility => size_of::<u8>(),
-            RenderNodes => RENDER_NODE_SIZE,
-            TextureInfo => TEXTURE_INFO_SIZE,
-            Faces => FACE_SIZE,
-            Lightmaps => size_of::<u8>(),
-            CollisionNodes => COLLISION_NODE_SIZE,
-            Leaves => LEAF_SIZE,
-            FaceList => FACELIST_SIZE,
-            Edges => EDGE_SIZE,
-            EdgeList => EDGELIST_SIZE,
-            Models => MODEL_SIZE,
+        match (self, format) {
+            (Entities, _) => size_of::<u8>(),
+            (Planes, _) => PLANE_SIZE,
+            (Textures, _) => size_of::<u8>(),
+            (Vertices, _) => VERTEX_SIZE,
+            (Visibility, _) => size_of::<u8>(),
+            (RenderNodes, BspFormat::Quake | BspFormat::HalfLife) => RENDER_NODE_SIZE,
+            (RenderNodes, BspFormat::Bsp2Rmq) => BSP2_RMQ_RENDER_NODE_SIZE,
+            (RenderNodes, BspFormat::Bsp2) => BSP2_RENDER_NODE_SIZE,
+            (TextureInfo, _) => TEXTURE_INFO_SIZE,
+            (Faces, BspFormat::Quake | BspFormat::HalfLife) => FACE_SIZE,
+            (Faces, _) => BSP2_FACE_SIZE,
+            (Lightmaps, _) => size_of::<u8>(),
+            (CollisionNodes, BspFormat::Quake | BspFormat::HalfLife) => COLLISION_NODE_SIZE,
+            (CollisionNodes, _) => BSP2_COLLISION_NODE_SIZE,
+            (Leaves, BspFormat::Quake | BspFormat::HalfLife) => LEAF_SIZE,
+            (Leaves, BspFormat::Bsp2Rmq) => BSP2_RMQ_LEAF_SIZE,
+            (Leaves, BspFormat::Bsp2) => BSP2_LEAF_SIZE,
+            (FaceList, BspFormat::Quake | BspFormat::HalfLife) => FACELIST_SIZE,
+            (FaceList, _) => BSP2_FACELIST_SIZE,
+            (Edges, BspFormat::Quake | BspFormat::HalfLife) => EDGE_SIZE,
+            (Edges, _) => BSP2_EDGE_SIZE,
+            (EdgeList, _) => EDGELIST_SIZE,
+            (Models, _) => MODEL_SIZE,
         }
     }
 }
 
 struct BspFileTable {
+    format: BspFormat,
     sections: [BspFileSection; SECTION_COUNT],
 }
 
 impl BspFileTable {
-    fn read_from<R>(reader: &mut R) -> Result<BspFileTable, BspFileError>
+    fn read_from<R>(reader: &mut R, format: BspFormat) -> Result<BspFileTable, BspFileError>
     where
         R: ReadBytesExt,
     {
@@ -207,15 +328,34 @@ impl BspFileTable {
         for (id, section) in sections.iter_mut().enumerate() {
             *section = BspFileSection::read_from(reader)?;
             let section_id = BspFileSectionId::from_usize(id).unwrap();
-            if section.size % section_id.element_size() != 0 {
+            let element_size = section_id.element_size(format);
+            if section.size % element_size != 0 {
                 Err(BspFileError::InvalidSectionSize {
                     section: section_id,
                     size: section.size,
+                    element_size,
                 })?
             }
+
+            let count = section.size / element_size;
+            if let Some(max) = format.max_count(section_id) {
+                if count > max {
+                    Err(BspFileError::LimitExceeded {
+                        section: section_id,
+                        format,
+                        count,
+                        max,
+                    })?
+                }
+            }
         }
 
-        Ok(BspFileTable { sections })
+        Ok(BspFileTable { format, sections })
+    }
+
+    // the number of elements in a section.
+    fn count(&self, section_id: BspFileSectionId) -> usize {
+        self.section(section_id).size / section_id.element_size(self.format)
     }
 
     fn section(&self, section_id: BspFileSectionId) -> BspFileSection {
@@ -265,14 +405,22 @@ struct BspFileTexture {
     width: u32,
     height: u32,
     mipmaps: [Vec<u8>; MIPLEVELS],
+    palette: Option<Box<[u8]>>,
+
+    // whether the texture data is stored in a WAD3 archive instead of the BSP file
+    external: bool,
 }
 
 // load a textures from the BSP file.
 //
 // converts the texture's name to all lowercase, including its frame specifier
 // if it has one.
+//
+// Half-Life textures are followed by their palette, or have no mipmap offsets
+// if they're stored in a WAD3 archive.
 fn load_texture<R>(
     mut reader: &mut R,
+    format: BspFormat,
     tex_section_ofs: u64,
     tex_ofs: u64,
 ) -> Result<BspFileTexture, failure::Error>
@@ -298,6 +446,17 @@ where
         mip_offsets[m] = reader.read_u32::<LittleEndian>()? as usize;
     }
 
+    if format == BspFormat::HalfLife && mip_offsets.iter().all(|ofs| *ofs == 0) {
+        return Ok(BspFileTexture {
+            name: tex_name,
+            width,
+            height,
+            mipmaps: [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
+            palette: None,
+            external: true,
+        });
+    }
+
     let mut mipmaps = [Vec::new(), Vec::new(), Vec::new(), Vec::new()];
     for m in 0..MIPLEVELS {
         let factor = 2usize.pow(m as u32);
@@ -309,15 +468,35 @@ where
             .read_to_end(&mut mipmaps[m])?;
     }
 
+    let palette = match format {
+        // the reader is at the end of the last mipmap
+        BspFormat::HalfLife => {
+            let color_count = reader.read_u16::<LittleEndian>()? as usize;
+            ensure!(
+                color_count == PALETTE_COLORS,
+                "Invalid palette size for texture {}: {}",
+                tex_name,
+                color_count
+            );
+            let mut palette = vec![0; 3 * PALETTE_COLORS];
+            reader.read_exact(&mut palette)?;
+            Some(palette.into_boxed_slice())
+        }
+
+        _ => None,
+    };
+
     Ok(BspFileTexture {
         name: tex_name,
         width,
         height,
         mipmaps,
+        palette,
+        external: false,
     })
 }
 
-fn load_render_node<R>(reader: &mut R) -> Result<BspRenderNode, failure::Error>
+fn load_render_node<R>(reader: &mut R, format: BspFormat) -> Result<BspRenderNode, failure::Error>
 where
     R: ReadBytesExt,
 {
@@ -329,36 +508,47 @@ where
     // If the child ID is positive, it points to another internal node. If it is negative, its
     // bitwise negation points to a leaf node.
 
-    let front = match reader.read_i16::<LittleEndian>()? {
+    let front = match read_index(reader, format)? {
         f if f < 0 => BspRenderNodeChild::Leaf((!f) as usize),
         f => BspRenderNodeChild::Node(f as usize),
     };
 
-    let back = match reader.read_i16::<LittleEndian>()? {
+    let back = match read_index(reader, format)? {
         b if b < 0 => BspRenderNodeChild::Leaf((!b) as usize),
         b => BspRenderNodeChild::Node(b as usize),
     };
 
-    let min = read_i16_3(reader)?;
-    let max = read_i16_3(reader)?;
+    let min = read_bounds(reader, format)?;
+    let max = read_bounds(reader, format)?;
 
-    let face_id = reader.read_i16::<LittleEndian>()?;
-    if face_id < 0 {
-        bail!("Invalid face id");
-    }
+    let (face_id, face_count) = match format {
+        BspFormat::Quake | BspFormat::HalfLife => {
+            let face_id = reader.read_i16::<LittleEndian>()?;
+            if face_id < 0 {
+                bail!("Invalid face id");
+            }
 
-    let face_count = reader.read_u16::<LittleEndian>()?;
-    if face_count as usize > MAX_FACES {
-        bail!("Invalid face count");
-    }
+            let face_count = reader.read_u16::<LittleEndian>()?;
+            if face_count as usize > MAX_FACES {
+                bail!("Invalid face count");
+            }
+
+            (face_id as usize, face_count as usize)
+        }
+
+        BspFormat::Bsp2Rmq | BspFormat::Bsp2 => (
+            reader.read_u32::<LittleEndian>()? as usize,
+            reader.read_u32::<LittleEndian>()? as usize,
+        ),
+    };
 
     Ok(BspRenderNode {
         plane_id: plane_id as usize,
         children: [front, back],
         min,
         max,
-        face_id: face_id as usize,
-        face_count: face_count as usize,
+        face_id,
+        face_count,
     })
 }
 
@@ -395,18 +585,41 @@ where
 
 /// Load a BSP file, returning the models it contains and a `String` describing the entities
 /// it contains.
+///
+/// Textures which a Half-Life map leaves to WAD3 archives are left blank. Use [`load_with`] to
+/// load them, or to add colored lighting from a `.lit` file.
 pub fn load<R>(data: R) -> Result<(Vec<Model>, String), failure::Error>
 where
     R: Read + Seek,
+{
+    load_with(data, None, |_| None)
+}
+
+/// Load a BSP file like [`load`], along with the files that accompany it.
+///
+/// `lit` holds the contents of the map's `.lit` file, if it has one. Its RGB lightmaps replace
+/// the monochrome ones in the BSP; an invalid `.lit` file is ignored with a warning.
+///
+/// `open_wad` loads the textures which aren't stored in the file from the WAD3 archives listed by
+/// the worldspawn entity. It is called with the file name of each archive, without its directory,
+/// and returns `None` if the archive can't be opened.
+pub fn load_with<R, F>(
+    data: R,
+    lit: Option<&[u8]>,
+    mut open_wad: F,
+) -> Result<(Vec<Model>, String), failure::Error>
+where
+    R: Read + Seek,
+    F: FnMut(&str) -> Option<Wad3>,
 {
     let mut reader = BufReader::new(data);
 
-    let _version = match reader.read_i32::<LittleEndian>()? {
-        VERSION => Ok(VERSION),
-        other => Err(BspFileError::UnsupportedVersion(other)),
-    }?;
+    let mut header = [0; 4];
+    reader.read_exact(&mut header)?;
+    let format = BspFormat::from_header(header)?;
+    debug!("BSP format: {:?}", format);
 
-    let table = BspFileTable::read_from(&mut reader)?;
+    let table = BspFileTable::read_from(&mut reader, format)?;
 
     let ent_section = table.section(BspFileSectionId::Entities);
     let plane_section = table.section(BspFileSectionId::Planes);
@@ -424,55 +637,27 @@ where
     let model_section = table.section(BspFileSectionId::Models);
     let render_node_section = table.section(BspFileSectionId::RenderNodes);
 
-    let plane_count = plane_section.size / PLANE_SIZE;
-    let vert_count = vert_section.size / VERTEX_SIZE;
-    let render_node_count = render_node_section.size / RENDER_NODE_SIZE;
-    let texinfo_count = texinfo_section.size / TEXTURE_INFO_SIZE;
-    let face_count = face_section.size / FACE_SIZE;
-    let collision_node_count = collision_node_section.size / COLLISION_NODE_SIZE;
-    let leaf_count = leaf_section.size / LEAF_SIZE;
-    let facelist_count = facelist_section.size / FACELIST_SIZE;
-    let edge_count = edge_section.size / EDGE_SIZE;
-    let edgelist_count = edgelist_section.size / EDGELIST_SIZE;
-    let model_count = model_section.size / MODEL_SIZE;
-
-    // check limits
-    ensure!(plane_count <= MAX_PLANES, "Plane count exceeds MAX_PLANES");
-    ensure!(
-        vert_count <= MAX_VERTICES,
-        "Vertex count exceeds MAX_VERTICES"
-    );
-    ensure!(
-        vis_section.size <= MAX_VISLIST,
-        "Visibility data size exceeds MAX_VISLIST"
-    );
-    ensure!(
-        render_node_count <= MAX_RENDER_NODES,
-        "Render node count exceeds MAX_RENDER_NODES"
-    );
-    ensure!(
-        collision_node_count <= MAX_COLLISION_NODES,
-        "Collision node count exceeds MAX_COLLISION_NODES"
-    );
-    ensure!(leaf_count <= MAX_LEAVES, "Leaf count exceeds MAX_LEAVES");
-    ensure!(edge_count <= MAX_EDGES, "Edge count exceeds MAX_EDGES");
-    ensure!(
-        edgelist_count <= MAX_EDGELIST,
-        "Edge list count exceeds MAX_EDGELIST"
-    );
+    let plane_count = table.count(BspFileSectionId::Planes);
+    let vert_count = table.count(BspFileSectionId::Vertices);
+    let render_node_count = table.count(BspFileSectionId::RenderNodes);
+    let texinfo_count = table.count(BspFileSectionId::TextureInfo);
+    let face_count = table.count(BspFileSectionId::Faces);
+    let collision_node_count = table.count(BspFileSectionId::CollisionNodes);
+    let leaf_count = table.count(BspFileSectionId::Leaves);
+    let facelist_count = table.count(BspFileSectionId::FaceList);
+    let edge_count = table.count(BspFileSectionId::Edges);
+    let edgelist_count = table.count(BspFileSectionId::EdgeList);
+    let model_count = table.count(BspFileSectionId::Models);
+
+    // the per-format limits are checked by BspFileTable::read_from
     ensure!(
         model_count > 0,
         "No brush models (need at least 1 for worldmodel)"
     );
-    ensure!(model_count <= MAX_MODELS, "Model count exceeds MAX_MODELS");
 
     reader.seek(SeekFrom::Start(ent_section.offset))?;
-    let mut ent_data = Vec::with_capacity(MAX_ENTSTRING);
+    let mut ent_data = Vec::with_capacity(ent_section.size);
     reader.read_until(0x00, &mut ent_data)?;
-    ensure!(
-        ent_data.len() <= MAX_ENTSTRING,
-        "Entity data exceeds MAX_ENTSTRING"
-    );
     let ent_string =
         String::from_utf8(ent_data).context("Failed to create string from entity data")?;
     table.check_end_position(&mut reader, BspFileSectionId::Entities)?;
@@ -512,7 +697,8 @@ where
         match tex_ofs {
             Some(ofs) => {
                 reader.seek(SeekFrom::Start(tex_section.offset + ofs as u64))?;
-                let texture = load_texture(&mut reader, tex_section.offset as u64, ofs as u64)?;
+                let texture =
+                    load_texture(&mut reader, format, tex_section.offset as u64, ofs as u64)?;
                 debug!(
                     "Texture {id:>width$}: {name}",
                     id = id,
@@ -529,11 +715,46 @@ where
                     width: 0,
                     height: 0,
                     mipmaps: [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
+                    palette: None,
+                    external: false,
                 });
             }
         }
     }
-    table.check_end_position(&mut reader, BspFileSectionId::Textures)?;
+
+    // Half-Life textures are followed by padding and need not be stored in order, so there's no
+    // telling where the last one ends
+    if format != BspFormat::HalfLife {
+        table.check_end_position(&mut reader, BspFileSectionId::Textures)?;
+    }
+
+    if file_textures.iter().any(|tex| tex.external) {
+        let wads: Vec<_> = worldspawn_wads(&ent_string)
+            .iter()
+            .filter_map(|name| open_wad(name))
+            .collect();
+
+        for texture in file_textures.iter_mut().filter(|tex| tex.external) {
+            match wads.iter().find(|wad| wad.contains(&texture.name)) {
+                Some(wad) => {
+                    let wad_texture = wad.open_texture(&texture.name)?;
+                    texture.width = wad_texture.width;
+                    texture.height = wad_texture.height;
+                    texture.mipmaps = wad_texture.mipmaps;
+                    texture.palette = Some(wad_texture.palette);
+                }
+
+                // leave the texture blank rather than refusing to load the map
+                None => {
+                    warn!("Texture {} not found in any WAD", texture.name);
+                    for (m, mipmap) in texture.mipmaps.iter_mut().enumerate() {
+                        let size = (texture.width as usize >> m) * (texture.height as usize >> m);
+                        *mipmap = vec![0; size];
+                    }
+                }
+            }
+        }
+    }
 
     struct BspFileTextureAnimations {
         primary: Vec<(usize, BspFileTexture)>,
@@ -593,16 +814,22 @@ where
                     width,
                     height,
                     mipmaps,
+                    palette,
+                    ..
                 } = file_texture;
 
                 let texture_id = textures.len();
                 static_texture_ids.insert(file_texture_id, texture_id);
 
                 textures.push(BspTexture {
-                    name,
+                    name: name.clone(),
                     width,
                     height,
-                    kind: BspTextureKind::Static(BspTextureFrame { mipmaps }),
+                    kind: BspTextureKind::Static(BspTextureFrame {
+                        name,
+                        mipmaps,
+                        palette,
+                    }),
                 });
             }
         };
@@ -639,7 +866,9 @@ where
             );
             corresponding_file_ids.push(file_id);
             primary.push(BspTextureFrame {
+                name: file_texture.name,
                 mipmaps: file_texture.mipmaps,
+                palette: file_texture.palette,
             });
         }
 
@@ -652,7 +881,9 @@ where
                 for (file_id, file_texture) in alt {
                     alt_corresp_file_ids.push(file_id);
                     alternate.push(BspTextureFrame {
+                        name: file_texture.name,
                         mipmaps: file_texture.mipmaps,
+                        palette: file_texture.palette,
                     });
                 }
                 Some(alternate)
@@ -717,7 +948,7 @@ where
     debug!("Render node count = {}", render_node_count);
     let mut render_nodes = Vec::with_capacity(render_node_count);
     for _ in 0..render_node_count {
-        render_nodes.push(load_render_node(&mut reader)?);
+        render_nodes.push(load_render_node(&mut reader, format)?);
     }
     table.check_end_position(&mut reader, BspFileSectionId::RenderNodes)?;
 
@@ -736,54 +967,7 @@ where
     reader.seek(SeekFrom::Start(face_section.offset))?;
     let mut faces = Vec::with_capacity(face_count);
     for _ in 0..face_count {
-        let plane_id = reader.read_i16::<LittleEndian>()?;
-        if plane_id < 0 || plane_id as usize > plane_count {
-            bail!("Invalid plane count");
-        }
-
-        let side = match reader.read_i16::<LittleEndian>()? {
-            0 => BspFaceSide::Front,
-            1 => BspFaceSide::Back,
-            _ => bail!("Invalid face side"),
-        };
-
-        let edge_id = reader.read_i32::<LittleEndian>()?;
-        if edge_id < 0 {
-            bail!("Invalid edge ID");
-        }
-
-        let edge_count = reader.read_i16::<LittleEndian>()?;
-        if edge_count < 3 {
-            bail!("Invalid edge count");
-        }
-
-        let texinfo_id = reader.read_i16::<LittleEndian>()?;
-        if texinfo_id < 0 || texinfo_id as usize > texinfo_count {
-            bail!("Invalid texinfo ID");
-        }
-
-        let mut light_styles = [0; MAX_LIGHTSTYLES];
-        for i in 0..light_styles.len() {
-            light_styles[i] = reader.read_u8()?;
-        }
-
-        let lightmap_id = match reader.read_i32::<LittleEndian>()? {
-            o if o < -1 => bail!("Invalid lightmap offset"),
-            -1 => None,
-            o => Some(o as usize),
-        };
-
-        faces.push(BspFace {
-            plane_id: plane_id as usize,
-            side,
-            edge_id: edge_id as usize,
-            edge_count: edge_count as usize,
-            texinfo_id: texinfo_id as usize,
-            light_styles,
-            lightmap_id,
-            texture_mins: [0, 0],
-            extents: [0, 0],
-        });
+        faces.push(load_face(&mut reader, format, plane_count, texinfo_count)?);
     }
     table.check_end_position(&mut reader, BspFileSectionId::Faces)?;
 
@@ -794,35 +978,26 @@ where
         .read_to_end(&mut lightmaps)?;
     table.check_end_position(&mut reader, BspFileSectionId::Lightmaps)?;
 
+    let mut lightmap_format = format.lightmap_format();
+    if let Some(lit) = lit {
+        if lightmap_format == BspLightmapFormat::Mono {
+            match read_lit(lit, lightmaps.len()) {
+                Ok(rgb) => {
+                    lightmaps = rgb;
+                    lightmap_format = BspLightmapFormat::Rgb;
+                }
+                Err(e) => warn!("Ignoring .lit file: {}", e),
+            }
+        } else {
+            warn!("Ignoring .lit file for a map with colored lighting");
+        }
+    }
+
     reader.seek(SeekFrom::Start(collision_node_section.offset))?;
 
     let mut collision_nodes = Vec::with_capacity(collision_node_count);
     for _ in 0..collision_node_count {
-        let plane_id = match reader.read_i32::<LittleEndian>()? {
-            x if x < 0 => bail!("Invalid plane id"),
-            x => x as usize,
-        };
-
-        let front = match reader.read_i16::<LittleEndian>()? {
-            x if x < 0 => match BspLeafContents::from_i16(-x) {
-                Some(c) => BspCollisionNodeChild::Contents(c),
-                None => bail!("Invalid leaf contents ({})", -x),
-            },
-            x => BspCollisionNodeChild::Node(x as usize),
-        };
-
-        let back = match reader.read_i16::<LittleEndian>()? {
-            x if x < 0 => match BspLeafContents::from_i16(-x) {
-                Some(c) => BspCollisionNodeChild::Contents(c),
-                None => bail!("Invalid leaf contents ({})", -x),
-            },
-            x => BspCollisionNodeChild::Node(x as usize),
-        };
-
-        collision_nodes.push(BspCollisionNode {
-            plane_id,
-            children: [front, back],
-        });
+        collision_nodes.push(load_collision_node(&mut reader, format)?);
     }
 
     let collision_nodes_rc = Rc::new(collision_nodes.into_boxed_slice());
@@ -867,44 +1042,14 @@ where
     // });
 
     for _ in 0..leaf_count {
-        // note the negation here (the constants are negative in the original engine to differentiate
-        // them from plane IDs)
-        let contents_id = -reader.read_i32::<LittleEndian>()?;
-
-        let contents = match BspLeafContents::from_i32(contents_id) {
-            Some(c) => c,
-            None => bail!("Invalid leaf contents ({})", contents_id),
-        };
-
-        let vis_offset = match reader.read_i32::<LittleEndian>()? {
-            x if x < -1 => bail!("Invalid visibility data offset"),
-            -1 => None,
-            x => Some(x as usize),
-        };
-
-        let min = read_i16_3(&mut reader)?;
-        let max = read_i16_3(&mut reader)?;
-
-        let facelist_id = reader.read_u16::<LittleEndian>()? as usize;
-        let facelist_count = reader.read_u16::<LittleEndian>()? as usize;
-        let mut sounds = [0u8; NUM_AMBIENTS];
-        reader.read_exact(&mut sounds)?;
-        leaves.push(BspLeaf {
-            contents,
-            vis_offset,
-            min,
-            max,
-            facelist_id,
-            facelist_count,
-            sounds,
-        });
+        leaves.push(load_leaf(&mut reader, format)?);
     }
     table.check_end_position(&mut reader, BspFileSectionId::Leaves)?;
 
     reader.seek(SeekFrom::Start(facelist_section.offset))?;
     let mut facelist = Vec::with_capacity(facelist_count);
     for _ in 0..facelist_count {
-        facelist.push(reader.read_u16::<LittleEndian>()? as usize);
+        facelist.push(read_unsigned_index(&mut reader, format)? as usize);
     }
     if reader.seek(SeekFrom::Current(0))?
         != reader.seek(SeekFrom::Start(
@@ -919,8 +1064,8 @@ where
     for _ in 0..edge_count {
         edges.push(BspEdge {
             vertex_ids: [
-                reader.read_u16::<LittleEndian>()?,
-                reader.read_u16::<LittleEndian>()?,
+                read_unsigned_index(&mut reader, format)?,
+                read_unsigned_index(&mut reader, format)?,
             ],
         });
     }
@@ -1036,6 +1181,7 @@ where
         texinfo: texinfo.into_boxed_slice(),
         faces: faces.into_boxed_slice(),
         lightmaps: lightmaps.into_boxed_slice(),
+        lightmap_format,
         hulls: [hull_0, hull_1, hull_2],
         leaves: leaves.into_boxed_slice(),
         facelist: facelist.into_boxed_slice(),
@@ -1123,6 +1269,223 @@ where
     Ok((models, ent_string))
 }
 
+// the file names of the WAD3 archives listed in the worldspawn entity's `wad` key.
+//
+// the key holds semicolon-separated paths on the machine which compiled the map.
+fn worldspawn_wads(ent_string: &str) -> Vec<String> {
+    let entities = match parse::entities(ent_string) {
+        Ok(e) => e,
+        Err(e) => {
+            warn!("Failed to parse entities: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let paths = match entities
+        .first()
+        .and_then(|worldspawn| worldspawn.get("wad"))
+    {
+        Some(p) => *p,
+        None => return Vec::new(),
+    };
+
+    paths
+        .split(';')
+        .filter_map(|path| path.rsplit(&['/', '\\'][..]).next())
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_owned())
+        .collect()
+}
+
+// the RGB lightmaps in a .lit file, which follow an 8-byte header of magic and version.
+//
+// the samples are in the same order as those of the BSP's monochrome lightmaps, of which there are
+// `mono_len`.
+fn read_lit(lit: &[u8], mono_len: usize) -> Result<Vec<u8>, failure::Error> {
+    let mut reader = lit;
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    ensure!(magic == LIT_MAGIC, "Bad magic number {:?}", magic);
+
+    let version = reader.read_i32::<LittleEndian>()?;
+    ensure!(version == LIT_VERSION, "Unsupported version {}", version);
+
+    ensure!(
+        reader.len() == 3 * mono_len,
+        "Lightmaps are {} bytes long, expected {}",
+        reader.len(),
+        3 * mono_len
+    );
+
+    Ok(reader.to_vec())
+}
+
+fn load_face<R>(
+    reader: &mut R,
+    format: BspFormat,
+    plane_count: usize,
+    texinfo_count: usize,
+) -> Result<BspFace, failure::Error>
+where
+    R: ReadBytesExt,
+{
+    let plane_id = read_index(reader, format)?;
+    if plane_id < 0 || plane_id as usize > plane_count {
+        bail!("Invalid plane count");
+    }
+
+    let side = match read_index(reader, format)? {
+        0 => BspFaceSide::Front,
+        1 => BspFaceSide::Back,
+        _ => bail!("Invalid face side"),
+    };
+
+    let edge_id = reader.read_i32::<LittleEndian>()?;
+    if edge_id < 0 {
+        bail!("Invalid edge ID");
+    }
+
+    let edge_count = read_index(reader, format)?;
+    if edge_count < 3 {
+        bail!("Invalid edge count");
+    }
+
+    let texinfo_id = read_index(reader, format)?;
+    if texinfo_id < 0 || texinfo_id as usize > texinfo_count {
+        bail!("Invalid texinfo ID");
+    }
+
+    let mut light_styles = [0; MAX_LIGHTSTYLES];
+    reader.read_exact(&mut light_styles)?;
+
+    // the offset is given in bytes, so convert it to samples
+    let lightmap_id = match reader.read_i32::<LittleEndian>()? {
+        o if o < -1 => bail!("Invalid lightmap offset"),
+        -1 => None,
+        o => Some(o as usize / format.lightmap_format().channels()),
+    };
+
+    Ok(BspFace {
+        plane_id: plane_id as usize,
+        side,
+        edge_id: edge_id as usize,
+        edge_count: edge_count as usize,
+        texinfo_id: texinfo_id as usize,
+        light_styles,
+        lightmap_id,
+        texture_mins: [0, 0],
+        extents: [0, 0],
+    })
+}
+
+fn load_collision_node<R>(
+    reader: &mut R,
+    format: BspFormat,
+) -> Result<BspCollisionNode, failure::Error>
+where
+    R: ReadBytesExt,
+{
+    let plane_id = match reader.read_i32::<LittleEndian>()? {
+        x if x < 0 => bail!("Invalid plane id"),
+        x => x as usize,
+    };
+
+    // negative child IDs are the negated contents of an implicit leaf
+    let mut read_child = || -> Result<BspCollisionNodeChild, failure::Error> {
+        Ok(match read_index(reader, format)? {
+            x if x < 0 => match BspLeafContents::from_i32(-x) {
+                Some(c) => BspCollisionNodeChild::Contents(c),
+                None => bail!("Invalid leaf contents ({})", -x),
+            },
+            x => BspCollisionNodeChild::Node(x as usize),
+        })
+    };
+
+    let front = read_child()?;
+    let back = read_child()?;
+
+    Ok(BspCollisionNode {
+        plane_id,
+        children: [front, back],
+    })
+}
+
+fn load_leaf<R>(reader: &mut R, format: BspFormat) -> Result<BspLeaf, failure::Error>
+where
+    R: ReadBytesExt,
+{
+    // note the negation here (the constants are negative in the original engine to differentiate
+    // them from plane IDs)
+    let contents_id = -reader.read_i32::<LittleEndian>()?;
+
+    let contents = match BspLeafContents::from_i32(contents_id) {
+        Some(c) => c,
+        None => bail!("Invalid leaf contents ({})", contents_id),
+    };
+
+    let vis_offset = match reader.read_i32::<LittleEndian>()? {
+        x if x < -1 => bail!("Invalid visibility data offset"),
+        -1 => None,
+        x => Some(x as usize),
+    };
+
+    let min = read_bounds(reader, format)?;
+    let max = read_bounds(reader, format)?;
+
+    let facelist_id = read_unsigned_index(reader, format)? as usize;
+    let facelist_count = read_unsigned_index(reader, format)? as usize;
+    let mut sounds = [0u8; NUM_AMBIENTS];
+    reader.read_exact(&mut sounds)?;
+
+    Ok(BspLeaf {
+        contents,
+        vis_offset,
+        min,
+        max,
+        facelist_id,
+        facelist_count,
+        sounds,
+    })
+}
+
+// read a signed index, which is 16 bits wide in the original format and 32 bits in the extended
+// formats.
+fn read_index<R>(reader: &mut R, format: BspFormat) -> Result<i32, std::io::Error>
+where
+    R: ReadBytesExt,
+{
+    match format {
+        BspFormat::Quake | BspFormat::HalfLife => Ok(reader.read_i16::<LittleEndian>()? as i32),
+        BspFormat::Bsp2Rmq | BspFormat::Bsp2 => reader.read_i32::<LittleEndian>(),
+    }
+}
+
+// read an unsigned index, which is 16 bits wide in the original format and 32 bits in the
+// extended formats.
+fn read_unsigned_index<R>(reader: &mut R, format: BspFormat) -> Result<u32, std::io::Error>
+where
+    R: ReadBytesExt,
+{
+    match format {
+        BspFormat::Quake | BspFormat::HalfLife => Ok(reader.read_u16::<LittleEndian>()? as u32),
+        BspFormat::Bsp2Rmq | BspFormat::Bsp2 => reader.read_u32::<LittleEndian>(),
+    }
+}
+
+// read one corner of a node or leaf bounding box.
+fn read_bounds<R>(reader: &mut R, format: BspFormat) -> Result<[f32; 3], std::io::Error>
+where
+    R: ReadBytesExt,
+{
+    match format {
+        BspFormat::Quake | BspFormat::HalfLife | BspFormat::Bsp2Rmq => {
+            let [x, y, z] = read_i16_3(reader)?;
+            Ok([x as f32, y as f32, z as f32])
+        }
+        BspFormat::Bsp2 => read_f32_3(reader),
+    }
+}
+
 fn read_i16_3<R>(reader: &mut R) -> Result<[i16; 3], std::io::Error>
 where
     R: ReadBytesExt,
@@ -1131,3 +1494,336 @@ where
     reader.read_i16_into::<LittleEndian>(&mut ar)?;
     Ok(ar)
 }
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    use crate::common::{
+        bsp::BspTextureMipmap,
+        model::ModelKind,
+        wad3::{
+            test::{miptex_fixture, wad_fixture},
+            TYPE_MIPTEX,
+        },
+    };
+    use byteorder::WriteBytesExt;
+    use std::io::Cursor;
+
+    // write a signed index in the width used by the format.
+    fn write_index(buf: &mut Vec<u8>, format: BspFormat, x: i32) {
+        match format {
+            BspFormat::Quake | BspFormat::HalfLife => {
+                buf.write_i16::<LittleEndian>(x as i16).unwrap()
+            }
+            _ => buf.write_i32::<LittleEndian>(x).unwrap(),
+        }
+    }
+
+    fn write_bounds(buf: &mut Vec<u8>, format: BspFormat, bounds: [f32; 3]) {
+        for x in bounds.iter() {
+            match format {
+                BspFormat::Bsp2 => buf.write_f32::<LittleEndian>(*x).unwrap(),
+                _ => buf.write_i16::<LittleEndian>(*x as i16).unwrap(),
+            }
+        }
+    }
+
+    fn write_i32s(buf: &mut Vec<u8>, xs: &[i32]) {
+        for x in xs {
+            buf.write_i32::<LittleEndian>(*x).unwrap();
+        }
+    }
+
+    fn write_f32s(buf: &mut Vec<u8>, xs: &[f32]) {
+        for x in xs {
+            buf.write_f32::<LittleEndian>(*x).unwrap();
+        }
+    }
+
+    // a map with a single triangle in an empty leaf, split from a solid leaf by the plane z = 0.
+    pub fn bsp_fixture(format: BspFormat) -> Vec<u8> {
+        // one texture, not stored in the file
+        let mut textures = Vec::new();
+        write_i32s(&mut textures, &[1, -1]);
+
+        bsp_fixture_with(
+            format,
+            b"{\n\"classname\" \"worldspawn\"\n}\n\0",
+            textures,
+            Vec::new(),
+        )
+    }
+
+    // the triangle uses texture 0 and, if there are any lightmaps, the lightmap at offset 0.
+    fn bsp_fixture_with(
+        format: BspFormat,
+        entities: &[u8],
+        textures: Vec<u8>,
+        lightmaps: Vec<u8>,
+    ) -> Vec<u8> {
+        let mut sections = vec![Vec::new(); SECTION_COUNT];
+
+        sections[BspFileSectionId::Entities as usize].extend_from_slice(entities);
+
+        // normal, distance and axis
+        let planes = &mut sections[BspFileSectionId::Planes as usize];
+        write_f32s(planes, &[0.0, 0.0, 1.0, 0.0]);
+        write_i32s(planes, &[Axis::Z as i32]);
+
+        sections[BspFileSectionId::Textures as usize] = textures;
+
+        write_f32s(
+            &mut sections[BspFileSectionId::Vertices as usize],
+            &[0.0, 0.0, 0.0, 64.0, 0.0, 0.0, 0.0, 64.0, 0.0],
+        );
+
+        let render_nodes = &mut sections[BspFileSectionId::RenderNodes as usize];
+        write_i32s(render_nodes, &[0]);
+        write_index(render_nodes, format, -2);
+        write_index(render_nodes, format, -1);
+        write_bounds(render_nodes, format, [-64.0, -64.0, -64.0]);
+        write_bounds(render_nodes, format, [64.0, 64.0, 64.0]);
+        write_index(render_nodes, format, 0);
+        write_index(render_nodes, format, 1);
+
+        let texinfo = &mut sections[BspFileSectionId::TextureInfo as usize];
+        write_f32s(texinfo, &[1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+        write_i32s(texinfo, &[0, 0]);
+
+        let faces = &mut sections[BspFileSectionId::Faces as usize];
+        write_index(faces, format, 0);
+        write_index(faces, format, 0);
+        write_i32s(faces, &[0]);
+        write_index(faces, format, 3);
+        write_index(faces, format, 0);
+        faces.extend_from_slice(&[0, 255, 255, 255]);
+        write_i32s(faces, &[if lightmaps.is_empty() { -1 } else { 0 }]);
+        sections[BspFileSectionId::Lightmaps as usize] = lightmaps;
+
+        let collision_nodes = &mut sections[BspFileSectionId::CollisionNodes as usize];
+        write_i32s(collision_nodes, &[0]);
+        write_index(collision_nodes, format, -(BspLeafContents::Empty as i32));
+        write_index(collision_nodes, format, -(BspLeafContents::Solid as i32));
+
+        let leaves = &mut sections[BspFileSectionId::Leaves as usize];
+        for (contents, facelist_count) in [(BspLeafContents::Solid, 0), (BspLeafContents::Empty, 1)]
+        {
+            write_i32s(leaves, &[-(contents as i32), -1]);
+            write_bounds(leaves, format, [-64.0, -64.0, 0.0]);
+            write_bounds(leaves, format, [64.0, 64.0, 64.0]);
+            write_index(leaves, format, 0);
+            write_index(leaves, format, facelist_count);
+            leaves.extend_from_slice(&[0; NUM_AMBIENTS]);
+        }
+
+        write_index(
+            &mut sections[BspFileSectionId::FaceList as usize],
+            format,
+            0,
+        );
+
+        // edge 0 is never used, since it can't be negated
+        let edges = &mut sections[BspFileSectionId::Edges as usize];
+        for v in [0, 0, 0, 1, 1, 2, 2, 0] {
+            write_index(edges, format, v);
+        }
+
+        write_i32s(
+            &mut sections[BspFileSectionId::EdgeList as usize],
+            &[1, 2, 3],
+        );
+
+        let models = &mut sections[BspFileSectionId::Models as usize];
+        write_f32s(
+            models,
+            &[-64.0, -64.0, -64.0, 64.0, 64.0, 64.0, 0.0, 0.0, 0.0],
+        );
+        write_i32s(models, &[0, 0, 0, 0, 1, 0, 1]);
+
+        let mut bsp = match format {
+            BspFormat::Quake => VERSION.to_le_bytes().to_vec(),
+            BspFormat::HalfLife => VERSION_HALF_LIFE.to_le_bytes().to_vec(),
+            BspFormat::Bsp2Rmq => BSP2_RMQ_MAGIC.to_vec(),
+            BspFormat::Bsp2 => BSP2_MAGIC.to_vec(),
+        };
+
+        let mut offset = 4 + 8 * SECTION_COUNT;
+        for data in sections.iter() {
+            write_i32s(&mut bsp, &[offset as i32, data.len() as i32]);
+            offset += data.len();
+        }
+
+        for data in sections {
+            bsp.extend(data);
+        }
+
+        bsp
+    }
+
+    #[test]
+    fn test_load_formats() {
+        for format in [
+            BspFormat::Quake,
+            BspFormat::HalfLife,
+            BspFormat::Bsp2Rmq,
+            BspFormat::Bsp2,
+        ] {
+            let (models, ents) = load(Cursor::new(bsp_fixture(format))).unwrap();
+            assert!(ents.contains("worldspawn"));
+            assert_eq!(models.len(), 1);
+
+            let bsp_data = match models[0].kind() {
+                ModelKind::Brush(bmodel) => bmodel.bsp_data(),
+                _ => panic!("world model is not a brush model"),
+            };
+
+            assert_eq!(bsp_data.render_nodes()[0].max, [64.0; 3], "{:?}", format);
+            assert_eq!(
+                bsp_data.leaves()[1].min,
+                [-64.0, -64.0, 0.0],
+                "{:?}",
+                format
+            );
+            assert_eq!(bsp_data.leaves()[1].facelist_count, 1, "{:?}", format);
+            assert_eq!(bsp_data.face(0).edge_count, 3, "{:?}", format);
+            assert_eq!(bsp_data.edges()[3].vertex_ids, [2, 0], "{:?}", format);
+            assert_eq!(
+                bsp_data.face_iter_vertices(0).collect::<Vec<_>>(),
+                vec![
+                    Vector3::new(0.0, 0.0, 0.0),
+                    Vector3::new(64.0, 0.0, 0.0),
+                    Vector3::new(0.0, 64.0, 0.0),
+                ],
+                "{:?}",
+                format
+            );
+            assert_eq!(
+                bsp_data.hulls()[1]
+                    .contents_at_point(Vector3::new(0.0, 0.0, -32.0))
+                    .unwrap(),
+                BspLeafContents::Solid
+            );
+        }
+    }
+
+    #[test]
+    fn test_half_life_textures_and_lightmaps() {
+        let wall = miptex_fixture("wall", 16, 16);
+
+        // an external texture has no mipmap offsets
+        let mut crate1 = b"crate1".to_vec();
+        crate1.resize(TEX_NAME_MAX, 0);
+        write_i32s(&mut crate1, &[16, 8, 0, 0, 0, 0]);
+
+        let mut textures = Vec::new();
+        write_i32s(&mut textures, &[2, 12, 12 + wall.len() as i32]);
+        textures.extend(wall);
+        textures.extend(crate1);
+
+        // the triangle's lightmap is 5x5 samples
+        let lightmaps: Vec<u8> = (0..25).flat_map(|i| [i, 100, 200]).collect();
+
+        let bsp = bsp_fixture_with(
+            BspFormat::HalfLife,
+            b"{\n\"classname\" \"worldspawn\"\n\"wad\" \"\\hl\\valve\\test.wad;\"\n}\n\0",
+            textures,
+            lightmaps,
+        );
+
+        let wad = wad_fixture(&[("CRATE1", TYPE_MIPTEX, miptex_fixture("CRATE1", 16, 8))]);
+        let mut opened = Vec::new();
+        let (models, _) = load_with(Cursor::new(bsp.clone()), None, |name| {
+            opened.push(name.to_owned());
+            Some(Wad3::load(Cursor::new(wad.clone())).unwrap())
+        })
+        .unwrap();
+        assert_eq!(opened, vec!["test.wad"]);
+
+        let bsp_data = match models[0].kind() {
+            ModelKind::Brush(bmodel) => bmodel.bsp_data(),
+            _ => panic!("world model is not a brush model"),
+        };
+
+        let frame = |tex: &BspTexture| match tex.kind() {
+            BspTextureKind::Static(frame) => frame.mipmap(BspTextureMipmap::Full).to_vec(),
+            _ => panic!("texture is animated"),
+        };
+        let palette = |tex: &BspTexture| match tex.kind() {
+            BspTextureKind::Static(frame) => frame.palette().map(|p| p.to_vec()),
+            _ => panic!("texture is animated"),
+        };
+
+        let textures = bsp_data.textures();
+        assert_eq!(textures[0].name(), "wall");
+        assert_eq!(&palette(&textures[0]).unwrap()[3 * 200..3 * 201], &[200; 3]);
+        assert_eq!(textures[1].dimensions(), (16, 8));
+        assert_eq!(frame(&textures[1])[..3], [0, 1, 2]);
+        assert!(palette(&textures[1]).is_some());
+
+        assert_eq!(bsp_data.lightmap_format(), BspLightmapFormat::Rgb);
+        let lightmaps = bsp_data.face_lightmaps(0);
+        assert_eq!(lightmaps.len(), 1);
+        assert_eq!((lightmaps[0].width(), lightmaps[0].height()), (5, 5));
+        assert_eq!(lightmaps[0].rgba()[4 * 24..], [24, 100, 200, 0xFF]);
+
+        // without the WAD, the texture is blank
+        let (models, _) = load(Cursor::new(bsp)).unwrap();
+        let bsp_data = match models[0].kind() {
+            ModelKind::Brush(bmodel) => bmodel.bsp_data(),
+            _ => panic!("world model is not a brush model"),
+        };
+        assert_eq!(frame(&bsp_data.textures()[1]), vec![0; 128]);
+        assert!(palette(&bsp_data.textures()[1]).is_none());
+    }
+
+    #[test]
+    fn test_lit_lightmaps() {
+        let mut textures = Vec::new();
+        write_i32s(&mut textures, &[1, -1]);
+        let bsp = bsp_fixture_with(
+            BspFormat::Quake,
+            b"{\n\"classname\" \"worldspawn\"\n}\n\0",
+            textures,
+            (0..25).collect(),
+        );
+
+        let mut lit = LIT_MAGIC.to_vec();
+        write_i32s(&mut lit, &[LIT_VERSION]);
+        lit.extend((0..25).flat_map(|i| [i, 100, 200]));
+
+        let lightmap = |lit: Option<&[u8]>| {
+            let (models, _) = load_with(Cursor::new(bsp.clone()), lit, |_| None).unwrap();
+            let bsp_data = match models[0].kind() {
+                ModelKind::Brush(bmodel) => bmodel.bsp_data(),
+                _ => panic!("world model is not a brush model"),
+            };
+            let lightmaps = bsp_data.face_lightmaps(0);
+            assert_eq!(lightmaps.len(), 1);
+            (bsp_data.lightmap_format(), lightmaps[0].rgba())
+        };
+
+        let (format, rgba) = lightmap(Some(&lit));
+        assert_eq!(format, BspLightmapFormat::Rgb);
+        assert_eq!(rgba[4 * 24..], [24, 100, 200, 0xFF]);
+
+        // a .lit file for a different map is ignored
+        let (format, rgba) = lightmap(Some(&lit[..lit.len() - 3]));
+        assert_eq!(format, BspLightmapFormat::Mono);
+        assert_eq!(rgba[4 * 24..], [24, 24, 24, 0xFF]);
+    }
+
+    #[test]
+    fn test_unsupported_format() {
+        for (header, expected) in [
+            (28i32.to_le_bytes(), "unsupported BSP format version 28"),
+            (*b"IBSP", "unsupported BSP format \"IBSP\""),
+        ] {
+            let mut bsp = bsp_fixture(BspFormat::Quake);
+            bsp[..4].copy_from_slice(&header);
+            let err = load(Cursor::new(bsp)).unwrap_err();
+            assert!(err.downcast_ref::<BspFileError>().is_some(), "{:?}", err);
+            assert!(err.to_string().starts_with(expected), "{}", err);
+        }
+    }
+}
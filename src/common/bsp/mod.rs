@@ -30,6 +30,10 @@
 //! # File Format
 //!
 //! The BSP file header consists only of the file format version number, stored as an `i32`.
+//! Quake maps use version 29 and Half-Life maps use version 30. Maps too large for that format use one of two extended formats,
+//! identified by the magic numbers `BSP2` and `2PSB` in place of the version number. These widen
+//! the 16-bit indices described below to 32 bits; `BSP2` also stores node and leaf bounding boxes
+//! as floats instead of 16-bit integers. See [`BspFormat`].
 //!
 //! This is followed by a series of "lumps" (as they are called in the Quake source code),
 //! which act as a directory into the BSP file data. There are 15 of these lumps, each
@@ -108,13 +112,14 @@
 //! followed by two 16-bit integers which point to the children in front and back of the plane. If
 //! the high bit is set, the ID points to a leaf node; if not, it points to another internal node.
 //!
-//! After the node IDs are a 16-bit integer face ID, which denotes the index of the first face in
-//! the face list that belongs to this node, and a 16-bit integer face count, which denotes the
-//! number of faces to draw starting with the face ID.
+//! After the node IDs are the node's bounding box, stored as two 3-component vectors of 16-bit
+//! integers, a 16-bit integer face ID, which denotes the index of the first face in the face list
+//! that belongs to this node, and a 16-bit integer face count, which denotes the number of faces
+//! to draw starting with the face ID.
 //!
 //! ## Edges
 //!
-//! The edges are stored as a pair of 16-bit integer vertex IDs.
+//! The edges are stored as a pair of 16-bit integer vertex IDs (32-bit in the extended formats).
 
 mod load;
 
@@ -127,7 +132,10 @@ use crate::server::world::{Trace, TraceEnd, TraceStart};
 use cgmath::Vector3;
 use chrono::Duration;
 
-pub use self::load::{load, BspFileError};
+pub use self::load::{load, load_with, BspFileError, BspFormat};
+
+#[cfg(test)]
+pub use self::load::test::bsp_fixture;
 
 // this is 4 in the original source, but the 4th hull is never used.
 const MAX_HULLS: usize = 3;
@@ -193,13 +201,30 @@ pub enum BspTextureMipmap {
 
 #[derive(Debug)]
 pub struct BspTextureFrame {
+    name: String,
     mipmaps: [Vec<u8>; MIPLEVELS],
+    palette: Option<Box<[u8]>>,
 }
 
 impl BspTextureFrame {
+    /// Returns the name of the texture this frame was loaded from.
+    ///
+    /// This is the texture's own name for static textures and e.g. `+0button` for the frames of an
+    /// animated texture.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn mipmap(&self, level: BspTextureMipmap) -> &[u8] {
         &self.mipmaps[level as usize]
     }
+
+    /// Returns the palette this frame's indices refer to as 256 RGB triples, if it has its own.
+    ///
+    /// Only Half-Life textures have their own palette. Quake textures use `gfx/palette.lmp`.
+    pub fn palette(&self) -> Option<&[u8]> {
+        self.palette.as_deref()
+    }
 }
 
 #[derive(Debug)]
@@ -254,8 +279,8 @@ pub enum BspRenderNodeChild {
 pub struct BspRenderNode {
     pub plane_id: usize,
     pub children: [BspRenderNodeChild; 2],
-    pub min: [i16; 3],
-    pub max: [i16; 3],
+    pub min: [f32; 3],
+    pub max: [f32; 3],
     pub face_id: usize,
     pub face_count: usize,
 }
@@ -284,6 +309,9 @@ pub struct BspFace {
     pub edge_count: usize,
     pub texinfo_id: usize,
     pub light_styles: [u8; MAX_LIGHTSTYLES],
+
+    /// The index of the face's first lightmap sample. Multiply by the number of channels in the
+    /// lightmap format to get a byte offset.
     pub lightmap_id: Option<usize>,
 
     pub texture_mins: [i16; 2],
@@ -655,8 +683,8 @@ impl BspCollisionHull {
 pub struct BspLeaf {
     pub contents: BspLeafContents,
     pub vis_offset: Option<usize>,
-    pub min: [i16; 3],
-    pub max: [i16; 3],
+    pub min: [f32; 3],
+    pub max: [f32; 3],
     pub facelist_id: usize,
     pub facelist_count: usize,
     pub sounds: [u8; MAX_SOUNDS],
@@ -664,7 +692,7 @@ pub struct BspLeaf {
 
 #[derive(Debug)]
 pub struct BspEdge {
-    pub vertex_ids: [u16; 2],
+    pub vertex_ids: [u32; 2],
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -679,10 +707,31 @@ pub struct BspEdgeIndex {
     pub index: usize,
 }
 
+/// The layout of the lightmap data.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BspLightmapFormat {
+    /// One byte of light level per sample.
+    Mono,
+
+    /// Three bytes of red, green and blue light level per sample.
+    Rgb,
+}
+
+impl BspLightmapFormat {
+    /// Returns the number of bytes per lightmap sample.
+    pub fn channels(&self) -> usize {
+        match self {
+            BspLightmapFormat::Mono => 1,
+            BspLightmapFormat::Rgb => 3,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct BspLightmap<'a> {
     width: u32,
     height: u32,
+    format: BspLightmapFormat,
     data: &'a [u8],
 }
 
@@ -695,9 +744,25 @@ impl<'a> BspLightmap<'a> {
         self.height
     }
 
+    pub fn format(&self) -> BspLightmapFormat {
+        self.format
+    }
+
     pub fn data(&self) -> &[u8] {
         self.data
     }
+
+    /// Returns the lightmap as RGBA with opaque alpha. Mono lightmaps become gray.
+    pub fn rgba(&self) -> Vec<u8> {
+        self.data
+            .chunks_exact(self.format.channels())
+            .flat_map(|sample| match *sample {
+                [l] => [l, l, l, 0xFF],
+                [r, g, b] => [r, g, b, 0xFF],
+                _ => unreachable!(),
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug)]
@@ -710,6 +775,7 @@ pub struct BspData {
     pub(crate) texinfo: Box<[BspTexInfo]>,
     pub(crate) faces: Box<[BspFace]>,
     pub(crate) lightmaps: Box<[u8]>,
+    pub(crate) lightmap_format: BspLightmapFormat,
     pub(crate) leaves: Box<[BspLeaf]>,
     pub(crate) facelist: Box<[usize]>,
     pub(crate) edges: Box<[BspEdge]>,
@@ -761,18 +827,20 @@ impl BspData {
             Some(lightmap_id) => {
                 let lightmap_w = face.extents[0] as u32 / 16 + 1;
                 let lightmap_h = face.extents[1] as u32 / 16 + 1;
-                let lightmap_size = (lightmap_w * lightmap_h) as usize;
+                let channels = self.lightmap_format.channels();
+                let lightmap_size = (lightmap_w * lightmap_h) as usize * channels;
 
                 face.light_styles
                     .iter()
                     .take_while(|style| **style != 255)
                     .enumerate()
                     .map(|(i, _)| {
-                        let start = lightmap_id + lightmap_size * i as usize;
+                        let start = lightmap_id * channels + lightmap_size * i as usize;
                         let end = start + lightmap_size;
                         BspLightmap {
                             width: lightmap_w,
                             height: lightmap_h,
+                            format: self.lightmap_format,
                             data: &self.lightmaps[start..end],
                         }
                     })
@@ -790,6 +858,10 @@ impl BspData {
         &self.lightmaps
     }
 
+    pub fn lightmap_format(&self) -> BspLightmapFormat {
+        self.lightmap_format
+    }
+
     pub fn leaves(&self) -> &[BspLeaf] {
         &self.leaves
     }
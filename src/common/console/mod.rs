@@ -22,6 +22,7 @@ use std::{
     cell::{Ref, RefCell},
     collections::{HashMap, VecDeque},
     fmt::Write,
+    io,
     iter::FromIterator,
     rc::Rc,
 };
@@ -175,7 +176,6 @@ struct Cvar {
     val: String,
 
     // If true, this variable should be archived in vars.rc
-    #[allow(dead_code)]
     archive: bool,
 
     // If true:
@@ -284,6 +284,27 @@ impl CvarRegistry {
         self.register_impl(name, default, true, true)
     }
 
+    /// Writes the values of the archived cvars as console commands which
+    /// restore them, sorted by name.
+    pub fn write_archived<W>(&self, mut writer: W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        let cvars = self.cvars.borrow();
+        let mut names: Vec<&String> = cvars
+            .iter()
+            .filter(|(_, cvar)| cvar.archive)
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+
+        for name in names {
+            writeln!(writer, "{} \"{}\"", name, cvars[name].val)?;
+        }
+
+        Ok(())
+    }
+
     pub fn get<S>(&self, name: S) -> Result<String, ConsoleError>
     where
         S: AsRef<str>,
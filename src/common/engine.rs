@@ -59,7 +59,7 @@ pub fn duration_to_f32(d: Duration) -> f32 {
 
 #[inline]
 pub fn duration_from_f32(f: f32) -> Duration {
-    Duration::microseconds((f * 1_000_000.0) as i64)
+    Duration::microseconds((f * 1_000_000.0).round() as i64)
 }
 
 #[inline]
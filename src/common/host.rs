@@ -38,6 +38,13 @@ pub trait Program: Sized {
 
     fn frame(&mut self, frame_duration: Duration);
     fn shutdown(&mut self);
+
+    /// Returns whether frames should run as fast as possible, regardless of
+    /// `host_maxfps`.
+    fn uncapped_frame_rate(&self) -> bool {
+        false
+    }
+
     fn cvars(&self) -> Ref<CvarRegistry>;
     fn cvars_mut(&self) -> RefMut<CvarRegistry>;
 }
@@ -119,6 +126,10 @@ where
 
     // Returns whether enough time has elapsed to run the next frame.
     fn check_frame_duration(&mut self, frame_duration: Duration) -> bool {
+        if self.program.uncapped_frame_rate() {
+            return true;
+        }
+
         let host_maxfps = self
             .program
             .cvars()
@@ -30,10 +30,13 @@ pub mod model;
 pub mod net;
 pub mod pak;
 pub mod parse;
+pub mod pk3;
+pub mod pmove;
 pub mod sprite;
 pub mod util;
 pub mod vfs;
 pub mod wad;
+pub mod wad3;
 
 pub fn default_base_dir() -> std::path::PathBuf {
     match std::env::current_dir() {
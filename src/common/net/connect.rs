@@ -32,8 +32,12 @@ use crate::common::{
 use byteorder::{LittleEndian, NetworkEndian, ReadBytesExt, WriteBytesExt};
 use chrono::Duration;
 use num::FromPrimitive;
+use serde::Serialize;
 
 pub const CONNECT_PROTOCOL_VERSION: u8 = 3;
+
+/// The UDP port servers listen on unless told otherwise.
+pub const DEFAULT_PORT: u16 = 26000;
 const CONNECT_CONTROL: i32 = 1 << 31;
 const CONNECT_LENGTH_MASK: i32 = 0x0000FFFF;
 
@@ -86,9 +90,12 @@ pub enum RequestCode {
     ServerInfo = 2,
     PlayerInfo = 3,
     RuleInfo = 4,
+
+    /// ProQuake remote console command.
+    Rcon = 5,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct RequestConnect {
     pub game_name: String,
     pub proto_ver: u8,
@@ -122,7 +129,7 @@ impl ConnectPacket for RequestConnect {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct RequestServerInfo {
     pub game_name: String,
 }
@@ -147,7 +154,7 @@ impl ConnectPacket for RequestServerInfo {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct RequestPlayerInfo {
     pub player_id: u8,
 }
@@ -171,7 +178,7 @@ impl ConnectPacket for RequestPlayerInfo {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct RequestRuleInfo {
     pub prev_cvar: String,
 }
@@ -196,13 +203,52 @@ impl ConnectPacket for RequestRuleInfo {
     }
 }
 
+/// A console command to be run on the server.
+///
+/// The command only runs if `password` matches the server's `rcon_password`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestRcon {
+    pub password: String,
+    pub command: String,
+}
+
+impl ConnectPacket for RequestRcon {
+    fn code(&self) -> u8 {
+        RequestCode::Rcon as u8
+    }
+
+    fn content_len(&self) -> usize {
+        let mut len = 0;
+
+        // password and terminating zero byte
+        len += self.password.len() + size_of::<u8>();
+
+        // command and terminating zero byte
+        len += self.command.len() + size_of::<u8>();
+
+        len
+    }
+
+    fn write_content<W>(&self, writer: &mut W) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        writer.write_all(self.password.as_bytes())?;
+        writer.write_u8(0)?;
+        writer.write_all(self.command.as_bytes())?;
+        writer.write_u8(0)?;
+        Ok(())
+    }
+}
+
 /// A request from a client to retrieve information from or connect to the server.
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Request {
     Connect(RequestConnect),
     ServerInfo(RequestServerInfo),
     PlayerInfo(RequestPlayerInfo),
     RuleInfo(RequestRuleInfo),
+    Rcon(RequestRcon),
 }
 
 impl Request {
@@ -237,6 +283,68 @@ impl Request {
             prev_cvar: prev_cvar.as_ref().to_string(),
         })
     }
+
+    pub fn rcon<S>(password: S, command: S) -> Request
+    where
+        S: AsRef<str>,
+    {
+        Request::Rcon(RequestRcon {
+            password: password.as_ref().to_owned(),
+            command: command.as_ref().to_owned(),
+        })
+    }
+
+    /// Parses a request from the bytes of a received packet.
+    pub fn from_bytes(packet: &[u8]) -> Result<Request, NetError> {
+        let mut reader = BufReader::new(packet);
+        read_control_header(&mut reader, packet.len())?;
+
+        // validate request code
+        let request_byte = reader.read_u8()?;
+        let request_code = match RequestCode::from_u8(request_byte) {
+            Some(r) => r,
+            None => {
+                return Err(NetError::InvalidData(format!(
+                    "request code {}",
+                    request_byte
+                )))
+            }
+        };
+
+        let request = match request_code {
+            RequestCode::Connect => {
+                let game_name = util::read_cstring(&mut reader)?;
+                let proto_ver = reader.read_u8()?;
+                Request::Connect(RequestConnect {
+                    game_name,
+                    proto_ver,
+                })
+            }
+
+            RequestCode::ServerInfo => {
+                let game_name = util::read_cstring(&mut reader)?;
+                Request::ServerInfo(RequestServerInfo { game_name })
+            }
+
+            RequestCode::PlayerInfo => {
+                let player_id = reader.read_u8()?;
+                Request::PlayerInfo(RequestPlayerInfo { player_id })
+            }
+
+            RequestCode::RuleInfo => {
+                let prev_cvar = util::read_cstring(&mut reader)?;
+                Request::RuleInfo(RequestRuleInfo { prev_cvar })
+            }
+
+            RequestCode::Rcon => {
+                let password = util::read_cstring(&mut reader)?;
+                let command = util::read_cstring(&mut reader)?;
+                Request::Rcon(RequestRcon { password, command })
+            }
+        };
+
+        Ok(request)
+    }
 }
 
 impl ConnectPacket for Request {
@@ -247,6 +355,7 @@ impl ConnectPacket for Request {
             ServerInfo(ref s) => s.code(),
             PlayerInfo(ref p) => p.code(),
             RuleInfo(ref r) => r.code(),
+            Rcon(ref r) => r.code(),
         }
     }
 
@@ -257,6 +366,7 @@ impl ConnectPacket for Request {
             ServerInfo(ref s) => s.content_len(),
             PlayerInfo(ref p) => p.content_len(),
             RuleInfo(ref r) => r.content_len(),
+            Rcon(ref r) => r.content_len(),
         }
     }
 
@@ -270,6 +380,7 @@ impl ConnectPacket for Request {
             ServerInfo(ref s) => s.write_content(writer),
             PlayerInfo(ref p) => p.write_content(writer),
             RuleInfo(ref r) => r.write_content(writer),
+            Rcon(ref r) => r.write_content(writer),
         }
     }
 }
@@ -281,9 +392,12 @@ pub enum ResponseCode {
     ServerInfo = 0x83,
     PlayerInfo = 0x84,
     RuleInfo = 0x85,
+
+    /// ProQuake remote console output.
+    Rcon = 0x86,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ResponseAccept {
     pub port: i32,
 }
@@ -307,7 +421,7 @@ impl ConnectPacket for ResponseAccept {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct ResponseReject {
     pub message: String,
 }
@@ -332,7 +446,7 @@ impl ConnectPacket for ResponseReject {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct ResponseServerInfo {
     pub address: String,
     pub hostname: String,
@@ -388,7 +502,7 @@ impl ConnectPacket for ResponseServerInfo {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct ResponsePlayerInfo {
     pub player_id: u8,
     pub player_name: String,
@@ -443,7 +557,7 @@ impl ConnectPacket for ResponsePlayerInfo {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct ResponseRuleInfo {
     pub cvar_name: String,
     pub cvar_val: String,
@@ -478,13 +592,125 @@ impl ConnectPacket for ResponseRuleInfo {
     }
 }
 
-#[derive(Debug)]
+/// The console output of an rcon command.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResponseRcon {
+    pub message: String,
+}
+
+impl ConnectPacket for ResponseRcon {
+    fn code(&self) -> u8 {
+        ResponseCode::Rcon as u8
+    }
+
+    fn content_len(&self) -> usize {
+        // message and terminating zero byte
+        self.message.len() + size_of::<u8>()
+    }
+
+    fn write_content<W>(&self, writer: &mut W) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        writer.write_all(self.message.as_bytes())?;
+        writer.write_u8(0)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Response {
     Accept(ResponseAccept),
     Reject(ResponseReject),
     ServerInfo(ResponseServerInfo),
     PlayerInfo(ResponsePlayerInfo),
     RuleInfo(ResponseRuleInfo),
+    Rcon(ResponseRcon),
+}
+
+impl Response {
+    /// Parses a response from the bytes of a received packet.
+    pub fn from_bytes(packet: &[u8]) -> Result<Response, NetError> {
+        let mut reader = BufReader::new(packet);
+        read_control_header(&mut reader, packet.len())?;
+
+        let response_byte = reader.read_u8()?;
+        let response_code = match ResponseCode::from_u8(response_byte) {
+            Some(r) => r,
+            None => {
+                return Err(NetError::InvalidData(format!(
+                    "response code {}",
+                    response_byte
+                )))
+            }
+        };
+
+        let response = match response_code {
+            ResponseCode::Accept => {
+                let port = reader.read_i32::<LittleEndian>()?;
+                Response::Accept(ResponseAccept { port })
+            }
+
+            ResponseCode::Reject => {
+                let message = util::read_cstring(&mut reader)?;
+                Response::Reject(ResponseReject { message })
+            }
+
+            ResponseCode::ServerInfo => {
+                let address = util::read_cstring(&mut reader)?;
+                let hostname = util::read_cstring(&mut reader)?;
+                let levelname = util::read_cstring(&mut reader)?;
+                let client_count = reader.read_u8()?;
+                let client_max = reader.read_u8()?;
+                let protocol_version = reader.read_u8()?;
+
+                Response::ServerInfo(ResponseServerInfo {
+                    address,
+                    hostname,
+                    levelname,
+                    client_count,
+                    client_max,
+                    protocol_version,
+                })
+            }
+
+            ResponseCode::PlayerInfo => {
+                let player_id = reader.read_u8()?;
+                let player_name = util::read_cstring(&mut reader)?;
+                let colors = reader.read_i32::<LittleEndian>()?;
+                let frags = reader.read_i32::<LittleEndian>()?;
+                let connect_duration = reader.read_i32::<LittleEndian>()?;
+                let address = util::read_cstring(&mut reader)?;
+
+                Response::PlayerInfo(ResponsePlayerInfo {
+                    player_id,
+                    player_name,
+                    colors,
+                    frags,
+                    connect_duration,
+                    address,
+                })
+            }
+
+            // the original engine sends no content after the last rule, which
+            // reads the same as an empty name
+            ResponseCode::RuleInfo => {
+                let cvar_name = util::read_cstring(&mut reader)?;
+                let cvar_val = util::read_cstring(&mut reader)?;
+                Response::RuleInfo(ResponseRuleInfo {
+                    cvar_name,
+                    cvar_val,
+                })
+            }
+
+            ResponseCode::Rcon => {
+                let message = util::read_cstring(&mut reader)?;
+                Response::Rcon(ResponseRcon { message })
+            }
+        };
+
+        Ok(response)
+    }
 }
 
 impl ConnectPacket for Response {
@@ -496,6 +722,7 @@ impl ConnectPacket for Response {
             ServerInfo(ref s) => s.code(),
             PlayerInfo(ref p) => p.code(),
             RuleInfo(ref r) => r.code(),
+            Rcon(ref r) => r.code(),
         }
     }
 
@@ -507,6 +734,7 @@ impl ConnectPacket for Response {
             ServerInfo(ref s) => s.content_len(),
             PlayerInfo(ref p) => p.content_len(),
             RuleInfo(ref r) => r.content_len(),
+            Rcon(ref r) => r.content_len(),
         }
     }
 
@@ -521,10 +749,43 @@ impl ConnectPacket for Response {
             ServerInfo(ref s) => s.write_content(writer),
             PlayerInfo(ref p) => p.write_content(writer),
             RuleInfo(ref r) => r.write_content(writer),
+            Rcon(ref r) => r.write_content(writer),
         }
     }
 }
 
+/// Reads and validates the control header of a connection packet.
+fn read_control_header<R>(reader: &mut R, len: usize) -> Result<(), NetError>
+where
+    R: ReadBytesExt,
+{
+    let control = reader.read_i32::<NetworkEndian>()?;
+
+    // TODO: figure out what a control value of -1 means
+    if control == -1 {
+        return Err(NetError::with_msg("Control value is -1"));
+    }
+
+    // high 4 bits must be 0x8000 (CONNECT_CONTROL)
+    if control & !CONNECT_LENGTH_MASK != CONNECT_CONTROL {
+        return Err(NetError::InvalidData(format!(
+            "control value {:X}",
+            control & !CONNECT_LENGTH_MASK
+        )));
+    }
+
+    // low 4 bits must be total length of packet
+    let control_len = (control & CONNECT_LENGTH_MASK) as usize;
+    if control_len != len {
+        return Err(NetError::InvalidData(format!(
+            "Actual packet length ({}) differs from header value ({})",
+            len, control_len,
+        )));
+    }
+
+    Ok(())
+}
+
 /// A socket that listens for new connections or queries.
 pub struct ConnectListener {
     socket: UdpSocket,
@@ -547,71 +808,23 @@ impl ConnectListener {
         // allocated at https://github.com/id-Software/Quake/blob/master/WinQuake/net_main.c#L851
         let mut recv_buf = [0u8; MAX_MESSAGE];
         let (len, remote) = self.socket.recv_from(&mut recv_buf)?;
-        let mut reader = BufReader::new(&recv_buf[..len]);
-
-        let control = reader.read_i32::<NetworkEndian>()?;
+        let request = Request::from_bytes(&recv_buf[..len])?;
 
-        // TODO: figure out what a control value of -1 means
-        if control == -1 {
-            return Err(NetError::with_msg("Control value is -1"));
-        }
-
-        // high 4 bits must be 0x8000 (CONNECT_CONTROL)
-        if control & !CONNECT_LENGTH_MASK != CONNECT_CONTROL {
-            return Err(NetError::InvalidData(format!(
-                "control value {:X}",
-                control & !CONNECT_LENGTH_MASK
-            )));
-        }
-
-        // low 4 bits must be total length of packet
-        let control_len = (control & CONNECT_LENGTH_MASK) as usize;
-        if control_len != len {
-            return Err(NetError::InvalidData(format!(
-                "Actual packet length ({}) differs from header value ({})",
-                len, control_len,
-            )));
-        }
-
-        // validate request code
-        let request_byte = reader.read_u8()?;
-        let request_code = match RequestCode::from_u8(request_byte) {
-            Some(r) => r,
-            None => {
-                return Err(NetError::InvalidData(format!(
-                    "request code {}",
-                    request_byte
-                )))
-            }
-        };
-
-        let request = match request_code {
-            RequestCode::Connect => {
-                let game_name = util::read_cstring(&mut reader).unwrap();
-                let proto_ver = reader.read_u8()?;
-                Request::Connect(RequestConnect {
-                    game_name,
-                    proto_ver,
-                })
-            }
-
-            RequestCode::ServerInfo => {
-                let game_name = util::read_cstring(&mut reader).unwrap();
-                Request::ServerInfo(RequestServerInfo { game_name })
-            }
-
-            RequestCode::PlayerInfo => {
-                let player_id = reader.read_u8()?;
-                Request::PlayerInfo(RequestPlayerInfo { player_id })
-            }
+        Ok((request, remote))
+    }
 
-            RequestCode::RuleInfo => {
-                let prev_cvar = util::read_cstring(&mut reader).unwrap();
-                Request::RuleInfo(RequestRuleInfo { prev_cvar })
-            }
-        };
+    /// Moves the listener into or out of nonblocking mode.
+    ///
+    /// In nonblocking mode, `recv_request` returns an `ErrorKind::WouldBlock`
+    /// I/O error if no request is waiting.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), NetError> {
+        self.socket.set_nonblocking(nonblocking)?;
+        Ok(())
+    }
 
-        Ok((request, remote))
+    /// Returns the address the listener is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr, NetError> {
+        Ok(self.socket.local_addr()?)
     }
 
     pub fn send_response(&self, response: Response, remote: SocketAddr) -> Result<(), NetError> {
@@ -634,6 +847,21 @@ impl ConnectSocket {
         Ok(ConnectSocket { socket })
     }
 
+    /// Moves the socket into or out of nonblocking mode.
+    ///
+    /// In nonblocking mode, `recv_response` returns `None` immediately if no
+    /// response is waiting and no timeout is given.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), NetError> {
+        self.socket.set_nonblocking(nonblocking)?;
+        Ok(())
+    }
+
+    /// Allows or disallows sending requests to broadcast addresses.
+    pub fn set_broadcast(&self, broadcast: bool) -> Result<(), NetError> {
+        self.socket.set_broadcast(broadcast)?;
+        Ok(())
+    }
+
     pub fn into_qsocket(self, remote: SocketAddr) -> QSocket {
         QSocket::new(self.socket, remote)
     }
@@ -666,75 +894,7 @@ impl ConnectSocket {
         };
         self.socket.set_read_timeout(None)?;
 
-        let mut reader = BufReader::new(&recv_buf[..len]);
-
-        let control = reader.read_i32::<NetworkEndian>()?;
-
-        // TODO: figure out what a control value of -1 means
-        if control == -1 {
-            return Err(NetError::with_msg("Control value is -1"));
-        }
-
-        // high 4 bits must be 0x8000 (CONNECT_CONTROL)
-        if control & !CONNECT_LENGTH_MASK != CONNECT_CONTROL {
-            return Err(NetError::InvalidData(format!(
-                "control value {:X}",
-                control & !CONNECT_LENGTH_MASK
-            )));
-        }
-
-        // low 4 bits must be total length of packet
-        let control_len = (control & CONNECT_LENGTH_MASK) as usize;
-        if control_len != len {
-            return Err(NetError::with_msg(format!(
-                "Actual packet length ({}) differs from header value ({})",
-                len, control_len,
-            )));
-        }
-
-        let response_byte = reader.read_u8()?;
-        let response_code = match ResponseCode::from_u8(response_byte) {
-            Some(r) => r,
-            None => {
-                return Err(NetError::InvalidData(format!(
-                    "response code {}",
-                    response_byte
-                )))
-            }
-        };
-
-        let response = match response_code {
-            ResponseCode::Accept => {
-                let port = reader.read_i32::<LittleEndian>()?;
-                Response::Accept(ResponseAccept { port })
-            }
-
-            ResponseCode::Reject => {
-                let message = util::read_cstring(&mut reader).unwrap();
-                Response::Reject(ResponseReject { message })
-            }
-
-            ResponseCode::ServerInfo => {
-                let address = util::read_cstring(&mut reader).unwrap();
-                let hostname = util::read_cstring(&mut reader).unwrap();
-                let levelname = util::read_cstring(&mut reader).unwrap();
-                let client_count = reader.read_u8()?;
-                let client_max = reader.read_u8()?;
-                let protocol_version = reader.read_u8()?;
-
-                Response::ServerInfo(ResponseServerInfo {
-                    address,
-                    hostname,
-                    levelname,
-                    client_count,
-                    client_max,
-                    protocol_version,
-                })
-            }
-
-            ResponseCode::PlayerInfo => unimplemented!(),
-            ResponseCode::RuleInfo => unimplemented!(),
-        };
+        let response = Response::from_bytes(&recv_buf[..len])?;
 
         Ok(Some((response, remote)))
     }
@@ -744,6 +904,8 @@ impl ConnectSocket {
 mod test {
     use super::*;
 
+    use crate::common::net::generate;
+
     // test_request_*_packet_len
     //
     // These tests ensure that ConnectPacket::packet_len() returns an accurate value by comparing it
@@ -788,6 +950,17 @@ mod test {
         assert_eq!(packet_len, packet.len());
     }
 
+    #[test]
+    fn test_request_rcon_packet_len() {
+        let request_rcon = RequestRcon {
+            password: String::from("secret"),
+            command: String::from("kick player"),
+        };
+        let packet_len = request_rcon.packet_len() as usize;
+        let packet = request_rcon.to_bytes().unwrap();
+        assert_eq!(packet_len, packet.len());
+    }
+
     #[test]
     fn test_response_accept_packet_len() {
         let response_accept = ResponseAccept { port: 26000 };
@@ -836,6 +1009,148 @@ mod test {
         assert_eq!(packet_len, packet.len());
     }
 
+    #[test]
+    fn test_response_rule_info_packet_len() {
+        let response_rule_info = ResponseRuleInfo {
+            cvar_name: String::from("sv_gravity"),
+            cvar_val: String::from("800"),
+        };
+        let packet_len = response_rule_info.packet_len() as usize;
+        let packet = response_rule_info.to_bytes().unwrap();
+        assert_eq!(packet_len, packet.len());
+    }
+
+    #[test]
+    fn test_response_rcon_packet_len() {
+        let response_rcon = ResponseRcon {
+            message: String::from("Kicked player\n"),
+        };
+        let packet_len = response_rcon.packet_len() as usize;
+        let packet = response_rcon.to_bytes().unwrap();
+        assert_eq!(packet_len, packet.len());
+    }
+
+    #[test]
+    fn test_request_round_trip() {
+        generate::for_each_input(|u| {
+            let src = generate::request(u)?;
+            let dst = Request::from_bytes(&src.to_bytes().unwrap()).unwrap();
+            assert_eq!(src, dst);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_response_round_trip() {
+        generate::for_each_input(|u| {
+            let src = generate::response(u)?;
+            let dst = Response::from_bytes(&src.to_bytes().unwrap()).unwrap();
+            assert_eq!(src, dst);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_from_bytes_garbage() {
+        generate::for_each_input(|u| {
+            let len = u.int_in_range(0..=u.len())?;
+            let mut packet = u.bytes(len)?.to_vec();
+
+            // give half of the packets a valid header so the parsers see the content
+            if packet.len() >= 4 && u.arbitrary()? {
+                let control = CONNECT_CONTROL | packet.len() as i32;
+                packet[..4].copy_from_slice(&control.to_be_bytes());
+            }
+
+            let _ = Request::from_bytes(&packet);
+            let _ = Response::from_bytes(&packet);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_request_invalid_utf8_fails() {
+        let mut packet = Request::rcon("secret", "status").to_bytes().unwrap();
+        packet[5] = 0xFF;
+        assert!(matches!(
+            Request::from_bytes(&packet),
+            Err(NetError::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn test_query_responses() {
+        let listener = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let mut socket = ConnectSocket::bind("127.0.0.1:0").unwrap();
+
+        socket
+            .send_request(
+                Request::PlayerInfo(RequestPlayerInfo { player_id: 0 }),
+                server_addr,
+            )
+            .unwrap();
+        let (request, remote) = listener.recv_request().unwrap();
+        assert!(matches!(
+            request,
+            Request::PlayerInfo(RequestPlayerInfo { player_id: 0 })
+        ));
+
+        let player_info = ResponsePlayerInfo {
+            player_id: 3,
+            player_name: String::from("player"),
+            colors: 0x4d,
+            frags: -2,
+            connect_duration: 120,
+            address: String::from("127.0.0.1:27001"),
+        };
+        let rule_info = ResponseRuleInfo {
+            cvar_name: String::from("sv_gravity"),
+            cvar_val: String::from("800"),
+        };
+        listener
+            .send_response(Response::PlayerInfo(player_info.clone()), remote)
+            .unwrap();
+        listener
+            .send_response(Response::RuleInfo(rule_info.clone()), remote)
+            .unwrap();
+
+        let timeout = Some(Duration::seconds(1));
+        match socket.recv_response(timeout).unwrap() {
+            Some((Response::PlayerInfo(r), _)) => assert_eq!(r, player_info),
+            r => panic!("unexpected response {:?}", r),
+        }
+        match socket.recv_response(timeout).unwrap() {
+            Some((Response::RuleInfo(r), _)) => assert_eq!(r, rule_info),
+            r => panic!("unexpected response {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_rcon() {
+        let listener = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let mut socket = ConnectSocket::bind("127.0.0.1:0").unwrap();
+
+        socket
+            .send_request(Request::rcon("secret", "map e1m2"), server_addr)
+            .unwrap();
+        let (request, remote) = listener.recv_request().unwrap();
+        assert_eq!(request, Request::rcon("secret", "map e1m2"));
+
+        let rcon = ResponseRcon {
+            message: String::from("Changing level to e1m2\n"),
+        };
+        listener
+            .send_response(Response::Rcon(rcon.clone()), remote)
+            .unwrap();
+
+        match socket.recv_response(Some(Duration::seconds(1))).unwrap() {
+            Some((Response::Rcon(r), _)) => assert_eq!(r, rcon),
+            r => panic!("unexpected response {:?}", r),
+        }
+    }
+
     #[test]
     fn test_connect_listener_bind() {
         let _listener = ConnectListener::bind("127.0.0.1:26000").unwrap();
@@ -0,0 +1,546 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Arbitrary network messages for round-trip tests and fuzzing.
+//!
+//! Each generator draws a message from an [`Unstructured`] byte source. Most
+//! fields are sent with less precision than they are stored with, so the
+//! generators only produce values the given protocol can represent exactly:
+//! serializing a generated message and reading it back must give the same
+//! message. This module is built for tests and with the `arbitrary` feature,
+//! which the fuzz targets in `fuzz/` enable.
+
+use ::arbitrary::{Error, Result, Unstructured};
+use cgmath::{Deg, Vector3};
+use chrono::Duration;
+use num::FromPrimitive;
+
+use crate::common::net::{
+    connect::{
+        Request, RequestConnect, RequestPlayerInfo, RequestRcon, RequestRuleInfo,
+        RequestServerInfo, Response, ResponseAccept, ResponsePlayerInfo, ResponseRcon,
+        ResponseReject, ResponseRuleInfo, ResponseServerInfo,
+    },
+    BeamEntityKind, ButtonFlags, ClientCmd, ClientStat, EntityEffects, EntityUpdate, GameType,
+    ItemFlags, PlayerColor, PlayerData, PointEntityKind, Protocol, ProtocolFlags, ProtocolVersion,
+    ServerCmd, SignOnStage, TempEntity,
+};
+
+/// Maximum length in characters of a generated string.
+const MAX_STRING_LEN: usize = 64;
+
+/// Maximum number of entries in a generated precache list.
+const MAX_PRECACHE_LEN: usize = 8;
+
+/// Maximum client send time in microseconds.
+///
+/// Send times are sent as seconds in a 32-bit float, which only keeps every
+/// microsecond for the first few seconds.
+const MAX_SEND_TIME_US: i64 = 1 << 22;
+
+/// Entity IDs at or above this value don't fit in a combined entity/channel
+/// field.
+const MAX_SMALL_ENTITY_ID: u16 = (1 << 13) - 1;
+
+/// Generates a protocol version and, for RMQ, a set of encoding flags.
+pub fn protocol(u: &mut Unstructured) -> Result<Protocol> {
+    let version = *u.choose(&[
+        ProtocolVersion::NetQuake,
+        ProtocolVersion::FitzQuake,
+        ProtocolVersion::Rmq,
+    ])?;
+    let flags = ProtocolFlags::from_bits_truncate(u.arbitrary()?) - ProtocolFlags::MORE_FLAGS;
+
+    Ok(Protocol::new(version, flags))
+}
+
+/// Generates a server command that can be sent with the given protocol.
+pub fn server_cmd(u: &mut Unstructured, protocol: Protocol) -> Result<ServerCmd> {
+    let extended = protocol.is_extended();
+    let flags = protocol.flags;
+
+    // the last three commands are FitzQuake extensions
+    let last = if extended { 38 } else { 35 };
+
+    Ok(match u.int_in_range(0..=last)? {
+        0 => ServerCmd::Bad,
+        1 => ServerCmd::NoOp,
+        2 => ServerCmd::Disconnect,
+        3 => ServerCmd::UpdateStat {
+            stat: ClientStat::from_u8(u.int_in_range(0..=ClientStat::KilledMonsters as u8)?)
+                .ok_or(Error::IncorrectFormat)?,
+            value: u.arbitrary()?,
+        },
+        4 => ServerCmd::Version {
+            version: u.arbitrary()?,
+        },
+        5 => ServerCmd::SetView {
+            ent_id: u.arbitrary()?,
+        },
+        6 => {
+            let (entity_id, channel) = if extended && u.arbitrary()? {
+                (u.arbitrary()?, u.arbitrary()?)
+            } else {
+                (
+                    u.int_in_range(0..=MAX_SMALL_ENTITY_ID)?,
+                    u.int_in_range(0..=7)?,
+                )
+            };
+
+            ServerCmd::Sound {
+                volume: option(u, |u| u.arbitrary())?,
+                attenuation: option(u, |u| Ok(u.arbitrary::<u8>()? as f32 / 64.0))?,
+                entity_id,
+                channel,
+                sound_id: id(u, extended)?,
+                position: coord_vector3(u, flags)?,
+            }
+        }
+        7 => ServerCmd::Time { time: float(u)? },
+        8 => ServerCmd::Print { text: string(u)? },
+        9 => ServerCmd::StuffText { text: string(u)? },
+        10 => ServerCmd::SetAngle {
+            angles: angle_vector3(u, flags)?,
+        },
+        11 => {
+            let protocol_version = *u.choose(&[
+                ProtocolVersion::NetQuake,
+                ProtocolVersion::FitzQuake,
+                ProtocolVersion::Rmq,
+            ])?;
+            let protocol_flags = match protocol_version {
+                ProtocolVersion::Rmq => ProtocolFlags::from_bits_truncate(u.arbitrary()?),
+                _ => ProtocolFlags::empty(),
+            };
+
+            ServerCmd::ServerInfo {
+                protocol_version: protocol_version.value(),
+                protocol_flags,
+                max_clients: u.arbitrary()?,
+                game_type: *u.choose(&[GameType::CoOp, GameType::Deathmatch])?,
+                message: string(u)?,
+                model_precache: precache(u)?,
+                sound_precache: precache(u)?,
+            }
+        }
+        12 => ServerCmd::LightStyle {
+            id: u.arbitrary()?,
+            value: string(u)?,
+        },
+        13 => ServerCmd::UpdateName {
+            player_id: u.arbitrary()?,
+            new_name: string(u)?,
+        },
+        14 => ServerCmd::UpdateFrags {
+            player_id: u.arbitrary()?,
+            new_frags: u.arbitrary()?,
+        },
+        15 => ServerCmd::PlayerData(player_data(u, protocol)?),
+        16 => ServerCmd::StopSound {
+            entity_id: u.int_in_range(0..=MAX_SMALL_ENTITY_ID)?,
+            channel: u.int_in_range(0..=7)?,
+        },
+        17 => ServerCmd::UpdateColors {
+            player_id: u.arbitrary()?,
+            new_colors: PlayerColor::from_bits(u.arbitrary()?),
+        },
+        18 => ServerCmd::Particle {
+            origin: coord_vector3(u, flags)?,
+            direction: Vector3::new(
+                u.arbitrary::<i8>()? as f32 / 16.0,
+                u.arbitrary::<i8>()? as f32 / 16.0,
+                u.arbitrary::<i8>()? as f32 / 16.0,
+            ),
+            count: u.arbitrary()?,
+            color: u.arbitrary()?,
+        },
+        19 => ServerCmd::Damage {
+            armor: u.arbitrary()?,
+            blood: u.arbitrary()?,
+            source: coord_vector3(u, flags)?,
+        },
+        20 => ServerCmd::SpawnStatic {
+            model_id: id(u, extended)?,
+            frame_id: id(u, extended)?,
+            colormap: u.arbitrary()?,
+            skin_id: u.arbitrary()?,
+            origin: coord_vector3(u, flags)?,
+            angles: angle_vector3(u, flags)?,
+            alpha: extension(u, extended, |u| u.arbitrary())?,
+        },
+        21 => ServerCmd::SpawnBaseline {
+            ent_id: u.arbitrary()?,
+            model_id: id(u, extended)?,
+            frame_id: id(u, extended)?,
+            colormap: u.arbitrary()?,
+            skin_id: u.arbitrary()?,
+            origin: coord_vector3(u, flags)?,
+            angles: angle_vector3(u, flags)?,
+            alpha: extension(u, extended, |u| u.arbitrary())?,
+        },
+        22 => ServerCmd::TempEntity {
+            temp_entity: temp_entity(u, protocol)?,
+        },
+        23 => ServerCmd::SetPause {
+            paused: u.arbitrary()?,
+        },
+        24 => ServerCmd::SignOnStage {
+            stage: SignOnStage::from_u8(u.int_in_range(0..=SignOnStage::Done as u8)?)
+                .ok_or(Error::IncorrectFormat)?,
+        },
+        25 => ServerCmd::CenterPrint { text: string(u)? },
+        26 => ServerCmd::KilledMonster,
+        27 => ServerCmd::FoundSecret,
+        28 => ServerCmd::SpawnStaticSound {
+            origin: coord_vector3(u, flags)?,
+            sound_id: id(u, extended)?,
+            volume: u.arbitrary()?,
+            attenuation: u.arbitrary()?,
+        },
+        29 => ServerCmd::Intermission,
+        30 => ServerCmd::Finale { text: string(u)? },
+        31 => ServerCmd::CdTrack {
+            track: u.arbitrary()?,
+            loop_: u.arbitrary()?,
+        },
+        32 => ServerCmd::SellScreen,
+        33 => ServerCmd::Cutscene { text: string(u)? },
+        34 | 35 => ServerCmd::FastUpdate(entity_update(u, protocol)?),
+        36 => ServerCmd::Skybox { name: string(u)? },
+        37 => ServerCmd::BonusFlash,
+        _ => ServerCmd::Fog {
+            density: u.arbitrary()?,
+            color: u.arbitrary()?,
+            time: u.arbitrary()?,
+        },
+    })
+}
+
+/// Generates an entity update that can be sent with the given protocol.
+pub fn entity_update(u: &mut Unstructured, protocol: Protocol) -> Result<EntityUpdate> {
+    let extended = protocol.is_extended();
+    let flags = protocol.flags;
+
+    Ok(EntityUpdate {
+        ent_id: u.arbitrary()?,
+        model_id: option(u, |u| id(u, extended))?,
+        frame_id: option(u, |u| id(u, extended))?,
+        colormap: option(u, |u| u.arbitrary())?,
+        skin_id: option(u, |u| u.arbitrary())?,
+        effects: option(u, |u| Ok(EntityEffects::from_bits_truncate(u.arbitrary()?)))?,
+        origin_x: option(u, |u| coord(u, flags))?,
+        pitch: option(u, |u| angle(u, flags))?,
+        origin_y: option(u, |u| coord(u, flags))?,
+        yaw: option(u, |u| angle(u, flags))?,
+        origin_z: option(u, |u| coord(u, flags))?,
+        roll: option(u, |u| angle(u, flags))?,
+        no_lerp: u.arbitrary()?,
+        alpha: extension(u, extended, |u| u.arbitrary())?,
+        scale: extension(u, extended, |u| u.arbitrary())?,
+        lerp_finish: extension(u, extended, |u| u.arbitrary())?,
+    })
+}
+
+/// Generates a client state update that can be sent with the given protocol.
+pub fn player_data(u: &mut Unstructured, protocol: Protocol) -> Result<PlayerData> {
+    let extended = protocol.is_extended();
+
+    Ok(PlayerData {
+        view_height: option(u, |u| Ok(u.arbitrary::<i8>()? as f32))?,
+        ideal_pitch: option(u, |u| Ok(Deg(u.arbitrary::<i8>()? as f32)))?,
+        punch_pitch: option(u, |u| Ok(Deg(u.arbitrary::<i8>()? as f32)))?,
+        velocity_x: option(u, |u| Ok(u.arbitrary::<i8>()? as f32 * 16.0))?,
+        punch_yaw: option(u, |u| Ok(Deg(u.arbitrary::<i8>()? as f32)))?,
+        velocity_y: option(u, |u| Ok(u.arbitrary::<i8>()? as f32 * 16.0))?,
+        punch_roll: option(u, |u| Ok(Deg(u.arbitrary::<i8>()? as f32)))?,
+        velocity_z: option(u, |u| Ok(u.arbitrary::<i8>()? as f32 * 16.0))?,
+        items: ItemFlags::from_bits_truncate(u.arbitrary()?),
+        on_ground: u.arbitrary()?,
+        in_water: u.arbitrary()?,
+        weapon_frame: option(u, |u| id(u, extended))?,
+        armor: option(u, |u| id(u, extended))?,
+        weapon: option(u, |u| id(u, extended))?,
+        health: u.arbitrary()?,
+        ammo: id(u, extended)?,
+        ammo_shells: id(u, extended)?,
+        ammo_nails: id(u, extended)?,
+        ammo_rockets: id(u, extended)?,
+        ammo_cells: id(u, extended)?,
+        active_weapon: u.arbitrary()?,
+        weapon_alpha: extension(u, extended, |u| u.arbitrary())?,
+    })
+}
+
+/// Generates a temporary entity that can be sent with the given protocol.
+pub fn temp_entity(u: &mut Unstructured, protocol: Protocol) -> Result<TempEntity> {
+    let flags = protocol.flags;
+
+    Ok(match u.arbitrary()? {
+        true => {
+            let kind = match u.arbitrary()? {
+                true => PointEntityKind::ColorExplosion {
+                    color_start: u.arbitrary()?,
+                    color_len: u.arbitrary()?,
+                },
+                false => *u.choose(&[
+                    PointEntityKind::Spike,
+                    PointEntityKind::SuperSpike,
+                    PointEntityKind::Gunshot,
+                    PointEntityKind::Explosion,
+                    PointEntityKind::TarExplosion,
+                    PointEntityKind::WizSpike,
+                    PointEntityKind::KnightSpike,
+                    PointEntityKind::LavaSplash,
+                    PointEntityKind::Teleport,
+                ])?,
+            };
+
+            TempEntity::Point {
+                kind,
+                origin: coord_vector3(u, flags)?,
+            }
+        }
+
+        false => TempEntity::Beam {
+            kind: match u.int_in_range(0..=3)? {
+                0 => BeamEntityKind::Grapple,
+                model_id => BeamEntityKind::Lightning { model_id },
+            },
+            entity_id: u.arbitrary()?,
+            start: coord_vector3(u, flags)?,
+            end: coord_vector3(u, flags)?,
+        },
+    })
+}
+
+/// Generates a client command that can be sent with the given protocol.
+pub fn client_cmd(u: &mut Unstructured, protocol: Protocol) -> Result<ClientCmd> {
+    Ok(match u.int_in_range(0..=4)? {
+        0 => ClientCmd::Bad,
+        1 => ClientCmd::NoOp,
+        2 => ClientCmd::Disconnect,
+        3 => ClientCmd::Move {
+            send_time: Duration::microseconds(u.int_in_range(0..=MAX_SEND_TIME_US)?),
+            angles: Vector3::new(
+                view_angle(u, protocol)?,
+                view_angle(u, protocol)?,
+                view_angle(u, protocol)?,
+            ),
+            fwd_move: u.arbitrary()?,
+            side_move: u.arbitrary()?,
+            up_move: u.arbitrary()?,
+            button_flags: ButtonFlags::from_bits_truncate(u.arbitrary()?),
+            impulse: u.arbitrary()?,
+        },
+        _ => ClientCmd::StringCmd { cmd: string(u)? },
+    })
+}
+
+/// Generates a connectionless request.
+pub fn request(u: &mut Unstructured) -> Result<Request> {
+    Ok(match u.int_in_range(0..=4)? {
+        0 => Request::Connect(RequestConnect {
+            game_name: string(u)?,
+            proto_ver: u.arbitrary()?,
+        }),
+        1 => Request::ServerInfo(RequestServerInfo {
+            game_name: string(u)?,
+        }),
+        2 => Request::PlayerInfo(RequestPlayerInfo {
+            player_id: u.arbitrary()?,
+        }),
+        3 => Request::RuleInfo(RequestRuleInfo {
+            prev_cvar: string(u)?,
+        }),
+        _ => Request::Rcon(RequestRcon {
+            password: string(u)?,
+            command: string(u)?,
+        }),
+    })
+}
+
+/// Generates a connectionless response.
+pub fn response(u: &mut Unstructured) -> Result<Response> {
+    Ok(match u.int_in_range(0..=5)? {
+        0 => Response::Accept(ResponseAccept {
+            port: u.arbitrary()?,
+        }),
+        1 => Response::Reject(ResponseReject {
+            message: string(u)?,
+        }),
+        2 => Response::ServerInfo(ResponseServerInfo {
+            address: string(u)?,
+            hostname: string(u)?,
+            levelname: string(u)?,
+            client_count: u.arbitrary()?,
+            client_max: u.arbitrary()?,
+            protocol_version: u.arbitrary()?,
+        }),
+        3 => Response::PlayerInfo(ResponsePlayerInfo {
+            player_id: u.arbitrary()?,
+            player_name: string(u)?,
+            colors: u.arbitrary()?,
+            frags: u.arbitrary()?,
+            connect_duration: u.arbitrary()?,
+            address: string(u)?,
+        }),
+        4 => Response::RuleInfo(ResponseRuleInfo {
+            cvar_name: string(u)?,
+            cvar_val: string(u)?,
+        }),
+        _ => Response::Rcon(ResponseRcon {
+            message: string(u)?,
+        }),
+    })
+}
+
+fn option<T, F>(u: &mut Unstructured, f: F) -> Result<Option<T>>
+where
+    F: FnOnce(&mut Unstructured) -> Result<T>,
+{
+    match u.arbitrary()? {
+        true => Ok(Some(f(u)?)),
+        false => Ok(None),
+    }
+}
+
+/// Like `option`, but always `None` for protocols without the FitzQuake
+/// extensions.
+fn extension<T, F>(u: &mut Unstructured, extended: bool, f: F) -> Result<Option<T>>
+where
+    F: FnOnce(&mut Unstructured) -> Result<T>,
+{
+    match extended {
+        true => option(u, f),
+        false => Ok(None),
+    }
+}
+
+/// Generates a model, frame, sound or ammo value, which may only exceed 255
+/// with the FitzQuake extensions.
+fn id(u: &mut Unstructured, extended: bool) -> Result<u16> {
+    match extended {
+        true => u.arbitrary(),
+        false => Ok(u.arbitrary::<u8>()? as u16),
+    }
+}
+
+fn float(u: &mut Unstructured) -> Result<f32> {
+    let f: f32 = u.arbitrary()?;
+    Ok(if f.is_finite() { f } else { 0.0 })
+}
+
+/// Generates a string with no interior nul bytes.
+fn string(u: &mut Unstructured) -> Result<String> {
+    let s: &str = u.arbitrary()?;
+    Ok(s.chars()
+        .filter(|c| *c != '\0')
+        .take(MAX_STRING_LEN)
+        .collect())
+}
+
+/// Generates a precache list, which is terminated by an empty name.
+fn precache(u: &mut Unstructured) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for _ in 0..u.int_in_range(0..=MAX_PRECACHE_LEN)? {
+        let name = string(u)?;
+        if !name.is_empty() {
+            names.push(name);
+        }
+    }
+
+    Ok(names)
+}
+
+fn coord(u: &mut Unstructured, flags: ProtocolFlags) -> Result<f32> {
+    Ok(if flags.contains(ProtocolFlags::FLOAT_COORD) {
+        float(u)?
+    } else if flags.contains(ProtocolFlags::INT32_COORD) {
+        u.arbitrary::<i32>()? as f32 / 16.0
+    } else if flags.contains(ProtocolFlags::COORD_24_BIT) {
+        // a fraction of 255 would be sent as the next whole number
+        u.arbitrary::<i16>()? as f32 + u.int_in_range(0..=254u8)? as f32 / 255.0
+    } else {
+        u.arbitrary::<i16>()? as f32 / 8.0
+    })
+}
+
+fn coord_vector3(u: &mut Unstructured, flags: ProtocolFlags) -> Result<Vector3<f32>> {
+    Ok(Vector3::new(
+        coord(u, flags)?,
+        coord(u, flags)?,
+        coord(u, flags)?,
+    ))
+}
+
+fn angle(u: &mut Unstructured, flags: ProtocolFlags) -> Result<Deg<f32>> {
+    if flags.contains(ProtocolFlags::FLOAT_ANGLE) {
+        Ok(Deg(float(u)?))
+    } else if flags.contains(ProtocolFlags::SHORT_ANGLE) {
+        angle16(u, flags)
+    } else {
+        Ok(Deg(u.arbitrary::<i8>()? as f32 * (360.0 / 256.0)))
+    }
+}
+
+fn angle_vector3(u: &mut Unstructured, flags: ProtocolFlags) -> Result<Vector3<Deg<f32>>> {
+    Ok(Vector3::new(
+        angle(u, flags)?,
+        angle(u, flags)?,
+        angle(u, flags)?,
+    ))
+}
+
+fn angle16(u: &mut Unstructured, flags: ProtocolFlags) -> Result<Deg<f32>> {
+    if flags.contains(ProtocolFlags::FLOAT_ANGLE) {
+        return Ok(Deg(float(u)?));
+    }
+
+    Ok(Deg(u.arbitrary::<i16>()? as f32 * (360.0 / 65536.0)))
+}
+
+fn view_angle(u: &mut Unstructured, protocol: Protocol) -> Result<Deg<f32>> {
+    // FitzQuake sends view angles with 16 bits of precision
+    match protocol.is_extended() {
+        true => angle16(u, protocol.flags),
+        false => angle(u, protocol.flags),
+    }
+}
+
+/// Runs `check` on a fixed series of pseudorandom inputs.
+///
+/// Generators that run out of input return an error, which ends that run.
+#[cfg(test)]
+pub fn for_each_input<F>(mut check: F)
+where
+    F: FnMut(&mut Unstructured) -> Result<()>,
+{
+    use rand::{rngs::SmallRng, RngCore, SeedableRng};
+
+    const RUNS: usize = 2000;
+    const INPUT_LEN: usize = 512;
+
+    let mut rng = SmallRng::seed_from_u64(0);
+    let mut data = [0; INPUT_LEN];
+    for _ in 0..RUNS {
+        rng.fill_bytes(&mut data);
+        let _ = check(&mut Unstructured::new(&data));
+    }
+}
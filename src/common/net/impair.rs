@@ -0,0 +1,503 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Simulated network conditions.
+//!
+//! An `ImpairedSocket` sits between a [`QSocket`](super::QSocket) and its UDP
+//! socket and can delay, drop, duplicate and reorder packets in both
+//! directions. It is controlled by the `net_fake*` cvars and is mostly useful
+//! for testing how the engine copes with a bad connection. With a fixed seed,
+//! the same sequence of packets is always impaired the same way.
+
+use std::{
+    io::{self, ErrorKind},
+    net::{SocketAddr, UdpSocket},
+    time::{Duration as StdDuration, Instant},
+};
+
+use crate::common::{console::CvarRegistry, net::BlockingMode};
+
+use chrono::Duration;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+/// A packet held back for reordering is released after this long even if no
+/// other packet has overtaken it.
+const REORDER_TIMEOUT_MS: u64 = 50;
+
+/// Network conditions to simulate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Impairment {
+    /// Round-trip delay, split evenly between the two directions.
+    pub lag: Duration,
+
+    /// Maximum extra delay added to each packet at random.
+    pub jitter: Duration,
+
+    /// Probability that a packet is dropped.
+    pub loss: f32,
+
+    /// Probability that a packet is delivered twice.
+    pub duplicate: f32,
+
+    /// Probability that a packet is held back until the next one is sent.
+    pub reorder: f32,
+}
+
+impl Impairment {
+    /// Returns an `Impairment` which leaves packets alone.
+    pub fn none() -> Impairment {
+        Impairment {
+            lag: Duration::zero(),
+            jitter: Duration::zero(),
+            loss: 0.0,
+            duplicate: 0.0,
+            reorder: 0.0,
+        }
+    }
+
+    /// Reads the impairment from the `net_fake*` cvars.
+    ///
+    /// Delays are in milliseconds and probabilities are percentages.
+    pub fn from_cvars(cvars: &CvarRegistry) -> Impairment {
+        let ms = |name| {
+            let value = cvars.get_value(name).unwrap_or(0.0).max(0.0);
+            Duration::microseconds((value * 1000.0) as i64)
+        };
+        let percent = |name| cvars.get_value(name).unwrap_or(0.0).clamp(0.0, 100.0) / 100.0;
+
+        Impairment {
+            lag: ms("net_fakelag"),
+            jitter: ms("net_fakejitter"),
+            loss: percent("net_fakeloss"),
+            duplicate: percent("net_fakedup"),
+            reorder: percent("net_fakereorder"),
+        }
+    }
+
+    /// Returns whether packets pass through unchanged.
+    pub fn is_none(&self) -> bool {
+        *self == Impairment::none()
+    }
+}
+
+impl Default for Impairment {
+    fn default() -> Self {
+        Impairment::none()
+    }
+}
+
+#[derive(Debug)]
+struct Packet {
+    due: Instant,
+
+    /// Breaks ties between packets due at the same time.
+    order: u64,
+
+    addr: SocketAddr,
+    data: Vec<u8>,
+}
+
+/// Packets travelling in one direction.
+#[derive(Debug, Default)]
+struct Link {
+    queue: Vec<Packet>,
+    held: Option<Packet>,
+    next_order: u64,
+}
+
+impl Link {
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty() && self.held.is_none()
+    }
+
+    /// Applies `impairment` to a packet and queues whatever survives.
+    fn push(
+        &mut self,
+        impairment: &Impairment,
+        rng: &mut SmallRng,
+        now: Instant,
+        addr: SocketAddr,
+        data: &[u8],
+    ) {
+        if rng.gen::<f32>() < impairment.loss {
+            return;
+        }
+
+        let copies = if rng.gen::<f32>() < impairment.duplicate {
+            2
+        } else {
+            1
+        };
+
+        // each direction gets half of the round-trip delay
+        let lag = impairment.lag.to_std().unwrap_or_default() / 2;
+        let jitter = impairment.jitter.to_std().unwrap_or_default();
+
+        for _ in 0..copies {
+            let mut delay = lag;
+            if !jitter.is_zero() {
+                delay += jitter.mul_f64(rng.gen::<f64>());
+            }
+
+            let packet = Packet {
+                due: now + delay,
+                order: self.next_order,
+                addr,
+                data: data.to_owned(),
+            };
+            self.next_order += 1;
+
+            if self.held.is_none() && rng.gen::<f32>() < impairment.reorder {
+                self.held = Some(packet);
+                continue;
+            }
+
+            let due = packet.due;
+            self.queue.push(packet);
+
+            // the held packet goes out after the one that overtook it
+            if let Some(mut held) = self.held.take() {
+                held.due = held.due.max(due);
+                held.order = self.next_order;
+                self.next_order += 1;
+                self.queue.push(held);
+            }
+        }
+    }
+
+    /// Returns the time at which the next packet should be delivered.
+    fn next_due(&self) -> Option<Instant> {
+        let held = self
+            .held
+            .as_ref()
+            .map(|h| h.due + StdDuration::from_millis(REORDER_TIMEOUT_MS));
+        self.queue.iter().map(|p| p.due).chain(held).min()
+    }
+
+    /// Removes the next packet which is due by `now`.
+    fn pop_due(&mut self, now: Instant) -> Option<Packet> {
+        if let Some(ref held) = self.held {
+            if now >= held.due + StdDuration::from_millis(REORDER_TIMEOUT_MS) {
+                let mut held = self.held.take().unwrap();
+                held.order = self.next_order;
+                self.next_order += 1;
+                self.queue.push(held);
+            }
+        }
+
+        let index = self
+            .queue
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.due <= now)
+            .min_by_key(|(_, p)| (p.due, p.order))
+            .map(|(i, _)| i)?;
+
+        Some(self.queue.swap_remove(index))
+    }
+}
+
+/// A UDP socket which simulates a bad network connection.
+///
+/// Without an impairment, packets go straight to and from the underlying
+/// socket. Otherwise outgoing packets are only sent once they are due, so the
+/// socket must be polled regularly, which `QSocket` does whenever it sends or
+/// receives.
+#[derive(Debug)]
+pub struct ImpairedSocket {
+    socket: UdpSocket,
+    impairment: Impairment,
+    rng: SmallRng,
+
+    mode: BlockingMode,
+
+    /// Whether `mode` has been applied to the underlying socket.
+    mode_applied: bool,
+
+    outgoing: Link,
+    incoming: Link,
+}
+
+impl ImpairedSocket {
+    /// Wraps a socket without impairing it.
+    pub fn new(socket: UdpSocket) -> ImpairedSocket {
+        ImpairedSocket::with_rng(socket, Impairment::none(), SmallRng::from_entropy())
+    }
+
+    /// Wraps a socket with an impairment whose random choices are determined
+    /// by `seed`.
+    pub fn with_seed(socket: UdpSocket, impairment: Impairment, seed: u64) -> ImpairedSocket {
+        ImpairedSocket::with_rng(socket, impairment, SmallRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(socket: UdpSocket, impairment: Impairment, rng: SmallRng) -> ImpairedSocket {
+        ImpairedSocket {
+            socket,
+            impairment,
+            rng,
+            mode: BlockingMode::Blocking,
+            mode_applied: false,
+            outgoing: Link::default(),
+            incoming: Link::default(),
+        }
+    }
+
+    pub fn impairment(&self) -> Impairment {
+        self.impairment
+    }
+
+    /// Changes the simulated conditions.
+    ///
+    /// Packets already delayed keep their delivery times.
+    pub fn set_impairment(&mut self, impairment: Impairment) {
+        self.impairment = impairment;
+    }
+
+    /// Sets how `recv_from` waits for packets.
+    pub fn set_blocking_mode(&mut self, mode: BlockingMode) -> io::Result<()> {
+        if self.mode != mode || !self.mode_applied {
+            self.mode = mode;
+            self.apply_mode()?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_mode(&mut self) -> io::Result<()> {
+        match self.mode {
+            BlockingMode::Blocking => {
+                self.socket.set_nonblocking(false)?;
+                self.socket.set_read_timeout(None)?;
+            }
+
+            BlockingMode::NonBlocking => {
+                self.socket.set_nonblocking(true)?;
+                self.socket.set_read_timeout(None)?;
+            }
+
+            BlockingMode::Timeout(d) => {
+                self.socket.set_nonblocking(false)?;
+                self.socket.set_read_timeout(Some(d.to_std().unwrap()))?;
+            }
+        }
+
+        self.mode_applied = true;
+        Ok(())
+    }
+
+    /// Returns whether packets currently pass straight through.
+    fn is_passthrough(&self) -> bool {
+        self.impairment.is_none() && self.outgoing.is_empty() && self.incoming.is_empty()
+    }
+
+    pub fn send_to(&mut self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        if self.is_passthrough() {
+            return self.socket.send_to(buf, addr);
+        }
+
+        let now = Instant::now();
+        self.outgoing
+            .push(&self.impairment, &mut self.rng, now, addr, buf);
+        self.flush(now)?;
+
+        Ok(buf.len())
+    }
+
+    /// Sends any delayed packets which are due.
+    pub fn flush(&mut self, now: Instant) -> io::Result<()> {
+        while let Some(packet) = self.outgoing.pop_due(now) {
+            self.socket.send_to(&packet.data, packet.addr)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        if self.is_passthrough() {
+            if !self.mode_applied {
+                self.apply_mode()?;
+            }
+
+            return self.socket.recv_from(buf);
+        }
+
+        let start = Instant::now();
+        let deadline = match self.mode {
+            BlockingMode::Blocking => None,
+            BlockingMode::NonBlocking => Some(start),
+            BlockingMode::Timeout(d) => Some(start + d.to_std().unwrap_or_default()),
+        };
+
+        // the underlying socket's mode is changed below
+        self.mode_applied = false;
+
+        loop {
+            let now = Instant::now();
+            self.flush(now)?;
+
+            if let Some(packet) = self.incoming.pop_due(now) {
+                let len = packet.data.len().min(buf.len());
+                buf[..len].copy_from_slice(&packet.data[..len]);
+                return Ok((len, packet.addr));
+            }
+
+            // wait for a packet, but not past the deadline or the next
+            // delayed packet in either direction
+            let wake = [deadline, self.incoming.next_due(), self.outgoing.next_due()]
+                .into_iter()
+                .flatten()
+                .min();
+            match wake.map(|w| w.saturating_duration_since(now)) {
+                Some(wait) if wait.is_zero() => self.socket.set_nonblocking(true)?,
+                wait => {
+                    self.socket.set_nonblocking(false)?;
+                    self.socket.set_read_timeout(wait)?;
+                }
+            }
+
+            match self.socket.recv_from(buf) {
+                Ok((len, addr)) => {
+                    self.incoming.push(
+                        &self.impairment,
+                        &mut self.rng,
+                        Instant::now(),
+                        addr,
+                        &buf[..len],
+                    );
+                }
+
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if deadline.is_some_and(|d| Instant::now() >= d) {
+                        return Err(e);
+                    }
+                }
+
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl From<UdpSocket> for ImpairedSocket {
+    fn from(socket: UdpSocket) -> Self {
+        ImpairedSocket::new(socket)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn socket_pair(impairment: Impairment, seed: u64) -> (ImpairedSocket, UdpSocket) {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        a.connect(b.local_addr().unwrap()).unwrap();
+        b.connect(a.local_addr().unwrap()).unwrap();
+        b.set_read_timeout(Some(StdDuration::from_millis(200)))
+            .unwrap();
+        (ImpairedSocket::with_seed(a, impairment, seed), b)
+    }
+
+    /// Sends packets 0..count and returns the ones which arrived, in order.
+    fn send_sequence(impairment: Impairment, seed: u64, count: u8) -> Vec<u8> {
+        let (mut sock, peer) = socket_pair(impairment, seed);
+        let peer_addr = peer.local_addr().unwrap();
+
+        for i in 0..count {
+            sock.send_to(&[i], peer_addr).unwrap();
+        }
+
+        // release anything still held back
+        let later = Instant::now() + StdDuration::from_secs(1);
+        sock.flush(later).unwrap();
+
+        let mut received = Vec::new();
+        let mut buf = [0u8; 16];
+        while let Ok(len) = peer.recv(&mut buf) {
+            received.extend_from_slice(&buf[..len]);
+        }
+        received
+    }
+
+    #[test]
+    fn test_unimpaired_passthrough() {
+        let received = send_sequence(Impairment::none(), 0, 16);
+        assert_eq!(received, (0..16).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_loss_is_deterministic() {
+        let impairment = Impairment {
+            loss: 0.5,
+            ..Impairment::none()
+        };
+
+        let first = send_sequence(impairment, 42, 32);
+        let second = send_sequence(impairment, 42, 32);
+        assert_eq!(first, second);
+        assert!(!first.is_empty() && first.len() < 32);
+        assert!(first.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_duplicate_all() {
+        let impairment = Impairment {
+            duplicate: 1.0,
+            ..Impairment::none()
+        };
+
+        let received = send_sequence(impairment, 0, 4);
+        assert_eq!(received, vec![0, 0, 1, 1, 2, 2, 3, 3]);
+    }
+
+    #[test]
+    fn test_reorder_swaps_neighbours() {
+        let impairment = Impairment {
+            reorder: 1.0,
+            ..Impairment::none()
+        };
+
+        // every packet that isn't overtaking a held one is held itself
+        let received = send_sequence(impairment, 0, 4);
+        assert_eq!(received, vec![1, 0, 3, 2]);
+    }
+
+    #[test]
+    fn test_lag_delays_delivery() {
+        let impairment = Impairment {
+            lag: Duration::milliseconds(100),
+            ..Impairment::none()
+        };
+        let (mut sock, peer) = socket_pair(impairment, 0);
+        let peer_addr = peer.local_addr().unwrap();
+        peer.set_nonblocking(true).unwrap();
+
+        sock.send_to(&[7], peer_addr).unwrap();
+        let mut buf = [0u8; 16];
+        assert!(peer.recv(&mut buf).is_err());
+
+        // half the round trip later, the packet is sent
+        sock.flush(Instant::now() + StdDuration::from_millis(50))
+            .unwrap();
+        peer.set_nonblocking(false).unwrap();
+        assert_eq!(peer.recv(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 7);
+    }
+}
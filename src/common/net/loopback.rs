@@ -0,0 +1,245 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! In-process message transport.
+//!
+//! A `LoopbackSocket` provides the same reliable and unreliable message
+//! semantics as a [`QSocket`](super::QSocket), but passes messages through
+//! shared in-memory queues instead of the network. This allows a client to
+//! connect to a server running in the same process.
+
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+use crate::common::net::{BlockingMode, NetError, MAX_DATAGRAM, MAX_MESSAGE};
+
+#[derive(Debug)]
+enum LoopbackMsg {
+    Reliable(Vec<u8>),
+    Unreliable(Vec<u8>),
+}
+
+/// Messages travelling in one direction.
+#[derive(Debug, Default)]
+struct LoopbackQueue {
+    msgs: VecDeque<LoopbackMsg>,
+
+    /// Whether a reliable message is waiting to be received.
+    ///
+    /// Like an unacknowledged `QSocket` message, this prevents the sender from
+    /// sending another reliable message until the receiver has read it.
+    reliable_pending: bool,
+}
+
+/// One end of an in-process connection.
+///
+/// Since both ends live on the same thread, blocking receives cannot wait for
+/// the other end to send anything and behave the same as nonblocking ones.
+#[derive(Debug)]
+pub struct LoopbackSocket {
+    send: Rc<RefCell<LoopbackQueue>>,
+    recv: Rc<RefCell<LoopbackQueue>>,
+}
+
+impl LoopbackSocket {
+    /// Creates a pair of connected sockets.
+    pub fn pair() -> (LoopbackSocket, LoopbackSocket) {
+        let a_to_b = Rc::new(RefCell::new(LoopbackQueue::default()));
+        let b_to_a = Rc::new(RefCell::new(LoopbackQueue::default()));
+
+        let a = LoopbackSocket {
+            send: a_to_b.clone(),
+            recv: b_to_a.clone(),
+        };
+
+        let b = LoopbackSocket {
+            send: b_to_a,
+            recv: a_to_b,
+        };
+
+        (a, b)
+    }
+
+    /// Returns whether the other end of the connection has been dropped.
+    pub fn is_disconnected(&self) -> bool {
+        // the only other reference to our send queue belongs to the peer
+        Rc::strong_count(&self.send) < 2
+    }
+
+    /// Returns whether the previous reliable message has been received.
+    pub fn can_send(&self) -> bool {
+        !self.send.borrow().reliable_pending
+    }
+
+    /// Begin sending a reliable message over this socket.
+    pub fn begin_send_msg(&mut self, msg: &[u8]) -> Result<(), NetError> {
+        if !self.can_send() {
+            return Err(NetError::with_msg(
+                "begin_send_msg: previous message unacknowledged",
+            ));
+        }
+
+        if msg.is_empty() {
+            return Err(NetError::with_msg(
+                "begin_send_msg: Input data has zero length",
+            ));
+        }
+
+        if msg.len() > MAX_MESSAGE {
+            return Err(NetError::with_msg(
+                "begin_send_msg: Input data exceeds MAX_MESSAGE",
+            ));
+        }
+
+        self.check_connected()?;
+
+        let mut send = self.send.borrow_mut();
+        send.msgs.push_back(LoopbackMsg::Reliable(msg.to_owned()));
+        send.reliable_pending = true;
+
+        Ok(())
+    }
+
+    /// Resend the last reliable message.
+    ///
+    /// Loopback messages cannot be lost, so this does nothing.
+    pub fn resend_msg(&mut self) -> Result<(), NetError> {
+        Ok(())
+    }
+
+    /// Send an unreliable message over this socket.
+    pub fn send_msg_unreliable(&mut self, content: &[u8]) -> Result<(), NetError> {
+        if content.is_empty() {
+            return Err(NetError::with_msg("Unreliable message has zero length"));
+        }
+
+        if content.len() > MAX_DATAGRAM {
+            return Err(NetError::with_msg(
+                "Unreliable message length exceeds MAX_DATAGRAM",
+            ));
+        }
+
+        self.check_connected()?;
+
+        self.send
+            .borrow_mut()
+            .msgs
+            .push_back(LoopbackMsg::Unreliable(content.to_owned()));
+
+        Ok(())
+    }
+
+    /// Receive a message on this socket.
+    ///
+    /// Messages are received in the order they were sent. If no message is
+    /// waiting, an empty message is returned regardless of `block`.
+    pub fn recv_msg(&mut self, _block: BlockingMode) -> Result<Vec<u8>, NetError> {
+        let mut recv = self.recv.borrow_mut();
+
+        match recv.msgs.pop_front() {
+            Some(LoopbackMsg::Reliable(msg)) => {
+                // this acknowledges the message
+                recv.reliable_pending = false;
+                Ok(msg)
+            }
+
+            Some(LoopbackMsg::Unreliable(msg)) => Ok(msg),
+
+            None => {
+                drop(recv);
+                self.check_connected()?;
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    fn check_connected(&self) -> Result<(), NetError> {
+        if self.is_disconnected() {
+            Err(NetError::with_msg("Loopback peer disconnected"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_loopback_message_order() {
+        let (mut client, mut server) = LoopbackSocket::pair();
+
+        server.begin_send_msg(&[1, 2, 3]).unwrap();
+        server.send_msg_unreliable(&[4]).unwrap();
+
+        assert_eq!(
+            client.recv_msg(BlockingMode::NonBlocking).unwrap(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(client.recv_msg(BlockingMode::NonBlocking).unwrap(), vec![4]);
+        assert!(client
+            .recv_msg(BlockingMode::NonBlocking)
+            .unwrap()
+            .is_empty());
+
+        // the other direction is independent
+        assert!(server
+            .recv_msg(BlockingMode::NonBlocking)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_loopback_reliable_acknowledged_on_receipt() {
+        let (mut client, mut server) = LoopbackSocket::pair();
+
+        server.begin_send_msg(&[1]).unwrap();
+        assert!(!server.can_send());
+        assert!(server.begin_send_msg(&[2]).is_err());
+
+        // unreliable messages can still be sent
+        server.send_msg_unreliable(&[3]).unwrap();
+
+        client.recv_msg(BlockingMode::NonBlocking).unwrap();
+        assert!(server.can_send());
+        server.begin_send_msg(&[2]).unwrap();
+    }
+
+    #[test]
+    fn test_loopback_size_limits() {
+        let (_client, mut server) = LoopbackSocket::pair();
+
+        assert!(server.begin_send_msg(&[]).is_err());
+        assert!(server.begin_send_msg(&[0; MAX_MESSAGE + 1]).is_err());
+        assert!(server.send_msg_unreliable(&[0; MAX_DATAGRAM + 1]).is_err());
+        server.begin_send_msg(&[0; MAX_MESSAGE]).unwrap();
+    }
+
+    #[test]
+    fn test_loopback_disconnect() {
+        let (client, mut server) = LoopbackSocket::pair();
+        assert!(!server.is_disconnected());
+
+        drop(client);
+        assert!(server.is_disconnected());
+        assert!(server.send_msg_unreliable(&[1]).is_err());
+        assert!(server.recv_msg(BlockingMode::NonBlocking).is_err());
+    }
+}
@@ -21,13 +21,24 @@
 // TODO: need to figure out an equivalence relation for read_/write_coord and read_/write_angle
 
 pub mod connect;
+#[cfg(any(test, feature = "arbitrary"))]
+pub mod generate;
+pub mod impair;
+pub mod loopback;
+pub mod query;
+
+pub use self::{
+    impair::{ImpairedSocket, Impairment},
+    loopback::LoopbackSocket,
+};
 
 use std::{
     collections::VecDeque,
     error::Error,
     fmt,
     io::{BufRead, BufReader, Cursor, Read, Write},
-    net::{SocketAddr, UdpSocket},
+    net::SocketAddr,
+    time::Instant,
 };
 
 use crate::common::{engine, util};
@@ -36,13 +47,108 @@ use byteorder::{LittleEndian, NetworkEndian, ReadBytesExt, WriteBytesExt};
 use cgmath::{Deg, Vector3, Zero};
 use chrono::Duration;
 use num::FromPrimitive;
+use serde::Serialize;
 
 pub const MAX_MESSAGE: usize = 8192;
 const MAX_DATAGRAM: usize = 1024;
 const HEADER_SIZE: usize = 8;
 const MAX_PACKET: usize = HEADER_SIZE + MAX_DATAGRAM;
 
-pub const PROTOCOL_VERSION: u8 = 15;
+/// Unacknowledged reliable packets are resent after this long.
+const RESEND_TIMEOUT_MS: i64 = 1000;
+
+/// The network protocol versions understood by this implementation.
+///
+/// The protocol in use is chosen by the server and announced to the client in
+/// the `ServerInfo` command; every message after that point is encoded
+/// according to the announced version.
+#[derive(Copy, Clone, Debug, Default, Eq, FromPrimitive, PartialEq)]
+pub enum ProtocolVersion {
+    /// The original NetQuake protocol (`sv_protocol 15`).
+    #[default]
+    NetQuake = 15,
+
+    /// The FitzQuake extended protocol (`sv_protocol 666`).
+    ///
+    /// This adds 16-bit model, sound and frame indices, entity alpha, fog and
+    /// skybox messages and 16-bit client view angles.
+    FitzQuake = 666,
+
+    /// The RMQ extended protocol (`sv_protocol 999`).
+    ///
+    /// This is a superset of the FitzQuake protocol in which the server may
+    /// choose the precision of coordinates and angles. The chosen encoding is
+    /// described by the `ProtocolFlags` sent alongside the version.
+    Rmq = 999,
+}
+
+impl ProtocolVersion {
+    /// Returns the numeric value of this protocol version as sent on the wire.
+    pub fn value(&self) -> i32 {
+        *self as i32
+    }
+
+    /// Returns `true` if this protocol supports the FitzQuake extensions.
+    pub fn is_extended(&self) -> bool {
+        *self != ProtocolVersion::NetQuake
+    }
+}
+
+bitflags! {
+    /// Encoding flags announced by an RMQ server in the `ServerInfo` command.
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+    pub struct ProtocolFlags: u32 {
+        /// Angles are sent as 16-bit integers.
+        const SHORT_ANGLE = 1 << 1;
+        /// Angles are sent as 32-bit floats.
+        const FLOAT_ANGLE = 1 << 2;
+        /// Coordinates are sent as a 16-bit integer part and an 8-bit fraction.
+        const COORD_24_BIT = 1 << 3;
+        /// Coordinates are sent as 32-bit floats.
+        const FLOAT_COORD = 1 << 4;
+        /// Entity updates may carry a scale.
+        const EDICT_SCALE = 1 << 5;
+        /// Entity alpha is clamped to the visible range.
+        const ALPHA_SANITY = 1 << 6;
+        /// Coordinates are sent as 32-bit integers in 1/16 units.
+        const INT32_COORD = 1 << 7;
+        /// Reserved for a second set of flags.
+        const MORE_FLAGS = 1 << 31;
+    }
+}
+
+/// The protocol version and encoding in effect for a connection.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Protocol {
+    pub version: ProtocolVersion,
+    pub flags: ProtocolFlags,
+}
+
+impl Protocol {
+    /// Creates a new `Protocol` from a version and its encoding flags.
+    ///
+    /// Only the RMQ protocol carries encoding flags; they are discarded for
+    /// every other version.
+    pub fn new(version: ProtocolVersion, flags: ProtocolFlags) -> Protocol {
+        let flags = match version {
+            ProtocolVersion::Rmq => flags,
+            _ => ProtocolFlags::empty(),
+        };
+
+        Protocol { version, flags }
+    }
+
+    /// Returns `true` if this protocol supports the FitzQuake extensions.
+    pub fn is_extended(&self) -> bool {
+        self.version.is_extended()
+    }
+}
+
+impl From<ProtocolVersion> for Protocol {
+    fn from(version: ProtocolVersion) -> Protocol {
+        Protocol::new(version, ProtocolFlags::empty())
+    }
+}
 
 #[allow(dead_code)]
 const NAME_LEN: usize = 64;
@@ -109,6 +215,12 @@ impl From<std::io::Error> for NetError {
     }
 }
 
+impl From<std::string::FromUtf8Error> for NetError {
+    fn from(error: std::string::FromUtf8Error) -> Self {
+        NetError::InvalidData(format!("string: {}", error))
+    }
+}
+
 // the original engine treats these as bitflags, but all of them are mutually exclusive except for
 // NETFLAG_DATA (reliable message) and NETFLAG_EOM (end of reliable message).
 #[derive(Debug, Eq, FromPrimitive, PartialEq)]
@@ -121,7 +233,7 @@ pub enum MsgKind {
 }
 
 bitflags! {
-    pub struct UpdateFlags: u16 {
+    pub struct UpdateFlags: u32 {
         const MORE_BITS = 1 << 0;
         const ORIGIN_X = 1 << 1;
         const ORIGIN_Y = 1 << 2;
@@ -137,11 +249,21 @@ bitflags! {
         const SKIN = 1 << 12;
         const EFFECTS = 1 << 13;
         const LONG_ENTITY = 1 << 14;
+
+        // FitzQuake extensions
+        const EXTEND_1 = 1 << 15;
+        const ALPHA = 1 << 16;
+        const FRAME_2 = 1 << 17;
+        const MODEL_2 = 1 << 18;
+        const LERP_FINISH = 1 << 19;
+        const SCALE = 1 << 20;
+        const EXTEND_2 = 1 << 23;
     }
 }
 
 bitflags! {
-    pub struct ClientUpdateFlags: u16 {
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct ClientUpdateFlags: u32 {
         const VIEW_HEIGHT = 1 << 0;
         const IDEAL_PITCH = 1 << 1;
         const PUNCH_PITCH = 1 << 2;
@@ -157,6 +279,19 @@ bitflags! {
         const WEAPON_FRAME = 1 << 12;
         const ARMOR = 1 << 13;
         const WEAPON = 1 << 14;
+
+        // FitzQuake extensions
+        const EXTEND_1 = 1 << 15;
+        const WEAPON_2 = 1 << 16;
+        const ARMOR_2 = 1 << 17;
+        const AMMO_2 = 1 << 18;
+        const SHELLS_2 = 1 << 19;
+        const NAILS_2 = 1 << 20;
+        const ROCKETS_2 = 1 << 21;
+        const CELLS_2 = 1 << 22;
+        const EXTEND_2 = 1 << 23;
+        const WEAPON_FRAME_2 = 1 << 24;
+        const WEAPON_ALPHA = 1 << 25;
     }
 }
 
@@ -165,11 +300,24 @@ bitflags! {
         const VOLUME = 1 << 0;
         const ATTENUATION = 1 << 1;
         const LOOPING = 1 << 2;
+
+        // FitzQuake extensions
+        const LARGE_ENTITY = 1 << 3;
+        const LARGE_SOUND = 1 << 4;
     }
 }
 
 bitflags! {
-    #[derive(Clone, Copy, Debug, PartialEq)]
+    /// Flags for the FitzQuake `SpawnBaseline2` and `SpawnStatic2` commands.
+    pub struct BaselineFlags: u8 {
+        const LARGE_MODEL = 1 << 0;
+        const LARGE_FRAME = 1 << 1;
+        const ALPHA = 1 << 2;
+    }
+}
+
+bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Serialize)]
     pub struct ItemFlags: u32 {
         const SHOTGUN          = 0x00000001;
         const SUPER_SHOTGUN    = 0x00000002;
@@ -209,7 +357,7 @@ bitflags! {
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
 pub struct PlayerColor {
     top: u8,
     bottom: u8,
@@ -255,7 +403,7 @@ pub struct ColorShift {
     pub percent: i32,
 }
 
-#[derive(Copy, Clone, Debug, Eq, FromPrimitive, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, FromPrimitive, PartialEq, Serialize)]
 pub enum ClientStat {
     Health = 0,
     Frags = 1,
@@ -293,7 +441,7 @@ pub enum TempEntityCode {
     Grapple = 13,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
 pub enum PointEntityKind {
     Spike,
     SuperSpike,
@@ -307,7 +455,7 @@ pub enum PointEntityKind {
     Teleport,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
 pub enum BeamEntityKind {
     /// Lightning bolt
     Lightning {
@@ -318,7 +466,7 @@ pub enum BeamEntityKind {
     Grapple,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub enum TempEntity {
     Point {
         kind: PointEntityKind,
@@ -333,7 +481,7 @@ pub enum TempEntity {
 }
 
 impl TempEntity {
-    pub fn read_temp_entity<R>(reader: &mut R) -> Result<TempEntity, NetError>
+    pub fn read_temp_entity<R>(reader: &mut R, protocol: Protocol) -> Result<TempEntity, NetError>
     where
         R: BufRead + ReadBytesExt,
     {
@@ -373,10 +521,10 @@ impl TempEntity {
                     Code::Teleport => PointEntityKind::Teleport,
                     _ => unreachable!(),
                 },
-                origin: read_coord_vector3(reader)?,
+                origin: read_coord_vector3(reader, protocol.flags)?,
             },
             Code::ColorExplosion => {
-                let origin = read_coord_vector3(reader)?;
+                let origin = read_coord_vector3(reader, protocol.flags)?;
                 let color_start = reader.read_u8()?;
                 let color_len = reader.read_u8()?;
 
@@ -398,19 +546,19 @@ impl TempEntity {
                     },
                 },
                 entity_id: reader.read_i16::<LittleEndian>()?,
-                start: read_coord_vector3(reader)?,
-                end: read_coord_vector3(reader)?,
+                start: read_coord_vector3(reader, protocol.flags)?,
+                end: read_coord_vector3(reader, protocol.flags)?,
             },
             Code::Grapple => Beam {
                 kind: BeamEntityKind::Grapple,
                 entity_id: reader.read_i16::<LittleEndian>()?,
-                start: read_coord_vector3(reader)?,
-                end: read_coord_vector3(reader)?,
+                start: read_coord_vector3(reader, protocol.flags)?,
+                end: read_coord_vector3(reader, protocol.flags)?,
             },
         })
     }
 
-    pub fn write_temp_entity<W>(&self, writer: &mut W) -> Result<(), NetError>
+    pub fn write_temp_entity<W>(&self, writer: &mut W, protocol: Protocol) -> Result<(), NetError>
     where
         W: WriteBytesExt,
     {
@@ -444,19 +592,19 @@ impl TempEntity {
 
                         // write code
                         writer.write_u8(code as u8)?;
+                        write_coord_vector3(writer, origin, protocol.flags)?;
                     }
                     PointEntityKind::ColorExplosion {
                         color_start,
                         color_len,
                     } => {
-                        // write code and colors
+                        // write code, origin and colors
                         writer.write_u8(Code::ColorExplosion as u8)?;
+                        write_coord_vector3(writer, origin, protocol.flags)?;
                         writer.write_u8(color_start)?;
                         writer.write_u8(color_len)?;
                     }
                 };
-
-                write_coord_vector3(writer, origin)?;
             }
 
             TempEntity::Beam {
@@ -470,15 +618,19 @@ impl TempEntity {
                         1 => Code::Lightning1,
                         2 => Code::Lightning2,
                         3 => Code::Lightning3,
-                        // TODO: error
-                        _ => panic!("invalid lightning model id: {}", model_id),
+                        _ => {
+                            return Err(NetError::with_msg(format!(
+                                "Invalid lightning model id: {}",
+                                model_id
+                            )))
+                        }
                     },
                     BeamEntityKind::Grapple => Code::Grapple,
                 };
-                writer.write_i16::<LittleEndian>(entity_id)?;
                 writer.write_u8(code as u8)?;
-                write_coord_vector3(writer, start)?;
-                write_coord_vector3(writer, end)?;
+                writer.write_i16::<LittleEndian>(entity_id)?;
+                write_coord_vector3(writer, start, protocol.flags)?;
+                write_coord_vector3(writer, end, protocol.flags)?;
             }
         }
 
@@ -486,7 +638,7 @@ impl TempEntity {
     }
 }
 
-#[derive(Copy, Clone, Ord, Debug, Eq, FromPrimitive, PartialOrd, PartialEq)]
+#[derive(Copy, Clone, Ord, Debug, Eq, FromPrimitive, PartialOrd, PartialEq, Serialize)]
 pub enum SignOnStage {
     Not = 0,
     Prespawn = 1,
@@ -496,7 +648,7 @@ pub enum SignOnStage {
 }
 
 bitflags! {
-    #[derive(Clone, Copy, Debug, PartialEq)]
+    #[derive(Clone, Copy, Debug, PartialEq, Serialize)]
     pub struct EntityEffects: u8 {
         const BRIGHT_FIELD = 0b0001;
         const MUZZLE_FLASH = 0b0010;
@@ -505,6 +657,32 @@ bitflags! {
     }
 }
 
+/// Encoded entity alpha indicating that the entity should use its default opacity.
+pub const ENTITY_ALPHA_DEFAULT: u8 = 0;
+
+/// Encoded entity alpha for a fully transparent entity.
+pub const ENTITY_ALPHA_ZERO: u8 = 1;
+
+/// Encoded entity alpha for a fully opaque entity.
+pub const ENTITY_ALPHA_ONE: u8 = 255;
+
+/// Encode an opacity value in the range `[0, 1]` as a FitzQuake alpha byte.
+pub fn encode_entity_alpha(alpha: f32) -> u8 {
+    if alpha == 0.0 {
+        ENTITY_ALPHA_DEFAULT
+    } else {
+        (alpha * 254.0 + 1.0).round().clamp(1.0, 255.0) as u8
+    }
+}
+
+/// Decode a FitzQuake alpha byte into an opacity value in the range `[0, 1]`.
+pub fn decode_entity_alpha(alpha: u8) -> f32 {
+    match alpha {
+        ENTITY_ALPHA_DEFAULT => 1.0,
+        a => (a - 1) as f32 / 254.0,
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct EntityState {
     pub origin: Vector3<f32>,
@@ -516,6 +694,9 @@ pub struct EntityState {
     pub colormap: u8,
     pub skin_id: usize,
     pub effects: EntityEffects,
+
+    /// Encoded entity alpha (see [`decode_entity_alpha`]).
+    pub alpha: u8,
 }
 
 impl EntityState {
@@ -528,15 +709,16 @@ impl EntityState {
             colormap: 0,
             skin_id: 0,
             effects: EntityEffects::empty(),
+            alpha: ENTITY_ALPHA_DEFAULT,
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct EntityUpdate {
     pub ent_id: u16,
-    pub model_id: Option<u8>,
-    pub frame_id: Option<u8>,
+    pub model_id: Option<u16>,
+    pub frame_id: Option<u16>,
     pub colormap: Option<u8>,
     pub skin_id: Option<u8>,
     pub effects: Option<EntityEffects>,
@@ -547,9 +729,14 @@ pub struct EntityUpdate {
     pub origin_z: Option<f32>,
     pub roll: Option<Deg<f32>>,
     pub no_lerp: bool,
+
+    // FitzQuake extensions
+    pub alpha: Option<u8>,
+    pub scale: Option<u8>,
+    pub lerp_finish: Option<u8>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct PlayerData {
     pub view_height: Option<f32>,
     pub ideal_pitch: Option<Deg<f32>>,
@@ -562,16 +749,17 @@ pub struct PlayerData {
     pub items: ItemFlags,
     pub on_ground: bool,
     pub in_water: bool,
-    pub weapon_frame: Option<u8>,
-    pub armor: Option<u8>,
-    pub weapon: Option<u8>,
+    pub weapon_frame: Option<u16>,
+    pub armor: Option<u16>,
+    pub weapon: Option<u16>,
     pub health: i16,
-    pub ammo: u8,
-    pub ammo_shells: u8,
-    pub ammo_nails: u8,
-    pub ammo_rockets: u8,
-    pub ammo_cells: u8,
+    pub ammo: u16,
+    pub ammo_shells: u16,
+    pub ammo_nails: u16,
+    pub ammo_rockets: u16,
+    pub ammo_cells: u16,
     pub active_weapon: u8,
+    pub weapon_alpha: Option<u8>,
 }
 
 impl EntityUpdate {
@@ -594,7 +782,158 @@ impl EntityUpdate {
             skin_id: self.skin_id.map_or(baseline.skin_id, |s| s as usize),
             effects: self.effects.unwrap_or(baseline.effects),
             colormap: self.colormap.unwrap_or(baseline.colormap),
+            alpha: self.alpha.unwrap_or(baseline.alpha),
+        }
+    }
+
+    /// Writes this update as a fast update command.
+    pub fn serialize<W>(&self, writer: &mut W, protocol: Protocol) -> Result<(), NetError>
+    where
+        W: WriteBytesExt,
+    {
+        let mut flags = UpdateFlags::empty();
+
+        if self.origin_x.is_some() {
+            flags |= UpdateFlags::ORIGIN_X;
+        }
+        if self.origin_y.is_some() {
+            flags |= UpdateFlags::ORIGIN_Y;
+        }
+        if self.origin_z.is_some() {
+            flags |= UpdateFlags::ORIGIN_Z;
+        }
+        if self.yaw.is_some() {
+            flags |= UpdateFlags::YAW;
+        }
+        if self.no_lerp {
+            flags |= UpdateFlags::NO_LERP;
+        }
+        if let Some(f) = self.frame_id {
+            flags |= UpdateFlags::FRAME;
+            if f > u8::MAX as u16 {
+                flags |= UpdateFlags::FRAME_2;
+            }
         }
+        if self.pitch.is_some() {
+            flags |= UpdateFlags::PITCH;
+        }
+        if self.roll.is_some() {
+            flags |= UpdateFlags::ROLL;
+        }
+        if let Some(m) = self.model_id {
+            flags |= UpdateFlags::MODEL;
+            if m > u8::MAX as u16 {
+                flags |= UpdateFlags::MODEL_2;
+            }
+        }
+        if self.colormap.is_some() {
+            flags |= UpdateFlags::COLORMAP;
+        }
+        if self.skin_id.is_some() {
+            flags |= UpdateFlags::SKIN;
+        }
+        if self.effects.is_some() {
+            flags |= UpdateFlags::EFFECTS;
+        }
+        if self.ent_id > u8::MAX as u16 {
+            flags |= UpdateFlags::LONG_ENTITY;
+        }
+        if self.alpha.is_some() {
+            flags |= UpdateFlags::ALPHA;
+        }
+        if self.scale.is_some() {
+            flags |= UpdateFlags::SCALE;
+        }
+        if self.lerp_finish.is_some() {
+            flags |= UpdateFlags::LERP_FINISH;
+        }
+
+        if flags.bits() >= 1 << 16 {
+            flags |= UpdateFlags::EXTEND_1;
+        }
+        if flags.bits() >= 1 << 24 {
+            flags |= UpdateFlags::EXTEND_2;
+        }
+        if flags.bits() >= 1 << 8 {
+            flags |= UpdateFlags::MORE_BITS;
+        }
+
+        if !protocol.is_extended() && flags.bits() >= UpdateFlags::EXTEND_1.bits() {
+            return Err(NetError::with_msg(format!(
+                "Update for entity {} exceeds the limits of protocol {}",
+                self.ent_id,
+                protocol.version.value(),
+            )));
+        }
+
+        let bits = flags.bits();
+        writer.write_u8(bits as u8 | FAST_UPDATE_FLAG)?;
+        if flags.contains(UpdateFlags::MORE_BITS) {
+            writer.write_u8((bits >> 8) as u8)?;
+        }
+        if flags.contains(UpdateFlags::EXTEND_1) {
+            writer.write_u8((bits >> 16) as u8)?;
+        }
+        if flags.contains(UpdateFlags::EXTEND_2) {
+            writer.write_u8((bits >> 24) as u8)?;
+        }
+
+        if flags.contains(UpdateFlags::LONG_ENTITY) {
+            writer.write_u16::<LittleEndian>(self.ent_id)?;
+        } else {
+            writer.write_u8(self.ent_id as u8)?;
+        }
+
+        if let Some(m) = self.model_id {
+            writer.write_u8(m as u8)?;
+        }
+        if let Some(f) = self.frame_id {
+            writer.write_u8(f as u8)?;
+        }
+        if let Some(c) = self.colormap {
+            writer.write_u8(c)?;
+        }
+        if let Some(s) = self.skin_id {
+            writer.write_u8(s)?;
+        }
+        if let Some(e) = self.effects {
+            writer.write_u8(e.bits())?;
+        }
+        if let Some(x) = self.origin_x {
+            write_coord(writer, x, protocol.flags)?;
+        }
+        if let Some(p) = self.pitch {
+            write_angle(writer, p, protocol.flags)?;
+        }
+        if let Some(y) = self.origin_y {
+            write_coord(writer, y, protocol.flags)?;
+        }
+        if let Some(y) = self.yaw {
+            write_angle(writer, y, protocol.flags)?;
+        }
+        if let Some(z) = self.origin_z {
+            write_coord(writer, z, protocol.flags)?;
+        }
+        if let Some(r) = self.roll {
+            write_angle(writer, r, protocol.flags)?;
+        }
+        if let Some(a) = self.alpha {
+            writer.write_u8(a)?;
+        }
+        if let Some(s) = self.scale {
+            writer.write_u8(s)?;
+        }
+        if flags.contains(UpdateFlags::FRAME_2) {
+            writer.write_u8((self.frame_id.unwrap() >> 8) as u8)?;
+        }
+        if flags.contains(UpdateFlags::MODEL_2) {
+            writer.write_u8((self.model_id.unwrap() >> 8) as u8)?;
+        }
+        if let Some(l) = self.lerp_finish {
+            writer.write_u8(l)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -652,15 +991,23 @@ pub enum ServerCmdCode {
     CdTrack = 32,
     SellScreen = 33,
     Cutscene = 34,
+
+    // FitzQuake extensions
+    Skybox = 37,
+    BonusFlash = 40,
+    Fog = 41,
+    SpawnBaseline2 = 42,
+    SpawnStatic2 = 43,
+    SpawnStaticSound2 = 44,
 }
 
-#[derive(Copy, Clone, Debug, Eq, FromPrimitive, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, FromPrimitive, PartialEq, Serialize)]
 pub enum GameType {
     CoOp = 0,
     Deathmatch = 1,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize)]
 pub enum ServerCmd {
     Bad,
     NoOp,
@@ -680,7 +1027,7 @@ pub enum ServerCmd {
         attenuation: Option<f32>,
         entity_id: u16,
         channel: i8,
-        sound_id: u8,
+        sound_id: u16,
         position: Vector3<f32>,
     },
     Time {
@@ -697,6 +1044,7 @@ pub enum ServerCmd {
     },
     ServerInfo {
         protocol_version: i32,
+        protocol_flags: ProtocolFlags,
         max_clients: u8,
         game_type: GameType,
         message: String,
@@ -735,23 +1083,33 @@ pub enum ServerCmd {
         blood: u8,
         source: Vector3<f32>,
     },
+    /// Spawn a static entity.
+    ///
+    /// If the model or frame ID exceeds 255 or an alpha value is present, this
+    /// is sent as the FitzQuake `SpawnStatic2` command.
     SpawnStatic {
-        model_id: u8,
-        frame_id: u8,
+        model_id: u16,
+        frame_id: u16,
         colormap: u8,
         skin_id: u8,
         origin: Vector3<f32>,
         angles: Vector3<Deg<f32>>,
+        alpha: Option<u8>,
     },
     // SpawnBinary, // unused
+    /// Set the baseline state of an entity.
+    ///
+    /// If the model or frame ID exceeds 255 or an alpha value is present, this
+    /// is sent as the FitzQuake `SpawnBaseline2` command.
     SpawnBaseline {
         ent_id: u16,
-        model_id: u8,
-        frame_id: u8,
+        model_id: u16,
+        frame_id: u16,
         colormap: u8,
         skin_id: u8,
         origin: Vector3<f32>,
         angles: Vector3<Deg<f32>>,
+        alpha: Option<u8>,
     },
     TempEntity {
         temp_entity: TempEntity,
@@ -767,9 +1125,13 @@ pub enum ServerCmd {
     },
     KilledMonster,
     FoundSecret,
+    /// Spawn a static looping sound.
+    ///
+    /// If the sound ID exceeds 255, this is sent as the FitzQuake
+    /// `SpawnStaticSound2` command.
     SpawnStaticSound {
         origin: Vector3<f32>,
-        sound_id: u8,
+        sound_id: u16,
         volume: u8,
         attenuation: u8,
     },
@@ -785,6 +1147,16 @@ pub enum ServerCmd {
     Cutscene {
         text: String,
     },
+    Skybox {
+        name: String,
+    },
+    BonusFlash,
+    Fog {
+        density: u8,
+        color: [u8; 3],
+        /// Fade time in hundredths of a second.
+        time: i16,
+    },
     FastUpdate(EntityUpdate),
 }
 
@@ -811,20 +1183,42 @@ impl ServerCmd {
             ServerCmd::UpdateColors { .. } => ServerCmdCode::UpdateColors,
             ServerCmd::Particle { .. } => ServerCmdCode::Particle,
             ServerCmd::Damage { .. } => ServerCmdCode::Damage,
-            ServerCmd::SpawnStatic { .. } => ServerCmdCode::SpawnStatic,
-            ServerCmd::SpawnBaseline { .. } => ServerCmdCode::SpawnBaseline,
+            ServerCmd::SpawnStatic {
+                model_id,
+                frame_id,
+                alpha,
+                ..
+            } => match baseline_flags(model_id, frame_id, alpha).is_empty() {
+                true => ServerCmdCode::SpawnStatic,
+                false => ServerCmdCode::SpawnStatic2,
+            },
+            ServerCmd::SpawnBaseline {
+                model_id,
+                frame_id,
+                alpha,
+                ..
+            } => match baseline_flags(model_id, frame_id, alpha).is_empty() {
+                true => ServerCmdCode::SpawnBaseline,
+                false => ServerCmdCode::SpawnBaseline2,
+            },
             ServerCmd::TempEntity { .. } => ServerCmdCode::TempEntity,
             ServerCmd::SetPause { .. } => ServerCmdCode::SetPause,
             ServerCmd::SignOnStage { .. } => ServerCmdCode::SignOnStage,
             ServerCmd::CenterPrint { .. } => ServerCmdCode::CenterPrint,
             ServerCmd::KilledMonster => ServerCmdCode::KilledMonster,
             ServerCmd::FoundSecret => ServerCmdCode::FoundSecret,
-            ServerCmd::SpawnStaticSound { .. } => ServerCmdCode::SpawnStaticSound,
+            ServerCmd::SpawnStaticSound { sound_id, .. } => match sound_id > u8::MAX as u16 {
+                false => ServerCmdCode::SpawnStaticSound,
+                true => ServerCmdCode::SpawnStaticSound2,
+            },
             ServerCmd::Intermission => ServerCmdCode::Intermission,
             ServerCmd::Finale { .. } => ServerCmdCode::Finale,
             ServerCmd::CdTrack { .. } => ServerCmdCode::CdTrack,
             ServerCmd::SellScreen => ServerCmdCode::SellScreen,
             ServerCmd::Cutscene { .. } => ServerCmdCode::Cutscene,
+            ServerCmd::Skybox { .. } => ServerCmdCode::Skybox,
+            ServerCmd::BonusFlash => ServerCmdCode::BonusFlash,
+            ServerCmd::Fog { .. } => ServerCmdCode::Fog,
             // TODO: figure out a more elegant way of doing this
             ServerCmd::FastUpdate(_) => panic!("FastUpdate has no code"),
         };
@@ -832,7 +1226,93 @@ impl ServerCmd {
         code as u8
     }
 
-    pub fn deserialize<R>(reader: &mut R) -> Result<Option<ServerCmd>, NetError>
+    /// The names of all server commands, as returned by `ServerCmd::name`.
+    pub const NAMES: [&'static str; 38] = [
+        "Bad",
+        "NoOp",
+        "Disconnect",
+        "UpdateStat",
+        "Version",
+        "SetView",
+        "Sound",
+        "Time",
+        "Print",
+        "StuffText",
+        "SetAngle",
+        "ServerInfo",
+        "LightStyle",
+        "UpdateName",
+        "UpdateFrags",
+        "PlayerData",
+        "StopSound",
+        "UpdateColors",
+        "Particle",
+        "Damage",
+        "SpawnStatic",
+        "SpawnBaseline",
+        "TempEntity",
+        "SetPause",
+        "SignOnStage",
+        "CenterPrint",
+        "KilledMonster",
+        "FoundSecret",
+        "SpawnStaticSound",
+        "Intermission",
+        "Finale",
+        "CdTrack",
+        "SellScreen",
+        "Cutscene",
+        "Skybox",
+        "BonusFlash",
+        "Fog",
+        "FastUpdate",
+    ];
+
+    /// Returns the name of this command's variant, e.g. `"StuffText"`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ServerCmd::Bad => "Bad",
+            ServerCmd::NoOp => "NoOp",
+            ServerCmd::Disconnect => "Disconnect",
+            ServerCmd::UpdateStat { .. } => "UpdateStat",
+            ServerCmd::Version { .. } => "Version",
+            ServerCmd::SetView { .. } => "SetView",
+            ServerCmd::Sound { .. } => "Sound",
+            ServerCmd::Time { .. } => "Time",
+            ServerCmd::Print { .. } => "Print",
+            ServerCmd::StuffText { .. } => "StuffText",
+            ServerCmd::SetAngle { .. } => "SetAngle",
+            ServerCmd::ServerInfo { .. } => "ServerInfo",
+            ServerCmd::LightStyle { .. } => "LightStyle",
+            ServerCmd::UpdateName { .. } => "UpdateName",
+            ServerCmd::UpdateFrags { .. } => "UpdateFrags",
+            ServerCmd::PlayerData(_) => "PlayerData",
+            ServerCmd::StopSound { .. } => "StopSound",
+            ServerCmd::UpdateColors { .. } => "UpdateColors",
+            ServerCmd::Particle { .. } => "Particle",
+            ServerCmd::Damage { .. } => "Damage",
+            ServerCmd::SpawnStatic { .. } => "SpawnStatic",
+            ServerCmd::SpawnBaseline { .. } => "SpawnBaseline",
+            ServerCmd::TempEntity { .. } => "TempEntity",
+            ServerCmd::SetPause { .. } => "SetPause",
+            ServerCmd::SignOnStage { .. } => "SignOnStage",
+            ServerCmd::CenterPrint { .. } => "CenterPrint",
+            ServerCmd::KilledMonster => "KilledMonster",
+            ServerCmd::FoundSecret => "FoundSecret",
+            ServerCmd::SpawnStaticSound { .. } => "SpawnStaticSound",
+            ServerCmd::Intermission => "Intermission",
+            ServerCmd::Finale { .. } => "Finale",
+            ServerCmd::CdTrack { .. } => "CdTrack",
+            ServerCmd::SellScreen => "SellScreen",
+            ServerCmd::Cutscene { .. } => "Cutscene",
+            ServerCmd::Skybox { .. } => "Skybox",
+            ServerCmd::BonusFlash => "BonusFlash",
+            ServerCmd::Fog { .. } => "Fog",
+            ServerCmd::FastUpdate(_) => "FastUpdate",
+        }
+    }
+
+    pub fn deserialize<R>(reader: &mut R, protocol: Protocol) -> Result<Option<ServerCmd>, NetError>
     where
         R: BufRead + ReadBytesExt,
     {
@@ -843,13 +1323,19 @@ impl ServerCmd {
         };
 
         if code_num & FAST_UPDATE_FLAG != 0 {
-            let all_bits;
-            let low_bits = code_num & !FAST_UPDATE_FLAG;
-            if low_bits & UpdateFlags::MORE_BITS.bits() as u8 != 0 {
-                let high_bits = reader.read_u8()?;
-                all_bits = (high_bits as u16) << 8 | low_bits as u16;
-            } else {
-                all_bits = low_bits as u16;
+            let mut all_bits = (code_num & !FAST_UPDATE_FLAG) as u32;
+            if all_bits & UpdateFlags::MORE_BITS.bits() != 0 {
+                all_bits |= (reader.read_u8()? as u32) << 8;
+            }
+
+            if protocol.is_extended() {
+                if all_bits & UpdateFlags::EXTEND_1.bits() != 0 {
+                    all_bits |= (reader.read_u8()? as u32) << 16;
+                }
+
+                if all_bits & UpdateFlags::EXTEND_2.bits() != 0 {
+                    all_bits |= (reader.read_u8()? as u32) << 24;
+                }
             }
 
             let update_flags = match UpdateFlags::from_bits(all_bits) {
@@ -869,16 +1355,16 @@ impl ServerCmd {
                 ent_id = reader.read_u8()? as u16;
             }
 
-            let model_id;
+            let mut model_id;
             if update_flags.contains(UpdateFlags::MODEL) {
-                model_id = Some(reader.read_u8()?);
+                model_id = Some(reader.read_u8()? as u16);
             } else {
                 model_id = None;
             }
 
-            let frame_id;
+            let mut frame_id;
             if update_flags.contains(UpdateFlags::FRAME) {
-                frame_id = Some(reader.read_u8()?);
+                frame_id = Some(reader.read_u8()? as u16);
             } else {
                 frame_id = None;
             }
@@ -915,48 +1401,84 @@ impl ServerCmd {
 
             let origin_x;
             if update_flags.contains(UpdateFlags::ORIGIN_X) {
-                origin_x = Some(read_coord(reader)?);
+                origin_x = Some(read_coord(reader, protocol.flags)?);
             } else {
                 origin_x = None;
             }
 
             let pitch;
             if update_flags.contains(UpdateFlags::PITCH) {
-                pitch = Some(read_angle(reader)?);
+                pitch = Some(read_angle(reader, protocol.flags)?);
             } else {
                 pitch = None;
             }
 
             let origin_y;
             if update_flags.contains(UpdateFlags::ORIGIN_Y) {
-                origin_y = Some(read_coord(reader)?);
+                origin_y = Some(read_coord(reader, protocol.flags)?);
             } else {
                 origin_y = None;
             }
 
             let yaw;
             if update_flags.contains(UpdateFlags::YAW) {
-                yaw = Some(read_angle(reader)?);
+                yaw = Some(read_angle(reader, protocol.flags)?);
             } else {
                 yaw = None;
             }
 
             let origin_z;
             if update_flags.contains(UpdateFlags::ORIGIN_Z) {
-                origin_z = Some(read_coord(reader)?);
+                origin_z = Some(read_coord(reader, protocol.flags)?);
             } else {
                 origin_z = None;
             }
 
             let roll;
             if update_flags.contains(UpdateFlags::ROLL) {
-                roll = Some(read_angle(reader)?);
+                roll = Some(read_angle(reader, protocol.flags)?);
             } else {
                 roll = None;
             }
 
             let no_lerp = update_flags.contains(UpdateFlags::NO_LERP);
 
+            let alpha = match update_flags.contains(UpdateFlags::ALPHA) {
+                true => Some(reader.read_u8()?),
+                false => None,
+            };
+
+            let scale = match update_flags.contains(UpdateFlags::SCALE) {
+                true => Some(reader.read_u8()?),
+                false => None,
+            };
+
+            // the high bytes of the frame and model IDs are sent after the rest of the update
+            if update_flags.contains(UpdateFlags::FRAME_2) {
+                let high = reader.read_u8()? as u16;
+                frame_id = match frame_id {
+                    Some(f) => Some(f | high << 8),
+                    None => {
+                        return Err(NetError::InvalidData(String::from("FRAME_2 without FRAME")))
+                    }
+                };
+            }
+
+            if update_flags.contains(UpdateFlags::MODEL_2) {
+                let high = reader.read_u8()? as u16;
+                model_id = match model_id {
+                    Some(m) => Some(m | high << 8),
+                    None => {
+                        return Err(NetError::InvalidData(String::from("MODEL_2 without MODEL")))
+                    }
+                };
+            }
+
+            let lerp_finish = match update_flags.contains(UpdateFlags::LERP_FINISH) {
+                true => Some(reader.read_u8()?),
+                false => None,
+            };
+
             return Ok(Some(ServerCmd::FastUpdate(EntityUpdate {
                 ent_id,
                 model_id,
@@ -971,6 +1493,9 @@ impl ServerCmd {
                 origin_z,
                 roll,
                 no_lerp,
+                alpha,
+                scale,
+                lerp_finish,
             })));
         }
 
@@ -1037,14 +1562,28 @@ impl ServerCmd {
                     false => None,
                 };
 
-                let entity_channel = reader.read_i16::<LittleEndian>()?;
-                let entity_id = (entity_channel >> 3) as u16;
-                let channel = (entity_channel & 0b111) as i8;
-                let sound_id = reader.read_u8()?;
+                let (entity_id, channel) = match flags.contains(SoundFlags::LARGE_ENTITY) {
+                    true => {
+                        let entity_id = reader.read_u16::<LittleEndian>()?;
+                        let channel = reader.read_i8()?;
+                        (entity_id, channel)
+                    }
+                    false => {
+                        let entity_channel = reader.read_u16::<LittleEndian>()?;
+                        let entity_id = entity_channel >> 3;
+                        let channel = (entity_channel & 0b111) as i8;
+                        (entity_id, channel)
+                    }
+                };
+
+                let sound_id = match flags.contains(SoundFlags::LARGE_SOUND) {
+                    true => reader.read_u16::<LittleEndian>()?,
+                    false => reader.read_u8()? as u16,
+                };
                 let position = Vector3::new(
-                    read_coord(reader)?,
-                    read_coord(reader)?,
-                    read_coord(reader)?,
+                    read_coord(reader, protocol.flags)?,
+                    read_coord(reader, protocol.flags)?,
+                    read_coord(reader, protocol.flags)?,
                 );
 
                 ServerCmd::Sound {
@@ -1082,9 +1621,9 @@ impl ServerCmd {
 
             ServerCmdCode::SetAngle => {
                 let angles = Vector3::new(
-                    read_angle(reader)?,
-                    read_angle(reader)?,
-                    read_angle(reader)?,
+                    read_angle(reader, protocol.flags)?,
+                    read_angle(reader, protocol.flags)?,
+                    read_angle(reader, protocol.flags)?,
                 );
 
                 ServerCmd::SetAngle { angles }
@@ -1092,6 +1631,21 @@ impl ServerCmd {
 
             ServerCmdCode::ServerInfo => {
                 let protocol_version = reader.read_i32::<LittleEndian>()?;
+                let protocol_flags = match protocol_version == ProtocolVersion::Rmq.value() {
+                    true => {
+                        let flags_bits = reader.read_u32::<LittleEndian>()?;
+                        match ProtocolFlags::from_bits(flags_bits) {
+                            Some(f) => f,
+                            None => {
+                                return Err(NetError::InvalidData(format!(
+                                    "ProtocolFlags: {:b}",
+                                    flags_bits
+                                )))
+                            }
+                        }
+                    }
+                    false => ProtocolFlags::empty(),
+                };
                 let max_clients = reader.read_u8()?;
                 let game_type_code = reader.read_u8()?;
                 let game_type = match GameType::from_u8(game_type_code) {
@@ -1104,11 +1658,11 @@ impl ServerCmd {
                     }
                 };
 
-                let message = util::read_cstring(reader).unwrap();
+                let message = util::read_cstring(reader)?;
 
                 let mut model_precache = Vec::new();
                 loop {
-                    let model_name = util::read_cstring(reader).unwrap();
+                    let model_name = util::read_cstring(reader)?;
                     if model_name.is_empty() {
                         break;
                     }
@@ -1117,7 +1671,7 @@ impl ServerCmd {
 
                 let mut sound_precache = Vec::new();
                 loop {
-                    let sound_name = util::read_cstring(reader).unwrap();
+                    let sound_name = util::read_cstring(reader)?;
                     if sound_name.is_empty() {
                         break;
                     }
@@ -1126,6 +1680,7 @@ impl ServerCmd {
 
                 ServerCmd::ServerInfo {
                     protocol_version,
+                    protocol_flags,
                     max_clients,
                     game_type,
                     message,
@@ -1136,13 +1691,13 @@ impl ServerCmd {
 
             ServerCmdCode::LightStyle => {
                 let id = reader.read_u8()?;
-                let value = util::read_cstring(reader).unwrap();
+                let value = util::read_cstring(reader)?;
                 ServerCmd::LightStyle { id, value }
             }
 
             ServerCmdCode::UpdateName => {
                 let player_id = reader.read_u8()?;
-                let new_name = util::read_cstring(reader).unwrap();
+                let new_name = util::read_cstring(reader)?;
                 ServerCmd::UpdateName {
                     player_id,
                     new_name,
@@ -1160,7 +1715,17 @@ impl ServerCmd {
             }
 
             ServerCmdCode::PlayerData => {
-                let flags_bits = reader.read_u16::<LittleEndian>()?;
+                let mut flags_bits = reader.read_u16::<LittleEndian>()? as u32;
+                if protocol.is_extended() {
+                    if flags_bits & ClientUpdateFlags::EXTEND_1.bits() != 0 {
+                        flags_bits |= (reader.read_u8()? as u32) << 16;
+                    }
+
+                    if flags_bits & ClientUpdateFlags::EXTEND_2.bits() != 0 {
+                        flags_bits |= (reader.read_u8()? as u32) << 24;
+                    }
+                }
+
                 let flags = match ClientUpdateFlags::from_bits(flags_bits) {
                     Some(f) => f,
                     None => {
@@ -1225,29 +1790,70 @@ impl ServerCmd {
                 let on_ground = flags.contains(ClientUpdateFlags::ON_GROUND);
                 let in_water = flags.contains(ClientUpdateFlags::IN_WATER);
 
-                let weapon_frame = match flags.contains(ClientUpdateFlags::WEAPON_FRAME) {
-                    true => Some(reader.read_u8()?),
+                let mut weapon_frame = match flags.contains(ClientUpdateFlags::WEAPON_FRAME) {
+                    true => Some(reader.read_u8()? as u16),
                     false => None,
                 };
 
-                let armor = match flags.contains(ClientUpdateFlags::ARMOR) {
-                    true => Some(reader.read_u8()?),
+                let mut armor = match flags.contains(ClientUpdateFlags::ARMOR) {
+                    true => Some(reader.read_u8()? as u16),
                     false => None,
                 };
 
-                let weapon = match flags.contains(ClientUpdateFlags::WEAPON) {
-                    true => Some(reader.read_u8()?),
+                let mut weapon = match flags.contains(ClientUpdateFlags::WEAPON) {
+                    true => Some(reader.read_u8()? as u16),
                     false => None,
                 };
 
                 let health = reader.read_i16::<LittleEndian>()?;
-                let ammo = reader.read_u8()?;
-                let ammo_shells = reader.read_u8()?;
-                let ammo_nails = reader.read_u8()?;
-                let ammo_rockets = reader.read_u8()?;
-                let ammo_cells = reader.read_u8()?;
+                let mut ammo = reader.read_u8()? as u16;
+                let mut ammo_shells = reader.read_u8()? as u16;
+                let mut ammo_nails = reader.read_u8()? as u16;
+                let mut ammo_rockets = reader.read_u8()? as u16;
+                let mut ammo_cells = reader.read_u8()? as u16;
                 let active_weapon = reader.read_u8()?;
 
+                // FitzQuake sends the high bytes of large values separately
+                if flags.contains(ClientUpdateFlags::WEAPON_2) {
+                    let high = (reader.read_u8()? as u16) << 8;
+                    weapon = Some(weapon.unwrap_or(0) | high);
+                }
+
+                if flags.contains(ClientUpdateFlags::ARMOR_2) {
+                    let high = (reader.read_u8()? as u16) << 8;
+                    armor = Some(armor.unwrap_or(0) | high);
+                }
+
+                if flags.contains(ClientUpdateFlags::AMMO_2) {
+                    ammo |= (reader.read_u8()? as u16) << 8;
+                }
+
+                if flags.contains(ClientUpdateFlags::SHELLS_2) {
+                    ammo_shells |= (reader.read_u8()? as u16) << 8;
+                }
+
+                if flags.contains(ClientUpdateFlags::NAILS_2) {
+                    ammo_nails |= (reader.read_u8()? as u16) << 8;
+                }
+
+                if flags.contains(ClientUpdateFlags::ROCKETS_2) {
+                    ammo_rockets |= (reader.read_u8()? as u16) << 8;
+                }
+
+                if flags.contains(ClientUpdateFlags::CELLS_2) {
+                    ammo_cells |= (reader.read_u8()? as u16) << 8;
+                }
+
+                if flags.contains(ClientUpdateFlags::WEAPON_FRAME_2) {
+                    let high = (reader.read_u8()? as u16) << 8;
+                    weapon_frame = Some(weapon_frame.unwrap_or(0) | high);
+                }
+
+                let weapon_alpha = match flags.contains(ClientUpdateFlags::WEAPON_ALPHA) {
+                    true => Some(reader.read_u8()?),
+                    false => None,
+                };
+
                 ServerCmd::PlayerData(PlayerData {
                     view_height,
                     ideal_pitch,
@@ -1270,6 +1876,7 @@ impl ServerCmd {
                     ammo_rockets,
                     ammo_cells,
                     active_weapon,
+                    weapon_alpha,
                 })
             }
 
@@ -1293,7 +1900,7 @@ impl ServerCmd {
             }
 
             ServerCmdCode::Particle => {
-                let origin = read_coord_vector3(reader)?;
+                let origin = read_coord_vector3(reader, protocol.flags)?;
 
                 let mut direction = Vector3::zero();
                 for i in 0..3 {
@@ -1314,7 +1921,7 @@ impl ServerCmd {
             ServerCmdCode::Damage => {
                 let armor = reader.read_u8()?;
                 let blood = reader.read_u8()?;
-                let source = read_coord_vector3(reader)?;
+                let source = read_coord_vector3(reader, protocol.flags)?;
 
                 ServerCmd::Damage {
                     armor,
@@ -1323,56 +1930,46 @@ impl ServerCmd {
                 }
             }
 
-            ServerCmdCode::SpawnStatic => {
-                let model_id = reader.read_u8()?;
-                let frame_id = reader.read_u8()?;
-                let colormap = reader.read_u8()?;
-                let skin_id = reader.read_u8()?;
-
-                let mut origin = Vector3::zero();
-                let mut angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
-                for i in 0..3 {
-                    origin[i] = read_coord(reader)?;
-                    angles[i] = read_angle(reader)?;
-                }
+            ServerCmdCode::SpawnStatic | ServerCmdCode::SpawnStatic2 => {
+                let flags = match code {
+                    ServerCmdCode::SpawnStatic2 => read_baseline_flags(reader)?,
+                    _ => BaselineFlags::empty(),
+                };
+                let baseline = read_baseline(reader, flags, protocol)?;
 
                 ServerCmd::SpawnStatic {
-                    model_id,
-                    frame_id,
-                    colormap,
-                    skin_id,
-                    origin,
-                    angles,
+                    model_id: baseline.model_id,
+                    frame_id: baseline.frame_id,
+                    colormap: baseline.colormap,
+                    skin_id: baseline.skin_id,
+                    origin: baseline.origin,
+                    angles: baseline.angles,
+                    alpha: baseline.alpha,
                 }
             }
 
-            ServerCmdCode::SpawnBaseline => {
+            ServerCmdCode::SpawnBaseline | ServerCmdCode::SpawnBaseline2 => {
                 let ent_id = reader.read_u16::<LittleEndian>()?;
-                let model_id = reader.read_u8()?;
-                let frame_id = reader.read_u8()?;
-                let colormap = reader.read_u8()?;
-                let skin_id = reader.read_u8()?;
-
-                let mut origin = Vector3::zero();
-                let mut angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
-                for i in 0..3 {
-                    origin[i] = read_coord(reader)?;
-                    angles[i] = read_angle(reader)?;
-                }
+                let flags = match code {
+                    ServerCmdCode::SpawnBaseline2 => read_baseline_flags(reader)?,
+                    _ => BaselineFlags::empty(),
+                };
+                let baseline = read_baseline(reader, flags, protocol)?;
 
                 ServerCmd::SpawnBaseline {
                     ent_id,
-                    model_id,
-                    frame_id,
-                    colormap,
-                    skin_id,
-                    origin,
-                    angles,
+                    model_id: baseline.model_id,
+                    frame_id: baseline.frame_id,
+                    colormap: baseline.colormap,
+                    skin_id: baseline.skin_id,
+                    origin: baseline.origin,
+                    angles: baseline.angles,
+                    alpha: baseline.alpha,
                 }
             }
 
             ServerCmdCode::TempEntity => {
-                let temp_entity = TempEntity::read_temp_entity(reader)?;
+                let temp_entity = TempEntity::read_temp_entity(reader, protocol)?;
 
                 ServerCmd::TempEntity { temp_entity }
             }
@@ -1414,9 +2011,12 @@ impl ServerCmd {
             ServerCmdCode::KilledMonster => ServerCmd::KilledMonster,
             ServerCmdCode::FoundSecret => ServerCmd::FoundSecret,
 
-            ServerCmdCode::SpawnStaticSound => {
-                let origin = read_coord_vector3(reader)?;
-                let sound_id = reader.read_u8()?;
+            ServerCmdCode::SpawnStaticSound | ServerCmdCode::SpawnStaticSound2 => {
+                let origin = read_coord_vector3(reader, protocol.flags)?;
+                let sound_id = match code {
+                    ServerCmdCode::SpawnStaticSound2 => reader.read_u16::<LittleEndian>()?,
+                    _ => reader.read_u8()? as u16,
+                };
                 let volume = reader.read_u8()?;
                 let attenuation = reader.read_u8()?;
 
@@ -1455,16 +2055,54 @@ impl ServerCmd {
 
                 ServerCmd::Cutscene { text }
             }
+
+            ServerCmdCode::Skybox => {
+                let name = match util::read_cstring(reader) {
+                    Ok(t) => t,
+                    Err(e) => return Err(NetError::with_msg(format!("{}", e))),
+                };
+
+                ServerCmd::Skybox { name }
+            }
+
+            ServerCmdCode::BonusFlash => ServerCmd::BonusFlash,
+
+            ServerCmdCode::Fog => {
+                let density = reader.read_u8()?;
+                let mut color = [0; 3];
+                reader.read_exact(&mut color)?;
+                let time = reader.read_i16::<LittleEndian>()?;
+
+                ServerCmd::Fog {
+                    density,
+                    color,
+                    time,
+                }
+            }
         };
 
         Ok(Some(cmd))
     }
 
-    pub fn serialize<W>(&self, writer: &mut W) -> Result<(), NetError>
+    pub fn serialize<W>(&self, writer: &mut W, protocol: Protocol) -> Result<(), NetError>
     where
         W: WriteBytesExt,
     {
-        writer.write_u8(self.code())?;
+        // fast updates encode their own command byte
+        if let ServerCmd::FastUpdate(ref update) = *self {
+            return update.serialize(writer, protocol);
+        }
+
+        let code = self.code();
+        if !protocol.is_extended() && code >= ServerCmdCode::Skybox as u8 {
+            return Err(NetError::with_msg(format!(
+                "Server command {:?} is not supported by protocol {}",
+                ServerCmdCode::from_u8(code).unwrap(),
+                protocol.version.value(),
+            )));
+        }
+
+        writer.write_u8(code)?;
 
         match *self {
             ServerCmd::Bad | ServerCmd::NoOp | ServerCmd::Disconnect => (),
@@ -1500,6 +2138,26 @@ impl ServerCmd {
                     sound_flags |= SoundFlags::ATTENUATION;
                 }
 
+                let large_entity = entity_id >= 1 << 13 || !(0..8).contains(&channel);
+                if large_entity {
+                    sound_flags |= SoundFlags::LARGE_ENTITY;
+                }
+
+                let large_sound = sound_id > u8::MAX as u16;
+                if large_sound {
+                    sound_flags |= SoundFlags::LARGE_SOUND;
+                }
+
+                if !protocol.is_extended() && (large_entity || large_sound) {
+                    return Err(NetError::with_msg(format!(
+                        "Sound (entity {}, channel {}, sound {}) exceeds the limits of protocol {}",
+                        entity_id,
+                        channel,
+                        sound_id,
+                        protocol.version.value(),
+                    )));
+                }
+
                 writer.write_u8(sound_flags.bits())?;
 
                 if let Some(v) = volume {
@@ -1507,17 +2165,26 @@ impl ServerCmd {
                 }
 
                 if let Some(a) = attenuation {
-                    writer.write_u8(a as u8 * SOUND_ATTENUATION_WRITE_FACTOR)?;
+                    writer.write_u8((a * SOUND_ATTENUATION_WRITE_FACTOR as f32) as u8)?;
                 }
 
-                // TODO: document this better. The entity and channel fields are combined in Sound commands.
-                let ent_channel = (entity_id as i16) << 3 | channel as i16 & 0b111;
-                writer.write_i16::<LittleEndian>(ent_channel)?;
+                if large_entity {
+                    writer.write_u16::<LittleEndian>(entity_id)?;
+                    writer.write_i8(channel)?;
+                } else {
+                    // TODO: document this better. The entity and channel fields are combined in Sound commands.
+                    let ent_channel = (entity_id as i16) << 3 | channel as i16 & 0b111;
+                    writer.write_i16::<LittleEndian>(ent_channel)?;
+                }
 
-                writer.write_u8(sound_id)?;
+                if large_sound {
+                    writer.write_u16::<LittleEndian>(sound_id)?;
+                } else {
+                    writer.write_u8(sound_id as u8)?;
+                }
 
                 for component in 0..3 {
-                    write_coord(writer, position[component])?;
+                    write_coord(writer, position[component], protocol.flags)?;
                 }
             }
 
@@ -1533,10 +2200,11 @@ impl ServerCmd {
                 writer.write_u8(0)?;
             }
 
-            ServerCmd::SetAngle { angles } => write_angle_vector3(writer, angles)?,
+            ServerCmd::SetAngle { angles } => write_angle_vector3(writer, angles, protocol.flags)?,
 
             ServerCmd::ServerInfo {
                 protocol_version,
+                protocol_flags,
                 max_clients,
                 game_type,
                 ref message,
@@ -1544,6 +2212,9 @@ impl ServerCmd {
                 ref sound_precache,
             } => {
                 writer.write_i32::<LittleEndian>(protocol_version)?;
+                if protocol_version == ProtocolVersion::Rmq.value() {
+                    writer.write_u32::<LittleEndian>(protocol_flags.bits())?;
+                }
                 writer.write_u8(max_clients)?;
                 writer.write_u8(game_type as u8)?;
 
@@ -1608,6 +2279,7 @@ impl ServerCmd {
                 ammo_rockets,
                 ammo_cells,
                 active_weapon,
+                weapon_alpha,
             }) => {
                 let mut flags = ClientUpdateFlags::empty();
                 if view_height.is_some() {
@@ -1654,8 +2326,47 @@ impl ServerCmd {
                     flags |= ClientUpdateFlags::WEAPON;
                 }
 
+                // FitzQuake extensions
+                let high_byte_flags = [
+                    (weapon.unwrap_or(0), ClientUpdateFlags::WEAPON_2),
+                    (armor.unwrap_or(0), ClientUpdateFlags::ARMOR_2),
+                    (ammo, ClientUpdateFlags::AMMO_2),
+                    (ammo_shells, ClientUpdateFlags::SHELLS_2),
+                    (ammo_nails, ClientUpdateFlags::NAILS_2),
+                    (ammo_rockets, ClientUpdateFlags::ROCKETS_2),
+                    (ammo_cells, ClientUpdateFlags::CELLS_2),
+                    (weapon_frame.unwrap_or(0), ClientUpdateFlags::WEAPON_FRAME_2),
+                ];
+                for (value, flag) in high_byte_flags {
+                    if value > u8::MAX as u16 {
+                        flags |= flag;
+                    }
+                }
+                if weapon_alpha.is_some() {
+                    flags |= ClientUpdateFlags::WEAPON_ALPHA;
+                }
+                if flags.bits() >= 1 << 16 {
+                    flags |= ClientUpdateFlags::EXTEND_1;
+                }
+                if flags.bits() >= 1 << 24 {
+                    flags |= ClientUpdateFlags::EXTEND_2;
+                }
+
+                if !protocol.is_extended() && flags.bits() > u16::MAX as u32 {
+                    return Err(NetError::with_msg(format!(
+                        "Player data exceeds the limits of protocol {}",
+                        protocol.version.value(),
+                    )));
+                }
+
                 // write flags
-                writer.write_u16::<LittleEndian>(flags.bits())?;
+                writer.write_u16::<LittleEndian>(flags.bits() as u16)?;
+                if flags.contains(ClientUpdateFlags::EXTEND_1) {
+                    writer.write_u8((flags.bits() >> 16) as u8)?;
+                }
+                if flags.contains(ClientUpdateFlags::EXTEND_2) {
+                    writer.write_u8((flags.bits() >> 24) as u8)?;
+                }
 
                 if let Some(vh) = view_height {
                     writer.write_u8(vh as i32 as u8)?;
@@ -1683,21 +2394,30 @@ impl ServerCmd {
                 }
                 writer.write_u32::<LittleEndian>(items.bits())?;
                 if let Some(wf) = weapon_frame {
-                    writer.write_u8(wf)?;
+                    writer.write_u8(wf as u8)?;
                 }
                 if let Some(a) = armor {
-                    writer.write_u8(a)?;
+                    writer.write_u8(a as u8)?;
                 }
                 if let Some(w) = weapon {
-                    writer.write_u8(w)?;
+                    writer.write_u8(w as u8)?;
                 }
                 writer.write_i16::<LittleEndian>(health)?;
-                writer.write_u8(ammo)?;
-                writer.write_u8(ammo_shells)?;
-                writer.write_u8(ammo_nails)?;
-                writer.write_u8(ammo_rockets)?;
-                writer.write_u8(ammo_cells)?;
+                writer.write_u8(ammo as u8)?;
+                writer.write_u8(ammo_shells as u8)?;
+                writer.write_u8(ammo_nails as u8)?;
+                writer.write_u8(ammo_rockets as u8)?;
+                writer.write_u8(ammo_cells as u8)?;
                 writer.write_u8(active_weapon)?;
+
+                for (value, flag) in high_byte_flags {
+                    if flags.contains(flag) {
+                        writer.write_u8((value >> 8) as u8)?;
+                    }
+                }
+                if let Some(wa) = weapon_alpha {
+                    writer.write_u8(wa)?;
+                }
             }
 
             ServerCmd::StopSound { entity_id, channel } => {
@@ -1719,7 +2439,7 @@ impl ServerCmd {
                 count,
                 color,
             } => {
-                write_coord_vector3(writer, origin)?;
+                write_coord_vector3(writer, origin, protocol.flags)?;
 
                 for i in 0..3 {
                     writer.write_i8(match direction[i] * PARTICLE_DIRECTION_WRITE_FACTOR {
@@ -1740,7 +2460,7 @@ impl ServerCmd {
             } => {
                 writer.write_u8(armor)?;
                 writer.write_u8(blood)?;
-                write_coord_vector3(writer, source)?;
+                write_coord_vector3(writer, source, protocol.flags)?;
             }
 
             ServerCmd::SpawnStatic {
@@ -1750,16 +2470,27 @@ impl ServerCmd {
                 skin_id,
                 origin,
                 angles,
+                alpha,
             } => {
-                writer.write_u8(model_id)?;
-                writer.write_u8(frame_id)?;
-                writer.write_u8(colormap)?;
-                writer.write_u8(skin_id)?;
-
-                for i in 0..3 {
-                    write_coord(writer, origin[i])?;
-                    write_angle(writer, angles[i])?;
+                let flags = baseline_flags(model_id, frame_id, alpha);
+                if !flags.is_empty() {
+                    writer.write_u8(flags.bits())?;
                 }
+
+                write_baseline(
+                    writer,
+                    flags,
+                    protocol,
+                    &Baseline {
+                        model_id,
+                        frame_id,
+                        colormap,
+                        skin_id,
+                        origin,
+                        angles,
+                        alpha,
+                    },
+                )?;
             }
 
             ServerCmd::SpawnBaseline {
@@ -1770,21 +2501,33 @@ impl ServerCmd {
                 skin_id,
                 origin,
                 angles,
+                alpha,
             } => {
                 writer.write_u16::<LittleEndian>(ent_id)?;
-                writer.write_u8(model_id)?;
-                writer.write_u8(frame_id)?;
-                writer.write_u8(colormap)?;
-                writer.write_u8(skin_id)?;
 
-                for i in 0..3 {
-                    write_coord(writer, origin[i])?;
-                    write_angle(writer, angles[i])?;
+                let flags = baseline_flags(model_id, frame_id, alpha);
+                if !flags.is_empty() {
+                    writer.write_u8(flags.bits())?;
                 }
+
+                write_baseline(
+                    writer,
+                    flags,
+                    protocol,
+                    &Baseline {
+                        model_id,
+                        frame_id,
+                        colormap,
+                        skin_id,
+                        origin,
+                        angles,
+                        alpha,
+                    },
+                )?;
             }
 
             ServerCmd::TempEntity { ref temp_entity } => {
-                temp_entity.write_temp_entity(writer)?;
+                temp_entity.write_temp_entity(writer, protocol)?;
             }
 
             ServerCmd::SetPause { paused } => {
@@ -1811,8 +2554,12 @@ impl ServerCmd {
                 volume,
                 attenuation,
             } => {
-                write_coord_vector3(writer, origin)?;
-                writer.write_u8(sound_id)?;
+                write_coord_vector3(writer, origin, protocol.flags)?;
+                if sound_id > u8::MAX as u16 {
+                    writer.write_u16::<LittleEndian>(sound_id)?;
+                } else {
+                    writer.write_u8(sound_id as u8)?;
+                }
                 writer.write_u8(volume)?;
                 writer.write_u8(attenuation)?;
             }
@@ -1836,8 +2583,25 @@ impl ServerCmd {
                 writer.write_u8(0)?;
             }
 
-            // TODO
-            ServerCmd::FastUpdate(_) => unimplemented!(),
+            ServerCmd::Skybox { ref name } => {
+                writer.write_all(name.as_bytes())?;
+                writer.write_u8(0)?;
+            }
+
+            ServerCmd::BonusFlash => (),
+
+            ServerCmd::Fog {
+                density,
+                color,
+                time,
+            } => {
+                writer.write_u8(density)?;
+                writer.write_all(&color)?;
+                writer.write_i16::<LittleEndian>(time)?;
+            }
+
+            // handled above
+            ServerCmd::FastUpdate(_) => unreachable!(),
         }
 
         Ok(())
@@ -1883,7 +2647,7 @@ impl ClientCmd {
         }
     }
 
-    pub fn deserialize<R>(reader: &mut R) -> Result<ClientCmd, NetError>
+    pub fn deserialize<R>(reader: &mut R, protocol: Protocol) -> Result<ClientCmd, NetError>
     where
         R: ReadBytesExt + BufRead,
     {
@@ -1904,11 +2668,14 @@ impl ClientCmd {
             ClientCmdCode::Disconnect => ClientCmd::Disconnect,
             ClientCmdCode::Move => {
                 let send_time = engine::duration_from_f32(reader.read_f32::<LittleEndian>()?);
-                let angles = Vector3::new(
-                    read_angle(reader)?,
-                    read_angle(reader)?,
-                    read_angle(reader)?,
-                );
+                let mut angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
+                for i in 0..3 {
+                    // FitzQuake sends view angles with 16 bits of precision
+                    angles[i] = match protocol.is_extended() {
+                        true => read_angle16(reader, protocol.flags)?,
+                        false => read_angle(reader, protocol.flags)?,
+                    };
+                }
                 let fwd_move = reader.read_i16::<LittleEndian>()?;
                 let side_move = reader.read_i16::<LittleEndian>()?;
                 let up_move = reader.read_i16::<LittleEndian>()?;
@@ -1934,7 +2701,7 @@ impl ClientCmd {
                 }
             }
             ClientCmdCode::StringCmd => {
-                let cmd = util::read_cstring(reader).unwrap();
+                let cmd = util::read_cstring(reader)?;
                 ClientCmd::StringCmd { cmd }
             }
         };
@@ -1942,7 +2709,7 @@ impl ClientCmd {
         Ok(cmd)
     }
 
-    pub fn serialize<W>(&self, writer: &mut W) -> Result<(), NetError>
+    pub fn serialize<W>(&self, writer: &mut W, protocol: Protocol) -> Result<(), NetError>
     where
         W: WriteBytesExt,
     {
@@ -1962,7 +2729,12 @@ impl ClientCmd {
                 impulse,
             } => {
                 writer.write_f32::<LittleEndian>(engine::duration_to_f32(send_time))?;
-                write_angle_vector3(writer, angles)?;
+                for angle in &angles[..] {
+                    match protocol.is_extended() {
+                        true => write_angle16(writer, *angle, protocol.flags)?,
+                        false => write_angle(writer, *angle, protocol.flags)?,
+                    }
+                }
                 writer.write_i16::<LittleEndian>(fwd_move)?;
                 writer.write_i16::<LittleEndian>(side_move)?;
                 writer.write_i16::<LittleEndian>(up_move)?;
@@ -1979,7 +2751,7 @@ impl ClientCmd {
     }
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BlockingMode {
     Blocking,
     NonBlocking,
@@ -1987,7 +2759,7 @@ pub enum BlockingMode {
 }
 
 pub struct QSocket {
-    socket: UdpSocket,
+    socket: ImpairedSocket,
     remote: SocketAddr,
 
     unreliable_send_sequence: u32,
@@ -2002,14 +2774,30 @@ pub struct QSocket {
     send_count: usize,
     resend_count: usize,
 
+    /// When the last reliable packet was sent.
+    send_time: Instant,
+
+    /// Whether the last reliable packet has been sent more than once.
+    resent: bool,
+
+    /// Round-trip time measured from the last reliable packet acknowledged
+    /// without a resend.
+    rtt: Option<Duration>,
+
     recv_sequence: u32,
     recv_buf: [u8; MAX_MESSAGE],
+
+    /// The chunks of a reliable message received so far.
+    recv_reliable: Vec<u8>,
 }
 
 impl QSocket {
-    pub fn new(socket: UdpSocket, remote: SocketAddr) -> QSocket {
+    pub fn new<S>(socket: S, remote: SocketAddr) -> QSocket
+    where
+        S: Into<ImpairedSocket>,
+    {
         QSocket {
-            socket,
+            socket: socket.into(),
             remote,
 
             unreliable_send_sequence: 0,
@@ -2023,16 +2811,42 @@ impl QSocket {
             send_count: 0,
             send_next: false,
             resend_count: 0,
+            send_time: Instant::now(),
+            resent: false,
+            rtt: None,
 
             recv_sequence: 0,
             recv_buf: [0; MAX_MESSAGE],
+            recv_reliable: Vec::new(),
         }
     }
 
+    /// Simulates the given network conditions on this socket.
+    pub fn set_impairment(&mut self, impairment: Impairment) {
+        self.socket.set_impairment(impairment);
+    }
+
+    /// Returns whether the last reliable packet has gone unacknowledged for
+    /// long enough that it should be resent.
+    fn resend_due(&self) -> bool {
+        !self.send_cache.is_empty()
+            && !self.send_next
+            && Duration::from_std(self.send_time.elapsed()).unwrap_or_else(|_| Duration::zero())
+                > Duration::milliseconds(RESEND_TIMEOUT_MS)
+    }
+
     pub fn can_send(&self) -> bool {
         self.send_queue.is_empty() && self.send_cache.is_empty()
     }
 
+    /// Returns the round-trip time to the remote, if it has been measured.
+    ///
+    /// This is measured from the acknowledgement of reliable packets, so it is
+    /// only updated when reliable messages are sent.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
     /// Begin sending a reliable message over this socket.
     pub fn begin_send_msg(&mut self, msg: &[u8]) -> Result<(), NetError> {
         // make sure all reliable messages have been ACKed in their entirety
@@ -2074,6 +2888,8 @@ impl QSocket {
             Err(NetError::with_msg("Attempted resend with empty send cache"))
         } else {
             self.socket.send_to(&self.send_cache, self.remote)?;
+            self.send_time = Instant::now();
+            self.resent = true;
             self.resend_count += 1;
 
             Ok(())
@@ -2109,8 +2925,9 @@ impl QSocket {
 
         // send the composed packet
         self.socket.send_to(&self.send_cache, self.remote)?;
+        self.send_time = Instant::now();
+        self.resent = false;
 
-        // TODO: update send time
         // bump send count
         self.send_count += 1;
 
@@ -2153,35 +2970,31 @@ impl QSocket {
     }
 
     /// Receive a message on this socket.
+    ///
+    /// Returns an empty message if nothing complete arrived. The chunks of a
+    /// partially received reliable message are kept for the next call.
     // TODO: the flow control in this function is completely baffling, make it a little less awful
     pub fn recv_msg(&mut self, block: BlockingMode) -> Result<Vec<u8>, NetError> {
         let mut msg = Vec::new();
 
-        match block {
-            BlockingMode::Blocking => {
-                self.socket.set_nonblocking(false)?;
-                self.socket.set_read_timeout(None)?;
-            }
-
-            BlockingMode::NonBlocking => {
-                self.socket.set_nonblocking(true)?;
-                self.socket.set_read_timeout(None)?;
-            }
-
-            BlockingMode::Timeout(d) => {
-                self.socket.set_nonblocking(false)?;
-                self.socket.set_read_timeout(Some(d.to_std().unwrap()))?;
-            }
+        // like the original engine, resend lost reliable packets as part of
+        // receiving
+        if self.resend_due() {
+            self.resend_msg()?;
         }
 
+        self.socket.set_blocking_mode(block)?;
+
         loop {
             let (packet_len, src_addr) = match self.socket.recv_from(&mut self.recv_buf) {
                 Ok(x) => x,
                 Err(e) => {
                     use std::io::ErrorKind;
                     match e.kind() {
-                        // these errors are expected in nonblocking mode
-                        ErrorKind::WouldBlock | ErrorKind::TimedOut => return Ok(Vec::new()),
+                        // these errors are expected in nonblocking mode. break
+                        // out rather than returning so that an acknowledged
+                        // message still gets its next chunk sent
+                        ErrorKind::WouldBlock | ErrorKind::TimedOut => break,
                         _ => return Err(NetError::from(e)),
                     }
                 }
@@ -2237,16 +3050,16 @@ impl QSocket {
                 MsgKind::Unreliable => {
                     // we've received a newer datagram, ignore
                     if sequence < self.unreliable_recv_sequence {
-                        println!("Stale datagram with sequence # {}", sequence);
-                        break;
+                        debug!("Stale datagram with sequence # {}", sequence);
+                        continue;
                     }
 
                     // we've skipped some datagrams, count them as dropped
                     if sequence > self.unreliable_recv_sequence {
                         let drop_count = sequence - self.unreliable_recv_sequence;
-                        println!(
+                        debug!(
                             "Dropped {} packet(s) ({} -> {})",
-                            drop_count, sequence, self.unreliable_recv_sequence
+                            drop_count, self.unreliable_recv_sequence, sequence
                         );
                     }
 
@@ -2254,20 +3067,28 @@ impl QSocket {
 
                     // copy the rest of the packet into the message buffer and return
                     reader.read_to_end(&mut msg)?;
+                    if self.send_next {
+                        self.send_msg_next()?;
+                    }
                     return Ok(msg);
                 }
 
                 MsgKind::Ack => {
-                    if sequence != self.send_sequence - 1 {
-                        println!("Stale ACK received");
+                    if sequence != self.send_sequence.wrapping_sub(1) {
+                        debug!("Stale ACK received");
                     } else if sequence != self.ack_sequence {
-                        println!("Duplicate ACK received");
+                        debug!("Duplicate ACK received");
                     } else {
                         self.ack_sequence += 1;
                         if self.ack_sequence != self.send_sequence {
                             return Err(NetError::with_msg("ACK sequencing error"));
                         }
 
+                        // an ACK for a resent packet may answer either copy
+                        if !self.resent {
+                            self.rtt = Duration::from_std(self.send_time.elapsed()).ok();
+                        }
+
                         // our last reliable message has been acked
                         if self.send_queue.is_empty() {
                             // the whole message is through, clear the send cache
@@ -2279,8 +3100,8 @@ impl QSocket {
                     }
                 }
 
-                // TODO: once we start reading a reliable message, don't allow other packets until
-                // we have the whole thing
+                // unreliable messages can arrive between the chunks of a
+                // reliable message, so the chunks are collected separately
                 MsgKind::Reliable | MsgKind::ReliableEom => {
                     // send ack message and increment self.recv_sequence
                     let mut ack_buf: [u8; HEADER_SIZE] = [0; HEADER_SIZE];
@@ -2292,15 +3113,16 @@ impl QSocket {
 
                     // if this was a duplicate, drop it
                     if sequence != self.recv_sequence {
-                        println!("Duplicate message received");
+                        debug!("Duplicate message received");
                         continue;
                     }
 
                     self.recv_sequence += 1;
-                    reader.read_to_end(&mut msg)?;
+                    reader.read_to_end(&mut self.recv_reliable)?;
 
                     // if this is the last chunk of a reliable message, break out and return
                     if msg_kind == MsgKind::ReliableEom {
+                        msg = std::mem::take(&mut self.recv_reliable);
                         break;
                     }
                 }
@@ -2315,86 +3137,361 @@ impl QSocket {
     }
 }
 
-fn read_coord<R>(reader: &mut R) -> Result<f32, NetError>
+/// A connection to a remote peer, either over the network or in-process.
+pub enum NetSocket {
+    // QSocket carries its receive buffer inline
+    Udp(Box<QSocket>),
+    Loopback(LoopbackSocket),
+}
+
+impl NetSocket {
+    /// Simulates the given network conditions on this socket.
+    ///
+    /// This has no effect on loopback sockets.
+    pub fn set_impairment(&mut self, impairment: Impairment) {
+        if let NetSocket::Udp(s) = self {
+            s.set_impairment(impairment);
+        }
+    }
+
+    pub fn can_send(&self) -> bool {
+        match self {
+            NetSocket::Udp(s) => s.can_send(),
+            NetSocket::Loopback(s) => s.can_send(),
+        }
+    }
+
+    /// Returns the round-trip time to the remote, if it has been measured.
+    ///
+    /// Loopback sockets always report a round-trip time of zero.
+    pub fn rtt(&self) -> Option<Duration> {
+        match self {
+            NetSocket::Udp(s) => s.rtt(),
+            NetSocket::Loopback(_) => Some(Duration::zero()),
+        }
+    }
+
+    pub fn begin_send_msg(&mut self, msg: &[u8]) -> Result<(), NetError> {
+        match self {
+            NetSocket::Udp(s) => s.begin_send_msg(msg),
+            NetSocket::Loopback(s) => s.begin_send_msg(msg),
+        }
+    }
+
+    pub fn resend_msg(&mut self) -> Result<(), NetError> {
+        match self {
+            NetSocket::Udp(s) => s.resend_msg(),
+            NetSocket::Loopback(s) => s.resend_msg(),
+        }
+    }
+
+    pub fn send_msg_unreliable(&mut self, content: &[u8]) -> Result<(), NetError> {
+        match self {
+            NetSocket::Udp(s) => s.send_msg_unreliable(content),
+            NetSocket::Loopback(s) => s.send_msg_unreliable(content),
+        }
+    }
+
+    pub fn recv_msg(&mut self, block: BlockingMode) -> Result<Vec<u8>, NetError> {
+        match self {
+            NetSocket::Udp(s) => s.recv_msg(block),
+            NetSocket::Loopback(s) => s.recv_msg(block),
+        }
+    }
+}
+
+impl From<QSocket> for NetSocket {
+    fn from(s: QSocket) -> NetSocket {
+        NetSocket::Udp(Box::new(s))
+    }
+}
+
+impl From<LoopbackSocket> for NetSocket {
+    fn from(s: LoopbackSocket) -> NetSocket {
+        NetSocket::Loopback(s)
+    }
+}
+
+// entity state shared by the SpawnStatic and SpawnBaseline commands
+struct Baseline {
+    model_id: u16,
+    frame_id: u16,
+    colormap: u8,
+    skin_id: u8,
+    origin: Vector3<f32>,
+    angles: Vector3<Deg<f32>>,
+    alpha: Option<u8>,
+}
+
+/// Returns the FitzQuake baseline flags needed to encode the given values.
+///
+/// If the result is empty, the baseline can be sent with the original command.
+fn baseline_flags(model_id: u16, frame_id: u16, alpha: Option<u8>) -> BaselineFlags {
+    let mut flags = BaselineFlags::empty();
+
+    if model_id > u8::MAX as u16 {
+        flags |= BaselineFlags::LARGE_MODEL;
+    }
+
+    if frame_id > u8::MAX as u16 {
+        flags |= BaselineFlags::LARGE_FRAME;
+    }
+
+    if alpha.is_some() {
+        flags |= BaselineFlags::ALPHA;
+    }
+
+    flags
+}
+
+fn read_baseline_flags<R>(reader: &mut R) -> Result<BaselineFlags, NetError>
 where
     R: BufRead + ReadBytesExt,
 {
-    Ok(reader.read_i16::<LittleEndian>()? as f32 / 8.0)
+    let flags_bits = reader.read_u8()?;
+    match BaselineFlags::from_bits(flags_bits) {
+        Some(f) => Ok(f),
+        None => Err(NetError::InvalidData(format!(
+            "BaselineFlags: {:b}",
+            flags_bits
+        ))),
+    }
 }
 
-fn read_coord_vector3<R>(reader: &mut R) -> Result<Vector3<f32>, NetError>
+fn read_baseline<R>(
+    reader: &mut R,
+    flags: BaselineFlags,
+    protocol: Protocol,
+) -> Result<Baseline, NetError>
+where
+    R: BufRead + ReadBytesExt,
+{
+    let model_id = match flags.contains(BaselineFlags::LARGE_MODEL) {
+        true => reader.read_u16::<LittleEndian>()?,
+        false => reader.read_u8()? as u16,
+    };
+    let frame_id = match flags.contains(BaselineFlags::LARGE_FRAME) {
+        true => reader.read_u16::<LittleEndian>()?,
+        false => reader.read_u8()? as u16,
+    };
+    let colormap = reader.read_u8()?;
+    let skin_id = reader.read_u8()?;
+
+    let mut origin = Vector3::zero();
+    let mut angles = Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0));
+    for i in 0..3 {
+        origin[i] = read_coord(reader, protocol.flags)?;
+        angles[i] = read_angle(reader, protocol.flags)?;
+    }
+
+    let alpha = match flags.contains(BaselineFlags::ALPHA) {
+        true => Some(reader.read_u8()?),
+        false => None,
+    };
+
+    Ok(Baseline {
+        model_id,
+        frame_id,
+        colormap,
+        skin_id,
+        origin,
+        angles,
+        alpha,
+    })
+}
+
+fn write_baseline<W>(
+    writer: &mut W,
+    flags: BaselineFlags,
+    protocol: Protocol,
+    baseline: &Baseline,
+) -> Result<(), NetError>
+where
+    W: WriteBytesExt,
+{
+    if flags.contains(BaselineFlags::LARGE_MODEL) {
+        writer.write_u16::<LittleEndian>(baseline.model_id)?;
+    } else {
+        writer.write_u8(baseline.model_id as u8)?;
+    }
+
+    if flags.contains(BaselineFlags::LARGE_FRAME) {
+        writer.write_u16::<LittleEndian>(baseline.frame_id)?;
+    } else {
+        writer.write_u8(baseline.frame_id as u8)?;
+    }
+
+    writer.write_u8(baseline.colormap)?;
+    writer.write_u8(baseline.skin_id)?;
+
+    for i in 0..3 {
+        write_coord(writer, baseline.origin[i], protocol.flags)?;
+        write_angle(writer, baseline.angles[i], protocol.flags)?;
+    }
+
+    if let Some(a) = baseline.alpha {
+        writer.write_u8(a)?;
+    }
+
+    Ok(())
+}
+
+fn read_coord<R>(reader: &mut R, flags: ProtocolFlags) -> Result<f32, NetError>
+where
+    R: BufRead + ReadBytesExt,
+{
+    Ok(if flags.contains(ProtocolFlags::FLOAT_COORD) {
+        reader.read_f32::<LittleEndian>()?
+    } else if flags.contains(ProtocolFlags::INT32_COORD) {
+        reader.read_i32::<LittleEndian>()? as f32 / 16.0
+    } else if flags.contains(ProtocolFlags::COORD_24_BIT) {
+        let whole = reader.read_i16::<LittleEndian>()? as f32;
+        let fraction = reader.read_u8()? as f32 / 255.0;
+        whole + fraction
+    } else {
+        reader.read_i16::<LittleEndian>()? as f32 / 8.0
+    })
+}
+
+fn read_coord_vector3<R>(reader: &mut R, flags: ProtocolFlags) -> Result<Vector3<f32>, NetError>
 where
     R: BufRead + ReadBytesExt,
 {
     Ok(Vector3::new(
-        read_coord(reader)?,
-        read_coord(reader)?,
-        read_coord(reader)?,
+        read_coord(reader, flags)?,
+        read_coord(reader, flags)?,
+        read_coord(reader, flags)?,
     ))
 }
 
-fn write_coord<W>(writer: &mut W, coord: f32) -> Result<(), NetError>
+/// Writes a coordinate in the encoding selected by `flags`.
+pub fn write_coord<W>(writer: &mut W, coord: f32, flags: ProtocolFlags) -> Result<(), NetError>
 where
     W: WriteBytesExt,
 {
-    writer.write_i16::<LittleEndian>((coord * 8.0) as i16)?;
+    if flags.contains(ProtocolFlags::FLOAT_COORD) {
+        writer.write_f32::<LittleEndian>(coord)?;
+    } else if flags.contains(ProtocolFlags::INT32_COORD) {
+        writer.write_i32::<LittleEndian>((coord * 16.0).round() as i32)?;
+    } else if flags.contains(ProtocolFlags::COORD_24_BIT) {
+        // the fractional byte is always added, so the integer part rounds down
+        let whole = coord.floor();
+        let fraction = ((coord - whole) * 255.0).round();
+        writer.write_i16::<LittleEndian>(whole as i16)?;
+        writer.write_u8(fraction as u8)?;
+    } else {
+        writer.write_i16::<LittleEndian>((coord * 8.0) as i16)?;
+    }
+
     Ok(())
 }
 
-fn write_coord_vector3<W>(writer: &mut W, coords: Vector3<f32>) -> Result<(), NetError>
+fn write_coord_vector3<W>(
+    writer: &mut W,
+    coords: Vector3<f32>,
+    flags: ProtocolFlags,
+) -> Result<(), NetError>
 where
     W: WriteBytesExt,
 {
     for coord in &coords[..] {
-        write_coord(writer, *coord)?;
+        write_coord(writer, *coord, flags)?;
     }
 
     Ok(())
 }
 
-fn read_angle<R>(reader: &mut R) -> Result<Deg<f32>, NetError>
+fn read_angle<R>(reader: &mut R, flags: ProtocolFlags) -> Result<Deg<f32>, NetError>
 where
     R: BufRead + ReadBytesExt,
 {
-    Ok(Deg(reader.read_i8()? as f32 * (360.0 / 256.0)))
+    if flags.contains(ProtocolFlags::FLOAT_ANGLE) {
+        Ok(Deg(reader.read_f32::<LittleEndian>()?))
+    } else if flags.contains(ProtocolFlags::SHORT_ANGLE) {
+        read_angle16(reader, flags)
+    } else {
+        Ok(Deg(reader.read_i8()? as f32 * (360.0 / 256.0)))
+    }
 }
 
 #[allow(dead_code)]
-fn read_angle_vector3<R>(reader: &mut R) -> Result<Vector3<Deg<f32>>, NetError>
+fn read_angle_vector3<R>(
+    reader: &mut R,
+    flags: ProtocolFlags,
+) -> Result<Vector3<Deg<f32>>, NetError>
 where
     R: BufRead + ReadBytesExt,
 {
     Ok(Vector3::new(
-        read_angle(reader)?,
-        read_angle(reader)?,
-        read_angle(reader)?,
+        read_angle(reader, flags)?,
+        read_angle(reader, flags)?,
+        read_angle(reader, flags)?,
     ))
 }
 
-fn write_angle<W>(writer: &mut W, angle: Deg<f32>) -> Result<(), NetError>
+/// Writes an angle in the encoding selected by `flags`.
+pub fn write_angle<W>(writer: &mut W, angle: Deg<f32>, flags: ProtocolFlags) -> Result<(), NetError>
 where
     W: WriteBytesExt,
 {
-    writer.write_u8(((angle.0 as i32 * 256 / 360) & 0xFF) as u8)?;
+    if flags.contains(ProtocolFlags::FLOAT_ANGLE) {
+        writer.write_f32::<LittleEndian>(angle.0)?;
+    } else if flags.contains(ProtocolFlags::SHORT_ANGLE) {
+        write_angle16(writer, angle, flags)?;
+    } else {
+        writer.write_u8(((angle.0 * 256.0 / 360.0).round() as i32 & 0xFF) as u8)?;
+    }
+
     Ok(())
 }
 
-fn write_angle_vector3<W>(writer: &mut W, angles: Vector3<Deg<f32>>) -> Result<(), NetError>
+fn write_angle_vector3<W>(
+    writer: &mut W,
+    angles: Vector3<Deg<f32>>,
+    flags: ProtocolFlags,
+) -> Result<(), NetError>
 where
     W: WriteBytesExt,
 {
     for angle in &angles[..] {
-        write_angle(writer, *angle)?;
+        write_angle(writer, *angle, flags)?;
     }
 
     Ok(())
 }
 
+fn read_angle16<R>(reader: &mut R, flags: ProtocolFlags) -> Result<Deg<f32>, NetError>
+where
+    R: BufRead + ReadBytesExt,
+{
+    if flags.contains(ProtocolFlags::FLOAT_ANGLE) {
+        return Ok(Deg(reader.read_f32::<LittleEndian>()?));
+    }
+
+    Ok(Deg(
+        reader.read_i16::<LittleEndian>()? as f32 * (360.0 / 65536.0)
+    ))
+}
+
+fn write_angle16<W>(writer: &mut W, angle: Deg<f32>, flags: ProtocolFlags) -> Result<(), NetError>
+where
+    W: WriteBytesExt,
+{
+    if flags.contains(ProtocolFlags::FLOAT_ANGLE) {
+        writer.write_f32::<LittleEndian>(angle.0)?;
+        return Ok(());
+    }
+
+    writer
+        .write_u16::<LittleEndian>(((angle.0 * 65536.0 / 360.0).round() as i32 & 0xFFFF) as u16)?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
-    use std::io::BufReader;
+    use std::{io::BufReader, net::UdpSocket};
 
     #[test]
     fn test_server_cmd_update_stat_read_write_eq() {
@@ -2404,9 +3501,12 @@ mod test {
         };
 
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2416,9 +3516,12 @@ mod test {
         let src = ServerCmd::Version { version: 42 };
 
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2428,9 +3531,12 @@ mod test {
         let src = ServerCmd::SetView { ent_id: 17 };
 
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2440,9 +3546,12 @@ mod test {
         let src = ServerCmd::Time { time: 23.07 };
 
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2454,9 +3563,12 @@ mod test {
         };
 
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2468,9 +3580,12 @@ mod test {
         };
 
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2479,6 +3594,7 @@ mod test {
     fn test_server_cmd_server_info_read_write_eq() {
         let src = ServerCmd::ServerInfo {
             protocol_version: 42,
+            protocol_flags: ProtocolFlags::empty(),
             max_clients: 16,
             game_type: GameType::Deathmatch,
             message: String::from("Test message"),
@@ -2487,9 +3603,12 @@ mod test {
         };
 
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2502,9 +3621,12 @@ mod test {
         };
 
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2517,9 +3639,12 @@ mod test {
         };
 
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2532,9 +3657,12 @@ mod test {
         };
 
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2547,9 +3675,12 @@ mod test {
         };
 
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2562,9 +3693,12 @@ mod test {
         };
 
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2573,9 +3707,12 @@ mod test {
     fn test_server_cmd_set_pause_read_write_eq() {
         let src = ServerCmd::SetPause { paused: true };
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2586,9 +3723,12 @@ mod test {
             stage: SignOnStage::Begin,
         };
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2599,9 +3739,12 @@ mod test {
             text: String::from("Center print test"),
         };
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2612,9 +3755,12 @@ mod test {
             text: String::from("Finale test"),
         };
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2623,9 +3769,12 @@ mod test {
     fn test_server_cmd_cd_track_read_write_eq() {
         let src = ServerCmd::CdTrack { track: 5, loop_: 1 };
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2636,9 +3785,35 @@ mod test {
             text: String::from("Cutscene test"),
         };
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
+        let mut reader = BufReader::new(packet.as_slice());
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(src, dst);
+    }
+
+    #[test]
+    fn test_server_cmd_temp_entity_color_explosion_read_write_eq() {
+        // the origin precedes the colors on the wire
+        let src = ServerCmd::TempEntity {
+            temp_entity: TempEntity::Point {
+                kind: PointEntityKind::ColorExplosion {
+                    color_start: 32,
+                    color_len: 8,
+                },
+                origin: Vector3::new(128.0, -64.0, 32.0),
+            },
+        };
+        let mut packet = Vec::new();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ServerCmd::deserialize(&mut reader).unwrap().unwrap();
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2649,9 +3824,10 @@ mod test {
             cmd: String::from("StringCmd test"),
         };
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ClientCmd::deserialize(&mut reader).unwrap();
+        let dst = ClientCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into()).unwrap();
 
         assert_eq!(src, dst);
     }
@@ -2670,13 +3846,548 @@ mod test {
         };
 
         let mut packet = Vec::new();
-        src.serialize(&mut packet).unwrap();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
         let mut reader = BufReader::new(packet.as_slice());
-        let dst = ClientCmd::deserialize(&mut reader).unwrap();
+        let dst = ClientCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into()).unwrap();
 
         assert_eq!(src, dst);
     }
 
+    fn round_trip(src: &ServerCmd, protocol: Protocol) -> ServerCmd {
+        let mut packet = Vec::new();
+        src.serialize(&mut packet, protocol).unwrap();
+        let mut reader = BufReader::new(packet.as_slice());
+        let dst = ServerCmd::deserialize(&mut reader, protocol)
+            .unwrap()
+            .unwrap();
+
+        // the whole command should have been consumed
+        assert!(reader.fill_buf().unwrap().is_empty());
+
+        dst
+    }
+
+    fn fitzquake_round_trip(src: &ServerCmd) -> ServerCmd {
+        round_trip(src, ProtocolVersion::FitzQuake.into())
+    }
+
+    fn rmq(flags: ProtocolFlags) -> Protocol {
+        Protocol::new(ProtocolVersion::Rmq, flags)
+    }
+
+    fn test_entity_update() -> EntityUpdate {
+        EntityUpdate {
+            ent_id: 300,
+            model_id: None,
+            frame_id: None,
+            colormap: None,
+            skin_id: None,
+            effects: None,
+            origin_x: Some(128.0),
+            pitch: None,
+            origin_y: Some(-64.5),
+            yaw: Some(Deg(90.0)),
+            origin_z: None,
+            roll: None,
+            no_lerp: false,
+            alpha: None,
+            scale: None,
+            lerp_finish: None,
+        }
+    }
+
+    #[test]
+    fn test_server_cmd_fast_update_read_write_eq() {
+        let src = ServerCmd::FastUpdate(EntityUpdate {
+            model_id: Some(12),
+            frame_id: Some(3),
+            colormap: Some(1),
+            skin_id: Some(2),
+            effects: Some(EntityEffects::DIM_LIGHT),
+            no_lerp: true,
+            ..test_entity_update()
+        });
+
+        let mut packet = Vec::new();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
+        let mut reader = BufReader::new(packet.as_slice());
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(src, dst);
+    }
+
+    #[test]
+    fn test_server_cmd_fast_update_fitzquake_read_write_eq() {
+        let src = ServerCmd::FastUpdate(EntityUpdate {
+            model_id: Some(513),
+            frame_id: Some(1024),
+            alpha: Some(encode_entity_alpha(0.5)),
+            scale: Some(16),
+            lerp_finish: Some(25),
+            ..test_entity_update()
+        });
+
+        assert_eq!(src, fitzquake_round_trip(&src));
+    }
+
+    #[test]
+    fn test_server_cmd_fast_update_extended_netquake_fails() {
+        let src = ServerCmd::FastUpdate(EntityUpdate {
+            model_id: Some(513),
+            ..test_entity_update()
+        });
+
+        let mut packet = Vec::new();
+        assert!(src
+            .serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .is_err());
+    }
+
+    #[test]
+    fn test_server_cmd_fast_update_to_json() {
+        let cmd = ServerCmd::FastUpdate(EntityUpdate {
+            effects: Some(EntityEffects::DIM_LIGHT),
+            ..test_entity_update()
+        });
+
+        let json = serde_json::to_value(&cmd).unwrap();
+        let update = &json["FastUpdate"];
+        assert_eq!(update["ent_id"], 300);
+        assert_eq!(update["origin_x"], 128.0);
+        assert_eq!(update["yaw"], 90.0);
+        assert!(update["pitch"].is_null());
+        assert_eq!(update["effects"], "DIM_LIGHT");
+    }
+
+    #[test]
+    fn test_server_cmd_player_data_fitzquake_read_write_eq() {
+        let src = ServerCmd::PlayerData(PlayerData {
+            view_height: Some(22.0),
+            ideal_pitch: None,
+            punch_pitch: Some(Deg(-2.0)),
+            velocity_x: Some(320.0),
+            punch_yaw: None,
+            velocity_y: None,
+            punch_roll: None,
+            velocity_z: Some(-16.0),
+            items: ItemFlags::SHOTGUN | ItemFlags::AXE | ItemFlags::SHELLS,
+            on_ground: true,
+            in_water: false,
+            weapon_frame: Some(300),
+            armor: Some(400),
+            weapon: Some(700),
+            health: 250,
+            ammo: 999,
+            ammo_shells: 300,
+            ammo_nails: 12,
+            ammo_rockets: 256,
+            ammo_cells: 1000,
+            active_weapon: 1,
+            weapon_alpha: Some(encode_entity_alpha(0.25)),
+        });
+
+        assert_eq!(src, fitzquake_round_trip(&src));
+    }
+
+    #[test]
+    fn test_server_cmd_sound_fitzquake_read_write_eq() {
+        let src = ServerCmd::Sound {
+            volume: Some(128),
+            attenuation: None,
+            entity_id: 9000,
+            channel: 3,
+            sound_id: 400,
+            position: Vector3::new(16.0, -32.0, 64.0),
+        };
+
+        assert_eq!(src, fitzquake_round_trip(&src));
+    }
+
+    #[test]
+    fn test_server_cmd_spawn_baseline_read_write_eq() {
+        let src = ServerCmd::SpawnBaseline {
+            ent_id: 17,
+            model_id: 4,
+            frame_id: 2,
+            colormap: 0,
+            skin_id: 1,
+            origin: Vector3::new(8.0, 16.0, -24.0),
+            angles: Vector3::new(Deg(0.0), Deg(90.0), Deg(0.0)),
+            alpha: None,
+        };
+
+        let mut packet = Vec::new();
+        src.serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .unwrap();
+        assert_eq!(packet[0], ServerCmdCode::SpawnBaseline as u8);
+        let mut reader = BufReader::new(packet.as_slice());
+        let dst = ServerCmd::deserialize(&mut reader, ProtocolVersion::NetQuake.into())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(src, dst);
+    }
+
+    #[test]
+    fn test_server_cmd_spawn_baseline_2_read_write_eq() {
+        let src = ServerCmd::SpawnBaseline {
+            ent_id: 17,
+            model_id: 300,
+            frame_id: 2,
+            colormap: 0,
+            skin_id: 1,
+            origin: Vector3::new(8.0, 16.0, -24.0),
+            angles: Vector3::new(Deg(0.0), Deg(90.0), Deg(0.0)),
+            alpha: Some(ENTITY_ALPHA_ONE),
+        };
+
+        assert_eq!(src.code(), ServerCmdCode::SpawnBaseline2 as u8);
+        assert_eq!(src, fitzquake_round_trip(&src));
+    }
+
+    #[test]
+    fn test_server_cmd_spawn_static_2_read_write_eq() {
+        let src = ServerCmd::SpawnStatic {
+            model_id: 3,
+            frame_id: 258,
+            colormap: 0,
+            skin_id: 0,
+            origin: Vector3::new(-8.0, 0.0, 24.0),
+            angles: Vector3::new(Deg(0.0), Deg(-90.0), Deg(0.0)),
+            alpha: None,
+        };
+
+        assert_eq!(src.code(), ServerCmdCode::SpawnStatic2 as u8);
+        assert_eq!(src, fitzquake_round_trip(&src));
+    }
+
+    #[test]
+    fn test_server_cmd_spawn_static_sound_2_read_write_eq() {
+        let src = ServerCmd::SpawnStaticSound {
+            origin: Vector3::new(32.0, 64.0, 128.0),
+            sound_id: 1000,
+            volume: 255,
+            attenuation: 64,
+        };
+
+        assert_eq!(src.code(), ServerCmdCode::SpawnStaticSound2 as u8);
+        assert_eq!(src, fitzquake_round_trip(&src));
+    }
+
+    #[test]
+    fn test_server_cmd_spawn_static_2_netquake_fails() {
+        let src = ServerCmd::SpawnStatic {
+            model_id: 3,
+            frame_id: 0,
+            colormap: 0,
+            skin_id: 0,
+            origin: Vector3::new(0.0, 0.0, 0.0),
+            angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+            alpha: Some(ENTITY_ALPHA_ZERO),
+        };
+
+        let mut packet = Vec::new();
+        assert!(src
+            .serialize(&mut packet, ProtocolVersion::NetQuake.into())
+            .is_err());
+    }
+
+    #[test]
+    fn test_server_cmd_fog_read_write_eq() {
+        let src = ServerCmd::Fog {
+            density: 32,
+            color: [64, 96, 128],
+            time: 250,
+        };
+
+        assert_eq!(src, fitzquake_round_trip(&src));
+    }
+
+    #[test]
+    fn test_server_cmd_skybox_read_write_eq() {
+        let src = ServerCmd::Skybox {
+            name: String::from("skybox_test"),
+        };
+
+        assert_eq!(src, fitzquake_round_trip(&src));
+    }
+
+    #[test]
+    fn test_server_cmd_bonus_flash_read_write_eq() {
+        let src = ServerCmd::BonusFlash;
+
+        assert_eq!(src, fitzquake_round_trip(&src));
+    }
+
+    #[test]
+    fn test_client_cmd_move_fitzquake_read_write_eq() {
+        let src = ClientCmd::Move {
+            send_time: Duration::milliseconds(1234),
+            // 16-bit angles can represent this, 8-bit angles can't
+            angles: Vector3::new(Deg(45.0), Deg(-22.5), Deg(1.40625)),
+            fwd_move: 200,
+            side_move: -350,
+            up_move: 0,
+            button_flags: ButtonFlags::JUMP,
+            impulse: 0,
+        };
+
+        let mut packet = Vec::new();
+        src.serialize(&mut packet, ProtocolVersion::FitzQuake.into())
+            .unwrap();
+        let mut reader = BufReader::new(packet.as_slice());
+        let dst = ClientCmd::deserialize(&mut reader, ProtocolVersion::FitzQuake.into()).unwrap();
+
+        assert_eq!(src, dst);
+    }
+
+    #[test]
+    fn test_server_cmd_server_info_rmq_read_write_eq() {
+        let src = ServerCmd::ServerInfo {
+            protocol_version: ProtocolVersion::Rmq.value(),
+            protocol_flags: ProtocolFlags::FLOAT_COORD | ProtocolFlags::SHORT_ANGLE,
+            max_clients: 8,
+            game_type: GameType::CoOp,
+            message: String::from("Test message"),
+            model_precache: vec![String::from("test1.bsp")],
+            sound_precache: vec![String::from("test1.wav")],
+        };
+
+        // the flags are sent whenever the announced version is RMQ, regardless of the protocol
+        // currently in use
+        assert_eq!(src, round_trip(&src, ProtocolVersion::NetQuake.into()));
+    }
+
+    #[test]
+    fn test_protocol_flags_ignored_without_rmq() {
+        let protocol = Protocol::new(ProtocolVersion::FitzQuake, ProtocolFlags::FLOAT_COORD);
+        assert_eq!(protocol.flags, ProtocolFlags::empty());
+    }
+
+    #[test]
+    fn test_coord_read_write() {
+        let cases = [
+            (ProtocolFlags::empty(), 2, 1.0 / 8.0),
+            (ProtocolFlags::COORD_24_BIT, 3, 1.0 / 255.0),
+            (ProtocolFlags::INT32_COORD, 4, 1.0 / 16.0),
+            (ProtocolFlags::FLOAT_COORD, 4, 0.0),
+        ];
+
+        for (flags, size, precision) in cases {
+            for coord in [0.0, 1234.5678, -1234.5678, -0.1, 4095.9] {
+                let mut packet = Vec::new();
+                write_coord(&mut packet, coord, flags).unwrap();
+                assert_eq!(packet.len(), size);
+                let mut reader = BufReader::new(packet.as_slice());
+                let dst = read_coord(&mut reader, flags).unwrap();
+                assert!(
+                    (coord - dst).abs() <= precision,
+                    "{:?}: wrote {}, read {}",
+                    flags,
+                    coord,
+                    dst
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_angle_read_write() {
+        let cases = [
+            (ProtocolFlags::empty(), 1, 360.0 / 256.0),
+            (ProtocolFlags::SHORT_ANGLE, 2, 360.0 / 65536.0),
+            (ProtocolFlags::FLOAT_ANGLE, 4, 0.0),
+        ];
+
+        for (flags, size, precision) in cases {
+            for angle in [0.0, 45.0, -90.0, 12.345, -170.0] {
+                let mut packet = Vec::new();
+                write_angle(&mut packet, Deg(angle), flags).unwrap();
+                assert_eq!(packet.len(), size);
+                let mut reader = BufReader::new(packet.as_slice());
+                let dst = read_angle(&mut reader, flags).unwrap();
+                assert!(
+                    (angle - dst.0).abs() <= precision,
+                    "{:?}: wrote {}, read {}",
+                    flags,
+                    angle,
+                    dst.0
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_server_cmd_fast_update_rmq_read_write_eq() {
+        let src = ServerCmd::FastUpdate(EntityUpdate {
+            // exactly representable with 32-bit integer coordinates and 16-bit angles
+            origin_x: Some(1234.0625),
+            origin_y: Some(-70000.5),
+            pitch: Some(Deg(-22.5)),
+            yaw: Some(Deg(45.0)),
+            roll: Some(Deg(1.40625)),
+            ..test_entity_update()
+        });
+
+        let protocol = rmq(ProtocolFlags::INT32_COORD | ProtocolFlags::SHORT_ANGLE);
+        assert_eq!(src, round_trip(&src, protocol));
+    }
+
+    #[test]
+    fn test_server_cmd_spawn_baseline_rmq_read_write_eq() {
+        let src = ServerCmd::SpawnBaseline {
+            ent_id: 12,
+            model_id: 300,
+            frame_id: 2,
+            colormap: 0,
+            skin_id: 1,
+            origin: Vector3::new(12.345, -6789.125, 0.001),
+            angles: Vector3::new(Deg(12.345), Deg(-179.9), Deg(0.5)),
+            alpha: None,
+        };
+
+        let protocol = rmq(ProtocolFlags::FLOAT_COORD | ProtocolFlags::FLOAT_ANGLE);
+        assert_eq!(src, round_trip(&src, protocol));
+    }
+
+    #[test]
+    fn test_server_cmd_temp_entity_rmq_read_write_eq() {
+        let src = ServerCmd::TempEntity {
+            temp_entity: TempEntity::Point {
+                kind: PointEntityKind::ColorExplosion {
+                    color_start: 32,
+                    color_len: 8,
+                },
+                origin: Vector3::new(9000.25, -12.75, 0.125),
+            },
+        };
+
+        let protocol = rmq(ProtocolFlags::FLOAT_COORD);
+        assert_eq!(src, round_trip(&src, protocol));
+    }
+
+    #[test]
+    fn test_client_cmd_move_rmq_read_write_eq() {
+        let src = ClientCmd::Move {
+            send_time: Duration::milliseconds(1234),
+            // only representable with float angles
+            angles: Vector3::new(Deg(12.345), Deg(-0.001), Deg(179.9)),
+            fwd_move: 200,
+            side_move: -350,
+            up_move: 0,
+            button_flags: ButtonFlags::ATTACK,
+            impulse: 0,
+        };
+
+        let protocol = rmq(ProtocolFlags::FLOAT_ANGLE);
+        let mut packet = Vec::new();
+        src.serialize(&mut packet, protocol).unwrap();
+        let mut reader = BufReader::new(packet.as_slice());
+        let dst = ClientCmd::deserialize(&mut reader, protocol).unwrap();
+
+        assert_eq!(src, dst);
+    }
+
+    #[test]
+    fn test_entity_alpha_encode_decode() {
+        assert_eq!(encode_entity_alpha(0.0), ENTITY_ALPHA_DEFAULT);
+        assert_eq!(encode_entity_alpha(1.0), ENTITY_ALPHA_ONE);
+        assert_eq!(decode_entity_alpha(ENTITY_ALPHA_DEFAULT), 1.0);
+        assert_eq!(decode_entity_alpha(ENTITY_ALPHA_ZERO), 0.0);
+        assert_eq!(decode_entity_alpha(ENTITY_ALPHA_ONE), 1.0);
+    }
+
+    #[test]
+    fn test_server_cmd_round_trip() {
+        generate::for_each_input(|u| {
+            let protocol = generate::protocol(u)?;
+            let src = generate::server_cmd(u, protocol)?;
+            assert_eq!(src, round_trip(&src, protocol), "{:?}", protocol);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_client_cmd_round_trip() {
+        generate::for_each_input(|u| {
+            let protocol = generate::protocol(u)?;
+            let src = generate::client_cmd(u, protocol)?;
+
+            let mut packet = Vec::new();
+            src.serialize(&mut packet, protocol).unwrap();
+            let mut reader = BufReader::new(packet.as_slice());
+            let dst = ClientCmd::deserialize(&mut reader, protocol).unwrap();
+
+            assert_eq!(src, dst, "{:?}", protocol);
+            assert!(reader.fill_buf().unwrap().is_empty());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_temp_entity_round_trip() {
+        generate::for_each_input(|u| {
+            let protocol = generate::protocol(u)?;
+            let src = generate::temp_entity(u, protocol)?;
+
+            let mut packet = Vec::new();
+            src.write_temp_entity(&mut packet, protocol).unwrap();
+            let mut reader = BufReader::new(packet.as_slice());
+            let dst = TempEntity::read_temp_entity(&mut reader, protocol).unwrap();
+
+            assert_eq!(src, dst, "{:?}", protocol);
+            assert!(reader.fill_buf().unwrap().is_empty());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_deserialize_garbage() {
+        // none of these should panic, whatever they return
+        generate::for_each_input(|u| {
+            let protocol = generate::protocol(u)?;
+            let data = u.bytes(u.len())?;
+
+            let mut reader = BufReader::new(data);
+            while let Ok(Some(_)) = ServerCmd::deserialize(&mut reader, protocol) {}
+
+            let mut reader = BufReader::new(data);
+            while ClientCmd::deserialize(&mut reader, protocol).is_ok() {}
+
+            let _ = TempEntity::read_temp_entity(&mut BufReader::new(data), protocol);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_server_cmd_invalid_utf8_fails() {
+        let packet = [ServerCmdCode::LightStyle as u8, 0, 0xC3, 0x28, 0];
+        let mut reader = BufReader::new(&packet[..]);
+        assert!(matches!(
+            ServerCmd::deserialize(&mut reader, Protocol::default()),
+            Err(NetError::InvalidData(_))
+        ));
+    }
+
+    #[test]
+    fn test_temp_entity_invalid_lightning_model_fails() {
+        let src = TempEntity::Beam {
+            kind: BeamEntityKind::Lightning { model_id: 4 },
+            entity_id: 1,
+            start: Vector3::zero(),
+            end: Vector3::zero(),
+        };
+
+        let mut packet = Vec::new();
+        assert!(src
+            .write_temp_entity(&mut packet, Protocol::default())
+            .is_err());
+    }
+
     fn gen_qsocket_pair() -> (QSocket, QSocket) {
         let src_udp = UdpSocket::bind("localhost:0").unwrap();
         let src_addr = src_udp.local_addr().unwrap();
@@ -2729,4 +4440,91 @@ mod test {
         let message = [0; MAX_DATAGRAM + 1];
         src.send_msg_unreliable(&message).unwrap();
     }
+
+    #[test]
+    fn test_qsocket_unreliable_between_reliable_chunks() {
+        let (mut src, mut dst) = gen_qsocket_pair();
+        let timeout = BlockingMode::Timeout(Duration::milliseconds(100));
+
+        let reliable: Vec<u8> = (0..MAX_DATAGRAM + 100).map(|i| i as u8).collect();
+        src.begin_send_msg(&reliable).unwrap();
+
+        // the first chunk arrives on its own and must be kept
+        assert!(dst.recv_msg(timeout).unwrap().is_empty());
+
+        // the ack releases the second chunk, which follows a datagram
+        src.send_msg_unreliable(b"datagram").unwrap();
+        assert!(src.recv_msg(timeout).unwrap().is_empty());
+
+        assert_eq!(dst.recv_msg(timeout).unwrap(), b"datagram");
+        assert_eq!(dst.recv_msg(timeout).unwrap(), reliable);
+    }
+
+    #[test]
+    fn test_qsocket_rtt_measured_on_ack() {
+        let (mut src, mut dst) = gen_qsocket_pair();
+        let timeout = BlockingMode::Timeout(Duration::milliseconds(100));
+        assert!(src.rtt().is_none());
+
+        src.begin_send_msg(b"reliable").unwrap();
+        assert_eq!(dst.recv_msg(timeout).unwrap(), b"reliable");
+        assert!(src.recv_msg(timeout).unwrap().is_empty());
+
+        let rtt = src.rtt().unwrap();
+        assert!(rtt >= Duration::zero() && rtt < Duration::seconds(1));
+    }
+
+    /// Sends a reliable message from `src` to `dst` and pumps both sockets
+    /// until it has been delivered and acknowledged.
+    fn pump_reliable(src: &mut QSocket, dst: &mut QSocket, message: &[u8]) -> Vec<Vec<u8>> {
+        src.begin_send_msg(message).unwrap();
+
+        let mut received = Vec::new();
+        for _ in 0..2000 {
+            let msg = dst.recv_msg(BlockingMode::NonBlocking).unwrap();
+            if !msg.is_empty() {
+                received.push(msg);
+            }
+
+            src.recv_msg(BlockingMode::NonBlocking).unwrap();
+            if src.can_send() && !received.is_empty() {
+                return received;
+            }
+
+            // resend right away instead of waiting for the timeout
+            if !src.can_send() && !src.send_next {
+                src.resend_msg().unwrap();
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+
+        panic!("message was not delivered");
+    }
+
+    #[test]
+    fn test_qsocket_reliable_over_impaired_link() {
+        let impairment = Impairment {
+            loss: 0.3,
+            duplicate: 0.2,
+            reorder: 0.2,
+            ..Impairment::none()
+        };
+
+        let src_udp = UdpSocket::bind("localhost:0").unwrap();
+        let dst_udp = UdpSocket::bind("localhost:0").unwrap();
+        let src_addr = src_udp.local_addr().unwrap();
+        let dst_addr = dst_udp.local_addr().unwrap();
+        let mut src = QSocket::new(ImpairedSocket::with_seed(src_udp, impairment, 1), dst_addr);
+        let mut dst = QSocket::new(ImpairedSocket::with_seed(dst_udp, impairment, 2), src_addr);
+
+        for len in [100, 3 * MAX_DATAGRAM + 1] {
+            let message: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let received = pump_reliable(&mut src, &mut dst, &message);
+            assert_eq!(received, vec![message]);
+        }
+
+        // with this seed, some packets had to be sent again
+        assert!(src.resend_count > 0);
+    }
 }
@@ -0,0 +1,219 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Server status queries.
+//!
+//! A query asks a server for its info, then for each active player and
+//! finally walks its rule list one cvar at a time, the same way `qstat` does
+//! for NetQuake servers.
+
+use std::{net::SocketAddr, time::Instant};
+
+use crate::common::net::{
+    connect::{
+        ConnectSocket, Request, Response, ResponsePlayerInfo, ResponseRuleInfo, ResponseServerInfo,
+    },
+    NetError, GAME_NAME,
+};
+
+use chrono::Duration;
+use serde::Serialize;
+
+/// Stops a rule walk against a server which never reports the end of its list.
+const MAX_RULES: usize = 256;
+
+/// Everything a server reports about itself.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ServerStatus {
+    pub address: SocketAddr,
+    pub info: ResponseServerInfo,
+    pub players: Vec<ResponsePlayerInfo>,
+    pub rules: Vec<ResponseRuleInfo>,
+}
+
+/// Queries the server at `remote`, waiting up to `timeout` for each reply.
+///
+/// Fails if the server doesn't answer the server info request. Missing player
+/// or rule replies only end that part of the query early.
+pub fn query_server(remote: SocketAddr, timeout: Duration) -> Result<ServerStatus, NetError> {
+    let mut socket = ConnectSocket::bind("0.0.0.0:0")?;
+
+    let info = match request(
+        &mut socket,
+        Request::server_info(GAME_NAME),
+        remote,
+        timeout,
+    )? {
+        Some(Response::ServerInfo(info)) => info,
+        Some(r) => {
+            return Err(NetError::InvalidData(format!(
+                "expected server info, got {:?}",
+                r
+            )))
+        }
+        None => return Err(NetError::with_msg(format!("No response from {}", remote))),
+    };
+
+    // the player ID in the request counts active clients, not slots
+    let mut players = Vec::new();
+    for player_id in 0..info.client_count {
+        match request(
+            &mut socket,
+            Request::player_info(player_id),
+            remote,
+            timeout,
+        )? {
+            Some(Response::PlayerInfo(player)) => players.push(player),
+            _ => break,
+        }
+    }
+
+    let mut rules: Vec<ResponseRuleInfo> = Vec::new();
+    while rules.len() < MAX_RULES {
+        let prev = rules.last().map_or("", |r| r.cvar_name.as_str());
+        match request(&mut socket, Request::rule_info(prev), remote, timeout)? {
+            Some(Response::RuleInfo(rule)) if !rule.cvar_name.is_empty() => rules.push(rule),
+            _ => break,
+        }
+    }
+
+    Ok(ServerStatus {
+        address: remote,
+        info,
+        players,
+        rules,
+    })
+}
+
+/// Sends `request` to `remote` and waits for a reply from that address.
+fn request(
+    socket: &mut ConnectSocket,
+    request: Request,
+    remote: SocketAddr,
+    timeout: Duration,
+) -> Result<Option<Response>, NetError> {
+    socket.send_request(request, remote)?;
+
+    let start = Instant::now();
+    loop {
+        let elapsed = Duration::from_std(start.elapsed()).unwrap_or_else(|_| Duration::zero());
+        let remaining = timeout - elapsed;
+        if remaining <= Duration::zero() {
+            return Ok(None);
+        }
+
+        match socket.recv_response(Some(remaining))? {
+            Some((response, addr)) if addr == remote => return Ok(Some(response)),
+            Some(_) => continue,
+            None => return Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::net::connect::ConnectListener;
+
+    #[test]
+    fn test_query_server() {
+        let listener = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = listener.local_addr().unwrap();
+
+        let info = ResponseServerInfo {
+            address: server_addr.to_string(),
+            hostname: String::from("test server"),
+            levelname: String::from("e1m1"),
+            client_count: 1,
+            client_max: 8,
+            protocol_version: 3,
+        };
+        let player = ResponsePlayerInfo {
+            player_id: 0,
+            player_name: String::from("player"),
+            colors: 0x4d,
+            frags: 12,
+            connect_duration: 90,
+            address: String::from("127.0.0.1:1234"),
+        };
+        let rules = [("deathmatch", "1"), ("fraglimit", "20")];
+
+        let server_info = info.clone();
+        let server_player = player.clone();
+        let server = std::thread::spawn(move || loop {
+            let (request, remote) = listener.recv_request().unwrap();
+            let response = match request {
+                Request::ServerInfo(_) => Response::ServerInfo(server_info.clone()),
+                Request::PlayerInfo(_) => Response::PlayerInfo(server_player.clone()),
+                Request::RuleInfo(r) => {
+                    let next = match r.prev_cvar.as_str() {
+                        "" => Some(0),
+                        prev => rules.iter().position(|(n, _)| *n == prev).map(|i| i + 1),
+                    };
+                    let (cvar_name, cvar_val) = next
+                        .and_then(|i| rules.get(i))
+                        .map_or((String::new(), String::new()), |(n, v)| {
+                            (n.to_string(), v.to_string())
+                        });
+                    let done = cvar_name.is_empty();
+                    listener
+                        .send_response(
+                            Response::RuleInfo(ResponseRuleInfo {
+                                cvar_name,
+                                cvar_val,
+                            }),
+                            remote,
+                        )
+                        .unwrap();
+                    if done {
+                        return;
+                    }
+                    continue;
+                }
+                r => panic!("unexpected request {:?}", r),
+            };
+            listener.send_response(response, remote).unwrap();
+        });
+
+        let status = query_server(server_addr, Duration::seconds(5)).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(status.address, server_addr);
+        assert_eq!(status.info, info);
+        assert_eq!(status.players, vec![player]);
+        assert_eq!(
+            status
+                .rules
+                .iter()
+                .map(|r| (r.cvar_name.as_str(), r.cvar_val.as_str()))
+                .collect::<Vec<_>>(),
+            rules.to_vec()
+        );
+    }
+
+    #[test]
+    fn test_query_server_no_response() {
+        // bound but never answered
+        let listener = ConnectListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = listener.local_addr().unwrap();
+
+        assert!(query_server(server_addr, Duration::milliseconds(50)).is_err());
+    }
+}
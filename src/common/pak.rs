@@ -16,19 +16,31 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
 //! Quake PAK archive manipulation.
+//!
+//! Only the file table is read when an archive is opened. The archive itself
+//! is memory-mapped, so file contents are paged in by the OS as they're used.
+//! New archives are written with [`PakBuilder`].
 
 use std::{
-    collections::{hash_map::Iter, HashMap},
+    collections::HashMap,
     fs,
-    io::{self, Read, Seek, SeekFrom},
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
+    ops::Range,
     path::Path,
 };
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use crate::common::vfs::normalize_path;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use memmap2::Mmap;
 use thiserror::Error;
 
 const PAK_MAGIC: [u8; 4] = [b'P', b'A', b'C', b'K'];
 const PAK_ENTRY_SIZE: usize = 64;
+const PAK_HEADER_SIZE: usize = 12;
+
+// names are NUL-terminated within the 56-byte field
+const MAX_NAME_LEN: usize = 55;
 
 #[derive(Error, Debug)]
 pub enum PakError {
@@ -50,11 +62,23 @@ pub enum PakError {
     NonUtf8FileName(#[from] std::string::FromUtf8Error),
     #[error("No such file in PAK archive: {0}")]
     NoSuchFile(String),
+    #[error("Invalid file name: {0:?}")]
+    InvalidFileName(String),
+    #[error("Archive is larger than 2 GiB")]
+    TooLarge,
 }
 
 /// An open Pak archive.
 #[derive(Debug)]
-pub struct Pak(HashMap<String, Box<[u8]>>);
+pub struct Pak {
+    data: Mmap,
+
+    // name and location of each file, in file table order
+    files: Vec<(String, Range<usize>)>,
+
+    // index into `files` by normalized path
+    index: HashMap<String, usize>,
+}
 
 impl Pak {
     // TODO: rename to from_path or similar
@@ -64,42 +88,49 @@ impl Pak {
     {
         debug!("Opening {}", path.as_ref().to_str().unwrap());
 
-        let mut infile = fs::File::open(path)?;
+        let infile = fs::File::open(path)?;
+
+        // SAFETY: the archive must not be modified while it's open. This is the
+        // same assumption every Quake engine makes about its PAKs
+        let data = unsafe { Mmap::map(&infile)? };
+
+        let mut reader = Cursor::new(&data[..]);
         let mut magic = [0u8; 4];
-        infile.read_exact(&mut magic)?;
+        reader.read_exact(&mut magic)?;
 
         if magic != PAK_MAGIC {
             Err(PakError::InvalidMagicNumber(magic))?;
         }
 
         // Locate the file table
-        let table_offset = match infile.read_i32::<LittleEndian>()? {
+        let table_offset = match reader.read_i32::<LittleEndian>()? {
             o if o <= 0 => Err(PakError::InvalidTableOffset(o))?,
             o => o as u32,
         };
 
-        let table_size = match infile.read_i32::<LittleEndian>()? {
+        let table_size = match reader.read_i32::<LittleEndian>()? {
             s if s <= 0 || s as usize % PAK_ENTRY_SIZE != 0 => Err(PakError::InvalidTableSize(s))?,
             s => s as u32,
         };
 
-        let mut map = HashMap::new();
-
-        for i in 0..(table_size as usize / PAK_ENTRY_SIZE) {
-            let entry_offset = table_offset as u64 + (i * PAK_ENTRY_SIZE) as u64;
-            infile.seek(SeekFrom::Start(entry_offset))?;
+        let mut files = Vec::new();
+        let mut index = HashMap::new();
 
+        reader.seek(SeekFrom::Start(table_offset as u64))?;
+        for _ in 0..(table_size as usize / PAK_ENTRY_SIZE) {
             let mut path_bytes = [0u8; 56];
-            infile.read_exact(&mut path_bytes)?;
+            reader.read_exact(&mut path_bytes)?;
 
-            let file_offset = match infile.read_i32::<LittleEndian>()? {
-                o if o <= 0 => Err(PakError::InvalidFileOffset(o))?,
-                o => o as u32,
+            let file_offset = match reader.read_i32::<LittleEndian>()? {
+                o if o <= 0 || o as usize > data.len() => Err(PakError::InvalidFileOffset(o))?,
+                o => o as usize,
             };
 
-            let file_size = match infile.read_i32::<LittleEndian>()? {
-                s if s <= 0 => Err(PakError::InvalidFileSize(s))?,
-                s => s as u32,
+            let file_size = match reader.read_i32::<LittleEndian>()? {
+                s if s < 0 || file_offset + s as usize > data.len() => {
+                    Err(PakError::InvalidFileSize(s))?
+                }
+                s => s as usize,
             };
 
             let last = path_bytes
@@ -109,21 +140,19 @@ impl Pak {
                     String::from_utf8_lossy(&path_bytes).into_owned(),
                 ))?;
             let path = String::from_utf8(path_bytes[0..last].to_vec())?;
-            infile.seek(SeekFrom::Start(file_offset as u64))?;
-
-            let mut data: Vec<u8> = Vec::with_capacity(file_size as usize);
-            (&mut infile)
-                .take(file_size as u64)
-                .read_to_end(&mut data)?;
 
-            map.insert(path, data.into_boxed_slice());
+            // like Quake, use the first of any files with the same name
+            index.entry(normalize_path(&path)).or_insert(files.len());
+            files.push((path, file_offset..file_offset + file_size));
         }
 
-        Ok(Pak(map))
+        Ok(Pak { data, files, index })
     }
 
     /// Opens a file in the file tree for reading.
     ///
+    /// Paths are matched without regard to case.
+    ///
     /// # Examples
     /// ```no_run
     /// # extern crate richter;
@@ -139,13 +168,281 @@ impl Pak {
         S: AsRef<str>,
     {
         let path = path.as_ref();
-        self.0
-            .get(path)
-            .map(|s| s.as_ref())
+        self.index
+            .get(&normalize_path(path))
+            .map(|i| &self.data[self.files[*i].1.clone()])
             .ok_or(PakError::NoSuchFile(path.to_owned()))
     }
 
-    pub fn iter<'a>(&self) -> Iter<String, impl AsRef<[u8]>> {
-        self.0.iter()
+    /// Returns the raw contents of the archive.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Iterates over the paths and contents of the files in the archive, in
+    /// the order of its file table.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.files
+            .iter()
+            .map(|(path, range)| (path.as_str(), &self.data[range.clone()]))
+    }
+}
+
+/// Builds a new PAK archive.
+///
+/// # Examples
+/// ```no_run
+/// # extern crate richter;
+/// use richter::common::pak::PakBuilder;
+///
+/// # fn main() {
+/// let mut builder = PakBuilder::new();
+/// builder.add("autoexec.cfg", b"map e1m1\n".to_vec()).unwrap();
+/// builder
+///     .write(std::fs::File::create("pak1.pak").unwrap())
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct PakBuilder {
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl PakBuilder {
+    pub fn new() -> PakBuilder {
+        PakBuilder::default()
+    }
+
+    /// Copies the files of an existing archive, so they can be changed and
+    /// written to a new one.
+    ///
+    /// Fails if the archive holds a file whose name can't be written back,
+    /// such as an empty name.
+    pub fn from_pak(pak: &Pak) -> Result<PakBuilder, PakError> {
+        let mut builder = PakBuilder::new();
+        for (path, data) in pak.iter() {
+            builder.add(path, data.to_vec())?;
+        }
+
+        Ok(builder)
+    }
+
+    /// Adds a file, replacing any file already added at the same path.
+    ///
+    /// Paths are compared without regard to case, like `Pak::open` does. A
+    /// path may be at most 55 bytes long, as names are NUL-terminated within
+    /// a 56-byte field.
+    pub fn add<S>(&mut self, path: S, data: Vec<u8>) -> Result<(), PakError>
+    where
+        S: AsRef<str>,
+    {
+        let path = path.as_ref();
+        if path.len() > MAX_NAME_LEN {
+            return Err(PakError::FileNameTooLong(path.to_owned()));
+        }
+
+        if path.is_empty() || path.contains('\0') {
+            return Err(PakError::InvalidFileName(path.to_owned()));
+        }
+
+        match self.position(path) {
+            Some(i) => self.files[i] = (path.to_owned(), data),
+            None => self.files.push((path.to_owned(), data)),
+        }
+
+        Ok(())
+    }
+
+    /// Removes a file, returning `false` if there was no file at that path.
+    pub fn remove<S>(&mut self, path: S) -> bool
+    where
+        S: AsRef<str>,
+    {
+        match self.position(path.as_ref()) {
+            Some(i) => {
+                self.files.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Iterates over the paths and contents of the files, in the order they
+    /// were added.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.files
+            .iter()
+            .map(|(path, data)| (path.as_str(), data.as_slice()))
+    }
+
+    /// Writes the archive: the header, then the contents of each file, then
+    /// the file table.
+    pub fn write<W>(&self, writer: W) -> Result<(), PakError>
+    where
+        W: Write,
+    {
+        let data_size: usize = self.files.iter().map(|(_, data)| data.len()).sum();
+        let table_offset = PAK_HEADER_SIZE + data_size;
+        let table_size = self.files.len() * PAK_ENTRY_SIZE;
+        if table_offset + table_size > i32::MAX as usize {
+            return Err(PakError::TooLarge);
+        }
+
+        let mut writer = io::BufWriter::new(writer);
+        writer.write_all(&PAK_MAGIC)?;
+        writer.write_i32::<LittleEndian>(table_offset as i32)?;
+        writer.write_i32::<LittleEndian>(table_size as i32)?;
+
+        for (_, data) in self.files.iter() {
+            writer.write_all(data)?;
+        }
+
+        let mut offset = PAK_HEADER_SIZE;
+        for (path, data) in self.files.iter() {
+            let mut name = [0u8; 56];
+            name[..path.len()].copy_from_slice(path.as_bytes());
+            writer.write_all(&name)?;
+            writer.write_i32::<LittleEndian>(offset as i32)?;
+            writer.write_i32::<LittleEndian>(data.len() as i32)?;
+            offset += data.len();
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn position(&self, path: &str) -> Option<usize> {
+        let path = normalize_path(path);
+        self.files
+            .iter()
+            .position(|(p, _)| normalize_path(p) == path)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Builds a PAK archive in memory, with the table at the end. If
+    /// `bad_size` is set, every file's size runs past the end of the archive.
+    pub fn pak_fixture(files: &[(&str, &[u8])], bad_size: bool) -> Vec<u8> {
+        let mut data = PAK_MAGIC.to_vec();
+        data.resize(12, 0);
+
+        let mut table = Vec::new();
+        for (path, contents) in files {
+            let mut path_bytes = [0u8; 56];
+            path_bytes[..path.len()].copy_from_slice(path.as_bytes());
+            table.extend_from_slice(&path_bytes);
+            table.extend_from_slice(&(data.len() as i32).to_le_bytes());
+            let size = contents.len() as i32 + if bad_size { 1000 } else { 0 };
+            table.extend_from_slice(&size.to_le_bytes());
+            data.extend_from_slice(contents);
+        }
+
+        let table_offset = data.len() as i32;
+        data[4..8].copy_from_slice(&table_offset.to_le_bytes());
+        data[8..12].copy_from_slice(&(table.len() as i32).to_le_bytes());
+        data.extend_from_slice(&table);
+
+        data
+    }
+
+    // writes a PAK archive holding the given files to a temporary file
+    fn write_pak(name: &str, files: &[(&str, &[u8])], bad_size: bool) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("richter-{}-{}.pak", name, std::process::id()));
+        fs::write(&path, pak_fixture(files, bad_size)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_open_files() {
+        let path = write_pak(
+            "open",
+            &[("progs.dat", b"progs"), ("maps/e1m1.bsp", b"bsp data")],
+            false,
+        );
+        let pak = Pak::new(&path).unwrap();
+
+        assert_eq!(pak.open("progs.dat").unwrap(), b"progs");
+        assert_eq!(pak.open("maps/e1m1.bsp").unwrap(), b"bsp data");
+        assert_eq!(pak.open("MAPS\\E1M1.BSP").unwrap(), b"bsp data");
+        assert!(matches!(pak.open("gfx.wad"), Err(PakError::NoSuchFile(_))));
+
+        let mut files: Vec<(&str, &[u8])> = pak.iter().collect();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                ("maps/e1m1.bsp", &b"bsp data"[..]),
+                ("progs.dat", &b"progs"[..])
+            ]
+        );
+
+        drop(pak);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_builder_from_bad_names() {
+        let path = write_pak("bad-names", &[("", b"nameless")], false);
+        let pak = Pak::new(&path).unwrap();
+
+        assert!(matches!(
+            PakBuilder::from_pak(&pak),
+            Err(PakError::InvalidFileName(_))
+        ));
+
+        drop(pak);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_file_past_end() {
+        let path = write_pak("past-end", &[("progs.dat", b"progs")], true);
+        let result = Pak::new(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(PakError::InvalidFileSize(1005))));
+    }
+
+    #[test]
+    fn test_builder_round_trip() {
+        let mut builder = PakBuilder::new();
+        builder.add("progs.dat", b"old progs".to_vec()).unwrap();
+        builder.add("maps/E1M1.bsp", b"bsp data".to_vec()).unwrap();
+        builder.add("empty.cfg", Vec::new()).unwrap();
+        builder.add("PROGS.DAT", b"progs".to_vec()).unwrap();
+        assert!(builder.remove("empty.CFG"));
+        assert!(!builder.remove("gfx.wad"));
+
+        assert!(matches!(
+            builder.add("a".repeat(56), Vec::new()),
+            Err(PakError::FileNameTooLong(_))
+        ));
+        builder.add("a".repeat(55), Vec::new()).unwrap();
+
+        let path = std::env::temp_dir().join(format!("richter-builder-{}.pak", std::process::id()));
+        builder.write(fs::File::create(&path).unwrap()).unwrap();
+
+        let pak = Pak::new(&path).unwrap();
+        assert_eq!(
+            pak.iter().collect::<Vec<_>>(),
+            builder.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(pak.open("progs.dat").unwrap(), b"progs");
+
+        // rebuilding an archive keeps its names and contents
+        let mut rebuilt = Vec::new();
+        PakBuilder::from_pak(&pak)
+            .unwrap()
+            .write(&mut rebuilt)
+            .unwrap();
+        assert_eq!(rebuilt, fs::read(&path).unwrap());
+
+        drop(pak);
+        fs::remove_file(&path).unwrap();
     }
 }
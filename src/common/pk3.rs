@@ -0,0 +1,425 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! PK3 (zip) archive reading.
+//!
+//! Only the central directory is read when an archive is opened. Stored files
+//! are read straight from the archive, and deflated files are decompressed
+//! when they're opened. Zip64, multi-disk and encrypted archives aren't
+//! supported.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    ops::Deref,
+    path::Path,
+};
+
+use crate::common::vfs::normalize_path;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use flate2::read::DeflateDecoder;
+use memmap2::Mmap;
+use thiserror::Error;
+
+const LOCAL_HEADER_MAGIC: u32 = 0x04034b50;
+const LOCAL_HEADER_SIZE: usize = 30;
+const CENTRAL_HEADER_MAGIC: u32 = 0x02014b50;
+const END_MAGIC: u32 = 0x06054b50;
+const END_SIZE: usize = 22;
+
+// the end of central directory record is followed by a comment of up to this
+// many bytes
+const MAX_COMMENT_SIZE: usize = u16::MAX as usize;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+const FLAG_ENCRYPTED: u16 = 0x0001;
+
+#[derive(Error, Debug)]
+pub enum Pk3Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("No end of central directory record")]
+    NoCentralDirectory,
+    #[error("Multi-disk and Zip64 archives are not supported")]
+    Unsupported,
+    #[error("Invalid central directory entry {0}")]
+    InvalidCentralHeader(usize),
+    #[error("Invalid local header for {0}")]
+    InvalidLocalHeader(String),
+    #[error("Encrypted file: {0}")]
+    Encrypted(String),
+    #[error("Unsupported compression method {method} for {path}")]
+    UnsupportedMethod { path: String, method: u16 },
+    #[error("Checksum mismatch for {0}")]
+    BadChecksum(String),
+    #[error("Size mismatch for {0}")]
+    BadSize(String),
+    #[error("Non-UTF-8 file name: {0}")]
+    NonUtf8FileName(#[from] std::string::FromUtf8Error),
+    #[error("No such file in PK3 archive: {0}")]
+    NoSuchFile(String),
+}
+
+#[derive(Debug)]
+enum Storage {
+    Mapped(Mmap),
+    Memory(Box<[u8]>),
+}
+
+impl Deref for Storage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Storage::Mapped(m) => m,
+            Storage::Memory(m) => m,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Pk3Entry {
+    method: u16,
+    flags: u16,
+    crc: u32,
+    compressed_size: usize,
+    size: usize,
+
+    // offset of the file's local header
+    header_offset: usize,
+}
+
+/// An open PK3 archive.
+#[derive(Debug)]
+pub struct Pk3 {
+    data: Storage,
+    files: HashMap<String, Pk3Entry>,
+}
+
+impl Pk3 {
+    /// Opens the archive at the given path.
+    pub fn new<P>(path: P) -> Result<Pk3, Pk3Error>
+    where
+        P: AsRef<Path>,
+    {
+        debug!("Opening {}", path.as_ref().display());
+
+        let infile = fs::File::open(path)?;
+
+        // SAFETY: the archive must not be modified while it's open, as with
+        // PAK archives
+        let data = unsafe { Mmap::map(&infile)? };
+        Pk3::from_storage(Storage::Mapped(data))
+    }
+
+    /// Reads an archive held in memory.
+    pub fn from_bytes<B>(data: B) -> Result<Pk3, Pk3Error>
+    where
+        B: Into<Box<[u8]>>,
+    {
+        Pk3::from_storage(Storage::Memory(data.into()))
+    }
+
+    fn from_storage(data: Storage) -> Result<Pk3, Pk3Error> {
+        // the end record is the last thing in the archive apart from its comment
+        let search_start = data.len().saturating_sub(END_SIZE + MAX_COMMENT_SIZE);
+        let end_offset = (search_start..=data.len().saturating_sub(END_SIZE))
+            .rev()
+            .filter(|ofs| *ofs + END_SIZE <= data.len())
+            .find(|ofs| {
+                // the comment may contain the magic number too, so check that
+                // the comment length matches
+                let comment_len = u16::from_le_bytes([data[ofs + 20], data[ofs + 21]]);
+                data[*ofs..*ofs + 4] == END_MAGIC.to_le_bytes()
+                    && ofs + END_SIZE + comment_len as usize == data.len()
+            })
+            .ok_or(Pk3Error::NoCentralDirectory)?;
+
+        let mut reader = Cursor::new(&data[..]);
+        reader.seek(SeekFrom::Start(end_offset as u64 + 4))?;
+        let disk = reader.read_u16::<LittleEndian>()?;
+        let directory_disk = reader.read_u16::<LittleEndian>()?;
+        let disk_entries = reader.read_u16::<LittleEndian>()?;
+        let entries = reader.read_u16::<LittleEndian>()?;
+        let _directory_size = reader.read_u32::<LittleEndian>()?;
+        let directory_offset = reader.read_u32::<LittleEndian>()?;
+
+        if disk != 0 || directory_disk != 0 || disk_entries != entries {
+            return Err(Pk3Error::Unsupported);
+        }
+
+        if entries == u16::MAX || directory_offset == u32::MAX {
+            return Err(Pk3Error::Unsupported);
+        }
+
+        let mut files = HashMap::new();
+
+        reader.seek(SeekFrom::Start(directory_offset as u64))?;
+        for i in 0..entries as usize {
+            if reader.read_u32::<LittleEndian>()? != CENTRAL_HEADER_MAGIC {
+                return Err(Pk3Error::InvalidCentralHeader(i));
+            }
+
+            let _version_made_by = reader.read_u16::<LittleEndian>()?;
+            let _version_needed = reader.read_u16::<LittleEndian>()?;
+            let flags = reader.read_u16::<LittleEndian>()?;
+            let method = reader.read_u16::<LittleEndian>()?;
+            let _mod_time = reader.read_u16::<LittleEndian>()?;
+            let _mod_date = reader.read_u16::<LittleEndian>()?;
+            let crc = reader.read_u32::<LittleEndian>()?;
+            let compressed_size = reader.read_u32::<LittleEndian>()? as usize;
+            let size = reader.read_u32::<LittleEndian>()? as usize;
+            let name_len = reader.read_u16::<LittleEndian>()? as usize;
+            let extra_len = reader.read_u16::<LittleEndian>()? as i64;
+            let comment_len = reader.read_u16::<LittleEndian>()? as i64;
+            let _disk_start = reader.read_u16::<LittleEndian>()?;
+            let _internal_attrs = reader.read_u16::<LittleEndian>()?;
+            let _external_attrs = reader.read_u32::<LittleEndian>()?;
+            let header_offset = reader.read_u32::<LittleEndian>()? as usize;
+
+            let mut name = vec![0; name_len];
+            reader.read_exact(&mut name)?;
+            reader.seek(SeekFrom::Current(extra_len + comment_len))?;
+
+            if header_offset + LOCAL_HEADER_SIZE > data.len() {
+                return Err(Pk3Error::InvalidCentralHeader(i));
+            }
+
+            let name = String::from_utf8(name)?;
+
+            // directories have entries of their own
+            if name.ends_with('/') {
+                continue;
+            }
+
+            files.insert(
+                normalize_path(&name),
+                Pk3Entry {
+                    method,
+                    flags,
+                    crc,
+                    compressed_size,
+                    size,
+                    header_offset,
+                },
+            );
+        }
+
+        Ok(Pk3 { data, files })
+    }
+
+    /// Opens a file in the archive for reading.
+    ///
+    /// Stored files are borrowed from the archive; deflated files are
+    /// decompressed into a new buffer.
+    pub fn open<S>(&self, path: S) -> Result<Cow<'_, [u8]>, Pk3Error>
+    where
+        S: AsRef<str>,
+    {
+        let path = path.as_ref();
+        let entry = self
+            .files
+            .get(&normalize_path(path))
+            .ok_or_else(|| Pk3Error::NoSuchFile(path.to_owned()))?;
+
+        if entry.flags & FLAG_ENCRYPTED != 0 {
+            return Err(Pk3Error::Encrypted(path.to_owned()));
+        }
+
+        let compressed = self.compressed_data(path, entry)?;
+        let contents = match entry.method {
+            METHOD_STORED => Cow::Borrowed(compressed),
+            METHOD_DEFLATED => {
+                // the recorded size can't be trusted to preallocate, but it
+                // does limit how much is inflated
+                let mut contents = Vec::new();
+                DeflateDecoder::new(compressed)
+                    .take(entry.size as u64 + 1)
+                    .read_to_end(&mut contents)?;
+                Cow::Owned(contents)
+            }
+            method => {
+                return Err(Pk3Error::UnsupportedMethod {
+                    path: path.to_owned(),
+                    method,
+                })
+            }
+        };
+
+        if contents.len() != entry.size {
+            return Err(Pk3Error::BadSize(path.to_owned()));
+        }
+
+        if crc32fast::hash(&contents) != entry.crc {
+            return Err(Pk3Error::BadChecksum(path.to_owned()));
+        }
+
+        Ok(contents)
+    }
+
+    /// Returns the raw contents of the archive.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the normalized paths of the files in the archive.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(|p| p.as_str())
+    }
+
+    // returns the file's data as stored in the archive
+    fn compressed_data(&self, path: &str, entry: &Pk3Entry) -> Result<&[u8], Pk3Error> {
+        let invalid = || Pk3Error::InvalidLocalHeader(path.to_owned());
+
+        // the name and extra field lengths can differ from the central
+        // directory's, so they're read from the local header
+        let mut reader = Cursor::new(&self.data[entry.header_offset..]);
+        if reader.read_u32::<LittleEndian>()? != LOCAL_HEADER_MAGIC {
+            return Err(invalid());
+        }
+
+        reader.seek(SeekFrom::Start(26))?;
+        let name_len = reader.read_u16::<LittleEndian>()? as usize;
+        let extra_len = reader.read_u16::<LittleEndian>()? as usize;
+
+        let start = entry.header_offset + LOCAL_HEADER_SIZE + name_len + extra_len;
+        self.data
+            .get(start..start + entry.compressed_size)
+            .ok_or_else(invalid)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use flate2::{write::DeflateEncoder, Compression};
+    use std::io::Write;
+
+    /// Builds a zip archive in memory. Files are deflated if the flag next to
+    /// them is set.
+    pub fn zip_fixture(files: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut directory = Vec::new();
+
+        for (path, contents, deflate) in files {
+            let (method, stored) = match deflate {
+                true => {
+                    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+                    encoder.write_all(contents).unwrap();
+                    (METHOD_DEFLATED, encoder.finish().unwrap())
+                }
+                false => (METHOD_STORED, contents.to_vec()),
+            };
+
+            let header_offset = data.len() as u32;
+            let crc = crc32fast::hash(contents);
+
+            // fields shared by the local and central headers
+            let mut common = Vec::new();
+            common.extend_from_slice(&20u16.to_le_bytes());
+            common.extend_from_slice(&0u16.to_le_bytes());
+            common.extend_from_slice(&method.to_le_bytes());
+            common.extend_from_slice(&[0; 4]);
+            common.extend_from_slice(&crc.to_le_bytes());
+            common.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+            common.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            common.extend_from_slice(&(path.len() as u16).to_le_bytes());
+
+            data.extend_from_slice(&LOCAL_HEADER_MAGIC.to_le_bytes());
+            data.extend_from_slice(&common);
+            data.extend_from_slice(&0u16.to_le_bytes());
+            data.extend_from_slice(path.as_bytes());
+            data.extend_from_slice(&stored);
+
+            directory.extend_from_slice(&CENTRAL_HEADER_MAGIC.to_le_bytes());
+            directory.extend_from_slice(&20u16.to_le_bytes());
+            directory.extend_from_slice(&common);
+            directory.extend_from_slice(&[0; 12]);
+            directory.extend_from_slice(&header_offset.to_le_bytes());
+            directory.extend_from_slice(path.as_bytes());
+        }
+
+        let directory_offset = data.len() as u32;
+        data.extend_from_slice(&directory);
+        data.extend_from_slice(&END_MAGIC.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        data.extend_from_slice(&directory_offset.to_le_bytes());
+
+        // a comment, which the end record must be found before
+        let comment = b"PK\x05\x06 is not the end";
+        data.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+        data.extend_from_slice(comment);
+
+        data
+    }
+
+    #[test]
+    fn test_stored_and_deflated() {
+        let progs = b"progs".repeat(100);
+        let pk3 = Pk3::from_bytes(zip_fixture(&[
+            ("maps/", b"", false),
+            ("maps/e1m1.bsp", b"bsp data", false),
+            ("progs.dat", &progs, true),
+        ]))
+        .unwrap();
+
+        let bsp = pk3.open("maps/e1m1.bsp").unwrap();
+        assert!(matches!(bsp, Cow::Borrowed(_)));
+        assert_eq!(&*bsp, b"bsp data");
+        assert_eq!(&*pk3.open("progs.dat").unwrap(), &progs[..]);
+        assert_eq!(&*pk3.open("PROGS.DAT").unwrap(), &progs[..]);
+
+        assert!(matches!(pk3.open("maps/"), Err(Pk3Error::NoSuchFile(_))));
+        let mut paths: Vec<&str> = pk3.paths().collect();
+        paths.sort();
+        assert_eq!(paths, ["maps/e1m1.bsp", "progs.dat"]);
+    }
+
+    #[test]
+    fn test_bad_archives() {
+        assert!(matches!(
+            Pk3::from_bytes(b"not a zip".to_vec()),
+            Err(Pk3Error::NoCentralDirectory)
+        ));
+
+        // corrupt the stored file's contents
+        let mut data = zip_fixture(&[("progs.dat", b"progs", false)]);
+        data[LOCAL_HEADER_SIZE + "progs.dat".len()] = b'P';
+        let pk3 = Pk3::from_bytes(data).unwrap();
+        assert!(matches!(
+            pk3.open("progs.dat"),
+            Err(Pk3Error::BadChecksum(_))
+        ));
+
+        // a file which inflates to much more than its recorded size
+        let mut data = zip_fixture(&[("progs.dat", &[0; 1 << 20], true)]);
+        let central = data
+            .windows(4)
+            .position(|w| w == CENTRAL_HEADER_MAGIC.to_le_bytes())
+            .unwrap();
+        data[central + 24..central + 28].copy_from_slice(&16u32.to_le_bytes());
+        let pk3 = Pk3::from_bytes(data).unwrap();
+        assert!(matches!(pk3.open("progs.dat"), Err(Pk3Error::BadSize(_))));
+    }
+}
@@ -0,0 +1,871 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Player movement.
+//!
+//! This is a port of the NetQuake player physics: the acceleration and friction applied by
+//! `SV_ClientThink`, the walking movement of `SV_Physics_Client` and the jumping and swimming
+//! behavior of the standard QuakeC `PlayerPreThink`. Movement is clipped against the world's
+//! collision hulls only, so other entities are ignored.
+
+use crate::{
+    common::{
+        bsp::{BspCollisionHull, BspError, BspLeafContents, BspModel},
+        engine,
+        net::{ButtonFlags, ClientCmd},
+    },
+    server::world::{
+        phys::{velocity_after_collision, CollisionFlags},
+        TraceEndKind,
+    },
+};
+
+use cgmath::{Angle, Deg, InnerSpace, Vector3, Zero};
+use chrono::Duration;
+
+/// Bottom of the player's bounding box relative to its origin.
+const PLAYER_MIN_Z: f32 = -24.0;
+
+/// Top of the player's bounding box relative to its origin.
+const PLAYER_MAX_Z: f32 = 32.0;
+
+/// Height of the player's eyes relative to its origin.
+const PLAYER_VIEW_Z: f32 = 22.0;
+
+/// The tallest step the player can walk up without jumping.
+const STEP_SIZE: f32 = 18.0;
+
+/// Distance kept between the player and any surface it collides with.
+const DIST_EPSILON: f32 = 0.03125;
+
+/// Surfaces with a normal steeper than this can't be stood on.
+const MIN_FLOOR_NORMAL_Z: f32 = 0.7;
+
+/// The most a player can accelerate themselves in midair.
+const MAX_AIR_SPEED: f32 = 30.0;
+
+/// Vertical velocity added when jumping off the ground.
+const JUMP_VELOCITY: f32 = 270.0;
+
+const MAX_CLIP_PLANES: usize = 5;
+const MAX_BUMPS: usize = 4;
+
+// traces stop at every change of contents, so crossing in and out of liquids
+// takes several of them
+const MAX_TRACE_STEPS: usize = 8;
+
+/// Physics settings which affect player movement.
+///
+/// These correspond to the server cvars of the same names.
+#[derive(Copy, Clone, Debug)]
+pub struct PhysicsVars {
+    pub sv_gravity: f32,
+    pub sv_friction: f32,
+    pub edgefriction: f32,
+    pub sv_stopspeed: f32,
+    pub sv_maxspeed: f32,
+    pub sv_accelerate: f32,
+    pub sv_maxvelocity: f32,
+    pub sv_nostep: bool,
+}
+
+impl Default for PhysicsVars {
+    fn default() -> Self {
+        PhysicsVars {
+            sv_gravity: 800.0,
+            sv_friction: 4.0,
+            edgefriction: 2.0,
+            sv_stopspeed: 100.0,
+            sv_maxspeed: 320.0,
+            sv_accelerate: 10.0,
+            sv_maxvelocity: 2000.0,
+            sv_nostep: false,
+        }
+    }
+}
+
+/// How deep the player is submerged in a liquid.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WaterLevel {
+    None = 0,
+    Feet = 1,
+    Waist = 2,
+    Eyes = 3,
+}
+
+/// The part of the player's state which is affected by movement.
+#[derive(Clone, Debug)]
+pub struct PlayerState {
+    pub origin: Vector3<f32>,
+    pub velocity: Vector3<f32>,
+    pub on_ground: bool,
+    pub water_level: WaterLevel,
+    pub water_kind: BspLeafContents,
+
+    /// Whether the jump button has been let go since the last jump.
+    pub jump_released: bool,
+}
+
+impl PlayerState {
+    pub fn new(origin: Vector3<f32>, velocity: Vector3<f32>, on_ground: bool) -> PlayerState {
+        PlayerState {
+            origin,
+            velocity,
+            on_ground,
+            water_level: WaterLevel::None,
+            water_kind: BspLeafContents::Empty,
+            jump_released: true,
+        }
+    }
+}
+
+/// The movement requested by a single client command.
+#[derive(Copy, Clone, Debug)]
+pub struct MoveInput {
+    /// View angles in (pitch, yaw, roll) order.
+    pub angles: Vector3<Deg<f32>>,
+    pub forward: f32,
+    pub side: f32,
+    pub up: f32,
+    pub jump: bool,
+}
+
+impl MoveInput {
+    /// Extracts the movement input from a client command.
+    ///
+    /// Returns `None` if the command is not a `ClientCmd::Move`.
+    pub fn from_cmd(cmd: &ClientCmd) -> Option<MoveInput> {
+        match *cmd {
+            ClientCmd::Move {
+                angles,
+                fwd_move,
+                side_move,
+                up_move,
+                button_flags,
+                ..
+            } => Some(MoveInput {
+                angles,
+                forward: fwd_move as f32,
+                side: side_move as f32,
+                up: up_move as f32,
+                jump: button_flags.contains(ButtonFlags::JUMP),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The world collision hulls used to move the player.
+#[derive(Debug)]
+pub struct PlayerHulls {
+    /// Hull 0, used for point contents.
+    point: BspCollisionHull,
+
+    /// Hull 1, sized for the player's bounding box.
+    player: BspCollisionHull,
+}
+
+impl PlayerHulls {
+    pub fn new(point: BspCollisionHull, player: BspCollisionHull) -> PlayerHulls {
+        PlayerHulls { point, player }
+    }
+
+    /// Returns the player hulls of a world model.
+    pub fn from_world(world: &BspModel) -> Result<PlayerHulls, BspError> {
+        Ok(PlayerHulls::new(world.hull(0)?, world.hull(1)?))
+    }
+}
+
+/// Runs one frame of player movement for the given input.
+///
+/// This applies the input the same way a NetQuake server running the standard QuakeC would:
+/// acceleration and friction first, then jumping, then gravity and collision.
+pub fn player_move(
+    hulls: &PlayerHulls,
+    vars: &PhysicsVars,
+    state: &mut PlayerState,
+    input: &MoveInput,
+    frame_time: Duration,
+) -> Result<(), BspError> {
+    let time = engine::duration_to_f32(frame_time);
+    let swimming = check_water(&hulls.point, state)?;
+
+    if swimming {
+        water_move(vars, state, input, time);
+    } else {
+        air_move(&hulls.point, vars, state, input, time)?;
+    }
+
+    if input.jump {
+        jump(state);
+    } else {
+        state.jump_released = true;
+    }
+
+    limit_velocity(vars, state);
+    if !swimming {
+        state.velocity.z -= vars.sv_gravity * time;
+    }
+    limit_velocity(vars, state);
+
+    walk_move(&hulls.player, vars, state, time)
+}
+
+/// Runs one frame of movement for a player with clipping disabled.
+///
+/// The player flies wherever the input takes it, passing through the world.
+pub fn noclip_move(
+    vars: &PhysicsVars,
+    state: &mut PlayerState,
+    input: &MoveInput,
+    frame_time: Duration,
+) {
+    let time = engine::duration_to_f32(frame_time);
+    let (forward, right, _) = angle_vectors(Vector3::new(
+        -input.angles.x / 3.0,
+        input.angles.y,
+        Deg(0.0),
+    ));
+
+    let mut wish_vel = forward * input.forward + right * input.side;
+    wish_vel.z += input.up;
+
+    let wish_speed = wish_vel.magnitude();
+    if wish_speed > vars.sv_maxspeed {
+        wish_vel *= vars.sv_maxspeed / wish_speed;
+    }
+
+    state.velocity = wish_vel;
+    state.origin += time * wish_vel;
+    state.on_ground = false;
+}
+
+/// Updates the water level of the player, returning whether it is swimming.
+fn check_water(hull: &BspCollisionHull, state: &mut PlayerState) -> Result<bool, BspError> {
+    state.water_level = WaterLevel::None;
+    state.water_kind = BspLeafContents::Empty;
+
+    let mut point = state.origin + Vector3::new(0.0, 0.0, PLAYER_MIN_Z + 1.0);
+    let contents = hull.contents_at_point(point)?;
+    if is_liquid(contents) {
+        state.water_kind = contents;
+        state.water_level = WaterLevel::Feet;
+
+        point.z = state.origin.z + (PLAYER_MIN_Z + PLAYER_MAX_Z) * 0.5;
+        if is_liquid(hull.contents_at_point(point)?) {
+            state.water_level = WaterLevel::Waist;
+
+            point.z = state.origin.z + PLAYER_VIEW_Z;
+            if is_liquid(hull.contents_at_point(point)?) {
+                state.water_level = WaterLevel::Eyes;
+            }
+        }
+    }
+
+    Ok(state.water_level > WaterLevel::Feet)
+}
+
+fn is_liquid(contents: BspLeafContents) -> bool {
+    matches!(
+        contents,
+        BspLeafContents::Water | BspLeafContents::Slime | BspLeafContents::Lava
+    )
+}
+
+/// Calculates the forward, right and up vectors for the given (pitch, yaw, roll) angles.
+fn angle_vectors(angles: Vector3<Deg<f32>>) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+    let (sp, cp) = angles.x.sin_cos();
+    let (sy, cy) = angles.y.sin_cos();
+    let (sr, cr) = angles.z.sin_cos();
+
+    let forward = Vector3::new(cp * cy, cp * sy, -sp);
+    let right = Vector3::new(-sr * sp * cy + cr * sy, -sr * sp * sy - cr * cy, -sr * cp);
+    let up = Vector3::new(cr * sp * cy + sr * sy, cr * sp * sy - sr * cy, cr * cp);
+
+    (forward, right, up)
+}
+
+fn air_move(
+    hull: &BspCollisionHull,
+    vars: &PhysicsVars,
+    state: &mut PlayerState,
+    input: &MoveInput,
+    time: f32,
+) -> Result<(), BspError> {
+    // the player model only pitches a third as far as the view does
+    let (forward, right, _) = angle_vectors(Vector3::new(
+        -input.angles.x / 3.0,
+        input.angles.y,
+        Deg(0.0),
+    ));
+
+    let mut wish_vel = forward * input.forward + right * input.side;
+    wish_vel.z = 0.0;
+
+    let mut wish_speed = wish_vel.magnitude();
+    let wish_dir = if wish_speed == 0.0 {
+        Vector3::zero()
+    } else {
+        wish_vel / wish_speed
+    };
+    if wish_speed > vars.sv_maxspeed {
+        wish_vel *= vars.sv_maxspeed / wish_speed;
+        wish_speed = vars.sv_maxspeed;
+    }
+
+    if state.on_ground {
+        friction(hull, vars, state, time)?;
+        accelerate(vars, state, wish_dir, wish_speed, time);
+    } else {
+        air_accelerate(vars, state, wish_vel, wish_speed, time);
+    }
+
+    Ok(())
+}
+
+fn friction(
+    hull: &BspCollisionHull,
+    vars: &PhysicsVars,
+    state: &mut PlayerState,
+    time: f32,
+) -> Result<(), BspError> {
+    let vel = state.velocity;
+    let speed = (vel.x * vel.x + vel.y * vel.y).sqrt();
+    if speed == 0.0 {
+        return Ok(());
+    }
+
+    // if the leading edge is over a dropoff, increase friction
+    let start = Vector3::new(
+        state.origin.x + vel.x / speed * 16.0,
+        state.origin.y + vel.y / speed * 16.0,
+        state.origin.z + PLAYER_MIN_Z,
+    );
+    let stop = start - Vector3::new(0.0, 0.0, 34.0);
+    let friction = if trace_move(hull, start, stop)?.fraction == 1.0 {
+        vars.sv_friction * vars.edgefriction
+    } else {
+        vars.sv_friction
+    };
+
+    let control = speed.max(vars.sv_stopspeed);
+    let new_speed = (speed - time * control * friction).max(0.0);
+    state.velocity *= new_speed / speed;
+
+    Ok(())
+}
+
+fn accelerate(
+    vars: &PhysicsVars,
+    state: &mut PlayerState,
+    wish_dir: Vector3<f32>,
+    wish_speed: f32,
+    time: f32,
+) {
+    let add_speed = wish_speed - state.velocity.dot(wish_dir);
+    if add_speed <= 0.0 {
+        return;
+    }
+
+    let accel_speed = (vars.sv_accelerate * time * wish_speed).min(add_speed);
+    state.velocity += accel_speed * wish_dir;
+}
+
+fn air_accelerate(
+    vars: &PhysicsVars,
+    state: &mut PlayerState,
+    wish_vel: Vector3<f32>,
+    wish_speed: f32,
+    time: f32,
+) {
+    let speed = wish_vel.magnitude();
+    if speed == 0.0 {
+        return;
+    }
+
+    let wish_dir = wish_vel / speed;
+    let add_speed = speed.min(MAX_AIR_SPEED) - state.velocity.dot(wish_dir);
+    if add_speed <= 0.0 {
+        return;
+    }
+
+    let accel_speed = (vars.sv_accelerate * wish_speed * time).min(add_speed);
+    state.velocity += accel_speed * wish_dir;
+}
+
+fn water_move(vars: &PhysicsVars, state: &mut PlayerState, input: &MoveInput, time: f32) {
+    let (forward, right, _) = angle_vectors(input.angles);
+    let mut wish_vel = forward * input.forward + right * input.side;
+
+    // sink slowly when not swimming
+    if input.forward == 0.0 && input.side == 0.0 && input.up == 0.0 {
+        wish_vel.z -= 60.0;
+    } else {
+        wish_vel.z += input.up;
+    }
+
+    let mut wish_speed = wish_vel.magnitude();
+    if wish_speed > vars.sv_maxspeed {
+        wish_vel *= vars.sv_maxspeed / wish_speed;
+        wish_speed = vars.sv_maxspeed;
+    }
+    wish_speed *= 0.7;
+
+    // water friction
+    let speed = state.velocity.magnitude();
+    let new_speed = if speed != 0.0 {
+        let new_speed = (speed - time * speed * vars.sv_friction).max(0.0);
+        state.velocity *= new_speed / speed;
+        new_speed
+    } else {
+        0.0
+    };
+
+    // water acceleration
+    if wish_speed == 0.0 {
+        return;
+    }
+
+    let add_speed = wish_speed - new_speed;
+    if add_speed <= 0.0 {
+        return;
+    }
+
+    let accel_speed = (vars.sv_accelerate * wish_speed * time).min(add_speed);
+    state.velocity += accel_speed * wish_vel.normalize();
+}
+
+fn jump(state: &mut PlayerState) {
+    // swim upward
+    if state.water_level >= WaterLevel::Waist {
+        state.velocity.z = match state.water_kind {
+            BspLeafContents::Water => 100.0,
+            BspLeafContents::Slime => 80.0,
+            _ => 50.0,
+        };
+        return;
+    }
+
+    if !state.on_ground || !state.jump_released {
+        return;
+    }
+
+    state.jump_released = false;
+    state.on_ground = false;
+    state.velocity.z += JUMP_VELOCITY;
+}
+
+fn limit_velocity(vars: &PhysicsVars, state: &mut PlayerState) {
+    for i in 0..3 {
+        state.velocity[i] = state.velocity[i].clamp(-vars.sv_maxvelocity, vars.sv_maxvelocity);
+    }
+}
+
+/// Slides the player along the floor, stepping up onto stairs if it runs into them.
+fn walk_move(
+    hull: &BspCollisionHull,
+    vars: &PhysicsVars,
+    state: &mut PlayerState,
+    time: f32,
+) -> Result<(), BspError> {
+    let old_on_ground = state.on_ground;
+    state.on_ground = false;
+
+    let old_origin = state.origin;
+    let old_velocity = state.velocity;
+
+    let flags = fly_move(hull, state, time)?;
+
+    // only try a step if something blocked horizontal movement
+    if !flags.contains(CollisionFlags::VERTICAL) {
+        return Ok(());
+    }
+
+    // don't climb stairs while jumping
+    if !old_on_ground && state.water_level == WaterLevel::None {
+        return Ok(());
+    }
+
+    if vars.sv_nostep {
+        return Ok(());
+    }
+
+    let no_step_origin = state.origin;
+    let no_step_velocity = state.velocity;
+    let no_step_on_ground = state.on_ground;
+
+    // try moving up and forward to go up a step
+    state.origin = old_origin;
+    push(hull, state, Vector3::new(0.0, 0.0, STEP_SIZE))?;
+
+    state.velocity = Vector3::new(old_velocity.x, old_velocity.y, 0.0);
+    fly_move(hull, state, time)?;
+
+    // move back down
+    let down = push(
+        hull,
+        state,
+        Vector3::new(0.0, 0.0, -STEP_SIZE + old_velocity.z * time),
+    )?;
+
+    match down {
+        Some(normal) if normal.z > MIN_FLOOR_NORMAL_Z => state.on_ground = true,
+
+        // if the push down didn't end up on good ground, use the move without
+        // the step up. this happens near wall / slope combinations.
+        _ => {
+            state.origin = no_step_origin;
+            state.velocity = no_step_velocity;
+            state.on_ground = no_step_on_ground;
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves the player by `offset` without changing its velocity.
+///
+/// Returns the normal of the surface hit, if any.
+fn push(
+    hull: &BspCollisionHull,
+    state: &mut PlayerState,
+    offset: Vector3<f32>,
+) -> Result<Option<Vector3<f32>>, BspError> {
+    let trace = trace_move(hull, state.origin, state.origin + offset)?;
+    state.origin = trace.end;
+    Ok(trace.normal)
+}
+
+/// Moves the player along its velocity for `time` seconds, sliding along any surfaces it hits.
+///
+/// Returns `HORIZONTAL` if the player hit a floor and `VERTICAL` if it hit a wall or step.
+fn fly_move(
+    hull: &BspCollisionHull,
+    state: &mut PlayerState,
+    time: f32,
+) -> Result<CollisionFlags, BspError> {
+    let mut flags = CollisionFlags::empty();
+    let mut planes = Vec::with_capacity(MAX_CLIP_PLANES);
+    let mut original_velocity = state.velocity;
+    let primal_velocity = state.velocity;
+    let mut time_left = time;
+
+    for _ in 0..MAX_BUMPS {
+        if state.velocity.is_zero() {
+            break;
+        }
+
+        let end = state.origin + time_left * state.velocity;
+        let trace = trace_move(hull, state.origin, end)?;
+
+        if trace.all_solid {
+            // the player is stuck in a wall
+            state.velocity = Vector3::zero();
+            return Ok(CollisionFlags::all());
+        }
+
+        if trace.fraction > 0.0 {
+            state.origin = trace.end;
+            original_velocity = state.velocity;
+            planes.clear();
+        }
+
+        let normal = match trace.normal {
+            Some(n) => n,
+            None => break,
+        };
+
+        if normal.z > MIN_FLOOR_NORMAL_Z {
+            flags |= CollisionFlags::HORIZONTAL;
+            state.on_ground = true;
+        }
+
+        if normal.z == 0.0 {
+            flags |= CollisionFlags::VERTICAL;
+        }
+
+        time_left -= time_left * trace.fraction;
+
+        if planes.len() >= MAX_CLIP_PLANES {
+            state.velocity = Vector3::zero();
+            return Ok(CollisionFlags::all());
+        }
+        planes.push(normal);
+
+        // find a velocity which runs parallel to all of the planes hit so far
+        let clipped = planes.iter().find_map(|plane| {
+            let (velocity, _) = velocity_after_collision(original_velocity, *plane, 1.0);
+            planes
+                .iter()
+                .all(|other| other == plane || velocity.dot(*other) >= 0.0)
+                .then_some(velocity)
+        });
+
+        match clipped {
+            Some(velocity) => state.velocity = velocity,
+
+            // go along the crease
+            None if planes.len() == 2 => {
+                let dir = planes[0].cross(planes[1]);
+                state.velocity = dir * dir.dot(state.velocity);
+            }
+
+            None => {
+                state.velocity = Vector3::zero();
+                return Ok(CollisionFlags::all());
+            }
+        }
+
+        // if the new velocity is against the original, stop dead to avoid
+        // tiny oscillations in sloping corners
+        if state.velocity.dot(primal_velocity) <= 0.0 {
+            state.velocity = Vector3::zero();
+            return Ok(flags);
+        }
+    }
+
+    Ok(flags)
+}
+
+/// The result of moving through a collision hull.
+struct MoveTrace {
+    /// How much of the move was completed, from 0 to 1.
+    fraction: f32,
+
+    /// Where the move ended.
+    end: Vector3<f32>,
+
+    /// The normal of the surface which stopped the move, if any.
+    normal: Option<Vector3<f32>>,
+
+    /// Whether the move started and ended inside a solid.
+    all_solid: bool,
+}
+
+/// Traces a move through a hull, stopping only at solid surfaces.
+fn trace_move(
+    hull: &BspCollisionHull,
+    start: Vector3<f32>,
+    end: Vector3<f32>,
+) -> Result<MoveTrace, BspError> {
+    let clear = MoveTrace {
+        fraction: 1.0,
+        end,
+        normal: None,
+        all_solid: false,
+    };
+
+    let delta = end - start;
+    let length2 = delta.magnitude2();
+    if length2 == 0.0 {
+        return Ok(clear);
+    }
+
+    let dir = delta.normalize();
+    let mut from = start;
+    for _ in 0..MAX_TRACE_STEPS {
+        let trace = hull.trace(from, end)?;
+        if trace.all_solid() {
+            return Ok(MoveTrace {
+                fraction: 0.0,
+                end: start,
+                normal: None,
+                all_solid: true,
+            });
+        }
+
+        let boundary = match trace.end().kind() {
+            TraceEndKind::Terminal => return Ok(clear),
+            TraceEndKind::Boundary(b) => b,
+        };
+
+        // the boundary plane faces back toward the start of the trace
+        let point = trace.end_point();
+        let normal = boundary.plane.normal();
+        if hull.contents_at_point(point - normal * DIST_EPSILON)? != BspLeafContents::Solid {
+            // crossed into a liquid or back out of one, keep going
+            if (end - point).magnitude2() <= DIST_EPSILON * DIST_EPSILON {
+                return Ok(clear);
+            }
+
+            from = point + dir * DIST_EPSILON;
+            continue;
+        }
+
+        // stop just short of the surface
+        let mut stop = point + normal * DIST_EPSILON;
+        if hull.contents_at_point(stop)? == BspLeafContents::Solid {
+            stop = from;
+        }
+
+        return Ok(MoveTrace {
+            fraction: ((stop - start).dot(delta) / length2).clamp(0.0, 1.0),
+            end: stop,
+            normal: Some(normal),
+            all_solid: false,
+        });
+    }
+
+    Ok(MoveTrace {
+        fraction: ((from - start).dot(delta) / length2).clamp(0.0, 1.0),
+        end: from,
+        normal: None,
+        all_solid: false,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // a solid block filling everything below z = 0
+    fn floor() -> BspCollisionHull {
+        BspCollisionHull::for_bounds(
+            Vector3::new(-4096.0, -4096.0, -4096.0),
+            Vector3::new(4096.0, 4096.0, 0.0),
+        )
+        .unwrap()
+    }
+
+    fn floor_hulls() -> PlayerHulls {
+        PlayerHulls::new(floor(), floor())
+    }
+
+    fn input(forward: f32, jump: bool) -> MoveInput {
+        MoveInput {
+            angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+            forward,
+            side: 0.0,
+            up: 0.0,
+            jump,
+        }
+    }
+
+    fn run(
+        hulls: &PlayerHulls,
+        vars: &PhysicsVars,
+        state: &mut PlayerState,
+        input: &MoveInput,
+        frames: usize,
+    ) {
+        for _ in 0..frames {
+            player_move(hulls, vars, state, input, Duration::milliseconds(10)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_fall_and_land() {
+        let hulls = floor_hulls();
+        let vars = PhysicsVars::default();
+        let mut state = PlayerState::new(Vector3::new(0.0, 0.0, 64.0), Vector3::zero(), false);
+
+        run(&hulls, &vars, &mut state, &input(0.0, false), 100);
+
+        assert!(state.on_ground);
+        assert!(state.origin.z > 0.0 && state.origin.z < 1.0);
+        assert_eq!(state.velocity, Vector3::zero());
+    }
+
+    #[test]
+    fn test_noclip_passes_through_world() {
+        let vars = PhysicsVars::default();
+        let mut state = PlayerState::new(Vector3::new(0.0, 0.0, 0.5), Vector3::zero(), true);
+        let mut down = input(0.0, false);
+        down.up = -100.0;
+
+        noclip_move(&vars, &mut state, &down, Duration::seconds(1));
+
+        assert!(!state.on_ground);
+        assert_eq!(state.origin, Vector3::new(0.0, 0.0, -99.5));
+        assert_eq!(state.velocity, Vector3::new(0.0, 0.0, -100.0));
+    }
+
+    #[test]
+    fn test_walk_reaches_max_speed() {
+        let hulls = floor_hulls();
+        let vars = PhysicsVars::default();
+        let mut state = PlayerState::new(Vector3::new(0.0, 0.0, 0.5), Vector3::zero(), true);
+
+        run(&hulls, &vars, &mut state, &input(400.0, false), 100);
+
+        assert!(state.on_ground);
+        assert!((state.velocity.x - vars.sv_maxspeed).abs() < 1.0);
+        assert!(state.origin.x > 0.0);
+        assert!(state.origin.z > 0.0);
+    }
+
+    #[test]
+    fn test_friction_stops_player() {
+        let hulls = floor_hulls();
+        let vars = PhysicsVars::default();
+        let mut state = PlayerState::new(
+            Vector3::new(0.0, 0.0, 0.5),
+            Vector3::new(320.0, 0.0, 0.0),
+            true,
+        );
+
+        run(&hulls, &vars, &mut state, &input(0.0, false), 100);
+
+        assert_eq!(state.velocity, Vector3::zero());
+    }
+
+    #[test]
+    fn test_jump_requires_release() {
+        let hulls = floor_hulls();
+        let vars = PhysicsVars::default();
+        let mut state = PlayerState::new(Vector3::new(0.0, 0.0, 0.5), Vector3::zero(), true);
+
+        run(&hulls, &vars, &mut state, &input(0.0, true), 1);
+        assert!(!state.on_ground);
+        assert!(state.velocity.z > 0.0);
+
+        // holding jump after landing doesn't jump again
+        run(&hulls, &vars, &mut state, &input(0.0, true), 100);
+        assert!(state.on_ground);
+        assert_eq!(state.velocity.z, 0.0);
+
+        run(&hulls, &vars, &mut state, &input(0.0, false), 1);
+        run(&hulls, &vars, &mut state, &input(0.0, true), 1);
+        assert!(state.velocity.z > 0.0);
+    }
+
+    #[test]
+    fn test_wall_blocks_movement() {
+        // a wall filling everything beyond x = 100
+        let wall = BspCollisionHull::for_bounds(
+            Vector3::new(100.0, -4096.0, -4096.0),
+            Vector3::new(4096.0, 4096.0, 4096.0),
+        )
+        .unwrap();
+        let hulls = PlayerHulls::new(floor(), wall);
+        let vars = PhysicsVars {
+            sv_gravity: 0.0,
+            ..Default::default()
+        };
+        let mut state = PlayerState::new(
+            Vector3::new(0.0, 0.0, 64.0),
+            Vector3::new(500.0, 100.0, 0.0),
+            false,
+        );
+
+        run(&hulls, &vars, &mut state, &input(0.0, false), 100);
+
+        assert!(state.origin.x < 100.0 && state.origin.x > 99.0);
+        assert_eq!(state.velocity.x, 0.0);
+        assert!(state.velocity.y > 0.0);
+    }
+}
@@ -16,49 +16,157 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
 use std::{
-    fs::File,
+    borrow::Cow,
+    cell::RefCell,
+    collections::BTreeMap,
+    fs::{self, File},
     io::{self, BufReader, Cursor, Read, Seek, SeekFrom},
-    path::{Path, PathBuf},
+    ops::Range,
+    path::{Component, Path, PathBuf},
+    rc::Rc,
 };
 
-use crate::common::pak::{Pak, PakError};
+use crate::common::{
+    pak::{Pak, PakError},
+    pk3::{Pk3, Pk3Error},
+};
 
+use glob::{MatchOptions, Pattern};
 use thiserror::Error;
 
+/// The directory holding the original game's data, which mods are layered
+/// over.
+pub const BASE_GAME: &str = "id1";
+
 #[derive(Error, Debug)]
 pub enum VfsError {
     #[error("Couldn't load pakfile: {0}")]
     Pak(#[from] PakError),
+    #[error("Couldn't load PK3 archive: {0}")]
+    Pk3(#[from] Pk3Error),
     #[error("File does not exist: {0}")]
     NoSuchFile(String),
+    #[error("Game directory does not exist: {0}")]
+    NoSuchGame(String),
+    #[error("Invalid game directory name: {0}")]
+    InvalidGame(String),
+    #[error("No base directory to load games from")]
+    NoBaseDir,
+    #[error("No game directory to write to")]
+    NoGameDir,
+    #[error("File names must be relative to the game directory: {0}")]
+    InvalidFileName(String),
+    #[error("Invalid pattern: {0}")]
+    Pattern(#[from] glob::PatternError),
 }
 
 #[derive(Debug)]
 enum VfsComponent {
-    Pak(Pak),
+    Pak { path: PathBuf, pak: Pak },
+    Pk3 { path: PathBuf, pk3: Pk3 },
     Directory(PathBuf),
 }
 
+impl VfsComponent {
+    fn pak<P>(path: P) -> Result<VfsComponent, VfsError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        Ok(VfsComponent::Pak {
+            path: path.to_path_buf(),
+            pak: Pak::new(path)?,
+        })
+    }
+
+    fn pk3<P>(path: P) -> Result<VfsComponent, VfsError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        Ok(VfsComponent::Pk3 {
+            path: path.to_path_buf(),
+            pk3: Pk3::new(path)?,
+        })
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            VfsComponent::Pak { path, .. } => path,
+            VfsComponent::Pk3 { path, .. } => path,
+            VfsComponent::Directory(path) => path,
+        }
+    }
+
+    // the raw contents of an archive
+    fn data(&self) -> &[u8] {
+        match self {
+            VfsComponent::Pak { pak, .. } => pak.data(),
+            VfsComponent::Pk3 { pk3, .. } => pk3.data(),
+            VfsComponent::Directory(_) => &[],
+        }
+    }
+}
+
+/// A file found by [`Vfs::list`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VfsEntry {
+    /// The file's normalized virtual path.
+    pub path: String,
+
+    /// The archive or directory the file is read from.
+    pub source: PathBuf,
+}
+
+/// An archive or directory in the search path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VfsSource {
+    pub path: PathBuf,
+
+    /// The number of files in the archive, or `None` for a directory.
+    pub file_count: Option<usize>,
+}
+
+/// Normalizes a virtual path for lookup by lowercasing ASCII letters and
+/// replacing backslashes with forward slashes.
+pub fn normalize_path(path: &str) -> String {
+    path.replace('\\', "/").to_ascii_lowercase()
+}
+
+/// A layered view of the game's files.
+///
+/// Components added later take priority over earlier ones. The components can
+/// be replaced while the filesystem is shared, which is how the `game` command
+/// switches mods without restarting the engine.
 #[derive(Debug)]
 pub struct Vfs {
-    components: Vec<VfsComponent>,
+    // the directory containing `id1/` and any mod directories
+    base_dir: Option<PathBuf>,
+
+    // the mod layered over `id1/`, if any
+    game: RefCell<Option<String>>,
+
+    components: RefCell<Vec<Rc<VfsComponent>>>,
 }
 
 impl Vfs {
     pub fn new() -> Vfs {
         Vfs {
-            components: Vec::new(),
+            base_dir: None,
+            game: RefCell::new(None),
+            components: RefCell::new(Vec::new()),
         }
     }
 
     /// Initializes the virtual filesystem using a base directory.
     pub fn with_base_dir(base_dir: PathBuf) -> Vfs {
-        let mut vfs = Vfs::new();
-
-        let mut game_dir = base_dir;
-        game_dir.push("id1");
+        Vfs::with_game(base_dir, None)
+    }
 
-        if !game_dir.is_dir() {
+    /// Initializes the virtual filesystem using a base directory, with the
+    /// given mod directory layered over `id1/`.
+    pub fn with_game(base_dir: PathBuf, game: Option<&str>) -> Vfs {
+        if !base_dir.join(BASE_GAME).is_dir() {
             log::error!(concat!(
                 "`id1/` directory does not exist! Use the `--base-dir` option with the name of the",
                 " directory which contains `id1/`."
@@ -67,45 +175,77 @@ impl Vfs {
             std::process::exit(1);
         }
 
-        vfs.add_directory(&game_dir).unwrap();
+        let vfs = Vfs {
+            base_dir: Some(base_dir),
+            ..Vfs::new()
+        };
 
-        // ...then add PAK archives.
-        let mut num_paks = 0;
-        let mut pak_path = game_dir;
-        for vfs_id in 0..crate::common::MAX_PAKFILES {
-            // Add the file name.
-            pak_path.push(format!("pak{}.pak", vfs_id));
-
-            // Keep adding PAKs until we don't find one or we hit MAX_PAKFILES.
-            if !pak_path.exists() {
-                // If the lowercase path doesn't exist, try again with uppercase.
-                pak_path.pop();
-                pak_path.push(format!("PAK{}.PAK", vfs_id));
-                if !pak_path.exists() {
-                    break;
-                }
-            }
+        if let Err(e) = vfs.set_game(game) {
+            log::error!("{}", e);
+            std::process::exit(1);
+        }
+
+        vfs
+    }
 
-            vfs.add_pakfile(&pak_path).unwrap();
-            num_paks += 1;
+    /// Returns the name of the mod layered over `id1/`, if any.
+    pub fn game(&self) -> Option<String> {
+        self.game.borrow().clone()
+    }
+
+    /// Rebuilds the filesystem from `id1/` and the given mod directory.
+    ///
+    /// Passing `None` or `id1` removes the current mod. Files which are already
+    /// open remain readable. If the new filesystem can't be built, the old one
+    /// is left in place.
+    pub fn set_game(&self, game: Option<&str>) -> Result<(), VfsError> {
+        let base_dir = self.base_dir.as_ref().ok_or(VfsError::NoBaseDir)?;
 
-            // Remove the file name, leaving the game directory.
-            pak_path.pop();
+        let game = game.filter(|g| !g.eq_ignore_ascii_case(BASE_GAME));
+        if let Some(g) = game {
+            if g.is_empty() || g.contains(['/', '\\', ':']) || g.contains("..") {
+                return Err(VfsError::InvalidGame(g.to_owned()));
+            }
         }
 
-        if num_paks == 0 {
+        let mut components = game_components(&base_dir.join(BASE_GAME))?;
+        if components.len() == 1 {
             log::warn!("No PAK files found.");
         }
 
-        vfs
+        if let Some(g) = game {
+            let game_dir = base_dir.join(g);
+            if !game_dir.is_dir() {
+                return Err(VfsError::NoSuchGame(g.to_owned()));
+            }
+
+            components.extend(game_components(&game_dir)?);
+        }
+
+        self.components
+            .replace(components.into_iter().map(Rc::new).collect());
+        self.game.replace(game.map(|g| g.to_owned()));
+
+        Ok(())
     }
 
     pub fn add_pakfile<P>(&mut self, path: P) -> Result<(), VfsError>
     where
         P: AsRef<Path>,
     {
-        let path = path.as_ref();
-        self.components.push(VfsComponent::Pak(Pak::new(path)?));
+        self.components
+            .get_mut()
+            .push(Rc::new(VfsComponent::pak(path)?));
+        Ok(())
+    }
+
+    pub fn add_pk3file<P>(&mut self, path: P) -> Result<(), VfsError>
+    where
+        P: AsRef<Path>,
+    {
+        self.components
+            .get_mut()
+            .push(Rc::new(VfsComponent::pk3(path)?));
         Ok(())
     }
 
@@ -114,30 +254,139 @@ impl Vfs {
         P: AsRef<Path>,
     {
         self.components
-            .push(VfsComponent::Directory(path.as_ref().to_path_buf()));
+            .get_mut()
+            .push(Rc::new(VfsComponent::Directory(
+                path.as_ref().to_path_buf(),
+            )));
         Ok(())
     }
 
-    pub fn open<S>(&self, virtual_path: S) -> Result<VirtualFile, VfsError>
+    /// Returns the most recently added directory, where files written by the
+    /// engine are placed.
+    ///
+    /// When a mod is loaded, this is the mod's directory.
+    pub fn game_dir(&self) -> Option<PathBuf> {
+        self.components
+            .borrow()
+            .iter()
+            .rev()
+            .find_map(|c| match **c {
+                VfsComponent::Directory(ref path) => Some(path.clone()),
+                _ => None,
+            })
+    }
+
+    /// Returns where a file named in a console command is written.
+    ///
+    /// `name` is relative to the game directory and may not leave it, so
+    /// absolute paths and `.` or `..` components are rejected.
+    pub fn game_file_path(&self, name: &str) -> Result<PathBuf, VfsError> {
+        let path = Path::new(name);
+        let mut components = path.components().peekable();
+        if components.peek().is_none() || !components.all(|c| matches!(c, Component::Normal(_))) {
+            return Err(VfsError::InvalidFileName(name.to_owned()));
+        }
+
+        Ok(self.game_dir().ok_or(VfsError::NoGameDir)?.join(path))
+    }
+
+    /// Returns the archives and directories files are read from, highest
+    /// priority first.
+    pub fn search_path(&self) -> Vec<VfsSource> {
+        self.components
+            .borrow()
+            .iter()
+            .rev()
+            .map(|c| VfsSource {
+                path: c.path().to_path_buf(),
+                file_count: match **c {
+                    VfsComponent::Pak { ref pak, .. } => Some(pak.iter().count()),
+                    VfsComponent::Pk3 { ref pk3, .. } => Some(pk3.paths().count()),
+                    VfsComponent::Directory(_) => None,
+                },
+            })
+            .collect()
+    }
+
+    /// Lists the files whose virtual paths match a glob pattern, sorted by
+    /// path.
+    ///
+    /// Matching ignores case, and wildcards don't match `/`, so `maps/*.bsp`
+    /// lists the maps but not files in subdirectories of `maps/`. Each file is
+    /// listed once, along with the component `open` would read it from.
+    pub fn list(&self, pattern: &str) -> Result<Vec<VfsEntry>, VfsError> {
+        let pattern = Pattern::new(&normalize_path(pattern))?;
+        let options = MatchOptions {
+            case_sensitive: false,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
+
+        // files in a directory can only match if they're no deeper than the
+        // pattern
+        let depth = pattern.as_str().split('/').count();
+
+        let mut files = BTreeMap::new();
+        for c in self.components.borrow().iter().rev() {
+            let paths: Vec<String> = match **c {
+                VfsComponent::Pak { ref pak, .. } => {
+                    pak.iter().map(|(path, _)| normalize_path(path)).collect()
+                }
+                VfsComponent::Pk3 { ref pk3, .. } => pk3.paths().map(|p| p.to_owned()).collect(),
+                VfsComponent::Directory(ref dir) => {
+                    let mut paths = Vec::new();
+                    list_dir(dir, "", depth, &mut paths);
+                    paths
+                }
+            };
+
+            for path in paths {
+                if pattern.matches_with(&path, options) {
+                    files.entry(path).or_insert_with(|| c.path().to_path_buf());
+                }
+            }
+        }
+
+        Ok(files
+            .into_iter()
+            .map(|(path, source)| VfsEntry { path, source })
+            .collect())
+    }
+
+    /// Opens a file for reading.
+    ///
+    /// Paths are matched without regard to case, and may use either forward
+    /// slashes or backslashes.
+    pub fn open<S>(&self, virtual_path: S) -> Result<VirtualFile<'static>, VfsError>
     where
         S: AsRef<str>,
     {
         let vp = virtual_path.as_ref();
 
         // iterate in reverse so later PAKs overwrite earlier ones
-        for c in self.components.iter().rev() {
-            match c {
-                VfsComponent::Pak(pak) => {
+        for c in self.components.borrow().iter().rev() {
+            match **c {
+                VfsComponent::Pak { ref pak, .. } => {
                     if let Ok(f) = pak.open(vp) {
-                        return Ok(VirtualFile::PakBacked(Cursor::new(f)));
+                        let f = ArchiveFile::new(c, f);
+                        return Ok(VirtualFile::ArchiveBacked(Cursor::new(f)));
                     }
                 }
 
-                VfsComponent::Directory(path) => {
-                    let mut full_path = path.to_owned();
-                    full_path.push(vp);
+                VfsComponent::Pk3 { ref pk3, .. } => match pk3.open(vp) {
+                    Ok(Cow::Borrowed(f)) => {
+                        let f = ArchiveFile::new(c, f);
+                        return Ok(VirtualFile::ArchiveBacked(Cursor::new(f)));
+                    }
+                    Ok(Cow::Owned(f)) => {
+                        return Ok(VirtualFile::MemoryBacked(Cursor::new(Cow::Owned(f))))
+                    }
+                    Err(Pk3Error::NoSuchFile(_)) => (),
+                    Err(e) => log::warn!("{}", e),
+                },
 
-                    if let Ok(f) = File::open(full_path) {
+                VfsComponent::Directory(ref path) => {
+                    if let Some(f) = find_file(path, vp).and_then(|p| File::open(p).ok()) {
                         return Ok(VirtualFile::FileBacked(BufReader::new(f)));
                     }
                 }
@@ -148,15 +397,125 @@ impl Vfs {
     }
 }
 
+// the directory itself, then its PAK archives in numerical order, then its PK3
+// archives in alphabetical order
+fn game_components(game_dir: &Path) -> Result<Vec<VfsComponent>, VfsError> {
+    let mut components = vec![VfsComponent::Directory(game_dir.to_path_buf())];
+
+    // Keep adding PAKs until we don't find one or we hit MAX_PAKFILES.
+    for vfs_id in 0..crate::common::MAX_PAKFILES {
+        match find_file(game_dir, &format!("pak{}.pak", vfs_id)) {
+            Some(pak_path) => components.push(VfsComponent::pak(pak_path)?),
+            None => break,
+        }
+    }
+
+    let mut pk3_paths: Vec<PathBuf> = match fs::read_dir(game_dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("pk3"))
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    pk3_paths.sort_by_key(|p| p.to_string_lossy().to_lowercase());
+
+    for path in pk3_paths.iter() {
+        components.push(VfsComponent::pk3(path)?);
+    }
+
+    Ok(components)
+}
+
+// finds the file in `dir` whose path matches `virtual_path` without regard to
+// case
+fn find_file(dir: &Path, virtual_path: &str) -> Option<PathBuf> {
+    // most files are named exactly as requested, so try that first
+    let exact = dir.join(virtual_path);
+    if exact.is_file() {
+        return Some(exact);
+    }
+
+    let mut path = dir.to_path_buf();
+    for component in virtual_path.split(['/', '\\']).filter(|c| !c.is_empty()) {
+        let entry = fs::read_dir(&path).ok()?.filter_map(|e| e.ok()).find(|e| {
+            e.file_name()
+                .to_str()
+                .is_some_and(|name| name.eq_ignore_ascii_case(component))
+        })?;
+        path = entry.path();
+    }
+
+    path.is_file().then_some(path)
+}
+
+// collects the normalized paths of the files in `dir`, descending at most
+// `depth` levels. `prefix` is the virtual path of `dir` itself
+fn list_dir(dir: &Path, prefix: &str, depth: usize, paths: &mut Vec<String>) {
+    if depth == 0 {
+        return;
+    }
+
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let name = match entry.file_name().into_string() {
+            Ok(n) => normalize_path(&n),
+            Err(_) => continue,
+        };
+
+        let path = entry.path();
+        if path.is_dir() {
+            list_dir(&path, &format!("{}{}/", prefix, name), depth - 1, paths);
+        } else {
+            paths.push(format!("{}{}", prefix, name));
+        }
+    }
+}
+
+/// A file stored uncompressed in an archive.
+///
+/// The file keeps its archive open, so it can still be read after the
+/// filesystem it came from has been rebuilt.
+#[derive(Debug)]
+pub struct ArchiveFile {
+    archive: Rc<VfsComponent>,
+    range: Range<usize>,
+}
+
+impl ArchiveFile {
+    // `contents` must be borrowed from the archive's data
+    fn new(archive: &Rc<VfsComponent>, contents: &[u8]) -> ArchiveFile {
+        let start = contents.as_ptr() as usize - archive.data().as_ptr() as usize;
+        ArchiveFile {
+            archive: archive.clone(),
+            range: start..start + contents.len(),
+        }
+    }
+}
+
+impl AsRef<[u8]> for ArchiveFile {
+    fn as_ref(&self) -> &[u8] {
+        &self.archive.data()[self.range.clone()]
+    }
+}
+
 pub enum VirtualFile<'a> {
-    PakBacked(Cursor<&'a [u8]>),
+    ArchiveBacked(Cursor<ArchiveFile>),
+    MemoryBacked(Cursor<Cow<'a, [u8]>>),
     FileBacked(BufReader<File>),
 }
 
 impl<'a> Read for VirtualFile<'a> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match self {
-            VirtualFile::PakBacked(curs) => curs.read(buf),
+            VirtualFile::ArchiveBacked(curs) => curs.read(buf),
+            VirtualFile::MemoryBacked(curs) => curs.read(buf),
             VirtualFile::FileBacked(file) => file.read(buf),
         }
     }
@@ -165,8 +524,222 @@ impl<'a> Read for VirtualFile<'a> {
 impl<'a> Seek for VirtualFile<'a> {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
         match self {
-            VirtualFile::PakBacked(curs) => curs.seek(pos),
+            VirtualFile::ArchiveBacked(curs) => curs.seek(pos),
+            VirtualFile::MemoryBacked(curs) => curs.seek(pos),
             VirtualFile::FileBacked(file) => file.seek(pos),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{pak::test::pak_fixture, pk3::test::zip_fixture};
+
+    fn read(vfs: &Vfs, path: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        vfs.open(path).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_pk3_priority() {
+        let base_dir = std::env::temp_dir().join(format!("richter-vfs-{}", std::process::id()));
+        let game_dir = base_dir.join("id1");
+        fs::create_dir_all(&game_dir).unwrap();
+
+        fs::write(game_dir.join("autoexec.cfg"), b"loose").unwrap();
+        fs::write(
+            game_dir.join("b.pk3"),
+            zip_fixture(&[("autoexec.cfg", b"b", true), ("b.cfg", b"b", false)]),
+        )
+        .unwrap();
+        fs::write(
+            game_dir.join("A.PK3"),
+            zip_fixture(&[("autoexec.cfg", b"a", false), ("a.cfg", b"a", true)]),
+        )
+        .unwrap();
+        fs::write(game_dir.join("notes.txt"), b"not an archive").unwrap();
+
+        let vfs = Vfs::with_base_dir(base_dir.clone());
+
+        // later archives override earlier ones, and archives override loose
+        // files
+        assert_eq!(read(&vfs, "autoexec.cfg"), b"b");
+        assert_eq!(read(&vfs, "a.cfg"), b"a");
+        assert_eq!(read(&vfs, "b.cfg"), b"b");
+        assert_eq!(read(&vfs, "notes.txt"), b"not an archive");
+        assert!(matches!(
+            vfs.open("missing.cfg"),
+            Err(VfsError::NoSuchFile(_))
+        ));
+
+        drop(vfs);
+        fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_game_layering() {
+        let base_dir = std::env::temp_dir().join(format!("richter-game-{}", std::process::id()));
+        let id1_dir = base_dir.join("id1");
+        let mod_dir = base_dir.join("mymod");
+        fs::create_dir_all(&id1_dir).unwrap();
+        fs::create_dir_all(&mod_dir).unwrap();
+
+        fs::write(id1_dir.join("quake.rc"), b"id1").unwrap();
+        fs::write(
+            id1_dir.join("pak0.pk3"),
+            zip_fixture(&[("progs.dat", b"id1", false), ("gfx.wad", b"id1", true)]),
+        )
+        .unwrap();
+        fs::write(mod_dir.join("progs.dat"), b"mod").unwrap();
+        fs::write(
+            mod_dir.join("mod.pk3"),
+            zip_fixture(&[("gfx.wad", b"mod", false)]),
+        )
+        .unwrap();
+
+        // the mod's loose files and archives override id1's
+        let vfs = Vfs::with_game(base_dir.clone(), Some("mymod"));
+        assert_eq!(vfs.game().as_deref(), Some("mymod"));
+        assert_eq!(vfs.game_dir(), Some(mod_dir.clone()));
+        assert_eq!(read(&vfs, "progs.dat"), b"mod");
+        assert_eq!(read(&vfs, "gfx.wad"), b"mod");
+        assert_eq!(read(&vfs, "quake.rc"), b"id1");
+
+        // files stay readable after the filesystem is rebuilt
+        let mut gfx_wad = vfs.open("gfx.wad").unwrap();
+        vfs.set_game(None).unwrap();
+        let mut data = Vec::new();
+        gfx_wad.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"mod");
+
+        assert_eq!(vfs.game(), None);
+        assert_eq!(vfs.game_dir(), Some(id1_dir));
+        assert_eq!(read(&vfs, "progs.dat"), b"id1");
+        assert_eq!(read(&vfs, "gfx.wad"), b"id1");
+
+        // a failed switch leaves the filesystem as it was
+        vfs.set_game(Some("mymod")).unwrap();
+        assert!(matches!(
+            vfs.set_game(Some("missing")),
+            Err(VfsError::NoSuchGame(_))
+        ));
+        assert!(matches!(
+            vfs.set_game(Some("../mymod")),
+            Err(VfsError::InvalidGame(_))
+        ));
+        assert_eq!(read(&vfs, "progs.dat"), b"mod");
+
+        drop(gfx_wad);
+        drop(vfs);
+        fs::remove_dir_all(&base_dir).unwrap();
+    }
+
+    #[test]
+    fn test_game_file_path() {
+        let mut vfs = Vfs::new();
+        assert!(matches!(
+            vfs.game_file_path("demo1.dem"),
+            Err(VfsError::NoGameDir)
+        ));
+
+        let game_dir = PathBuf::from("quake").join("id1");
+        vfs.add_directory(&game_dir).unwrap();
+        assert_eq!(
+            vfs.game_file_path("demos/demo1.dem").unwrap(),
+            game_dir.join("demos").join("demo1.dem")
+        );
+
+        for name in [
+            "",
+            "/tmp/demo1.dem",
+            "../demo1.dem",
+            "demos/../../x",
+            "./demo1",
+        ] {
+            assert!(
+                matches!(vfs.game_file_path(name), Err(VfsError::InvalidFileName(_))),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_case_insensitive_list() {
+        let base_dir = std::env::temp_dir().join(format!("richter-list-{}", std::process::id()));
+        let game_dir = base_dir.join("id1");
+        fs::create_dir_all(game_dir.join("Maps/sub")).unwrap();
+
+        fs::write(
+            game_dir.join("PAK0.PAK"),
+            pak_fixture(
+                &[
+                    ("progs.dat", b"pak"),
+                    ("maps/e1m1.bsp", b"pak"),
+                    ("gfx/palette.lmp", b"pak"),
+                ],
+                false,
+            ),
+        )
+        .unwrap();
+        fs::write(
+            game_dir.join("zz.pk3"),
+            zip_fixture(&[
+                ("maps/E1M1.bsp", b"pk3", false),
+                ("maps/start.bsp", b"pk3", true),
+            ]),
+        )
+        .unwrap();
+        fs::write(game_dir.join("Maps/E1M2.BSP"), b"loose").unwrap();
+        fs::write(game_dir.join("Maps/sub/e1m3.bsp"), b"loose").unwrap();
+
+        let vfs = Vfs::with_base_dir(base_dir.clone());
+
+        assert_eq!(read(&vfs, "maps/e1m2.bsp"), b"loose");
+        assert_eq!(read(&vfs, "MAPS\\E1M1.BSP"), b"pk3");
+        assert_eq!(read(&vfs, "Progs.Dat"), b"pak");
+
+        let entry = |path: &str, source: &str| VfsEntry {
+            path: path.to_owned(),
+            source: game_dir.join(source),
+        };
+
+        // wildcards don't descend into subdirectories
+        assert_eq!(
+            vfs.list("MAPS/*.bsp").unwrap(),
+            [
+                entry("maps/e1m1.bsp", "zz.pk3"),
+                entry("maps/e1m2.bsp", ""),
+                entry("maps/start.bsp", "zz.pk3"),
+            ]
+        );
+        assert_eq!(vfs.list("*.dat").unwrap(), [entry("progs.dat", "PAK0.PAK")]);
+        assert!(matches!(
+            vfs.list("maps/[").unwrap_err(),
+            VfsError::Pattern(_)
+        ));
+
+        assert_eq!(
+            vfs.search_path(),
+            [
+                VfsSource {
+                    path: game_dir.join("zz.pk3"),
+                    file_count: Some(2),
+                },
+                VfsSource {
+                    path: game_dir.join("PAK0.PAK"),
+                    file_count: Some(3),
+                },
+                VfsSource {
+                    path: game_dir.clone(),
+                    file_count: None,
+                },
+            ]
+        );
+
+        drop(vfs);
+        fs::remove_dir_all(&base_dir).unwrap();
+    }
+}
@@ -0,0 +1,325 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Half-Life WAD3 texture archive reading.
+//!
+//! WAD3 archives share the layout of Quake's WAD2 archives, but hold mip
+//! textures which carry their own 256-color palette. Half-Life maps usually
+//! store only the names of their textures and load them from the archives
+//! listed in the worldspawn entity's `wad` key.
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Seek, SeekFrom},
+};
+
+use crate::common::bsp::MIPLEVELS;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use thiserror::Error;
+
+const MAGIC: [u8; 4] = *b"WAD3";
+const LUMPINFO_SIZE: usize = 32;
+const LUMP_NAME_SIZE: usize = 16;
+
+// the lump type of a mip texture
+pub(crate) const TYPE_MIPTEX: u8 = 0x43;
+
+// name, width, height and 4 mipmap offsets
+const MIPTEX_HEADER_SIZE: usize = LUMP_NAME_SIZE + 4 * 2 + 4 * MIPLEVELS;
+const PALETTE_COLORS: usize = 256;
+
+#[derive(Error, Debug)]
+pub enum Wad3Error {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid magic number")]
+    InvalidMagicNumber,
+    #[error("Invalid lump table")]
+    InvalidLumpTable,
+    #[error("Compressed lump: {0}")]
+    Compressed(String),
+    #[error("No such texture in WAD: {0}")]
+    NoSuchTexture(String),
+    #[error("Invalid texture: {0}")]
+    InvalidTexture(String),
+}
+
+/// A mip texture with its own palette.
+#[derive(Debug)]
+pub struct Wad3Texture {
+    pub(crate) name: String,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) mipmaps: [Vec<u8>; MIPLEVELS],
+    pub(crate) palette: Box<[u8]>,
+}
+
+impl Wad3Texture {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns the palette indices of the given mipmap level.
+    pub fn mipmap(&self, level: usize) -> &[u8] {
+        &self.mipmaps[level]
+    }
+
+    /// Returns the texture's palette as 256 RGB triples.
+    pub fn palette(&self) -> &[u8] {
+        &self.palette
+    }
+}
+
+/// A WAD3 archive.
+///
+/// Textures are looked up by name without regard to case.
+#[derive(Debug)]
+pub struct Wad3 {
+    // keyed by lowercase name
+    textures: HashMap<String, Box<[u8]>>,
+}
+
+impl Wad3 {
+    pub fn load<R>(mut reader: R) -> Result<Wad3, Wad3Error>
+    where
+        R: Read + Seek,
+    {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(Wad3Error::InvalidMagicNumber);
+        }
+
+        let lump_count = reader.read_i32::<LittleEndian>()?;
+        let lumpinfo_ofs = reader.read_i32::<LittleEndian>()?;
+        if lump_count < 0 || lumpinfo_ofs < 0 {
+            return Err(Wad3Error::InvalidLumpTable);
+        }
+
+        reader.seek(SeekFrom::Start(lumpinfo_ofs as u64))?;
+        let mut lumpinfo = vec![0; lump_count as usize * LUMPINFO_SIZE];
+        reader.read_exact(&mut lumpinfo)?;
+
+        let mut textures = HashMap::new();
+        for info in lumpinfo.chunks(LUMPINFO_SIZE) {
+            let mut info = io::Cursor::new(info);
+            let offset = info.read_u32::<LittleEndian>()?;
+            let disk_size = info.read_u32::<LittleEndian>()?;
+            let _size = info.read_u32::<LittleEndian>()?;
+            let kind = info.read_u8()?;
+            let compression = info.read_u8()?;
+            let _pad = info.read_u16::<LittleEndian>()?;
+            let mut name_bytes = [0; LUMP_NAME_SIZE];
+            info.read_exact(&mut name_bytes)?;
+            let name = read_name(&name_bytes).to_lowercase();
+
+            // other lumps (fonts, pictures) aren't used by maps
+            if kind != TYPE_MIPTEX {
+                continue;
+            }
+
+            if compression != 0 {
+                return Err(Wad3Error::Compressed(name));
+            }
+
+            let mut data = Vec::with_capacity(disk_size as usize);
+            reader.seek(SeekFrom::Start(offset as u64))?;
+            (&mut reader)
+                .take(disk_size as u64)
+                .read_to_end(&mut data)?;
+            if data.len() != disk_size as usize {
+                return Err(Wad3Error::InvalidLumpTable);
+            }
+
+            // keep the first of any duplicates
+            textures
+                .entry(name)
+                .or_insert_with(|| data.into_boxed_slice());
+        }
+
+        Ok(Wad3 { textures })
+    }
+
+    /// Returns the names of the textures in the archive, in lowercase.
+    pub fn texture_names(&self) -> impl Iterator<Item = &str> {
+        self.textures.keys().map(|k| k.as_str())
+    }
+
+    /// Returns whether the archive contains a texture.
+    pub fn contains<S>(&self, name: S) -> bool
+    where
+        S: AsRef<str>,
+    {
+        self.textures.contains_key(&name.as_ref().to_lowercase())
+    }
+
+    pub fn open_texture<S>(&self, name: S) -> Result<Wad3Texture, Wad3Error>
+    where
+        S: AsRef<str>,
+    {
+        let name = name.as_ref().to_lowercase();
+        match self.textures.get(&name) {
+            Some(data) => read_miptex(data).ok_or(Wad3Error::InvalidTexture(name)),
+            None => Err(Wad3Error::NoSuchTexture(name)),
+        }
+    }
+}
+
+// convert a NUL-padded name to a str, stopping at the first NUL.
+fn read_name(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+// parse a mip texture, returning None if any part of it is out of bounds.
+//
+// the palette follows the last mipmap as a 16-bit color count and the colors.
+fn read_miptex(data: &[u8]) -> Option<Wad3Texture> {
+    if data.len() < MIPTEX_HEADER_SIZE {
+        return None;
+    }
+
+    let name = read_name(&data[..LUMP_NAME_SIZE]).to_lowercase();
+    let u32_at = |ofs: usize| u32::from_le_bytes(data[ofs..ofs + 4].try_into().unwrap());
+    let width = u32_at(LUMP_NAME_SIZE);
+    let height = u32_at(LUMP_NAME_SIZE + 4);
+
+    let mut mipmaps = [Vec::new(), Vec::new(), Vec::new(), Vec::new()];
+    let mut end = MIPTEX_HEADER_SIZE;
+    for (m, mipmap) in mipmaps.iter_mut().enumerate() {
+        let start = u32_at(LUMP_NAME_SIZE + 8 + 4 * m) as usize;
+        let size = (width as usize >> m) * (height as usize >> m);
+        end = start.checked_add(size)?;
+        mipmap.extend_from_slice(data.get(start..end)?);
+    }
+
+    let count = u16::from_le_bytes(data.get(end..end + 2)?.try_into().unwrap()) as usize;
+    if count != PALETTE_COLORS {
+        return None;
+    }
+    let palette = data.get(end + 2..end + 2 + 3 * count)?.into();
+
+    Some(Wad3Texture {
+        name,
+        width,
+        height,
+        mipmaps,
+        palette,
+    })
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Builds a mip texture with a palette, as stored in WAD3 archives and
+    /// Half-Life maps. Pixel `i` of each mipmap has index `i % 256`, and
+    /// palette color `i` is `[i, i, i]`.
+    pub fn miptex_fixture(name: &str, width: u32, height: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut name_bytes = [0; LUMP_NAME_SIZE];
+        name_bytes[..name.len()].copy_from_slice(name.as_bytes());
+        data.extend_from_slice(&name_bytes);
+        data.extend_from_slice(&width.to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+
+        let mut offset = MIPTEX_HEADER_SIZE;
+        for m in 0..MIPLEVELS {
+            data.extend_from_slice(&(offset as u32).to_le_bytes());
+            offset += (width as usize >> m) * (height as usize >> m);
+        }
+
+        for m in 0..MIPLEVELS {
+            let size = (width as usize >> m) * (height as usize >> m);
+            data.extend((0..size).map(|i| i as u8));
+        }
+
+        data.extend_from_slice(&(PALETTE_COLORS as u16).to_le_bytes());
+        data.extend((0..PALETTE_COLORS).flat_map(|i| [i as u8; 3]));
+        data.extend_from_slice(&[0; 2]);
+        data
+    }
+
+    /// Builds a WAD3 archive from lumps given as name, type and data.
+    pub fn wad_fixture(lumps: &[(&str, u8, Vec<u8>)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&MAGIC);
+        data.extend_from_slice(&(lumps.len() as i32).to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+
+        let mut table = Vec::new();
+        for (name, kind, lump) in lumps {
+            table.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            table.extend_from_slice(&(lump.len() as u32).to_le_bytes());
+            table.extend_from_slice(&(lump.len() as u32).to_le_bytes());
+            table.extend_from_slice(&[*kind, 0, 0, 0]);
+            let mut name_bytes = [0; LUMP_NAME_SIZE];
+            name_bytes[..name.len()].copy_from_slice(name.as_bytes());
+            table.extend_from_slice(&name_bytes);
+            data.extend_from_slice(lump);
+        }
+
+        let table_ofs = data.len() as i32;
+        data[8..12].copy_from_slice(&table_ofs.to_le_bytes());
+        data.extend(table);
+        data
+    }
+
+    #[test]
+    fn test_open_texture() {
+        let wad = Wad3::load(Cursor::new(wad_fixture(&[
+            ("CRATE1", TYPE_MIPTEX, miptex_fixture("CRATE1", 16, 8)),
+            ("FONT", 0x46, vec![0; 8]),
+        ])))
+        .unwrap();
+
+        assert_eq!(wad.texture_names().collect::<Vec<_>>(), vec!["crate1"]);
+        assert!(wad.contains("Crate1"));
+
+        let tex = wad.open_texture("crate1").unwrap();
+        assert_eq!((tex.width(), tex.height()), (16, 8));
+        assert_eq!(tex.mipmap(0).len(), 128);
+        assert_eq!(tex.mipmap(3), &[0, 1]);
+        assert_eq!(&tex.palette()[3 * 200..3 * 201], &[200, 200, 200]);
+
+        assert!(matches!(
+            wad.open_texture("font"),
+            Err(Wad3Error::NoSuchTexture(_))
+        ));
+    }
+
+    #[test]
+    fn test_truncated_texture() {
+        let mut miptex = miptex_fixture("crate1", 16, 8);
+        miptex.truncate(miptex.len() - 100);
+        let wad = Wad3::load(Cursor::new(wad_fixture(&[("crate1", TYPE_MIPTEX, miptex)]))).unwrap();
+        assert!(matches!(
+            wad.open_texture("crate1"),
+            Err(Wad3Error::InvalidTexture(_))
+        ));
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright © 2018 Cormac O'Brien
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use crate::common::console::{ConsoleError, CvarRegistry};
+
+/// Server cvars reported in response to rule info queries, in query order.
+pub const RULE_CVARS: &[&str] = &[
+    "sv_maxspeed",
+    "sv_friction",
+    "sv_gravity",
+    "teamplay",
+    "fraglimit",
+    "timelimit",
+    "noexit",
+];
+
+pub fn register_cvars(cvars: &CvarRegistry) -> Result<(), ConsoleError> {
+    cvars.register("coop", "0")?;
+    cvars.register("deathmatch", "0")?;
+    cvars.register("edgefriction", "2")?;
+    cvars.register_notify("fraglimit", "0")?;
+    cvars.register("hostname", "UNNAMED")?;
+    cvars.register("maxplayers", "1")?;
+    cvars.register_notify("noexit", "0")?;
+    cvars.register("pausable", "1")?;
+
+    // remote console is disabled while this is empty
+    cvars.register("rcon_password", "")?;
+    cvars.register("samelevel", "0")?;
+    cvars.register("skill", "1")?;
+    cvars.register("sv_accelerate", "10")?;
+    cvars.register("sv_aim", "0.93")?;
+    cvars.register_notify("sv_friction", "4")?;
+    cvars.register("sv_idealpitchscale", "0.8")?;
+    cvars.register_notify("sv_maxspeed", "320")?;
+    cvars.register("sv_maxvelocity", "2000")?;
+    cvars.register("sv_nostep", "0")?;
+    cvars.register("sv_protocol", "15")?;
+    cvars.register("sv_stopspeed", "100")?;
+    cvars.register("sys_ticrate", "0.05")?;
+    cvars.register_notify("teamplay", "0")?;
+    cvars.register("temp1", "0")?;
+    cvars.register_notify("timelimit", "0")?;
+
+    // the client registers this as well, so if it is running in the same
+    // process the duplicate cvar error can be ignored
+    let _ = cvars.register_notify("sv_gravity", "800");
+
+    // simulated network conditions are shared with the client as well
+    for name in [
+        "net_fakedup",
+        "net_fakejitter",
+        "net_fakelag",
+        "net_fakeloss",
+        "net_fakereorder",
+    ] {
+        let _ = cvars.register(name, "0");
+    }
+
+    Ok(())
+}
@@ -15,27 +15,42 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+mod cvars;
+pub mod net;
 pub mod precache;
 pub mod progs;
 pub mod world;
 
+pub use self::cvars::{register_cvars, RULE_CVARS};
+
 use std::{
     cell::{Ref, RefCell},
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    mem,
     rc::Rc,
 };
 
 use crate::{
     common::{
+        bsp,
         console::CvarRegistry,
         engine::{duration_from_f32, duration_to_f32},
         math::Hyperplane,
-        model::Model,
+        model::{Model, ModelKind},
+        net::{
+            write_angle, write_coord, ButtonFlags, EntityEffects, EntityState, ItemFlags, NetError,
+            PlayerData, Protocol, ProtocolFlags, ProtocolVersion, ServerCmd, DEFAULT_VIEWHEIGHT,
+            ENTITY_ALPHA_DEFAULT,
+        },
         parse,
-        vfs::Vfs,
+        pmove::{self, MoveInput, PhysicsVars, PlayerHulls, PlayerState},
+        vfs::{Vfs, VfsError},
     },
     server::{
-        progs::{functions::FunctionKind, GlobalAddrFunction},
+        progs::{
+            functions::{BuiltinFunctionId, FunctionKind},
+            GlobalAddrFunction,
+        },
         world::{FieldAddrEntityId, FieldAddrVector, MoveKind},
     },
 };
@@ -45,7 +60,7 @@ use self::{
     progs::{
         globals::{
             GLOBAL_ADDR_ARG_0, GLOBAL_ADDR_ARG_1, GLOBAL_ADDR_ARG_2, GLOBAL_ADDR_ARG_3,
-            GLOBAL_ADDR_RETURN,
+            GLOBAL_ADDR_ARG_4, GLOBAL_ADDR_RETURN,
         },
         EntityFieldAddr, EntityId, ExecutionContext, FunctionId, GlobalAddrEntity, GlobalAddrFloat,
         Globals, LoadProgs, Opcode, ProgsError, StringId, StringTable,
@@ -57,12 +72,97 @@ use self::{
 };
 
 use arrayvec::ArrayVec;
-use cgmath::{InnerSpace, Vector3, Zero};
+use byteorder::{LittleEndian, WriteBytesExt};
+use cgmath::{Deg, InnerSpace, Vector3, Zero};
 use chrono::Duration;
+use num::FromPrimitive;
+use thiserror::Error;
 
 const MAX_DATAGRAM: usize = 1024;
 const MAX_LIGHTSTYLES: usize = 64;
 
+#[derive(Error, Debug)]
+pub enum ServerError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Virtual filesystem error: {0}")]
+    Vfs(#[from] VfsError),
+    #[error("QuakeC error: {0}")]
+    Progs(#[from] ProgsError),
+    #[error("Network error: {0}")]
+    Net(#[from] NetError),
+    #[error("Couldn't load map {name}: {msg}")]
+    Map { name: String, msg: String },
+    #[error("No client in slot {0}")]
+    NoSuchClient(usize),
+}
+
+/// Where the QuakeC `Write*` functions send their data.
+#[derive(Copy, Clone, Debug, FromPrimitive)]
+enum MessageDest {
+    /// Unreliably to every client.
+    Broadcast = 0,
+
+    /// Reliably to the client in `msg_entity`.
+    One = 1,
+
+    /// Reliably to every client.
+    All = 2,
+
+    /// To each client as it signs on.
+    Init = 3,
+}
+
+/// Messages written by QuakeC for the server to deliver.
+///
+/// Commands are encoded in the level's protocol, but QuakeC may write any
+/// bytes it likes, so they must be decoded before use.
+#[derive(Debug, Default)]
+pub struct Messages {
+    /// Reliable messages for every client.
+    pub reliable: Vec<u8>,
+
+    /// Unreliable messages for every client.
+    pub unreliable: Vec<u8>,
+
+    /// Reliable messages for individual clients, indexed by client slot.
+    pub clients: Vec<Vec<u8>>,
+}
+
+impl Messages {
+    fn client_mut(&mut self, slot: usize) -> &mut Vec<u8> {
+        if self.clients.len() <= slot {
+            self.clients.resize_with(slot + 1, Vec::new);
+        }
+
+        &mut self.clients[slot]
+    }
+}
+
+/// Returns the protocol selected by the `sv_protocol` cvar.
+fn protocol_from_cvars(cvars: &CvarRegistry) -> Protocol {
+    let sv_protocol = cvars.get_value("sv_protocol").unwrap_or(15.0) as i32;
+    let version = match ProtocolVersion::from_i32(sv_protocol) {
+        Some(v) => v,
+        None => {
+            warn!(
+                "Unrecognized protocol {}, using {}",
+                sv_protocol,
+                ProtocolVersion::NetQuake.value()
+            );
+            ProtocolVersion::NetQuake
+        }
+    };
+
+    Protocol::new(version, ProtocolFlags::empty())
+}
+
+/// Serializes a command written by a QuakeC builtin.
+fn write_cmd(buf: &mut Vec<u8>, cmd: &ServerCmd, protocol: Protocol) -> Result<(), ProgsError> {
+    cmd.serialize(buf, protocol)
+        .map_err(|e| ProgsError::with_msg(format!("Couldn't write {}: {}", cmd.name(), e)))
+}
+
 /// The state of a client's connection to the server.
 pub enum ClientState {
     /// The client is still connecting.
@@ -78,8 +178,17 @@ pub struct ClientActive {
     privileged: bool,
 
     /// ID of the entity controlled by this client.
-    #[allow(dead_code)]
     entity_id: EntityId,
+
+    /// The movement from the client's most recent move command.
+    input: Option<MoveInput>,
+}
+
+impl ClientActive {
+    /// Returns the ID of the entity controlled by this client.
+    pub fn entity_id(&self) -> EntityId {
+        self.entity_id
+    }
 }
 
 bitflags! {
@@ -117,15 +226,40 @@ impl ClientSlots {
         self.slots.get(id)?.as_ref()
     }
 
+    /// Returns a mutable reference to the client in a slot.
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut ClientState> {
+        self.slots.get_mut(id)?.as_mut()
+    }
+
     /// Returns the maximum number of simultaneous clients.
     pub fn limit(&self) -> usize {
         self.slots.len()
     }
 
+    /// Returns the number of occupied slots.
+    pub fn count(&self) -> usize {
+        self.slots.iter().filter(|s| s.is_some()).count()
+    }
+
     /// Finds an available connection slot for a new client.
-    pub fn find_available(&mut self) -> Option<&mut ClientState> {
-        let slot = self.slots.iter_mut().find(|s| s.is_none())?;
-        Some(slot.insert(ClientState::Connecting))
+    ///
+    /// The slot is marked as connecting and its index is returned.
+    pub fn find_available(&mut self) -> Option<usize> {
+        let id = self.slots.iter().position(|s| s.is_none())?;
+        self.slots[id] = Some(ClientState::Connecting);
+        Some(id)
+    }
+
+    /// Vacates a slot, returning the client that occupied it.
+    pub fn free(&mut self, id: usize) -> Option<ClientState> {
+        self.slots.get_mut(id)?.take()
+    }
+
+    /// Returns every occupied slot to the connecting state.
+    pub fn reconnect_all(&mut self) {
+        for slot in self.slots.iter_mut().flatten() {
+            *slot = ClientState::Connecting;
+        }
     }
 }
 
@@ -159,6 +293,9 @@ pub enum SessionState {
 
     /// The server is active (in-game).
     Active(SessionActive),
+
+    /// The level is being moved between states.
+    Unloaded,
 }
 
 /// Contains the state of the server during level load.
@@ -171,12 +308,13 @@ impl SessionLoading {
         vfs: Rc<Vfs>,
         cvars: Rc<RefCell<CvarRegistry>>,
         progs: LoadProgs,
+        max_clients: usize,
         models: Vec<Model>,
         entmap: String,
-    ) -> SessionLoading {
-        SessionLoading {
-            level: LevelState::new(vfs, cvars, progs, models, entmap),
-        }
+    ) -> Result<SessionLoading, ProgsError> {
+        Ok(SessionLoading {
+            level: LevelState::new(vfs, cvars, progs, max_clients, models, entmap)?,
+        })
     }
 
     /// Adds a name to the sound precache.
@@ -198,8 +336,9 @@ impl SessionLoading {
     /// Completes the loading process.
     ///
     /// This consumes the `ServerLoading` and returns a `ServerActive`.
-    pub fn finish(self) -> SessionActive {
-        SessionActive { level: self.level }
+    pub fn finish(mut self) -> Result<SessionActive, ProgsError> {
+        self.level.create_baselines()?;
+        Ok(SessionActive { level: self.level })
     }
 }
 
@@ -222,13 +361,71 @@ impl Session {
         progs: LoadProgs,
         models: Vec<Model>,
         entmap: String,
-    ) -> Session {
-        Session {
+    ) -> Result<Session, ProgsError> {
+        Ok(Session {
             persist: SessionPersistent::new(max_clients),
-            state: SessionState::Loading(SessionLoading {
-                level: LevelState::new(vfs, cvars, progs, models, entmap),
-            }),
-        }
+            state: SessionState::Loading(SessionLoading::new(
+                vfs,
+                cvars,
+                progs,
+                max_clients,
+                models,
+                entmap,
+            )?),
+        })
+    }
+
+    /// Loads `progs.dat` and the named map and starts a new session.
+    ///
+    /// `map_name` is the name of the map without the `maps/` prefix or `.bsp`
+    /// extension, e.g. `e1m1`.
+    pub fn load<S>(
+        vfs: Rc<Vfs>,
+        cvars: Rc<RefCell<CvarRegistry>>,
+        max_clients: usize,
+        map_name: S,
+    ) -> Result<Session, ServerError>
+    where
+        S: AsRef<str>,
+    {
+        let map_name = map_name.as_ref();
+
+        let progs = progs::load(vfs.open("progs.dat")?)?;
+
+        let (models, entmap) =
+            bsp::load(vfs.open(format!("maps/{}.bsp", map_name))?).map_err(|e| {
+                ServerError::Map {
+                    name: map_name.to_owned(),
+                    msg: e.to_string(),
+                }
+            })?;
+
+        let mut session = Session::new(max_clients, vfs, cvars, progs, models, entmap)?;
+        session.level_mut().map_name = map_name.to_owned();
+
+        Ok(session)
+    }
+
+    /// Replaces the current level with the map `map_name`.
+    ///
+    /// Clients keep their slots but must spawn again in the new level. If the
+    /// map can't be loaded, the current level is left running.
+    pub fn change_level<S>(&mut self, map_name: S) -> Result<(), ServerError>
+    where
+        S: AsRef<str>,
+    {
+        let level = self.level();
+        let next = Session::load(
+            level.vfs.clone(),
+            level.cvars.clone(),
+            self.max_clients(),
+            map_name,
+        )?;
+
+        self.state = next.state;
+        self.persist.client_slots.reconnect_all();
+
+        Ok(())
     }
 
     /// Returns the maximum number of clients allowed on the server.
@@ -241,6 +438,112 @@ impl Session {
         self.persist.client(slot)
     }
 
+    /// Returns the client connection slots.
+    #[inline]
+    pub fn clients(&self) -> &ClientSlots {
+        &self.persist.client_slots
+    }
+
+    /// Reserves a slot for a connecting client and returns its index.
+    ///
+    /// Returns `None` if the server is full.
+    pub fn connect_client(&mut self) -> Option<usize> {
+        self.persist.client_slots.find_available()
+    }
+
+    /// Spawns the entity for a connecting client and activates it.
+    ///
+    /// This runs the `ClientConnect` and `PutClientInServer` QuakeC functions
+    /// for the client's entity and returns the entity's ID.
+    pub fn spawn_client(
+        &mut self,
+        slot: usize,
+        name: &str,
+        colors: u8,
+    ) -> Result<EntityId, ServerError> {
+        match self.persist.client_slots.get(slot) {
+            Some(ClientState::Connecting) => (),
+            Some(ClientState::Active(active)) => return Ok(active.entity_id),
+            None => return Err(ServerError::NoSuchClient(slot)),
+        }
+
+        let entity_id = EntityId(slot + 1);
+        self.level_mut().spawn_client(entity_id, name, colors)?;
+
+        *self.persist.client_slots.get_mut(slot).unwrap() = ClientState::Active(ClientActive {
+            privileged: false,
+            entity_id,
+            input: None,
+        });
+
+        Ok(entity_id)
+    }
+
+    /// Removes a client from the server.
+    ///
+    /// If the client was active, this runs the `ClientDisconnect` QuakeC
+    /// function for its entity.
+    pub fn drop_client(&mut self, slot: usize) -> Result<(), ServerError> {
+        // anything QuakeC wrote to the client goes with it
+        if let Some(msg) = self.level_mut().messages.clients.get_mut(slot) {
+            msg.clear();
+        }
+
+        match self.persist.client_slots.free(slot) {
+            Some(ClientState::Active(active)) => {
+                self.level_mut().drop_client(active.entity_id)?;
+                Ok(())
+            }
+            Some(ClientState::Connecting) => Ok(()),
+            None => Err(ServerError::NoSuchClient(slot)),
+        }
+    }
+
+    /// Applies a move command from an active client to its entity.
+    ///
+    /// The movement itself is kept until the next frame moves the entity.
+    pub fn set_client_move(
+        &mut self,
+        ent_id: EntityId,
+        input: MoveInput,
+        buttons: ButtonFlags,
+        impulse: u8,
+    ) -> Result<(), ServerError> {
+        let slot = ent_id.0.wrapping_sub(1);
+        if let Some(ClientState::Active(active)) = self.persist.client_slots.get_mut(slot) {
+            active.input = Some(input);
+        }
+
+        self.level_mut()
+            .set_client_move(ent_id, input.angles, buttons, impulse)?;
+        Ok(())
+    }
+
+    /// Runs a single server frame.
+    ///
+    /// The first frame completes the loading process.
+    pub fn frame(&mut self, frame_time: Duration) -> Result<(), ServerError> {
+        if let SessionState::Loading(_) = self.state {
+            // swap in a placeholder until the level has been moved out
+            let state = std::mem::replace(&mut self.state, SessionState::Unloaded);
+            if let SessionState::Loading(loading) = state {
+                self.state = SessionState::Active(loading.finish()?);
+            }
+        }
+
+        let Session { persist, state } = self;
+        if let SessionState::Active(ref mut active) = state {
+            active.level.physics(&persist.client_slots, frame_time)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes and returns the messages QuakeC has written since the last call.
+    pub fn take_messages(&mut self) -> Messages {
+        mem::take(&mut self.level_mut().messages)
+    }
+
     pub fn precache_sound(&mut self, name_id: StringId) {
         if let SessionState::Loading(ref mut loading) = self.state {
             loading.precache_sound(name_id);
@@ -257,11 +560,13 @@ impl Session {
         }
     }
 
+    /// Returns the state of the current level.
     #[inline]
-    fn level(&self) -> &LevelState {
+    pub fn level(&self) -> &LevelState {
         match self.state {
             SessionState::Loading(ref loading) => &loading.level,
             SessionState::Active(ref active) => &active.level,
+            SessionState::Unloaded => unreachable!(),
         }
     }
 
@@ -270,6 +575,7 @@ impl Session {
         match self.state {
             SessionState::Loading(ref mut loading) => &mut loading.level,
             SessionState::Active(ref mut active) => &mut active.level,
+            SessionState::Unloaded => unreachable!(),
         }
     }
 
@@ -292,8 +598,8 @@ impl Session {
     #[inline]
     pub fn time(&self) -> Option<Duration> {
         match self.state {
-            SessionState::Loading(_) => None,
             SessionState::Active(ref active) => Some(active.level.time),
+            _ => None,
         }
     }
 }
@@ -309,6 +615,24 @@ pub struct LevelState {
     model_precache: Precache,
     lightstyles: [StringId; MAX_LIGHTSTYLES],
 
+    /// Number of entities following the world which are reserved for clients.
+    max_clients: usize,
+
+    /// Name of the map, e.g. `e1m1`.
+    map_name: String,
+
+    /// The level title from the `message` key of the map's `worldspawn`.
+    message: String,
+
+    /// The CD track from the `sounds` key of the map's `worldspawn`.
+    cd_track: u8,
+
+    /// The initial state of each entity, indexed by entity ID.
+    ///
+    /// Entity updates sent to clients only include values which differ from
+    /// the baseline.
+    baselines: Vec<Option<EntityState>>,
+
     /// Amount of time the current level has been active.
     time: Duration,
 
@@ -325,8 +649,21 @@ pub struct LevelState {
     /// This contains the entities and world geometry.
     world: World,
 
-    #[allow(dead_code)]
-    datagram: ArrayVec<u8, MAX_DATAGRAM>,
+    /// The worldmodel's collision hulls, through which players are moved.
+    player_hulls: Option<PlayerHulls>,
+
+    /// Client functions which have failed. They run for every player every
+    /// frame, so each one's failure is only logged the first time.
+    failed_client_functions: HashSet<GlobalAddrFunction>,
+
+    /// The protocol in which QuakeC messages are encoded.
+    protocol: Protocol,
+
+    /// Messages written by QuakeC which haven't been delivered yet.
+    messages: Messages,
+
+    /// Messages sent to each client as it signs on.
+    signon: Vec<u8>,
 }
 
 impl LevelState {
@@ -334,9 +671,10 @@ impl LevelState {
         vfs: Rc<Vfs>,
         cvars: Rc<RefCell<CvarRegistry>>,
         progs: LoadProgs,
+        max_clients: usize,
         models: Vec<Model>,
         entmap: String,
-    ) -> LevelState {
+    ) -> Result<LevelState, ProgsError> {
         let LoadProgs {
             cx,
             globals,
@@ -355,9 +693,30 @@ impl LevelState {
             model_precache.precache(string_table.borrow().get(model_name).unwrap());
         }
 
-        let world = World::create(models, entity_def.clone(), string_table.clone()).unwrap();
+        let player_hulls = match models.first().map(|m| m.kind()) {
+            Some(ModelKind::Brush(ref bmodel)) => PlayerHulls::from_world(bmodel).ok(),
+            _ => None,
+        };
+
+        let mut world = World::create(models, entity_def.clone(), string_table.clone())?;
         let entity_list = parse::entities(&entmap).unwrap();
 
+        // the entities following the world are reserved for clients
+        for _ in 0..max_clients {
+            world.alloc_uninitialized()?;
+        }
+
+        let worldspawn = entity_list.first();
+        let message = worldspawn
+            .and_then(|w| w.get("message"))
+            .map_or_else(String::new, |m| m.to_string());
+        let cd_track = worldspawn
+            .and_then(|w| w.get("sounds"))
+            .and_then(|t| t.parse().ok())
+            .unwrap_or(0);
+
+        let protocol = protocol_from_cvars(&cvars.borrow());
+
         let mut level = LevelState {
             vfs,
             cvars,
@@ -365,20 +724,291 @@ impl LevelState {
             sound_precache,
             model_precache,
             lightstyles: [StringId(0); MAX_LIGHTSTYLES],
+            max_clients,
+            map_name: String::new(),
+            message,
+            cd_track,
+            baselines: Vec::new(),
             time: Duration::zero(),
 
             cx,
             globals,
             world,
+            player_hulls,
+            failed_client_functions: HashSet::new(),
 
-            datagram: ArrayVec::new(),
+            protocol,
+            messages: Messages::default(),
+            signon: Vec::new(),
         };
 
         for entity in entity_list {
-            level.spawn_entity_from_map(entity).unwrap();
+            // One entity using something the server can't do yet shouldn't
+            // stop the level from loading, so it's left out instead.
+            let classname = entity.get("classname").copied().unwrap_or_default();
+            if let Err(e) = level.spawn_entity_from_map(entity) {
+                warn!("Couldn't spawn {}: {}", classname, e);
+            }
         }
 
-        level
+        Ok(level)
+    }
+
+    /// Returns the name of the map, e.g. `e1m1`.
+    pub fn map_name(&self) -> &str {
+        &self.map_name
+    }
+
+    /// Returns the level title.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns the CD track to play during this level.
+    pub fn cd_track(&self) -> u8 {
+        self.cd_track
+    }
+
+    /// Returns the amount of time the level has been active.
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+
+    /// Returns the protocol the level's messages are encoded in.
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Returns the messages sent to each client as it signs on.
+    pub fn signon(&self) -> &[u8] {
+        &self.signon
+    }
+
+    /// Returns the names in the model precache, excluding the empty name at index 0.
+    pub fn model_names(&self) -> impl Iterator<Item = &str> {
+        self.model_precache.iter().skip(1)
+    }
+
+    /// Returns the names in the sound precache, excluding the empty name at index 0.
+    pub fn sound_names(&self) -> impl Iterator<Item = &str> {
+        self.sound_precache.iter().skip(1)
+    }
+
+    /// Returns the value of each light style.
+    pub fn lightstyles(&self) -> Vec<String> {
+        let strs = self.string_table.borrow();
+        self.lightstyles
+            .iter()
+            .map(|id| strs.get(*id).unwrap_or_default().to_owned())
+            .collect()
+    }
+
+    /// Returns the IDs of all allocated entities.
+    pub fn entity_ids(&self) -> Vec<EntityId> {
+        let mut ids = Vec::new();
+        self.world.list_entities(&mut ids);
+        ids
+    }
+
+    /// Returns the baseline for an entity, if one was created.
+    pub fn baseline(&self, ent_id: EntityId) -> Option<&EntityState> {
+        self.baselines.get(ent_id.0)?.as_ref()
+    }
+
+    /// Returns the network-visible state of an entity.
+    pub fn entity_state(&self, ent_id: EntityId) -> Result<EntityState, ProgsError> {
+        let ent = self.world.try_entity(ent_id)?;
+        let angles: Vector3<f32> = ent.load(FieldAddrVector::Angles)?.into();
+
+        Ok(EntityState {
+            origin: ent.origin()?,
+            angles: Vector3::new(Deg(angles.x), Deg(angles.y), Deg(angles.z)),
+            model_id: ent.model_index()?,
+            frame_id: ent.load(FieldAddrFloat::FrameId)? as usize,
+            colormap: ent.load(FieldAddrFloat::Colormap)? as u8,
+            skin_id: ent.load(FieldAddrFloat::SkinId)? as usize,
+            effects: EntityEffects::from_bits_truncate(ent.load(FieldAddrFloat::Effects)? as u8),
+            alpha: ENTITY_ALPHA_DEFAULT,
+        })
+    }
+
+    /// Records the current state of every visible entity as its baseline.
+    ///
+    /// Client entities always receive a baseline; other entities only receive
+    /// one if they have a model.
+    pub fn create_baselines(&mut self) -> Result<(), ProgsError> {
+        self.baselines.clear();
+        for ent_id in self.entity_ids() {
+            let mut state = self.entity_state(ent_id)?;
+
+            if ent_id.0 > 0 && ent_id.0 <= self.max_clients {
+                state.colormap = ent_id.0 as u8;
+                state.model_id = self
+                    .model_precache
+                    .find("progs/player.mdl")
+                    .unwrap_or(state.model_id);
+            } else if state.model_id == 0 {
+                continue;
+            }
+
+            if self.baselines.len() <= ent_id.0 {
+                self.baselines.resize(ent_id.0 + 1, None);
+            }
+            self.baselines[ent_id.0] = Some(state);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the frag count of a client entity.
+    pub fn frags(&self, ent_id: EntityId) -> Result<i32, ProgsError> {
+        Ok(self.world.try_entity(ent_id)?.load(FieldAddrFloat::Frags)? as i32)
+    }
+
+    /// Returns the view angles of a client entity.
+    pub fn view_angles(&self, ent_id: EntityId) -> Result<Vector3<Deg<f32>>, ProgsError> {
+        let angles: Vector3<f32> = self
+            .world
+            .try_entity(ent_id)?
+            .load(FieldAddrVector::Angles)?
+            .into();
+        Ok(Vector3::new(Deg(angles.x), Deg(angles.y), Deg(angles.z)))
+    }
+
+    /// Returns the player state to send to the client controlling an entity.
+    ///
+    /// Values equal to their defaults are omitted, as in the original engine.
+    pub fn client_data(&self, ent_id: EntityId) -> Result<PlayerData, ProgsError> {
+        let ent = self.world.try_entity(ent_id)?;
+
+        let nonzero = |v: f32| if v != 0.0 { Some(v) } else { None };
+        let nonzero_angle = |v: f32| nonzero(v).map(Deg);
+
+        let view_height = ent.load(FieldAddrFloat::ViewOffsetZ)?;
+        let punch: Vector3<f32> = ent.load(FieldAddrVector::PunchAngle)?.into();
+        let velocity = ent.velocity()?;
+        let flags = ent.flags()?;
+
+        let weapon = {
+            let name_id = ent.string_id(FieldAddrStringId::WeaponModelName as i16)?;
+            let strs = self.string_table.borrow();
+            strs.get(name_id)
+                .and_then(|name| self.model_precache.find(name))
+                .unwrap_or(0)
+        };
+
+        Ok(PlayerData {
+            view_height: match view_height {
+                h if h == DEFAULT_VIEWHEIGHT => None,
+                h => Some(h),
+            },
+            ideal_pitch: nonzero_angle(ent.load(FieldAddrFloat::IdealPitch)?),
+            punch_pitch: nonzero_angle(punch.x),
+            velocity_x: nonzero(velocity.x),
+            punch_yaw: nonzero_angle(punch.y),
+            velocity_y: nonzero(velocity.y),
+            punch_roll: nonzero_angle(punch.z),
+            velocity_z: nonzero(velocity.z),
+            items: ItemFlags::from_bits_truncate(ent.load(FieldAddrFloat::Items)? as u32),
+            on_ground: flags.contains(EntityFlags::ON_GROUND),
+            in_water: ent.load(FieldAddrFloat::WaterLevel)? >= 2.0,
+            weapon_frame: nonzero(ent.load(FieldAddrFloat::WeaponFrame)?).map(|f| f as u16),
+            armor: nonzero(ent.load(FieldAddrFloat::ArmorValue)?).map(|a| a as u16),
+            weapon: match weapon {
+                0 => None,
+                w => Some(w as u16),
+            },
+            health: ent.load(FieldAddrFloat::Health)? as i16,
+            ammo: ent.load(FieldAddrFloat::CurrentAmmo)? as u16,
+            ammo_shells: ent.load(FieldAddrFloat::AmmoShells)? as u16,
+            ammo_nails: ent.load(FieldAddrFloat::AmmoNails)? as u16,
+            ammo_rockets: ent.load(FieldAddrFloat::AmmoRockets)? as u16,
+            ammo_cells: ent.load(FieldAddrFloat::AmmoCells)? as u16,
+            active_weapon: ent.load(FieldAddrFloat::Weapon)? as u8,
+            weapon_alpha: None,
+        })
+    }
+
+    /// Sets up a client's entity and runs the QuakeC functions that place the
+    /// client in the level.
+    pub fn spawn_client(
+        &mut self,
+        ent_id: EntityId,
+        name: &str,
+        colors: u8,
+    ) -> Result<(), ProgsError> {
+        let name_id = self.string_table.borrow_mut().find_or_insert(name);
+
+        let ent = self.world.entity_mut(ent_id)?;
+        ent.put_string_id(name_id, FieldAddrStringId::NetName as i16)?;
+        ent.store(FieldAddrFloat::Colormap, ent_id.0 as f32)?;
+        ent.store(FieldAddrFloat::Team, ((colors & 0x0F) + 1) as f32)?;
+
+        self.globals
+            .store(GlobalAddrFloat::Time, duration_to_f32(self.time))?;
+        self.globals.store(GlobalAddrEntity::Self_, ent_id)?;
+
+        for f in [
+            GlobalAddrFunction::SetNewArgs,
+            GlobalAddrFunction::ClientConnect,
+            GlobalAddrFunction::PutClientInServer,
+        ] {
+            let f_id = self.globals.function_id(f as i16)?;
+            self.execute_program(f_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Stores the view angles, buttons and impulse from a client's move command
+    /// on its entity.
+    pub fn set_client_move(
+        &mut self,
+        ent_id: EntityId,
+        angles: Vector3<Deg<f32>>,
+        buttons: ButtonFlags,
+        impulse: u8,
+    ) -> Result<(), ProgsError> {
+        let ent = self.world.entity_mut(ent_id)?;
+        ent.store(
+            FieldAddrVector::ViewAngle,
+            [angles.x.0, angles.y.0, angles.z.0],
+        )?;
+
+        let button = |flag| if buttons.contains(flag) { 1.0 } else { 0.0 };
+        ent.store(FieldAddrFloat::Button0, button(ButtonFlags::ATTACK))?;
+        ent.store(FieldAddrFloat::Button2, button(ButtonFlags::JUMP))?;
+
+        // impulses are kept until the entity thinks
+        if impulse != 0 {
+            ent.store(FieldAddrFloat::Impulse, impulse as f32)?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs the QuakeC function that removes a client's entity from the level.
+    ///
+    /// The client is leaving whether or not the function succeeds, so its
+    /// failure is only logged.
+    pub fn drop_client(&mut self, ent_id: EntityId) -> Result<(), ProgsError> {
+        self.globals
+            .store(GlobalAddrFloat::Time, duration_to_f32(self.time))?;
+        self.globals.store(GlobalAddrEntity::Self_, ent_id)?;
+
+        let f_id = self
+            .globals
+            .function_id(GlobalAddrFunction::ClientDisconnect as i16)?;
+        if let Err(e) = self.execute_program(f_id) {
+            warn!("ClientDisconnect failed for entity {}: {}", ent_id.0, e);
+        }
+
+        // clear the name so that the entity no longer shows up as a player
+        let ent = self.world.entity_mut(ent_id)?;
+        ent.put_string_id(StringId(0), FieldAddrStringId::NetName as i16)?;
+        ent.store(FieldAddrFloat::ModelIndex, 0.0)?;
+
+        Ok(())
     }
 
     #[inline]
@@ -420,9 +1050,24 @@ impl LevelState {
 
     /// Execute a QuakeC function in the VM.
     pub fn execute_program(&mut self, f: FunctionId) -> Result<(), ProgsError> {
-        let mut runaway = 100000;
-
         let exit_depth = self.cx.call_stack_depth();
+        let result = self.run_program(f, exit_depth);
+
+        if result.is_err() {
+            // leave the functions the error interrupted so that the next program starts with the
+            // call stack and locals it would have had
+            while self.cx.call_stack_depth() > exit_depth {
+                if self.cx.leave_function(&mut self.globals).is_err() {
+                    break;
+                }
+            }
+        }
+
+        result
+    }
+
+    fn run_program(&mut self, f: FunctionId, exit_depth: usize) -> Result<(), ProgsError> {
+        let mut runaway = 100000;
 
         self.cx.enter_function(&mut self.globals, f)?;
 
@@ -478,8 +1123,7 @@ impl LevelState {
                 }
 
                 Call0 | Call1 | Call2 | Call3 | Call4 | Call5 | Call6 | Call7 | Call8 => {
-                    // TODO: pass to equivalent of PF_VarString
-                    let _arg_count = op as usize - Opcode::Call0 as usize;
+                    let arg_count = op as usize - Opcode::Call0 as usize;
 
                     let f_to_call = self.globals.function_id(a)?;
                     if f_to_call.0 == 0 {
@@ -497,68 +1141,43 @@ impl LevelState {
                             SetOrigin => self.builtin_set_origin()?,
                             SetModel => self.builtin_set_model()?,
                             SetSize => self.builtin_set_size()?,
-                            Break => unimplemented!(),
                             Random => self.globals.builtin_random()?,
-                            Sound => unimplemented!(),
-                            Normalize => unimplemented!(),
-                            Error => unimplemented!(),
-                            ObjError => unimplemented!(),
                             VLen => self.globals.builtin_v_len()?,
                             VecToYaw => self.globals.builtin_vec_to_yaw()?,
                             Spawn => self.builtin_spawn()?,
                             Remove => self.builtin_remove()?,
-                            TraceLine => unimplemented!(),
-                            CheckClient => unimplemented!(),
-                            Find => unimplemented!(),
                             PrecacheSound => self.builtin_precache_sound()?,
                             PrecacheModel => self.builtin_precache_model()?,
-                            StuffCmd => unimplemented!(),
-                            FindRadius => unimplemented!(),
-                            BPrint => unimplemented!(),
-                            SPrint => unimplemented!(),
                             DPrint => self.builtin_dprint()?,
-                            FToS => unimplemented!(),
-                            VToS => unimplemented!(),
-                            CoreDump => unimplemented!(),
-                            TraceOn => unimplemented!(),
-                            TraceOff => unimplemented!(),
-                            EPrint => unimplemented!(),
-                            WalkMove => unimplemented!(),
 
                             DropToFloor => self.builtin_drop_to_floor()?,
                             LightStyle => self.builtin_light_style()?,
                             RInt => self.globals.builtin_r_int()?,
                             Floor => self.globals.builtin_floor()?,
                             Ceil => self.globals.builtin_ceil()?,
-                            CheckBottom => unimplemented!(),
-                            PointContents => unimplemented!(),
                             FAbs => self.globals.builtin_f_abs()?,
-                            Aim => unimplemented!(),
                             Cvar => self.builtin_cvar()?,
-                            LocalCmd => unimplemented!(),
-                            NextEnt => unimplemented!(),
-                            Particle => unimplemented!(),
-                            ChangeYaw => unimplemented!(),
-                            VecToAngles => unimplemented!(),
-                            WriteByte => unimplemented!(),
-                            WriteChar => unimplemented!(),
-                            WriteShort => unimplemented!(),
-                            WriteLong => unimplemented!(),
-                            WriteCoord => unimplemented!(),
-                            WriteAngle => unimplemented!(),
-                            WriteString => unimplemented!(),
-                            WriteEntity => unimplemented!(),
-                            MoveToGoal => unimplemented!(),
-                            PrecacheFile => unimplemented!(),
-                            MakeStatic => unimplemented!(),
-                            ChangeLevel => unimplemented!(),
                             CvarSet => self.builtin_cvar_set()?,
-                            CenterPrint => unimplemented!(),
                             AmbientSound => self.builtin_ambient_sound()?,
-                            PrecacheModel2 => unimplemented!(),
-                            PrecacheSound2 => unimplemented!(),
-                            PrecacheFile2 => unimplemented!(),
-                            SetSpawnArgs => unimplemented!(),
+                            MakeStatic => self.builtin_make_static()?,
+                            Sound => self.builtin_sound()?,
+                            StuffCmd => self.builtin_stuff_cmd()?,
+                            BPrint => self.builtin_bprint(arg_count)?,
+                            SPrint => self.builtin_sprint(arg_count)?,
+                            CenterPrint => self.builtin_center_print(arg_count)?,
+                            WriteByte | WriteChar | WriteShort | WriteLong | WriteCoord
+                            | WriteAngle | WriteString | WriteEntity => self.builtin_write(b)?,
+                            Break | Normalize | Error | ObjError | TraceLine | CheckClient
+                            | Find | FindRadius | FToS | VToS | CoreDump | TraceOn | TraceOff
+                            | EPrint | WalkMove | CheckBottom | PointContents | Aim | LocalCmd
+                            | NextEnt | Particle | ChangeYaw | VecToAngles | MoveToGoal
+                            | PrecacheFile | ChangeLevel | PrecacheModel2 | PrecacheSound2
+                            | PrecacheFile2 | SetSpawnArgs => {
+                                return Err(ProgsError::with_msg(format!(
+                                    "Built-in function {} is not implemented",
+                                    name
+                                )))
+                            }
                         }
                         debug!("Returning from built-in function {}", name);
                     } else {
@@ -683,9 +1302,19 @@ impl LevelState {
         self.globals
             .put_entity_id(ent_id, GlobalAddrEntity::Self_ as i16)?;
 
-        self.execute_program_by_name(classname)?;
+        if let Err(e) = self.execute_program_by_name(classname) {
+            // don't leave a half-spawned entity in the level
+            if ent_id.0 != 0 && self.world.entity_exists(ent_id) {
+                self.world.remove_entity(ent_id)?;
+            }
 
-        self.link_entity(ent_id, true)?;
+            return Err(e);
+        }
+
+        // static entities remove themselves
+        if self.world.entity_exists(ent_id) {
+            self.link_entity(ent_id, true)?;
+        }
 
         Ok(ent_id)
     }
@@ -767,7 +1396,9 @@ impl LevelState {
         let start_frame = self
             .globals
             .function_id(GlobalAddrFunction::StartFrame as i16)?;
-        self.execute_program(start_frame)?;
+        if let Err(e) = self.execute_program(start_frame) {
+            warn!("StartFrame failed: {}", e);
+        }
 
         // TODO: don't alloc
         let mut ent_ids = Vec::new();
@@ -775,6 +1406,11 @@ impl LevelState {
         self.world.list_entities(&mut ent_ids);
 
         for ent_id in ent_ids {
+            if !self.world.entity_exists(ent_id) {
+                // Removed by another entity earlier this frame.
+                continue;
+            }
+
             if self.globals.load(GlobalAddrFloat::ForceRetouch)? != 0.0 {
                 // Force all entities to touch triggers, even if they didn't
                 // move. This is required when e.g. creating new triggers, as
@@ -787,27 +1423,15 @@ impl LevelState {
                 // Quake solves this by using a linked list and always spawning
                 // at the end so that newly spawned entities always have physics
                 // run this frame.
-                self.link_entity(ent_id, true)?;
+                if let Err(e) = self.link_entity(ent_id, true) {
+                    warn!("Failed to relink entity {}: {}", ent_id.0, e);
+                }
             }
 
-            let max_clients = clients.limit();
-            if ent_id.0 != 0 && ent_id.0 < max_clients {
-                self.physics_player(clients, ent_id)?;
-            } else {
-                match self.world.entity(ent_id).move_kind()? {
-                    MoveKind::Walk => {
-                        todo!("MoveKind::Walk");
-                    }
-
-                    MoveKind::Push => self.physics_push(ent_id, frame_time)?,
-                    // No actual physics for this entity, but still let it think.
-                    MoveKind::None => self.think(ent_id, frame_time)?,
-                    MoveKind::NoClip => self.physics_noclip(ent_id, frame_time)?,
-                    MoveKind::Step => self.physics_step(ent_id, frame_time)?,
-
-                    // all airborne entities have the same physics
-                    _ => unimplemented!(),
-                }
+            // One entity using something the server can't do yet shouldn't
+            // stop the rest of the level, so its physics are just skipped.
+            if let Err(e) = self.physics_entity(clients, ent_id, frame_time) {
+                warn!("Physics failed for entity {}: {}", ent_id.0, e);
             }
 
             match self.globals.load(GlobalAddrFloat::ForceRetouch)? {
@@ -816,28 +1440,221 @@ impl LevelState {
             }
         }
 
-        // TODO: increase sv.time by host_frametime
-        unimplemented!();
+        self.time = self.time + frame_time;
+
+        Ok(())
+    }
+
+    /// Runs one frame of physics for a single entity, according to its move kind.
+    pub fn physics_entity(
+        &mut self,
+        clients: &ClientSlots,
+        ent_id: EntityId,
+        frame_time: Duration,
+    ) -> Result<(), ProgsError> {
+        // the entities following the world belong to the clients
+        if ent_id.0 != 0 && ent_id.0 <= clients.limit() {
+            return self.physics_player(clients, ent_id, frame_time);
+        }
+
+        match self.world.entity(ent_id).move_kind()? {
+            MoveKind::Push => self.physics_push(ent_id, frame_time),
+            // No actual physics for this entity, but still let it think.
+            MoveKind::None => self.think(ent_id, frame_time),
+            MoveKind::NoClip => self.physics_noclip(ent_id, frame_time),
+            MoveKind::Step => self.physics_step(ent_id, frame_time),
+
+            // all airborne entities have the same physics
+            MoveKind::Toss | MoveKind::Bounce | MoveKind::Fly | MoveKind::FlyMissile => {
+                self.physics_toss(ent_id, frame_time)
+            }
+
+            // only clients walk
+            k => Err(ProgsError::with_msg(format!(
+                "{:?} physics are not supported for entity {}",
+                k, ent_id.0
+            ))),
+        }
     }
 
-    // TODO: rename arguments when implementing
+    /// Runs one frame of physics for a client's entity.
+    ///
+    /// Walking players are moved by `pmove::player_move`, the same code clients
+    /// use to predict their own movement.
     pub fn physics_player(
         &mut self,
         clients: &ClientSlots,
         ent_id: EntityId,
+        frame_time: Duration,
     ) -> Result<(), ProgsError> {
         let client_id = ent_id.0.checked_sub(1).ok_or_else(|| {
             ProgsError::with_msg(format!("Invalid client entity ID: {:?}", ent_id))
         })?;
 
-        if clients.get(client_id).is_none() {
-            // No client in this slot.
-            return Ok(());
+        let input = match clients.get(client_id) {
+            Some(ClientState::Active(active)) => active.input,
+            // No spawned client in this slot.
+            _ => return Ok(()),
+        };
+
+        let ent = self.world.entity_mut(ent_id)?;
+        let alive = ent.load(FieldAddrFloat::Health)? > 0.0;
+        let input = match input {
+            Some(i) if alive => i,
+            // dead players can't move themselves
+            _ => MoveInput {
+                angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+                forward: 0.0,
+                side: 0.0,
+                up: 0.0,
+                jump: false,
+            },
+        };
+
+        if alive {
+            // the player model only pitches a third as far as the view does
+            let view_angles: Vector3<f32> = ent.load(FieldAddrVector::ViewAngle)?.into();
+            let mut angles: Vector3<f32> = ent.load(FieldAddrVector::Angles)?.into();
+            angles.x = -view_angles.x / 3.0;
+            angles.y = view_angles.y;
+            ent.store(FieldAddrVector::Angles, angles.into())?;
         }
 
+        // Jumping is done by player_move, so the QuakeC mustn't see the jump
+        // button. PlayerPreThink also overwrites the JUMP_RELEASED flag, so
+        // the flag is read beforehand.
+        let jump_released = ent.flags()?.contains(EntityFlags::JUMP_RELEASED);
+        ent.store(FieldAddrFloat::Button2, 0.0)?;
+
+        self.run_client_function(ent_id, GlobalAddrFunction::PlayerPreThink)?;
+
+        let sv_maxvelocity = self.cvars.borrow().get_value("sv_maxvelocity").unwrap();
         let ent = self.world.entity_mut(ent_id)?;
-        ent.limit_velocity(self.cvars.borrow().get_value("sv_maxvelocity").unwrap())?;
-        unimplemented!();
+        ent.limit_velocity(sv_maxvelocity)?;
+
+        match ent.move_kind()? {
+            MoveKind::None => self.think(ent_id, frame_time)?,
+            MoveKind::Walk => {
+                self.think(ent_id, frame_time)?;
+                self.move_player(ent_id, &input, jump_released, frame_time)?;
+            }
+            MoveKind::NoClip => {
+                self.think(ent_id, frame_time)?;
+                let vars = self.physics_vars();
+                let ent = self.world.entity_mut(ent_id)?;
+                let mut state = PlayerState::new(ent.origin()?, ent.velocity()?, false);
+                pmove::noclip_move(&vars, &mut state, &input, frame_time);
+                ent.store(FieldAddrVector::Origin, state.origin.into())?;
+                ent.store(FieldAddrVector::Velocity, state.velocity.into())?;
+                ent.remove_flags(EntityFlags::ON_GROUND)?;
+            }
+            // e.g. players falling after death
+            MoveKind::Toss | MoveKind::Bounce | MoveKind::Fly => {
+                self.physics_toss(ent_id, frame_time)?
+            }
+            k => {
+                return Err(ProgsError::with_msg(format!(
+                    "{:?} physics are not supported for players",
+                    k
+                )))
+            }
+        }
+
+        self.link_entity(ent_id, true)?;
+        self.run_client_function(ent_id, GlobalAddrFunction::PlayerPostThink)?;
+
+        Ok(())
+    }
+
+    /// Runs one of the QuakeC functions called around player movement with
+    /// `self` set to the player's entity.
+    ///
+    /// These use builtins the server doesn't have yet, so failures are only
+    /// logged to keep the player moving.
+    fn run_client_function(
+        &mut self,
+        ent_id: EntityId,
+        f: GlobalAddrFunction,
+    ) -> Result<(), ProgsError> {
+        self.globals
+            .store(GlobalAddrFloat::Time, duration_to_f32(self.time))?;
+        self.globals.store(GlobalAddrEntity::Self_, ent_id)?;
+
+        let f_id = self.globals.function_id(f as i16)?;
+        if let Err(e) = self.execute_program(f_id) {
+            if self.failed_client_functions.insert(f) {
+                warn!("{:?} failed for entity {}: {}", f, ent_id.0, e);
+            } else {
+                debug!("{:?} failed for entity {}: {}", f, ent_id.0, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves a walking player through the world according to its input.
+    pub fn move_player(
+        &mut self,
+        ent_id: EntityId,
+        input: &MoveInput,
+        jump_released: bool,
+        frame_time: Duration,
+    ) -> Result<(), ProgsError> {
+        let vars = self.physics_vars();
+        let hulls = self
+            .player_hulls
+            .as_ref()
+            .ok_or_else(|| ProgsError::with_msg("World has no player collision hulls"))?;
+
+        let ent = self.world.entity_mut(ent_id)?;
+        let mut state = PlayerState::new(
+            ent.origin()?,
+            ent.velocity()?,
+            ent.flags()?.contains(EntityFlags::ON_GROUND),
+        );
+        state.jump_released = jump_released;
+
+        pmove::player_move(hulls, &vars, &mut state, input, frame_time)
+            .map_err(|e| ProgsError::with_msg(format!("Player movement failed: {}", e)))?;
+
+        ent.store(FieldAddrVector::Origin, state.origin.into())?;
+        ent.store(FieldAddrVector::Velocity, state.velocity.into())?;
+
+        if state.on_ground {
+            ent.add_flags(EntityFlags::ON_GROUND)?;
+            ent.store(FieldAddrEntityId::Ground, EntityId(0))?;
+        } else {
+            ent.remove_flags(EntityFlags::ON_GROUND)?;
+        }
+
+        if state.jump_released {
+            ent.add_flags(EntityFlags::JUMP_RELEASED)?;
+        } else {
+            ent.remove_flags(EntityFlags::JUMP_RELEASED)?;
+        }
+
+        // QuakeC uses the negated leaf contents values
+        ent.store(FieldAddrFloat::WaterLevel, state.water_level as i32 as f32)?;
+        ent.store(FieldAddrFloat::Contents, -(state.water_kind as i32) as f32)?;
+
+        Ok(())
+    }
+
+    /// Returns the physics settings used to move players.
+    fn physics_vars(&self) -> PhysicsVars {
+        let cvars = self.cvars.borrow();
+        let value = |name| cvars.get_value(name).unwrap();
+
+        PhysicsVars {
+            sv_gravity: value("sv_gravity"),
+            sv_friction: value("sv_friction"),
+            edgefriction: value("edgefriction"),
+            sv_stopspeed: value("sv_stopspeed"),
+            sv_maxspeed: value("sv_maxspeed"),
+            sv_accelerate: value("sv_accelerate"),
+            sv_maxvelocity: value("sv_maxvelocity"),
+            sv_nostep: value("sv_nostep") != 0.0,
+        }
     }
 
     pub fn physics_push(
@@ -949,7 +1766,8 @@ impl LevelState {
 
             if ent.flags()?.contains(EntityFlags::ON_GROUND) && hit_sound {
                 // Entity hit the ground this frame.
-                todo!("SV_StartSound(demon/dland2.wav)");
+                // TODO: play demon/dland2.wav once the server can start sounds
+                debug!("Entity {} landed", ent_id.0);
             }
         }
 
@@ -960,27 +1778,238 @@ impl LevelState {
         Ok(())
     }
 
+    /// Runs the physics of entities flying through the air, such as missiles, gibs and dropped
+    /// items.
+    ///
+    /// Tossed and bouncing entities fall under gravity until they come to rest on a floor.
+    pub fn physics_toss(
+        &mut self,
+        ent_id: EntityId,
+        frame_time: Duration,
+    ) -> Result<(), ProgsError> {
+        self.think(ent_id, frame_time)?;
+        if !self.world.entity_exists(ent_id) {
+            // Entity removed itself.
+            return Ok(());
+        }
+
+        let ent = self.world.entity_mut(ent_id)?;
+        if ent.flags()?.contains(EntityFlags::ON_GROUND) {
+            return Ok(());
+        }
+
+        let move_kind = ent.move_kind()?;
+        let sv_maxvelocity = self.cvars.borrow().get_value("sv_maxvelocity").unwrap();
+        let sv_gravity = self.cvars.borrow().get_value("sv_gravity").unwrap();
+        ent.limit_velocity(sv_maxvelocity)?;
+        if !matches!(move_kind, MoveKind::Fly | MoveKind::FlyMissile) {
+            ent.apply_gravity(sv_gravity, frame_time)?;
+        }
+
+        let frame_time_f = duration_to_f32(frame_time);
+        let angles: Vector3<f32> = ent.load(FieldAddrVector::Angles)?.into();
+        let angle_vel: Vector3<f32> = ent.load(FieldAddrVector::AngularVelocity)?.into();
+        ent.store(
+            FieldAddrVector::Angles,
+            (angles + frame_time_f * angle_vel).into(),
+        )?;
+
+        let velocity = ent.velocity()?;
+        let (trace, hit_entity) = self.push_entity(ent_id, frame_time_f * velocity)?;
+        let normal = match trace.end().kind() {
+            TraceEndKind::Terminal => return Ok(()),
+            TraceEndKind::Boundary(b) => b.plane.normal(),
+        };
+
+        if !self.world.entity_exists(ent_id) {
+            // Entity removed by touch function.
+            return Ok(());
+        }
+
+        let overbounce = match move_kind {
+            MoveKind::Bounce => 1.5,
+            _ => 1.0,
+        };
+        let ent = self.world.entity_mut(ent_id)?;
+        let (velocity, _) = phys::velocity_after_collision(ent.velocity()?, normal, overbounce);
+        ent.store(FieldAddrVector::Velocity, velocity.into())?;
+
+        // TODO: magic constant
+        if normal.z > 0.7 && (velocity.z < 60.0 || move_kind != MoveKind::Bounce) {
+            // Come to rest on the floor.
+            ent.add_flags(EntityFlags::ON_GROUND)?;
+            ent.store(FieldAddrEntityId::Ground, hit_entity.unwrap_or(EntityId(0)))?;
+            ent.store(FieldAddrVector::Velocity, Vector3::zero().into())?;
+            ent.store(FieldAddrVector::AngularVelocity, Vector3::zero().into())?;
+        }
+
+        // TODO SV_CheckWaterTransition
+
+        Ok(())
+    }
+
+    /// Moves an entity by `offset`, stopping at the first thing in its way, and runs the touch
+    /// functions of the entity and whatever it hit.
+    pub fn push_entity(
+        &mut self,
+        ent_id: EntityId,
+        offset: Vector3<f32>,
+    ) -> Result<(Trace, Option<EntityId>), ProgsError> {
+        let ent = self.world.entity(ent_id);
+        let origin = ent.origin()?;
+        let min = ent.min()?;
+        let max = ent.max()?;
+        let kind = match (ent.move_kind()?, ent.solid()?) {
+            (MoveKind::FlyMissile, _) => CollideKind::Missile,
+            (_, EntitySolid::Trigger | EntitySolid::Not) => CollideKind::NoMonsters,
+            _ => CollideKind::Normal,
+        };
+
+        let (trace, hit_entity) =
+            self.world
+                .move_entity(ent_id, origin, min, max, origin + offset, kind)?;
+
+        self.world
+            .entity_mut(ent_id)?
+            .store(FieldAddrVector::Origin, trace.end_point().into())?;
+        self.link_entity(ent_id, true)?;
+
+        if let (TraceEndKind::Boundary(_), Some(hit)) = (trace.end().kind(), hit_entity) {
+            self.impact_entities(ent_id, hit)?;
+        }
+
+        Ok((trace, hit_entity))
+    }
+
+    /// Returns whether an entity is stuck inside something solid.
+    pub fn entity_stuck(&mut self, ent_id: EntityId) -> Result<bool, ProgsError> {
+        let ent = self.world.entity(ent_id);
+        let origin = ent.origin()?;
+        let min = ent.min()?;
+        let max = ent.max()?;
+
+        let (trace, _) =
+            self.world
+                .move_entity(ent_id, origin, min, max, origin, CollideKind::Normal)?;
+
+        Ok(trace.start_solid() || trace.all_solid())
+    }
+
+    /// Moves a pusher such as a door or a platform along its velocity for `move_time`.
+    ///
+    /// Entities riding on the pusher or in its way are moved along with it. If one of them can't
+    /// be moved, everything is put back where it was and the pusher's `blocked` function is run.
     pub fn move_push(
         &mut self,
         ent_id: EntityId,
         _frame_time: Duration,
         move_time: Duration,
     ) -> Result<(), ProgsError> {
+        let move_time_f = duration_to_f32(move_time);
+
         let ent = self.world.entity_mut(ent_id)?;
+        let local_time = ent.load(FieldAddrFloat::LocalTime)?;
+        ent.store(FieldAddrFloat::LocalTime, local_time + move_time_f)?;
 
         let vel: Vector3<f32> = ent.load(FieldAddrVector::Velocity)?.into();
         if vel.is_zero() {
             // Entity doesn't need to move.
-            let local_time = ent.load(FieldAddrFloat::LocalTime)?;
-            let new_local_time = local_time + duration_to_f32(move_time);
-            ent.store(FieldAddrFloat::LocalTime, new_local_time)?;
             return Ok(());
         }
 
-        let move_time_f = duration_to_f32(move_time);
-        let _move_vector = vel * move_time_f;
-        // TODO let mins =
-        todo!()
+        let move_vector = vel * move_time_f;
+        let push_origin = ent.origin()?;
+        let push_min = ent.abs_min()? + move_vector;
+        let push_max = ent.abs_max()? + move_vector;
+        ent.store(FieldAddrVector::Origin, (push_origin + move_vector).into())?;
+        self.link_entity(ent_id, false)?;
+
+        // TODO: don't alloc
+        let mut ent_ids = Vec::new();
+        self.world.list_entities(&mut ent_ids);
+
+        // entities which have been moved, with their original positions
+        let mut moved = Vec::new();
+
+        for check_id in ent_ids {
+            if check_id == ent_id || !self.world.entity_exists(check_id) {
+                continue;
+            }
+
+            let check = self.world.entity(check_id);
+            if matches!(
+                check.move_kind()?,
+                MoveKind::Push | MoveKind::None | MoveKind::NoClip
+            ) {
+                continue;
+            }
+
+            let riding = check.flags()?.contains(EntityFlags::ON_GROUND)
+                && check.load(FieldAddrEntityId::Ground)? == ent_id;
+            if !riding {
+                let check_min = check.abs_min()?;
+                let check_max = check.abs_max()?;
+                if (0..3).any(|i| check_min[i] >= push_max[i] || check_max[i] <= push_min[i]) {
+                    continue;
+                }
+
+                // only push entities the pusher has actually run into
+                if !self.entity_stuck(check_id)? {
+                    continue;
+                }
+            }
+
+            moved.push((check_id, self.world.entity(check_id).origin()?));
+
+            // the pusher mustn't get in the way of what it's pushing
+            let ent = self.world.entity_mut(ent_id)?;
+            let solid = ent.load(FieldAddrFloat::Solid)?;
+            ent.store(FieldAddrFloat::Solid, EntitySolid::Not as u32 as f32)?;
+            let pushed = self.push_entity(check_id, move_vector);
+            self.world
+                .entity_mut(ent_id)?
+                .store(FieldAddrFloat::Solid, solid)?;
+            pushed?;
+
+            if !self.world.entity_exists(check_id) || !self.entity_stuck(check_id)? {
+                continue;
+            }
+
+            if matches!(
+                self.world.entity(check_id).solid()?,
+                EntitySolid::Not | EntitySolid::Trigger
+            ) {
+                // Corpses and the like can be left inside the pusher.
+                continue;
+            }
+
+            // Blocked, so put everything back.
+            let ent = self.world.entity_mut(ent_id)?;
+            ent.store(FieldAddrVector::Origin, push_origin.into())?;
+            ent.store(FieldAddrFloat::LocalTime, local_time)?;
+            self.link_entity(ent_id, false)?;
+
+            for (moved_id, origin) in moved {
+                if let Ok(moved_ent) = self.world.entity_mut(moved_id) {
+                    moved_ent.store(FieldAddrVector::Origin, origin.into())?;
+                    self.link_entity(moved_id, false)?;
+                }
+            }
+
+            let blocked = self
+                .world
+                .entity(ent_id)
+                .load(FieldAddrFunctionId::Blocked)?;
+            if blocked.0 != 0 {
+                self.globals.store(GlobalAddrEntity::Self_, ent_id)?;
+                self.globals.store(GlobalAddrEntity::Other, check_id)?;
+                self.execute_program(blocked)?;
+            }
+
+            break;
+        }
+
+        Ok(())
     }
 
     const MAX_BALLISTIC_COLLISIONS: usize = 4;
@@ -1596,18 +2625,294 @@ impl LevelState {
         Ok(())
     }
 
+    /// Adds a looping sound to the sign-on, so that every client plays it.
     pub fn builtin_ambient_sound(&mut self) -> Result<(), ProgsError> {
-        let _pos = self.globals.get_vector(GLOBAL_ADDR_ARG_0 as i16)?;
+        let pos = self.globals.get_vector(GLOBAL_ADDR_ARG_0 as i16)?;
         let name = self.globals.string_id(GLOBAL_ADDR_ARG_1 as i16)?;
-        let _volume = self.globals.get_float(GLOBAL_ADDR_ARG_2 as i16)?;
-        let _attenuation = self.globals.get_float(GLOBAL_ADDR_ARG_3 as i16)?;
+        let volume = self.globals.get_float(GLOBAL_ADDR_ARG_2 as i16)?;
+        let attenuation = self.globals.get_float(GLOBAL_ADDR_ARG_3 as i16)?;
 
-        let _sound_index = match self.sound_id(name) {
+        let sound_id = match self.sound_id(name) {
             Some(i) => i,
             None => return Err(ProgsError::with_msg("sound not precached")),
         };
 
-        // TODO: write to server signon packet
+        let protocol = self.protocol;
+        write_cmd(
+            &mut self.signon,
+            &ServerCmd::SpawnStaticSound {
+                origin: pos.into(),
+                sound_id: sound_id as u16,
+                volume: (volume * 255.0) as u8,
+                attenuation: (attenuation * 64.0) as u8,
+            },
+            protocol,
+        )
+    }
+
+    /// Adds an entity to the sign-on as a static entity and removes it from the
+    /// level.
+    ///
+    /// Static entities are drawn by the client but never updated, like torches.
+    pub fn builtin_make_static(&mut self) -> Result<(), ProgsError> {
+        let ent_id = self.globals.entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+        let state = self.entity_state(ent_id)?;
+
+        let protocol = self.protocol;
+        write_cmd(
+            &mut self.signon,
+            &ServerCmd::SpawnStatic {
+                model_id: state.model_id as u16,
+                frame_id: state.frame_id as u16,
+                colormap: state.colormap,
+                skin_id: state.skin_id as u8,
+                origin: state.origin,
+                angles: state.angles,
+                alpha: None,
+            },
+            protocol,
+        )?;
+
+        self.world.remove_entity(ent_id)?;
+
         Ok(())
     }
+
+    /// Returns the client slot of a client's entity.
+    fn client_slot(&self, ent_id: EntityId) -> Option<usize> {
+        match ent_id.0 {
+            0 => None,
+            n if n <= self.max_clients => Some(n - 1),
+            _ => None,
+        }
+    }
+
+    /// Concatenates the string arguments from `first` on, since the print
+    /// functions accept any number of strings.
+    fn var_string(&self, first: usize, arg_count: usize) -> Result<String, ProgsError> {
+        let strs = self.string_table.borrow();
+        let mut string = String::new();
+        for i in first..arg_count {
+            let s_id = self.globals.string_id((GLOBAL_ADDR_ARG_0 + 3 * i) as i16)?;
+            string.push_str(strs.get(s_id).unwrap_or_default());
+        }
+
+        Ok(string)
+    }
+
+    /// Queues a reliable command for the client controlling `ent_id`.
+    ///
+    /// Commands for entities which don't belong to a client are dropped with a
+    /// warning, as in the original engine.
+    fn write_client_cmd(&mut self, ent_id: EntityId, cmd: ServerCmd) -> Result<(), ProgsError> {
+        let slot = match self.client_slot(ent_id) {
+            Some(s) => s,
+            None => {
+                warn!("Can't send {} to non-client {}", cmd.name(), ent_id.0);
+                return Ok(());
+            }
+        };
+
+        let protocol = self.protocol;
+        write_cmd(self.messages.client_mut(slot), &cmd, protocol)
+    }
+
+    pub fn builtin_sound(&mut self) -> Result<(), ProgsError> {
+        let ent_id = self.globals.entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+        let channel = self.globals.get_float(GLOBAL_ADDR_ARG_1 as i16)? as i32;
+        let name_id = self.globals.string_id(GLOBAL_ADDR_ARG_2 as i16)?;
+        let volume = (self.globals.get_float(GLOBAL_ADDR_ARG_3 as i16)? * 255.0) as i32;
+        let attenuation = self.globals.get_float(GLOBAL_ADDR_ARG_4 as i16)?;
+
+        if !(0..=255).contains(&volume) {
+            return Err(ProgsError::with_msg(format!(
+                "Sound volume {} out of range",
+                volume
+            )));
+        }
+
+        if !(0.0..=4.0).contains(&attenuation) {
+            return Err(ProgsError::with_msg(format!(
+                "Sound attenuation {} out of range",
+                attenuation
+            )));
+        }
+
+        if !(0..8).contains(&channel) {
+            return Err(ProgsError::with_msg(format!(
+                "Sound channel {} out of range",
+                channel
+            )));
+        }
+
+        let sound_id = match self.sound_id(name_id) {
+            Some(i) => i,
+            None => {
+                let strs = self.string_table.borrow();
+                warn!(
+                    "Sound {} was not precached",
+                    strs.get(name_id).unwrap_or_default()
+                );
+                return Ok(());
+            }
+        };
+
+        // sounds are only effects, so they're dropped if the frame is full
+        if self.messages.unreliable.len() > MAX_DATAGRAM - 16 {
+            return Ok(());
+        }
+
+        // sounds come from the center of the entity
+        let ent = self.world.try_entity(ent_id)?;
+        let position = ent.origin()? + (ent.min()? + ent.max()?) * 0.5;
+
+        let protocol = self.protocol;
+        write_cmd(
+            &mut self.messages.unreliable,
+            &ServerCmd::Sound {
+                volume: match volume {
+                    255 => None,
+                    v => Some(v as u8),
+                },
+                attenuation: if attenuation == 1.0 {
+                    None
+                } else {
+                    Some(attenuation)
+                },
+                entity_id: ent_id.0 as u16,
+                channel: channel as i8,
+                sound_id: sound_id as u16,
+                position,
+            },
+            protocol,
+        )
+    }
+
+    pub fn builtin_stuff_cmd(&mut self) -> Result<(), ProgsError> {
+        let ent_id = self.globals.entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+        let text = self.var_string(1, 2)?;
+        self.write_client_cmd(ent_id, ServerCmd::StuffText { text })
+    }
+
+    pub fn builtin_bprint(&mut self, arg_count: usize) -> Result<(), ProgsError> {
+        let text = self.var_string(0, arg_count)?;
+        let protocol = self.protocol;
+        write_cmd(
+            &mut self.messages.reliable,
+            &ServerCmd::Print { text },
+            protocol,
+        )
+    }
+
+    pub fn builtin_sprint(&mut self, arg_count: usize) -> Result<(), ProgsError> {
+        let ent_id = self.globals.entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+        let text = self.var_string(1, arg_count)?;
+        self.write_client_cmd(ent_id, ServerCmd::Print { text })
+    }
+
+    pub fn builtin_center_print(&mut self, arg_count: usize) -> Result<(), ProgsError> {
+        let ent_id = self.globals.entity_id(GLOBAL_ADDR_ARG_0 as i16)?;
+        let text = self.var_string(1, arg_count)?;
+        self.write_client_cmd(ent_id, ServerCmd::CenterPrint { text })
+    }
+
+    /// Returns the message buffer selected by the first argument of a `Write*`
+    /// function.
+    fn write_dest(&mut self) -> Result<&mut Vec<u8>, ProgsError> {
+        let dest = self.globals.get_float(GLOBAL_ADDR_ARG_0 as i16)? as i32;
+        match MessageDest::from_i32(dest) {
+            Some(MessageDest::Broadcast) => Ok(&mut self.messages.unreliable),
+            Some(MessageDest::One) => {
+                let ent_id = self.globals.load(GlobalAddrEntity::MsgEntity)?;
+                match self.client_slot(ent_id) {
+                    Some(slot) => Ok(self.messages.client_mut(slot)),
+                    None => Err(ProgsError::with_msg(format!(
+                        "msg_entity {} is not a client",
+                        ent_id.0
+                    ))),
+                }
+            }
+            Some(MessageDest::All) => Ok(&mut self.messages.reliable),
+            Some(MessageDest::Init) => Ok(&mut self.signon),
+            None => Err(ProgsError::with_msg(format!(
+                "Invalid message destination {}",
+                dest
+            ))),
+        }
+    }
+
+    /// Implements the `Write*` functions, which append a value to a message.
+    pub fn builtin_write(&mut self, f: BuiltinFunctionId) -> Result<(), ProgsError> {
+        use BuiltinFunctionId::*;
+
+        let flags = self.protocol.flags;
+        let arg = GLOBAL_ADDR_ARG_1 as i16;
+        let net_err = |e: NetError| ProgsError::with_msg(e.to_string());
+
+        let mut data = Vec::new();
+        match f {
+            WriteByte => data.write_u8(self.globals.get_float(arg)? as i32 as u8)?,
+            WriteChar => data.write_i8(self.globals.get_float(arg)? as i32 as i8)?,
+            WriteShort => {
+                data.write_i16::<LittleEndian>(self.globals.get_float(arg)? as i32 as i16)?
+            }
+            WriteLong => data.write_i32::<LittleEndian>(self.globals.get_float(arg)? as i32)?,
+            WriteCoord => {
+                write_coord(&mut data, self.globals.get_float(arg)?, flags).map_err(net_err)?
+            }
+            WriteAngle => {
+                write_angle(&mut data, Deg(self.globals.get_float(arg)?), flags).map_err(net_err)?
+            }
+            WriteString => {
+                let s_id = self.globals.string_id(arg)?;
+                let strs = self.string_table.borrow();
+                data.extend(strs.get(s_id).unwrap_or_default().as_bytes());
+                data.push(0);
+            }
+            WriteEntity => data.write_i16::<LittleEndian>(self.globals.entity_id(arg)?.0 as i16)?,
+            _ => unreachable!(),
+        }
+
+        self.write_dest()?.extend(data);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_client_slots_fill_and_free() {
+        let mut slots = ClientSlots::new(2);
+        assert_eq!(slots.find_available(), Some(0));
+        assert_eq!(slots.find_available(), Some(1));
+        assert_eq!(slots.find_available(), None);
+        assert_eq!(slots.count(), 2);
+
+        assert!(matches!(slots.free(0), Some(ClientState::Connecting)));
+        assert!(slots.free(0).is_none());
+        assert_eq!(slots.count(), 1);
+
+        // freed slots are reused first
+        assert_eq!(slots.find_available(), Some(0));
+    }
+
+    #[test]
+    fn test_client_slots_reconnect_all() {
+        let mut slots = ClientSlots::new(3);
+        slots.find_available();
+        slots.find_available();
+        *slots.get_mut(1).unwrap() = ClientState::Active(ClientActive {
+            privileged: false,
+            entity_id: EntityId(2),
+            input: None,
+        });
+
+        slots.reconnect_all();
+        assert!(matches!(slots.get(0), Some(ClientState::Connecting)));
+        assert!(matches!(slots.get(1), Some(ClientState::Connecting)));
+        assert!(slots.get(2).is_none());
+    }
 }
@@ -0,0 +1,1786 @@
+// Copyright © 2018 Cormac O'Brien.
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of this software
+// and associated documentation files (the "Software"), to deal in the Software without
+// restriction, including without limitation the rights to use, copy, modify, merge, publish,
+// distribute, sublicense, and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all copies or
+// substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING
+// BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+// NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
+// DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
+
+//! Network front end for a server `Session`.
+//!
+//! This accepts connections and queries on a `ConnectListener`, gives each
+//! connected client its own `QSocket`, walks the client through the sign-on
+//! process and sends it the state of the level every frame. Clients in the
+//! same process connect over a `LoopbackSocket` instead.
+//!
+//! Console commands can be run on the server remotely with an rcon request
+//! carrying the `rcon_password`.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    io::{BufReader, ErrorKind},
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    rc::Rc,
+};
+
+use crate::{
+    common::{
+        console::{CmdRegistry, CvarRegistry},
+        net::{
+            connect::{
+                ConnectListener, Request, RequestRcon, Response, ResponseAccept,
+                ResponsePlayerInfo, ResponseRcon, ResponseReject, ResponseRuleInfo,
+                ResponseServerInfo, CONNECT_PROTOCOL_VERSION,
+            },
+            BlockingMode, ClientCmd, EntityState, EntityUpdate, GameType, Impairment,
+            LoopbackSocket, NetError, NetSocket, PlayerColor, Protocol, QSocket, ServerCmd,
+            SignOnStage, GAME_NAME, MAX_MESSAGE,
+        },
+        parse,
+        pmove::MoveInput,
+    },
+    server::{progs::EntityId, ServerError, Session, RULE_CVARS},
+};
+
+use cgmath::Deg;
+use chrono::Duration;
+
+const MAX_DATAGRAM: usize = 1024;
+
+/// Clients which send nothing for this long are dropped.
+const CLIENT_TIMEOUT_SECS: i64 = 300;
+
+/// A reconnect from the same address within this long of the original
+/// connection is treated as a lost `Accept` rather than a new connection.
+const RECONNECT_GRACE_SECS: i64 = 2;
+
+/// Minimum change in an entity's origin before it is sent to clients.
+const ORIGIN_EPSILON: f32 = 0.1;
+
+/// Longest rcon output that fits in a response packet.
+const MAX_RCON_OUTPUT: usize = MAX_MESSAGE - 6;
+
+/// Most connectionless requests handled in one frame. The rest wait for the
+/// next frame, so a flood of requests can't hold up the level.
+const MAX_REQUESTS_PER_FRAME: usize = 64;
+
+/// Console commands which act on the server itself.
+///
+/// Functions in the server's `CmdRegistry` can't borrow the server, so they
+/// queue one of these to be run after they return.
+#[derive(Debug, PartialEq)]
+enum ConsoleAction {
+    Status,
+    Kick(String),
+    ChangeLevel(String),
+}
+
+/// A client connected to the server.
+struct Connection {
+    sock: NetSocket,
+
+    /// The client's address, or `None` for loopback clients.
+    address: Option<SocketAddr>,
+
+    /// The port the client was told to send to.
+    port: u16,
+
+    name: String,
+    colors: u8,
+
+    /// Server uptime when the client connected.
+    connect_time: Duration,
+
+    /// Server uptime when the client last sent a message.
+    recv_time: Duration,
+
+    /// The client's entity, once it has spawned.
+    entity_id: Option<EntityId>,
+
+    /// Whether the client has completed the sign-on process.
+    spawned: bool,
+
+    /// The entity the next update starts from.
+    ///
+    /// Updates which don't fit in a datagram continue from here on the next
+    /// frame, so every entity gets its turn.
+    next_update: EntityId,
+
+    /// Reliable messages waiting for the previous message to be acknowledged.
+    reliable: VecDeque<Vec<u8>>,
+}
+
+impl Connection {
+    /// Makes sure the next reliable command starts a new message.
+    fn end_reliable_message(&mut self) {
+        if self.reliable.back().is_some_and(|m| !m.is_empty()) {
+            self.reliable.push_back(Vec::new());
+        }
+    }
+
+    /// Queues server commands to be sent reliably.
+    ///
+    /// Commands are appended to the last queued message as long as it stays
+    /// under `MAX_MESSAGE` bytes.
+    fn send_reliable(&mut self, cmds: &[ServerCmd], protocol: Protocol) -> Result<(), NetError> {
+        for cmd in cmds {
+            let mut buf = Vec::new();
+            cmd.serialize(&mut buf, protocol)?;
+
+            match self.reliable.back_mut() {
+                Some(last) if last.len() + buf.len() <= MAX_MESSAGE => last.extend(buf),
+                _ => self.reliable.push_back(buf),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A server `Session` together with its network connections.
+pub struct NetServer {
+    /// Listener for connection requests and queries, if the server is public.
+    listener: Option<ConnectListener>,
+    session: Session,
+    cvars: Rc<RefCell<CvarRegistry>>,
+    protocol: Protocol,
+
+    /// Connections indexed by client slot.
+    connections: Vec<Option<Connection>>,
+
+    /// Unreliable messages written by QuakeC this frame, which go out with
+    /// every client's update.
+    datagram: Vec<u8>,
+
+    /// Commands available to rcon.
+    cmds: CmdRegistry,
+
+    /// Actions queued by the last command in `cmds`.
+    actions: Rc<RefCell<Vec<ConsoleAction>>>,
+
+    /// Total time elapsed over all frames.
+    uptime: Duration,
+}
+
+impl NetServer {
+    /// Creates a server which only accepts loopback clients.
+    ///
+    /// The protocol is the one the level was loaded with, which is taken from
+    /// the `sv_protocol` cvar.
+    pub fn new(session: Session, cvars: Rc<RefCell<CvarRegistry>>) -> NetServer {
+        let protocol = session.level().protocol();
+
+        let mut connections = Vec::new();
+        connections.resize_with(session.max_clients(), || None);
+
+        let actions = Rc::new(RefCell::new(Vec::new()));
+        let mut cmds = CmdRegistry::new(Rc::new(RefCell::new(Vec::new())));
+        register_cmds(&mut cmds, actions.clone());
+
+        NetServer {
+            listener: None,
+            session,
+            cvars,
+            protocol,
+            connections,
+            datagram: Vec::new(),
+            cmds,
+            actions,
+            uptime: Duration::zero(),
+        }
+    }
+
+    /// Creates a server which accepts clients on the given address.
+    pub fn bind<A>(
+        addr: A,
+        session: Session,
+        cvars: Rc<RefCell<CvarRegistry>>,
+    ) -> Result<NetServer, ServerError>
+    where
+        A: ToSocketAddrs,
+    {
+        let mut server = NetServer::new(session, cvars);
+        server.listen(addr)?;
+        Ok(server)
+    }
+
+    /// Begins accepting network clients on the given address.
+    pub fn listen<A>(&mut self, addr: A) -> Result<(), ServerError>
+    where
+        A: ToSocketAddrs,
+    {
+        let listener = ConnectListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        self.listener = Some(listener);
+        Ok(())
+    }
+
+    /// Returns the address the listener is bound to, if there is one.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.as_ref()?.local_addr().ok()
+    }
+
+    /// Connects a client in the same process.
+    ///
+    /// Returns the client's end of the connection, or `None` if the server is
+    /// full.
+    pub fn connect_loopback(&mut self) -> Result<Option<LoopbackSocket>, ServerError> {
+        let slot = match self.session.connect_client() {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+
+        let (client, server) = LoopbackSocket::pair();
+        self.add_client(slot, server.into(), None, 0)?;
+
+        Ok(Some(client))
+    }
+
+    /// Returns the server session.
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Runs console commands on the server and returns their output.
+    ///
+    /// Server commands are looked up in the server's command registry.
+    /// Anything else is treated as a cvar, which is printed or set.
+    pub fn exec(&mut self, text: &str) -> Result<String, ServerError> {
+        let text = format!("{}\n", text);
+        let commands = match parse::console::commands(&text) {
+            Ok((_, c)) => c,
+            Err(_) => return Ok(String::from("Couldn't parse command\n")),
+        };
+
+        let mut output = String::new();
+        for args in commands {
+            let (name, args) = match args.split_first() {
+                Some(a) => a,
+                None => continue,
+            };
+
+            if self.cmds.contains(name) {
+                match self.cmds.exec(name, args) {
+                    Ok(o) => push_line(&mut output, &o),
+                    Err(e) => push_line(&mut output, &e.to_string()),
+                }
+
+                let actions: Vec<_> = self.actions.borrow_mut().drain(..).collect();
+                for action in actions {
+                    let o = self.run_action(action)?;
+                    push_line(&mut output, &o);
+                }
+            } else if self.cvars.borrow().contains(name) {
+                let cvars = self.cvars.borrow();
+                match args.first() {
+                    Some(value) => {
+                        if let Err(e) = cvars.set(name, value) {
+                            push_line(&mut output, &e.to_string());
+                        }
+                    }
+                    None => push_line(
+                        &mut output,
+                        &format!("\"{}\" is \"{}\"", name, cvars.get(name).unwrap()),
+                    ),
+                }
+            } else {
+                push_line(&mut output, &format!("Unrecognized command \"{}\"", name));
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Loads a new level and signs every client on again.
+    ///
+    /// If the map can't be loaded, the current level keeps running and the
+    /// error is returned.
+    pub fn change_level(&mut self, map_name: &str) -> Result<(), ServerError> {
+        self.session.change_level(map_name)?;
+        info!("Changed level to {}", map_name);
+
+        // the new level may have been loaded with a different protocol
+        self.protocol = self.session.level().protocol();
+        self.datagram.clear();
+
+        let protocol = self.protocol;
+        for slot in 0..self.connections.len() {
+            let cmds = self.server_info_cmds(slot);
+            let conn = match self.connections[slot].as_mut() {
+                Some(c) => c,
+                None => continue,
+            };
+
+            // anything not yet sent belongs to the old level
+            conn.reliable.clear();
+            conn.entity_id = None;
+            conn.spawned = false;
+
+            // the client must run `reconnect` before it sees the new sign-on
+            conn.send_reliable(
+                &[ServerCmd::StuffText {
+                    text: String::from("reconnect\n"),
+                }],
+                protocol,
+            )?;
+            conn.end_reliable_message();
+            conn.send_reliable(&cmds, protocol)?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs a single server frame.
+    ///
+    /// This answers pending queries and connection requests, handles client
+    /// messages, runs the level and sends each spawned client an update.
+    ///
+    /// Errors from individual clients cause those clients to be dropped;
+    /// errors from the level itself are returned.
+    pub fn frame(&mut self, frame_time: Duration) -> Result<(), ServerError> {
+        self.uptime = self.uptime + frame_time;
+
+        let impairment = Impairment::from_cvars(&self.cvars.borrow());
+        for conn in self.connections.iter_mut().flatten() {
+            conn.sock.set_impairment(impairment);
+        }
+
+        self.handle_requests()?;
+
+        for slot in 0..self.connections.len() {
+            if self.connections[slot].is_none() {
+                continue;
+            }
+
+            if let Err(e) = self.read_client(slot) {
+                warn!("Dropping client {}: {}", slot, e);
+                self.drop_client(slot)?;
+            }
+        }
+
+        self.session.frame(frame_time)?;
+        self.queue_messages()?;
+
+        for slot in 0..self.connections.len() {
+            if self.connections[slot].is_none() {
+                continue;
+            }
+
+            if let Err(e) = self.send_client(slot) {
+                warn!("Dropping client {}: {}", slot, e);
+                self.drop_client(slot)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Queues the messages QuakeC wrote this frame for the clients they're
+    /// addressed to.
+    fn queue_messages(&mut self) -> Result<(), ServerError> {
+        let messages = self.session.take_messages();
+        let protocol = self.protocol;
+
+        let reliable = read_server_cmds(&messages.reliable, protocol);
+        for slot in 0..self.connections.len() {
+            let conn = match self.connections[slot].as_mut() {
+                Some(c) => c,
+                None => continue,
+            };
+
+            let mut result = conn.send_reliable(&reliable, protocol);
+            if let Some(msg) = messages.clients.get(slot) {
+                result = result
+                    .and_then(|_| conn.send_reliable(&read_server_cmds(msg, protocol), protocol));
+            }
+
+            if let Err(e) = result {
+                warn!("Dropping client {}: {}", slot, e);
+                self.drop_client(slot)?;
+            }
+        }
+
+        self.datagram = messages.unreliable;
+
+        Ok(())
+    }
+
+    /// Disconnects all clients.
+    pub fn shutdown(&mut self) {
+        for slot in 0..self.connections.len() {
+            if let Some(ref mut conn) = self.connections[slot] {
+                let mut msg = Vec::new();
+                if ServerCmd::Disconnect
+                    .serialize(&mut msg, self.protocol)
+                    .is_ok()
+                {
+                    let _ = conn.sock.send_msg_unreliable(&msg);
+                }
+            }
+
+            if let Err(e) = self.drop_client(slot) {
+                warn!("Error dropping client {}: {}", slot, e);
+            }
+        }
+    }
+
+    fn handle_requests(&mut self) -> Result<(), ServerError> {
+        for _ in 0..MAX_REQUESTS_PER_FRAME {
+            let listener = match self.listener {
+                Some(ref l) => l,
+                None => return Ok(()),
+            };
+
+            let (request, remote) = match listener.recv_request() {
+                Ok(r) => r,
+                Err(NetError::Io(e)) => match e.kind() {
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut => return Ok(()),
+
+                    // truncated requests and ICMP errors from departed clients
+                    ErrorKind::UnexpectedEof
+                    | ErrorKind::ConnectionRefused
+                    | ErrorKind::ConnectionReset => {
+                        debug!("Bad request: {}", e);
+                        continue;
+                    }
+
+                    _ => return Err(e.into()),
+                },
+
+                // malformed requests shouldn't take the server down
+                Err(e) => {
+                    debug!("Bad request: {}", e);
+                    continue;
+                }
+            };
+
+            // nor should a request the server fails to handle
+            let response = match self.handle_request(request, remote) {
+                Ok(r) => r,
+                Err(e) => {
+                    warn!("Couldn't handle request from {}: {}", remote, e);
+                    continue;
+                }
+            };
+
+            if let (Some(r), Some(listener)) = (response, self.listener.as_ref()) {
+                if let Err(e) = listener.send_response(r, remote) {
+                    debug!("Failed to respond to {}: {}", remote, e);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the response to a connectionless request, if it gets one.
+    fn handle_request(
+        &mut self,
+        request: Request,
+        remote: SocketAddr,
+    ) -> Result<Option<Response>, ServerError> {
+        Ok(match request {
+            Request::Connect(connect) => {
+                if connect.game_name != GAME_NAME || connect.proto_ver != CONNECT_PROTOCOL_VERSION {
+                    Some(Response::Reject(ResponseReject {
+                        message: String::from("Incompatible version.\n"),
+                    }))
+                } else {
+                    Some(self.accept(remote)?)
+                }
+            }
+
+            Request::ServerInfo(info) => {
+                if info.game_name != GAME_NAME {
+                    None
+                } else {
+                    Some(Response::ServerInfo(self.server_info()?))
+                }
+            }
+
+            Request::PlayerInfo(info) => {
+                self.player_info(info.player_id)?.map(Response::PlayerInfo)
+            }
+
+            Request::RuleInfo(info) => Some(Response::RuleInfo(self.rule_info(&info.prev_cvar))),
+
+            Request::Rcon(rcon) => Some(Response::Rcon(self.rcon(rcon, remote)?)),
+        })
+    }
+
+    fn accept(&mut self, remote: SocketAddr) -> Result<Response, ServerError> {
+        let existing = self
+            .connections
+            .iter()
+            .position(|c| c.as_ref().is_some_and(|c| c.address == Some(remote)));
+
+        if let Some(slot) = existing {
+            let conn = self.connections[slot].as_ref().unwrap();
+            if self.uptime - conn.connect_time < Duration::seconds(RECONNECT_GRACE_SECS) {
+                // the client probably didn't receive our first response
+                return Ok(Response::Accept(ResponseAccept {
+                    port: conn.port as i32,
+                }));
+            }
+
+            // the client is reconnecting, so throw away the old connection
+            self.drop_client(slot)?;
+        }
+
+        let slot = match self.session.connect_client() {
+            Some(s) => s,
+            None => {
+                return Ok(Response::Reject(ResponseReject {
+                    message: String::from("Server is full.\n"),
+                }))
+            }
+        };
+
+        let local = match self.local_addr() {
+            Some(SocketAddr::V6(_)) => SocketAddr::from(([0u16; 8], 0)),
+            _ => SocketAddr::from(([0, 0, 0, 0], 0)),
+        };
+        let bound = UdpSocket::bind(local).and_then(|s| {
+            let port = s.local_addr()?.port();
+            Ok((s, port))
+        });
+        let (socket, port) = match bound {
+            Ok(b) => b,
+            Err(e) => {
+                // out of sockets or ports, which shouldn't affect anyone
+                // already connected
+                warn!("Couldn't open a socket for {}: {}", remote, e);
+                self.session.drop_client(slot)?;
+                return Ok(Response::Reject(ResponseReject {
+                    message: String::from("Couldn't open a connection.\n"),
+                }));
+            }
+        };
+
+        let sock = QSocket::new(socket, remote).into();
+        self.add_client(slot, sock, Some(remote), port)?;
+
+        Ok(Response::Accept(ResponseAccept { port: port as i32 }))
+    }
+
+    /// Sets up a connection in `slot` and sends the client the server info.
+    fn add_client(
+        &mut self,
+        slot: usize,
+        sock: NetSocket,
+        address: Option<SocketAddr>,
+        port: u16,
+    ) -> Result<(), ServerError> {
+        let mut conn = Connection {
+            sock,
+            address,
+            port,
+            name: String::from("unconnected"),
+            colors: 0,
+            connect_time: self.uptime,
+            recv_time: self.uptime,
+            entity_id: None,
+            spawned: false,
+            next_update: EntityId(1),
+            reliable: VecDeque::new(),
+        };
+
+        conn.send_reliable(&self.server_info_cmds(slot), self.protocol)?;
+
+        match address {
+            Some(a) => info!("Client {} connected from {}", slot, a),
+            None => info!("Client {} connected locally", slot),
+        }
+        self.connections[slot] = Some(conn);
+
+        Ok(())
+    }
+
+    /// Returns the commands which begin the sign-on process for `slot`.
+    fn server_info_cmds(&self, slot: usize) -> Vec<ServerCmd> {
+        let level = self.session.level();
+        let game_type = match self.cvars.borrow().get_value("deathmatch").unwrap_or(0.0) {
+            d if d != 0.0 => GameType::Deathmatch,
+            _ => GameType::CoOp,
+        };
+
+        vec![
+            ServerCmd::Print {
+                text: format!("\x02\nRICHTER SERVER {}\n", env!("CARGO_PKG_VERSION")),
+            },
+            ServerCmd::ServerInfo {
+                protocol_version: self.protocol.version.value(),
+                protocol_flags: self.protocol.flags,
+                max_clients: self.session.max_clients() as u8,
+                game_type,
+                message: level.message().to_owned(),
+                model_precache: level.model_names().map(|s| s.to_owned()).collect(),
+                sound_precache: level.sound_names().map(|s| s.to_owned()).collect(),
+            },
+            ServerCmd::CdTrack {
+                track: level.cd_track(),
+                loop_: level.cd_track(),
+            },
+            ServerCmd::SetView {
+                ent_id: (slot + 1) as i16,
+            },
+            ServerCmd::SignOnStage {
+                stage: SignOnStage::Prespawn,
+            },
+        ]
+    }
+
+    fn server_info(&self) -> Result<ResponseServerInfo, ServerError> {
+        Ok(ResponseServerInfo {
+            address: self
+                .local_addr()
+                .map_or_else(|| String::from("local"), |a| a.to_string()),
+            hostname: self
+                .cvars
+                .borrow()
+                .get("hostname")
+                .unwrap_or_else(|_| String::from("UNNAMED")),
+            levelname: self.session.level().map_name().to_owned(),
+            client_count: self.connections.iter().filter(|c| c.is_some()).count() as u8,
+            client_max: self.session.max_clients() as u8,
+            protocol_version: CONNECT_PROTOCOL_VERSION,
+        })
+    }
+
+    fn player_info(&self, player_id: u8) -> Result<Option<ResponsePlayerInfo>, ServerError> {
+        // the player ID counts active clients only
+        let (slot, conn) = match self
+            .connections
+            .iter()
+            .enumerate()
+            .filter_map(|(i, c)| Some((i, c.as_ref()?)))
+            .filter(|(_, c)| c.spawned)
+            .nth(player_id as usize)
+        {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        let frags = match conn.entity_id {
+            Some(id) => self.session.level().frags(id)?,
+            None => 0,
+        };
+
+        Ok(Some(ResponsePlayerInfo {
+            player_id: slot as u8,
+            player_name: conn.name.clone(),
+            colors: conn.colors as i32,
+            frags,
+            connect_duration: (self.uptime - conn.connect_time).num_seconds() as i32,
+            address: conn
+                .address
+                .map_or_else(|| String::from("local"), |a| a.to_string()),
+        }))
+    }
+
+    fn rule_info(&self, prev_cvar: &str) -> ResponseRuleInfo {
+        // an empty previous cvar requests the first rule, and an empty
+        // response marks the end of the list
+        let next = match prev_cvar {
+            "" => RULE_CVARS.first(),
+            prev => RULE_CVARS
+                .iter()
+                .position(|c| *c == prev)
+                .and_then(|i| RULE_CVARS.get(i + 1)),
+        };
+
+        match next {
+            Some(name) => ResponseRuleInfo {
+                cvar_name: name.to_string(),
+                cvar_val: self.cvars.borrow().get(name).unwrap_or_default(),
+            },
+            None => ResponseRuleInfo {
+                cvar_name: String::new(),
+                cvar_val: String::new(),
+            },
+        }
+    }
+
+    fn rcon(&mut self, rcon: RequestRcon, remote: SocketAddr) -> Result<ResponseRcon, ServerError> {
+        let password = self.cvars.borrow().get("rcon_password").unwrap_or_default();
+        if password.is_empty() {
+            return Ok(ResponseRcon {
+                message: String::from("rcon is disabled on this server.\n"),
+            });
+        }
+
+        if rcon.password != password {
+            warn!("Bad rcon password from {}", remote);
+            return Ok(ResponseRcon {
+                message: String::from("Bad rcon_password.\n"),
+            });
+        }
+
+        info!("rcon from {}: {}", remote, rcon.command);
+        let mut message = self.exec(&rcon.command)?;
+
+        if message.len() > MAX_RCON_OUTPUT {
+            let mut end = MAX_RCON_OUTPUT;
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+            message.truncate(end);
+        }
+
+        Ok(ResponseRcon { message })
+    }
+
+    fn run_action(&mut self, action: ConsoleAction) -> Result<String, ServerError> {
+        Ok(match action {
+            ConsoleAction::Status => self.status(),
+
+            ConsoleAction::Kick(target) => match self.find_client(&target) {
+                Some(slot) => {
+                    let name = self.connections[slot].as_ref().unwrap().name.clone();
+                    self.kick_client(slot)?;
+                    format!("Kicked {}", name)
+                }
+                None => format!("No client matching \"{}\"", target),
+            },
+
+            ConsoleAction::ChangeLevel(map_name) => match self.change_level(&map_name) {
+                Ok(()) => format!("Changing level to {}", map_name),
+                Err(e @ ServerError::Net(_)) => return Err(e),
+                Err(e) => format!("Couldn't change level to {}: {}", map_name, e),
+            },
+        })
+    }
+
+    /// Describes the server and its clients in the style of the original
+    /// `status` command.
+    fn status(&self) -> String {
+        let mut status = format!(
+            "host:    {}\nmap:     {}\nplayers: {} active ({} max)\n\n",
+            self.cvars
+                .borrow()
+                .get("hostname")
+                .unwrap_or_else(|_| String::from("UNNAMED")),
+            self.session.level().map_name(),
+            self.connections.iter().filter(|c| c.is_some()).count(),
+            self.session.max_clients(),
+        );
+
+        for (slot, conn) in self.connections.iter().enumerate() {
+            let conn = match conn {
+                Some(c) => c,
+                None => continue,
+            };
+
+            let frags = match conn.entity_id {
+                Some(id) => self.session.level().frags(id).unwrap_or(0),
+                None => 0,
+            };
+            let time = (self.uptime - conn.connect_time).num_seconds();
+
+            status.push_str(&format!(
+                "#{:<2} {:<16.16} {:>3} {:>2}:{:02}:{:02}\n   {}\n",
+                slot + 1,
+                conn.name,
+                frags,
+                time / 3600,
+                time / 60 % 60,
+                time % 60,
+                conn.address
+                    .map_or_else(|| String::from("local"), |a| a.to_string()),
+            ));
+        }
+
+        status
+    }
+
+    /// Finds a client by name or by `#` followed by its user ID.
+    fn find_client(&self, target: &str) -> Option<usize> {
+        if let Some(id) = target.strip_prefix('#') {
+            let slot = id.trim().parse::<usize>().ok()?.checked_sub(1)?;
+            return self.connections.get(slot)?.as_ref().map(|_| slot);
+        }
+
+        self.connections
+            .iter()
+            .position(|c| c.as_ref().is_some_and(|c| c.name == target))
+    }
+
+    fn kick_client(&mut self, slot: usize) -> Result<(), ServerError> {
+        if let Some(ref mut conn) = self.connections[slot] {
+            let mut msg = Vec::new();
+            ServerCmd::Print {
+                text: String::from("Kicked by server console\n"),
+            }
+            .serialize(&mut msg, self.protocol)?;
+            ServerCmd::Disconnect.serialize(&mut msg, self.protocol)?;
+
+            // the client is dropped whether or not this arrives
+            let _ = conn.sock.send_msg_unreliable(&msg);
+        }
+
+        info!("Kicked client {}", slot);
+        self.drop_client(slot)
+    }
+
+    fn read_client(&mut self, slot: usize) -> Result<(), ServerError> {
+        loop {
+            let msg = {
+                let conn = self.connections[slot].as_mut().unwrap();
+                let msg = conn.sock.recv_msg(BlockingMode::NonBlocking)?;
+                if msg.is_empty() {
+                    break;
+                }
+                conn.recv_time = self.uptime;
+                msg
+            };
+
+            let mut reader = BufReader::new(msg.as_slice());
+            while let Some(cmd) = read_client_cmd(&mut reader, self.protocol)? {
+                match cmd {
+                    ClientCmd::Bad => {
+                        return Err(NetError::InvalidData(String::from("bad client command")).into())
+                    }
+
+                    ClientCmd::NoOp => (),
+
+                    ClientCmd::Disconnect => {
+                        info!("Client {} disconnected", slot);
+                        return self.drop_client(slot);
+                    }
+
+                    ClientCmd::Move {
+                        button_flags,
+                        impulse,
+                        ..
+                    } => {
+                        if let (Some(id), Some(input)) = (
+                            self.connections[slot].as_ref().unwrap().entity_id,
+                            MoveInput::from_cmd(&cmd),
+                        ) {
+                            self.session
+                                .set_client_move(id, input, button_flags, impulse)?;
+                        }
+                    }
+
+                    ClientCmd::StringCmd { cmd } => self.exec_client_cmd(slot, &cmd)?,
+                }
+
+                if self.connections[slot].is_none() {
+                    // the client was dropped
+                    return Ok(());
+                }
+            }
+        }
+
+        let conn = self.connections[slot].as_ref().unwrap();
+        if self.uptime - conn.recv_time > Duration::seconds(CLIENT_TIMEOUT_SECS) {
+            info!("Client {} timed out", slot);
+            self.drop_client(slot)?;
+        }
+
+        Ok(())
+    }
+
+    fn exec_client_cmd(&mut self, slot: usize, cmd: &str) -> Result<(), ServerError> {
+        let mut args = cmd.split_whitespace();
+        let name = match args.next() {
+            Some(n) => n,
+            None => return Ok(()),
+        };
+
+        match name {
+            "prespawn" => self.prespawn(slot)?,
+            "spawn" => self.spawn(slot)?,
+            "begin" => {
+                let conn = self.connections[slot].as_mut().unwrap();
+                conn.spawned = true;
+                info!("{} entered the game", conn.name);
+            }
+
+            "name" => {
+                let new_name = cmd[name.len()..].trim().trim_matches('"');
+                let new_name: String = new_name.chars().take(15).collect();
+                self.connections[slot].as_mut().unwrap().name = new_name.clone();
+                self.broadcast_reliable(&[ServerCmd::UpdateName {
+                    player_id: slot as u8,
+                    new_name,
+                }])?;
+            }
+
+            "color" => {
+                let top: u8 = args.next().and_then(|a| a.parse().ok()).unwrap_or(0);
+                let bottom: u8 = args.next().and_then(|a| a.parse().ok()).unwrap_or(top);
+                let colors = PlayerColor::new(top.min(13), bottom.min(13));
+                self.connections[slot].as_mut().unwrap().colors = colors.bits();
+                self.broadcast_reliable(&[ServerCmd::UpdateColors {
+                    player_id: slot as u8,
+                    new_colors: colors,
+                }])?;
+            }
+
+            "say" | "say_team" => {
+                let text = cmd[name.len()..].trim().trim_matches('"');
+                let sender = self.connections[slot].as_ref().unwrap().name.clone();
+                self.broadcast_reliable(&[ServerCmd::Print {
+                    text: format!("\x01{}: {}\n", sender, text),
+                }])?;
+            }
+
+            // TODO: remaining client commands (kill, pause, god, ...)
+            _ => debug!("Client {} sent unhandled command: {}", slot, cmd),
+        }
+
+        Ok(())
+    }
+
+    fn prespawn(&mut self, slot: usize) -> Result<(), ServerError> {
+        let level = self.session.level();
+
+        let mut cmds = read_server_cmds(level.signon(), self.protocol);
+        for ent_id in level.entity_ids() {
+            if let Some(baseline) = level.baseline(ent_id) {
+                cmds.push(ServerCmd::SpawnBaseline {
+                    ent_id: ent_id.0 as u16,
+                    model_id: baseline.model_id as u16,
+                    frame_id: baseline.frame_id as u16,
+                    colormap: baseline.colormap,
+                    skin_id: baseline.skin_id as u8,
+                    origin: baseline.origin,
+                    angles: baseline.angles,
+                    alpha: None,
+                });
+            }
+        }
+
+        cmds.push(ServerCmd::SignOnStage {
+            stage: SignOnStage::ClientInfo,
+        });
+
+        let protocol = self.protocol;
+        self.connections[slot]
+            .as_mut()
+            .unwrap()
+            .send_reliable(&cmds, protocol)?;
+
+        Ok(())
+    }
+
+    fn spawn(&mut self, slot: usize) -> Result<(), ServerError> {
+        let (name, colors) = {
+            let conn = self.connections[slot].as_ref().unwrap();
+            (conn.name.clone(), conn.colors)
+        };
+
+        let ent_id = self.session.spawn_client(slot, &name, colors)?;
+        let level = self.session.level();
+
+        let mut cmds = Vec::new();
+
+        // tell the client about everyone, and everyone about the client
+        for (i, conn) in self.connections.iter().enumerate() {
+            if let Some(c) = conn {
+                let frags = match c.entity_id {
+                    Some(id) => level.frags(id)?,
+                    None => 0,
+                };
+
+                cmds.push(ServerCmd::UpdateName {
+                    player_id: i as u8,
+                    new_name: c.name.clone(),
+                });
+                cmds.push(ServerCmd::UpdateFrags {
+                    player_id: i as u8,
+                    new_frags: frags as i16,
+                });
+                cmds.push(ServerCmd::UpdateColors {
+                    player_id: i as u8,
+                    new_colors: PlayerColor::from_bits(c.colors),
+                });
+            }
+        }
+
+        for (id, value) in level.lightstyles().into_iter().enumerate() {
+            cmds.push(ServerCmd::LightStyle {
+                id: id as u8,
+                value,
+            });
+        }
+
+        cmds.push(ServerCmd::SetAngle {
+            angles: level.view_angles(ent_id)?,
+        });
+        cmds.push(ServerCmd::PlayerData(level.client_data(ent_id)?));
+        cmds.push(ServerCmd::SignOnStage {
+            stage: SignOnStage::Begin,
+        });
+
+        let protocol = self.protocol;
+        let conn = self.connections[slot].as_mut().unwrap();
+        conn.entity_id = Some(ent_id);
+        conn.send_reliable(&cmds, protocol)?;
+
+        let (name, colors) = (conn.name.clone(), conn.colors);
+        self.broadcast_reliable(&[
+            ServerCmd::UpdateName {
+                player_id: slot as u8,
+                new_name: name,
+            },
+            ServerCmd::UpdateColors {
+                player_id: slot as u8,
+                new_colors: PlayerColor::from_bits(colors),
+            },
+        ])?;
+
+        Ok(())
+    }
+
+    /// Queues reliable commands for every connected client.
+    fn broadcast_reliable(&mut self, cmds: &[ServerCmd]) -> Result<(), ServerError> {
+        let protocol = self.protocol;
+        for conn in self.connections.iter_mut().flatten() {
+            conn.send_reliable(cmds, protocol)?;
+        }
+
+        Ok(())
+    }
+
+    fn send_client(&mut self, slot: usize) -> Result<(), ServerError> {
+        if self.connections[slot].as_ref().unwrap().spawned {
+            let (datagram, next_update) = self.client_datagram(slot)?;
+            let conn = self.connections[slot].as_mut().unwrap();
+            conn.next_update = next_update;
+            conn.sock.send_msg_unreliable(&datagram)?;
+        }
+
+        // lost packets are resent by the socket itself
+        let conn = self.connections[slot].as_mut().unwrap();
+        if conn.sock.can_send() {
+            if let Some(msg) = conn.reliable.pop_front() {
+                conn.sock.begin_send_msg(&msg)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds the unreliable per-frame update for a spawned client.
+    ///
+    /// The client's own entity is always updated. The other entities are
+    /// updated in turn starting from `Connection::next_update`, and the entity
+    /// to start from on the next frame is returned with the message.
+    fn client_datagram(&self, slot: usize) -> Result<(Vec<u8>, EntityId), ServerError> {
+        let level = self.session.level();
+        let conn = self.connections[slot].as_ref().unwrap();
+        let client_ent = conn.entity_id;
+
+        let mut msg = Vec::new();
+        ServerCmd::Time {
+            time: level.time().num_milliseconds() as f32 / 1000.0,
+        }
+        .serialize(&mut msg, self.protocol)?;
+
+        if let Some(id) = client_ent {
+            ServerCmd::PlayerData(level.client_data(id)?).serialize(&mut msg, self.protocol)?;
+        }
+
+        // Sounds and effects go before the entities, which can wait for the
+        // next frame if they don't fit.
+        if msg.len() + self.datagram.len() <= MAX_DATAGRAM {
+            msg.extend(&self.datagram);
+        }
+
+        let mut ent_ids: Vec<_> = level
+            .entity_ids()
+            .into_iter()
+            .filter(|id| id.0 != 0 && Some(*id) != client_ent)
+            .collect();
+        let start = ent_ids
+            .iter()
+            .position(|id| id.0 >= conn.next_update.0)
+            .unwrap_or(0);
+        ent_ids.rotate_left(start);
+        let ent_ids = client_ent.into_iter().chain(ent_ids);
+
+        let uninitialized = EntityState::uninitialized();
+        for ent_id in ent_ids {
+            let state = level.entity_state(ent_id)?;
+            if state.model_id == 0 && Some(ent_id) != client_ent {
+                continue;
+            }
+
+            let baseline = level.baseline(ent_id).unwrap_or(&uninitialized);
+            let update = entity_update(ent_id, baseline, &state);
+
+            let mut buf = Vec::new();
+            if let Err(e) = update.serialize(&mut buf, self.protocol) {
+                debug!("Skipping entity {}: {}", ent_id.0, e);
+                continue;
+            }
+
+            if msg.len() + buf.len() > MAX_DATAGRAM {
+                // This entity and the ones after it miss this frame's update
+                // and go first in the next one.
+                return Ok((msg, ent_id));
+            }
+
+            msg.extend(buf);
+        }
+
+        Ok((msg, conn.next_update))
+    }
+
+    fn drop_client(&mut self, slot: usize) -> Result<(), ServerError> {
+        let conn = match self.connections[slot].take() {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+
+        // the connection is gone, so the rest of the teardown has to happen
+        if let Err(e) = self.session.drop_client(slot) {
+            warn!("Error removing client {} from the level: {}", slot, e);
+        }
+
+        if conn.spawned {
+            self.broadcast_reliable(&[
+                ServerCmd::Print {
+                    text: format!("{} left the game\n", conn.name),
+                },
+                ServerCmd::UpdateName {
+                    player_id: slot as u8,
+                    new_name: String::new(),
+                },
+                ServerCmd::UpdateFrags {
+                    player_id: slot as u8,
+                    new_frags: 0,
+                },
+                ServerCmd::UpdateColors {
+                    player_id: slot as u8,
+                    new_colors: PlayerColor::from_bits(0),
+                },
+            ])?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes the commands in a message written by QuakeC.
+///
+/// QuakeC can write any bytes it likes, so everything from the first command
+/// which can't be decoded is dropped with a warning.
+fn read_server_cmds(msg: &[u8], protocol: Protocol) -> Vec<ServerCmd> {
+    let mut reader = BufReader::new(msg);
+    let mut cmds = Vec::new();
+    loop {
+        match ServerCmd::deserialize(&mut reader, protocol) {
+            Ok(Some(cmd)) => cmds.push(cmd),
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Dropping malformed message from QuakeC: {}", e);
+                break;
+            }
+        }
+    }
+
+    cmds
+}
+
+fn read_client_cmd(
+    reader: &mut BufReader<&[u8]>,
+    protocol: Protocol,
+) -> Result<Option<ClientCmd>, NetError> {
+    use std::io::BufRead as _;
+
+    if reader.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(ClientCmd::deserialize(reader, protocol)?))
+}
+
+fn register_cmds(cmds: &mut CmdRegistry, actions: Rc<RefCell<Vec<ConsoleAction>>>) {
+    let status = actions.clone();
+    cmds.insert(
+        "status",
+        Box::new(move |_| {
+            status.borrow_mut().push(ConsoleAction::Status);
+            String::new()
+        }),
+    )
+    .unwrap();
+
+    let kick = actions.clone();
+    cmds.insert(
+        "kick",
+        Box::new(move |args| match args.len() {
+            0 => String::from("usage: kick <name> | kick # <user ID>"),
+            _ => {
+                kick.borrow_mut().push(ConsoleAction::Kick(args.join(" ")));
+                String::new()
+            }
+        }),
+    )
+    .unwrap();
+
+    for name in ["map", "changelevel"] {
+        let change_level = actions.clone();
+        cmds.insert(
+            name,
+            Box::new(move |args| match args.first() {
+                Some(map) => {
+                    change_level
+                        .borrow_mut()
+                        .push(ConsoleAction::ChangeLevel(map.to_string()));
+                    String::new()
+                }
+                None => String::from("usage: map <mapname>"),
+            }),
+        )
+        .unwrap();
+    }
+}
+
+/// Appends command output to `output`, ending it with a newline.
+fn push_line(output: &mut String, line: &str) {
+    if line.is_empty() {
+        return;
+    }
+
+    output.push_str(line);
+    if !line.ends_with('\n') {
+        output.push('\n');
+    }
+}
+
+/// Builds an update containing the values of `state` which differ from
+/// `baseline`.
+fn entity_update(ent_id: EntityId, baseline: &EntityState, state: &EntityState) -> EntityUpdate {
+    let origin = |i: usize| {
+        if (state.origin[i] - baseline.origin[i]).abs() > ORIGIN_EPSILON {
+            Some(state.origin[i])
+        } else {
+            None
+        }
+    };
+
+    let angle = |i: usize| -> Option<Deg<f32>> {
+        if state.angles[i] != baseline.angles[i] {
+            Some(state.angles[i])
+        } else {
+            None
+        }
+    };
+
+    fn changed<T: PartialEq>(new: T, old: T) -> Option<T> {
+        if new != old {
+            Some(new)
+        } else {
+            None
+        }
+    }
+
+    EntityUpdate {
+        ent_id: ent_id.0 as u16,
+        model_id: changed(state.model_id, baseline.model_id).map(|m| m as u16),
+        frame_id: changed(state.frame_id, baseline.frame_id).map(|f| f as u16),
+        colormap: changed(state.colormap, baseline.colormap),
+        skin_id: changed(state.skin_id, baseline.skin_id).map(|s| s as u8),
+        effects: changed(state.effects, baseline.effects),
+        origin_x: origin(0),
+        pitch: angle(0),
+        origin_y: origin(1),
+        yaw: angle(1),
+        origin_z: origin(2),
+        roll: angle(2),
+        no_lerp: false,
+        alpha: None,
+        scale: None,
+        lerp_finish: None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_console_cmds_queue_actions() {
+        let actions = Rc::new(RefCell::new(Vec::new()));
+        let mut cmds = CmdRegistry::new(Rc::new(RefCell::new(Vec::new())));
+        register_cmds(&mut cmds, actions.clone());
+
+        assert_eq!(cmds.exec("kick", &["#", "2"]).unwrap(), "");
+        assert_eq!(cmds.exec("changelevel", &["e1m2"]).unwrap(), "");
+        assert!(cmds.exec("map", &[]).unwrap().starts_with("usage"));
+        assert_eq!(
+            *actions.borrow(),
+            vec![
+                ConsoleAction::Kick(String::from("# 2")),
+                ConsoleAction::ChangeLevel(String::from("e1m2")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_server_cmds_drops_malformed() {
+        let protocol = Protocol::from(ProtocolVersion::NetQuake);
+        let print = || ServerCmd::Print {
+            text: String::from("hello\n"),
+        };
+
+        let mut msg = Vec::new();
+        print().serialize(&mut msg, protocol).unwrap();
+        print().serialize(&mut msg, protocol).unwrap();
+        assert_eq!(read_server_cmds(&msg, protocol), vec![print(), print()]);
+
+        // a command cut short by QuakeC
+        msg.extend([ServerCmdCode::Sound as u8, 0xFF]);
+        assert_eq!(read_server_cmds(&msg, protocol), vec![print(), print()]);
+    }
+
+    use cgmath::Vector3;
+
+    #[test]
+    fn test_entity_update_unchanged_is_empty() {
+        let state = EntityState {
+            origin: Vector3::new(1.0, 2.0, 3.0),
+            model_id: 4,
+            ..EntityState::uninitialized()
+        };
+
+        let update = entity_update(EntityId(7), &state, &state);
+        assert_eq!(
+            update,
+            EntityUpdate {
+                ent_id: 7,
+                model_id: None,
+                frame_id: None,
+                colormap: None,
+                skin_id: None,
+                effects: None,
+                origin_x: None,
+                pitch: None,
+                origin_y: None,
+                yaw: None,
+                origin_z: None,
+                roll: None,
+                no_lerp: false,
+                alpha: None,
+                scale: None,
+                lerp_finish: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_entity_update_changed_fields() {
+        let baseline = EntityState {
+            model_id: 4,
+            ..EntityState::uninitialized()
+        };
+        let state = EntityState {
+            origin: Vector3::new(64.0, 0.05, 0.0),
+            angles: Vector3::new(Deg(0.0), Deg(90.0), Deg(0.0)),
+            model_id: 4,
+            frame_id: 2,
+            ..EntityState::uninitialized()
+        };
+
+        let update = entity_update(EntityId(300), &baseline, &state);
+        assert_eq!(update.ent_id, 300);
+        assert_eq!(update.model_id, None);
+        assert_eq!(update.frame_id, Some(2));
+        assert_eq!(update.origin_x, Some(64.0));
+
+        // changes below the threshold aren't sent
+        assert_eq!(update.origin_y, None);
+        assert_eq!(update.yaw, Some(Deg(90.0)));
+        assert_eq!(update.pitch, None);
+    }
+
+    use crate::{
+        common::{
+            bsp::{self, BspFormat},
+            net::{
+                connect::ConnectSocket, ButtonFlags, ProtocolFlags, ProtocolVersion, ServerCmdCode,
+            },
+            vfs::Vfs,
+        },
+        server::{
+            cvars::register_cvars,
+            progs::{
+                self,
+                functions::FunctionId,
+                globals::{
+                    GlobalAddrFunction, GLOBAL_ADDR_ARG_0, GLOBAL_ADDR_ARG_1, GLOBAL_ADDR_ARG_2,
+                    GLOBAL_ADDR_ARG_3,
+                },
+            },
+            world::{EntityFlags, FieldAddrFloat, FieldAddrVector, MoveKind},
+        },
+    };
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use std::{collections::HashSet, io::Cursor};
+
+    // progs in which every function global refers to function 1, which is
+    // named "worldspawn" so the map can be spawned and returns immediately.
+    // function 3 does bprint("hello\n") and can stand in for ClientConnect.
+    fn progs_fixture() -> Vec<u8> {
+        let strings = b"\0worldspawn\0classname\0bprint\0ClientConnect\0hello\n\0";
+
+        let mut statements = Vec::new();
+        for x in [[0i16; 4], [33, 92, 4, 0], [52, 93, 0, 0], [0; 4]] {
+            for y in x {
+                statements.write_i16::<LittleEndian>(y).unwrap();
+            }
+        }
+
+        // the classname is the only field looked up by name
+        let mut field_defs = Vec::new();
+        field_defs.write_u16::<LittleEndian>(1).unwrap();
+        field_defs.write_u16::<LittleEndian>(28).unwrap();
+        field_defs.write_i32::<LittleEndian>(12).unwrap();
+
+        let mut functions = Vec::new();
+        for (first_statement, name) in [(0, 0), (0, 1), (-23, 22), (1, 29)] {
+            for x in [first_statement, 0, 0, 0, name, 0, 0] {
+                functions.write_i32::<LittleEndian>(x).unwrap();
+            }
+            functions.extend_from_slice(&[0; 8]);
+        }
+
+        // followed by the string and function constants used by function 3
+        let mut globals = Vec::new();
+        for addr in 0..92 {
+            let value = if addr >= 82 { 1 } else { 0 };
+            globals.write_i32::<LittleEndian>(value).unwrap();
+        }
+        globals.write_i32::<LittleEndian>(43).unwrap();
+        globals.write_i32::<LittleEndian>(2).unwrap();
+
+        let lumps = [
+            (statements, 4),
+            (Vec::new(), 0),
+            (field_defs, 1),
+            (functions, 4),
+            (strings.to_vec(), strings.len()),
+            (globals, 94),
+        ];
+
+        let mut progs = Vec::new();
+        progs.write_i32::<LittleEndian>(6).unwrap();
+        progs.write_i32::<LittleEndian>(5927).unwrap();
+
+        let mut offset = 4 + 4 + 8 * lumps.len() + 4;
+        for (data, count) in lumps.iter() {
+            progs.write_i32::<LittleEndian>(offset as i32).unwrap();
+            progs.write_i32::<LittleEndian>(*count as i32).unwrap();
+            offset += data.len();
+        }
+        progs.write_i32::<LittleEndian>(105).unwrap();
+
+        for (data, _) in lumps {
+            progs.extend(data);
+        }
+
+        progs
+    }
+
+    fn send_cmd(sock: &mut LoopbackSocket, cmd: ClientCmd, reliable: bool) {
+        let mut msg = Vec::new();
+        cmd.serialize(
+            &mut msg,
+            Protocol::new(ProtocolVersion::NetQuake, ProtocolFlags::empty()),
+        )
+        .unwrap();
+
+        match reliable {
+            true => sock.begin_send_msg(&msg).unwrap(),
+            false => sock.send_msg_unreliable(&msg).unwrap(),
+        }
+    }
+
+    // counts the messages received since the last call
+    fn recv_all(sock: &mut LoopbackSocket) -> usize {
+        let mut count = 0;
+        while !sock.recv_msg(BlockingMode::NonBlocking).unwrap().is_empty() {
+            count += 1;
+        }
+
+        count
+    }
+
+    // starts a server on the fixture map
+    fn fixture_server() -> NetServer {
+        let cvars = Rc::new(RefCell::new(CvarRegistry::new(Rc::new(RefCell::new(
+            Vec::new(),
+        )))));
+        register_cvars(&cvars.borrow()).unwrap();
+
+        let progs = progs::load(Cursor::new(progs_fixture())).unwrap();
+        let (models, entmap) = bsp::load(Cursor::new(bsp::bsp_fixture(BspFormat::Quake))).unwrap();
+        let session =
+            Session::new(1, Rc::new(Vfs::new()), cvars.clone(), progs, models, entmap).unwrap();
+
+        NetServer::new(session, cvars)
+    }
+
+    // starts a server on the fixture map and connects a loopback client
+    fn connected_client() -> (NetServer, LoopbackSocket) {
+        let mut server = fixture_server();
+        let client = server.connect_loopback().unwrap().unwrap();
+        server.frame(Duration::milliseconds(50)).unwrap();
+
+        (server, client)
+    }
+
+    // runs a sign-on command and returns the server commands sent in reply
+    fn sign_on_cmd(
+        server: &mut NetServer,
+        client: &mut LoopbackSocket,
+        cmd: &str,
+    ) -> Vec<ServerCmd> {
+        recv_all(client);
+        send_cmd(
+            client,
+            ClientCmd::StringCmd {
+                cmd: String::from(cmd),
+            },
+            true,
+        );
+        server.frame(Duration::milliseconds(50)).unwrap();
+
+        let mut cmds = Vec::new();
+        loop {
+            let msg = client.recv_msg(BlockingMode::NonBlocking).unwrap();
+            if msg.is_empty() {
+                break;
+            }
+
+            cmds.extend(read_server_cmds(&msg, server.protocol));
+        }
+
+        cmds
+    }
+
+    // starts a server on the fixture map and signs a loopback client on
+    fn spawned_client() -> (NetServer, LoopbackSocket) {
+        let (mut server, mut client) = connected_client();
+        for cmd in ["prespawn", "spawn", "begin"] {
+            sign_on_cmd(&mut server, &mut client, cmd);
+        }
+
+        recv_all(&mut client);
+        (server, client)
+    }
+
+    #[test]
+    fn test_client_connect_bprint() {
+        let (mut server, mut client) = connected_client();
+        server
+            .session
+            .level_mut()
+            .globals
+            .put_function_id(FunctionId(3), GlobalAddrFunction::ClientConnect as i16)
+            .unwrap();
+
+        let mut cmds = Vec::new();
+        for cmd in ["prespawn", "spawn", "begin"] {
+            cmds.extend(sign_on_cmd(&mut server, &mut client, cmd));
+        }
+
+        assert!(cmds.iter().any(|cmd| matches!(
+            cmd,
+            ServerCmd::Print { text } if text == "hello\n"
+        )));
+        assert!(server.connections[0].as_ref().unwrap().spawned);
+    }
+
+    #[test]
+    fn test_prespawn_sends_statics() {
+        let (mut server, mut client) = connected_client();
+
+        // what a torch's spawn function does
+        let level = server.session.level_mut();
+        let sound_id = level.string_table.borrow_mut().insert("ambience/fire1.wav");
+        level.precache_sound(sound_id);
+        level
+            .globals
+            .put_vector([64.0, 32.0, 16.0], GLOBAL_ADDR_ARG_0 as i16)
+            .unwrap();
+        level
+            .globals
+            .put_string_id(sound_id, GLOBAL_ADDR_ARG_1 as i16)
+            .unwrap();
+        level
+            .globals
+            .put_float(0.5, GLOBAL_ADDR_ARG_2 as i16)
+            .unwrap();
+        level
+            .globals
+            .put_float(3.0, GLOBAL_ADDR_ARG_3 as i16)
+            .unwrap();
+        level.builtin_ambient_sound().unwrap();
+
+        let torch = level.world.alloc_uninitialized().unwrap();
+        let ent = level.world.entity_mut(torch).unwrap();
+        ent.store(FieldAddrFloat::ModelIndex, 1.0).unwrap();
+        ent.store(FieldAddrVector::Origin, [64.0, 32.0, 16.0])
+            .unwrap();
+        level
+            .globals
+            .put_entity_id(torch, GLOBAL_ADDR_ARG_0 as i16)
+            .unwrap();
+        level.builtin_make_static().unwrap();
+        assert!(!level.world.entity_exists(torch));
+
+        let cmds = sign_on_cmd(&mut server, &mut client, "prespawn");
+        assert!(cmds.contains(&ServerCmd::SpawnStaticSound {
+            origin: Vector3::new(64.0, 32.0, 16.0),
+            sound_id: 1,
+            volume: 127,
+            attenuation: 192,
+        }));
+        assert!(cmds.contains(&ServerCmd::SpawnStatic {
+            model_id: 1,
+            frame_id: 0,
+            colormap: 0,
+            skin_id: 0,
+            origin: Vector3::new(64.0, 32.0, 16.0),
+            angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+            alpha: None,
+        }));
+        assert_eq!(
+            cmds.last(),
+            Some(&ServerCmd::SignOnStage {
+                stage: SignOnStage::ClientInfo
+            })
+        );
+    }
+
+    #[test]
+    fn test_requests_limited_per_frame() {
+        let mut server = fixture_server();
+        server.listen("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+
+        let mut socket = ConnectSocket::bind("127.0.0.1:0").unwrap();
+        for _ in 0..MAX_REQUESTS_PER_FRAME + 10 {
+            socket
+                .send_request(Request::server_info(GAME_NAME), server_addr)
+                .unwrap();
+        }
+
+        let mut responses = || {
+            let mut count = 0;
+            while socket
+                .recv_response(Some(Duration::milliseconds(100)))
+                .unwrap()
+                .is_some()
+            {
+                count += 1;
+            }
+            count
+        };
+
+        // the requests left over are answered on the next frame
+        server.frame(Duration::milliseconds(50)).unwrap();
+        assert_eq!(responses(), MAX_REQUESTS_PER_FRAME);
+        server.frame(Duration::milliseconds(50)).unwrap();
+        assert_eq!(responses(), 10);
+    }
+
+    #[test]
+    fn test_loopback_client_moves() {
+        let (mut server, mut client) = spawned_client();
+        let frame_time = Duration::milliseconds(50);
+
+        let ent_id = server.connections[0].as_ref().unwrap().entity_id.unwrap();
+
+        // do what PutClientInServer would if the progs weren't empty
+        let ent = server.session.level_mut().world.entity_mut(ent_id).unwrap();
+        ent.store(FieldAddrFloat::MoveKind, MoveKind::Walk as u32 as f32)
+            .unwrap();
+        ent.store(FieldAddrFloat::Health, 100.0).unwrap();
+        ent.store(FieldAddrVector::Origin, [0.0, 0.0, 32.0])
+            .unwrap();
+
+        let mut updates = 0;
+        for i in 0..20 {
+            send_cmd(
+                &mut client,
+                ClientCmd::Move {
+                    send_time: frame_time * i,
+                    angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+                    fwd_move: 200,
+                    side_move: 0,
+                    up_move: 0,
+                    button_flags: ButtonFlags::empty(),
+                    impulse: 0,
+                },
+                false,
+            );
+            server.frame(frame_time).unwrap();
+            updates += recv_all(&mut client);
+        }
+
+        // the player fell onto the floor and walked forward
+        let ent = server.session.level().world.entity(ent_id);
+        let origin = ent.origin().unwrap();
+        assert!(ent.flags().unwrap().contains(EntityFlags::ON_GROUND));
+        assert!(origin.z >= 0.0 && origin.z < 1.0, "{:?}", origin);
+        assert!(origin.x > 0.0, "{:?}", origin);
+
+        assert!(server.connections[0].as_ref().unwrap().spawned);
+        assert!(updates >= 20);
+    }
+
+    #[test]
+    fn test_datagram_rotates_entities() {
+        let (mut server, _client) = spawned_client();
+
+        // far more visible entities than fit in one datagram
+        let level = server.session.level_mut();
+        for i in 0..200 {
+            let ent_id = level.world.alloc_uninitialized().unwrap();
+            let ent = level.world.entity_mut(ent_id).unwrap();
+            ent.store(FieldAddrFloat::ModelIndex, 1.0).unwrap();
+            ent.store(FieldAddrVector::Origin, [i as f32, 0.0, 0.0])
+                .unwrap();
+        }
+
+        let client_ent = server.connections[0].as_ref().unwrap().entity_id.unwrap();
+        let mut updated = HashSet::new();
+        let mut first_updates = Vec::new();
+        for _ in 0..10 {
+            let (msg, next_update) = server.client_datagram(0).unwrap();
+            server.connections[0].as_mut().unwrap().next_update = next_update;
+
+            let mut reader = BufReader::new(msg.as_slice());
+            let mut ent_ids = Vec::new();
+            while let Some(cmd) = ServerCmd::deserialize(&mut reader, server.protocol).unwrap() {
+                if let ServerCmd::FastUpdate(update) = cmd {
+                    ent_ids.push(update.ent_id as usize);
+                }
+            }
+
+            // the client's own entity always comes first
+            assert_eq!(ent_ids[0], client_ent.0);
+            first_updates.push(ent_ids[1]);
+            updated.extend(ent_ids);
+        }
+
+        assert!(first_updates.windows(2).any(|w| w[0] != w[1]));
+        assert!(updated.len() > 200, "{}", updated.len());
+    }
+}
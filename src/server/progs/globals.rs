@@ -213,7 +213,7 @@ impl GlobalAddr for GlobalAddrEntity {
 #[derive(FromPrimitive)]
 pub enum GlobalAddrField {}
 
-#[derive(FromPrimitive)]
+#[derive(Copy, Clone, Debug, Eq, FromPrimitive, Hash, PartialEq)]
 pub enum GlobalAddrFunction {
     Main = 82,
     StartFrame = 83,
@@ -589,22 +589,22 @@ impl ExecutionContext {
             self.string_table.borrow().get(def.name_id).unwrap()
         );
 
-        // save stack frame
-        self.call_stack.push(StackFrame {
-            instr_id: self.pc,
-            func_id: self.current_function,
-        });
-
-        // check call stack overflow
-        if self.call_stack.len() >= MAX_CALL_STACK_DEPTH {
+        // check for overflow before touching either stack, so that a failed call leaves nothing
+        // behind to unwind
+        if self.call_stack.len() + 1 >= MAX_CALL_STACK_DEPTH {
             return Err(ProgsError::CallStackOverflow);
         }
 
-        // preemptively check local stack overflow
         if self.local_stack.len() + def.locals > MAX_LOCAL_STACK_DEPTH {
             return Err(ProgsError::LocalStackOverflow);
         }
 
+        // save stack frame
+        self.call_stack.push(StackFrame {
+            instr_id: self.pc,
+            func_id: self.current_function,
+        });
+
         // save locals to stack
         for i in 0..def.locals {
             self.local_stack
@@ -806,6 +806,12 @@ impl Entity {
         Ok(())
     }
 
+    pub fn remove_flags(&mut self, flags: EntityFlags) -> Result<(), EntityError> {
+        let result = self.flags()? - flags;
+        self.put_float(result.bits() as f32, FieldAddrFloat::Flags as i16)?;
+        Ok(())
+    }
+
     pub fn owner(&self) -> Result<EntityId, EntityError> {
         Ok(self.entity_id(FieldAddrEntityId::Owner as i16)?)
     }